@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use osmpbf::{Element, ElementReader};
+use osmpbf::{BlockDecoding, Element, ElementReader};
 use std::env;
 
 criterion_group!(benches, bench_count);
@@ -18,21 +18,27 @@ fn bench_count(c: &mut Criterion) {
     #[cfg(feature = "zlib-ng")]
     println!("Using zlib-ng");
 
-    c.bench_function(format!("Benchmarking using {file}").as_str(), |b| {
-        b.iter(|| {
-            let path = std::path::Path::new(file);
-            let reader = ElementReader::from_path(path).unwrap();
-            reader
-                .par_map_reduce(
-                    |element| match element {
-                        Element::Node(_) | Element::DenseNode(_) => (1, 0, 0),
-                        Element::Way(_) => (0, 1, 0),
-                        Element::Relation(_) => (0, 0, 1),
-                    },
-                    || (0u64, 0u64, 0u64),
-                    |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2),
-                )
-                .unwrap()
-        })
-    });
+    for block_decoding in [BlockDecoding::Eager, BlockDecoding::Lazy] {
+        c.bench_function(
+            format!("Benchmarking using {file} ({block_decoding:?})").as_str(),
+            |b| {
+                b.iter(|| {
+                    let path = std::path::Path::new(file);
+                    let mut reader = ElementReader::from_path(path).unwrap();
+                    reader.set_block_decoding(block_decoding);
+                    reader
+                        .par_map_reduce(
+                            |element| match element {
+                                Element::Node(_) | Element::DenseNode(_) => (1, 0, 0),
+                                Element::Way(_) => (0, 1, 0),
+                                Element::Relation(_) => (0, 0, 1),
+                            },
+                            || (0u64, 0u64, 0u64),
+                            |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2),
+                        )
+                        .unwrap()
+                })
+            },
+        );
+    }
 }
@@ -18,7 +18,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     reader.read_ways_and_deps(
         |way| {
             // Filter ways. Return true if tags contain "building": "yes".
-            way.tags().any(|key_value| key_value == ("building", "yes"))
+            way.has_tag_value("building", "yes")
         },
         |element| {
             // Increment counter for ways and nodes
@@ -0,0 +1,486 @@
+//! Convert blocks to Apache Arrow record batches
+//!
+//! Each [`PrimitiveBlock`] is converted to three [`RecordBatch`]es, one for nodes (including
+//! dense nodes), one for ways and one for relations. All batches start with an `id` column,
+//! followed by the columns that are specific to the element type, and end with the tags and
+//! metadata columns:
+//!
+//! | column      | type                              | nullable |
+//! |-------------|-----------------------------------|----------|
+//! | `id`        | `Int64`                           | no       |
+//! | ...         |                                   |          |
+//! | `tags`      | `Map<Utf8, Utf8>`                 | no       |
+//! | `version`   | `Int32`                           | yes      |
+//! | `timestamp` | `Timestamp(Millisecond, "UTC")`   | yes      |
+//! | `changeset` | `Int64`                           | yes      |
+//! | `uid`       | `Int32`                           | yes      |
+//! | `user`      | `Utf8`                            | yes      |
+//! | `visible`   | `Boolean`                         | no       |
+//!
+//! The element specific columns are:
+//! * nodes: `lat` and `lon` as `Float64` in degrees.
+//! * ways: `refs` as `List<Int64>` and `locations` as `List<Struct<lat: Float64, lon: Float64>>`.
+//!   The locations are null if the file does not store locations on ways.
+//! * relations: `members` as `List<Struct<type: Utf8, ref: Int64, role: Utf8>>`. The type is one
+//!   of `node`, `way` and `relation`.
+
+use crate::blob::BlobDecode;
+use crate::block::PrimitiveBlock;
+use crate::elements::{RelMemberType, Way};
+use crate::error::Result;
+use crate::object::OsmObject;
+use crate::reader::ElementReader;
+use arrow_array::builder::{
+    BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, ListBuilder, MapBuilder,
+    StringBuilder, TimestampMillisecondBuilder,
+};
+use arrow_array::{ArrayRef, Float64Array, Int64Array, ListArray, StringArray, StructArray};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_schema::{DataType, Field, Fields, SchemaRef};
+use std::io::Read;
+use std::sync::Arc;
+
+pub use arrow_array::RecordBatch;
+
+/// The nodes, ways and relations of a block as Arrow record batches.
+#[derive(Clone, Debug)]
+pub struct ElementBatches {
+    /// Nodes and dense nodes, see [`node_schema`].
+    pub nodes: RecordBatch,
+    /// Ways, see [`way_schema`].
+    pub ways: RecordBatch,
+    /// Relations, see [`relation_schema`].
+    pub relations: RecordBatch,
+}
+
+/// Returns the schema of node record batches.
+pub fn node_schema() -> SchemaRef {
+    NodeColumns::default()
+        .finish()
+        .expect("empty node batch")
+        .schema()
+}
+
+/// Returns the schema of way record batches.
+pub fn way_schema() -> SchemaRef {
+    WayColumns::default()
+        .finish()
+        .expect("empty way batch")
+        .schema()
+}
+
+/// Returns the schema of relation record batches.
+pub fn relation_schema() -> SchemaRef {
+    RelationColumns::default()
+        .finish()
+        .expect("empty relation batch")
+        .schema()
+}
+
+impl PrimitiveBlock {
+    /// Converts the elements of this block to Arrow record batches. Tags are decoded according to
+    /// the [`TagDecoding`](crate::block::TagDecoding) policy of the block.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    ///
+    /// for blob in reader {
+    ///     if let BlobDecode::OsmData(block) = blob?.decode()? {
+    ///         let batches = block.to_record_batches()?;
+    ///         println!("{} ways", batches.ways.num_rows());
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn to_record_batches(&self) -> Result<ElementBatches> {
+        let mut nodes = NodeColumns::default();
+        let mut ways = WayColumns::default();
+        let mut relations = RelationColumns::default();
+        for group in self.groups() {
+            for node in group.nodes() {
+                nodes.append(&node, node.lat(), node.lon())?;
+            }
+            for node in group.dense_nodes() {
+                nodes.append(&node, node.lat(), node.lon())?;
+            }
+            for way in group.ways() {
+                ways.append(&way)?;
+            }
+            for relation in group.relations() {
+                relations.common.append(&relation)?;
+                relations.members.append(relation.members().map(|member| {
+                    let member_type = match member.member_type {
+                        RelMemberType::Node => "node",
+                        RelMemberType::Way => "way",
+                        RelMemberType::Relation => "relation",
+                    };
+                    (member_type, member.member_id, member.role().ok())
+                }));
+            }
+        }
+        Ok(ElementBatches {
+            nodes: nodes.finish()?,
+            ways: ways.finish()?,
+            relations: relations.finish()?,
+        })
+    }
+}
+
+impl<R: Read + Send> ElementReader<R> {
+    /// Decodes the blocks in parallel, converts them to Arrow record batches and calls the given
+    /// closure with the batches of each block. The closure is called from multiple threads and
+    /// the blocks are not visited in file order.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    /// use std::sync::Mutex;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    /// let node_batches = Mutex::new(vec![]);
+    ///
+    /// reader.par_for_each_record_batches(|batches| {
+    ///     node_batches.lock().unwrap().push(batches.nodes);
+    /// })?;
+    ///
+    /// let nodes: usize = node_batches.lock().unwrap().iter().map(|b| b.num_rows()).sum();
+    /// # assert_eq!(nodes, 3);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn par_for_each_record_batches<F>(self, f: F) -> Result<()>
+    where
+        F: Fn(ElementBatches) + Sync + Send,
+    {
+        self.par_map_reduce_blobs(
+            |blob| match blob.decode()? {
+                BlobDecode::OsmData(block) => {
+                    let batches = block.to_record_batches()?;
+                    let elements = batches.nodes.num_rows()
+                        + batches.ways.num_rows()
+                        + batches.relations.num_rows();
+                    f(batches);
+                    Ok(((), elements as u64))
+                }
+                BlobDecode::OsmHeader(_) | BlobDecode::Unknown(_) => Ok(((), 0)),
+            },
+            || (),
+            |_, _| (),
+        )
+    }
+}
+
+/// The columns that all element types have in common.
+struct CommonColumns {
+    ids: Int64Builder,
+    tags: MapBuilder<StringBuilder, StringBuilder>,
+    versions: Int32Builder,
+    timestamps: TimestampMillisecondBuilder,
+    changesets: Int64Builder,
+    uids: Int32Builder,
+    users: StringBuilder,
+    visible: BooleanBuilder,
+}
+
+impl Default for CommonColumns {
+    fn default() -> Self {
+        CommonColumns {
+            ids: Int64Builder::new(),
+            tags: MapBuilder::new(None, StringBuilder::new(), StringBuilder::new()),
+            versions: Int32Builder::new(),
+            timestamps: TimestampMillisecondBuilder::new().with_timezone("UTC"),
+            changesets: Int64Builder::new(),
+            uids: Int32Builder::new(),
+            users: StringBuilder::new(),
+            visible: BooleanBuilder::new(),
+        }
+    }
+}
+
+impl CommonColumns {
+    fn append<'a>(&mut self, object: &impl OsmObject<'a>) -> Result<()> {
+        self.ids.append_value(object.id());
+        for (key, value) in object.tags() {
+            self.tags.keys().append_value(key);
+            self.tags.values().append_value(value);
+        }
+        self.tags.append(true)?;
+        let metadata = object.metadata();
+        self.versions.append_option(metadata.version());
+        self.timestamps.append_option(metadata.milli_timestamp());
+        self.changesets.append_option(metadata.changeset());
+        self.uids.append_option(metadata.uid());
+        self.users
+            .append_option(metadata.user().and_then(|user| user.ok()));
+        self.visible.append_value(metadata.visible());
+        Ok(())
+    }
+
+    /// Creates a record batch with the id column, the given columns and the tags and metadata
+    /// columns.
+    fn finish(&mut self, columns: Vec<(&str, ArrayRef, bool)>) -> Result<RecordBatch> {
+        let id: ArrayRef = Arc::new(self.ids.finish());
+        let common: [(&str, ArrayRef, bool); 7] = [
+            ("tags", Arc::new(self.tags.finish()), false),
+            ("version", Arc::new(self.versions.finish()), true),
+            ("timestamp", Arc::new(self.timestamps.finish()), true),
+            ("changeset", Arc::new(self.changesets.finish()), true),
+            ("uid", Arc::new(self.uids.finish()), true),
+            ("user", Arc::new(self.users.finish()), true),
+            ("visible", Arc::new(self.visible.finish()), false),
+        ];
+        let columns = std::iter::once(("id", id, false))
+            .chain(columns)
+            .chain(common);
+        Ok(RecordBatch::try_from_iter_with_nullable(columns)?)
+    }
+}
+
+#[derive(Default)]
+struct NodeColumns {
+    common: CommonColumns,
+    lats: Float64Builder,
+    lons: Float64Builder,
+}
+
+impl NodeColumns {
+    fn append<'a>(&mut self, node: &impl OsmObject<'a>, lat: f64, lon: f64) -> Result<()> {
+        self.common.append(node)?;
+        self.lats.append_value(lat);
+        self.lons.append_value(lon);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<RecordBatch> {
+        self.common.finish(vec![
+            ("lat", Arc::new(self.lats.finish()), false),
+            ("lon", Arc::new(self.lons.finish()), false),
+        ])
+    }
+}
+
+struct WayColumns {
+    common: CommonColumns,
+    refs: ListBuilder<Int64Builder>,
+    locations: StructList<LocationValues>,
+}
+
+impl Default for WayColumns {
+    fn default() -> Self {
+        WayColumns {
+            common: CommonColumns::default(),
+            refs: ListBuilder::new(Int64Builder::new()).with_field(Field::new(
+                "item",
+                DataType::Int64,
+                false,
+            )),
+            locations: StructList::default(),
+        }
+    }
+}
+
+impl WayColumns {
+    fn append(&mut self, way: &Way) -> Result<()> {
+        self.common.append(way)?;
+        for node_id in way.refs() {
+            self.refs.values().append_value(node_id);
+        }
+        self.refs.append(true);
+        let locations = way.node_locations();
+        if locations.len() == 0 {
+            self.locations.append_null();
+        } else {
+            self.locations
+                .append(locations.map(|loc| (loc.lat(), loc.lon())));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<RecordBatch> {
+        let (lats, lons) = self.locations.values;
+        let locations = self.locations.list.finish(
+            vec![
+                Field::new("lat", DataType::Float64, false),
+                Field::new("lon", DataType::Float64, false),
+            ],
+            vec![
+                Arc::new(Float64Array::from(lats)),
+                Arc::new(Float64Array::from(lons)),
+            ],
+        );
+        self.common.finish(vec![
+            ("refs", Arc::new(self.refs.finish()), false),
+            ("locations", locations, true),
+        ])
+    }
+}
+
+#[derive(Default)]
+struct RelationColumns {
+    common: CommonColumns,
+    members: StructList<MemberValues>,
+}
+
+impl RelationColumns {
+    fn finish(mut self) -> Result<RecordBatch> {
+        let (types, refs, roles) = self.members.values;
+        let members = self.members.list.finish(
+            vec![
+                Field::new("type", DataType::Utf8, false),
+                Field::new("ref", DataType::Int64, false),
+                Field::new("role", DataType::Utf8, true),
+            ],
+            vec![
+                Arc::new(StringArray::from(types)),
+                Arc::new(Int64Array::from(refs)),
+                Arc::new(StringArray::from(roles)),
+            ],
+        );
+        self.common.finish(vec![("members", members, false)])
+    }
+}
+
+/// The latitudes and longitudes of way node locations.
+type LocationValues = (Vec<f64>, Vec<f64>);
+
+/// The types, ids and roles of relation members.
+type MemberValues = (Vec<&'static str>, Vec<i64>, Vec<Option<String>>);
+
+/// A list of structs that are collected as one vector per struct field.
+#[derive(Default)]
+struct StructList<T> {
+    list: ListOffsets,
+    values: T,
+}
+
+impl StructList<LocationValues> {
+    fn append(&mut self, locations: impl Iterator<Item = (f64, f64)>) {
+        let (lats, lons) = &mut self.values;
+        let start = lats.len();
+        for (lat, lon) in locations {
+            lats.push(lat);
+            lons.push(lon);
+        }
+        self.list.push(lats.len() - start, true);
+    }
+
+    fn append_null(&mut self) {
+        self.list.push(0, false);
+    }
+}
+
+impl StructList<MemberValues> {
+    fn append<'a>(&mut self, members: impl Iterator<Item = (&'static str, i64, Option<&'a str>)>) {
+        let (types, refs, roles) = &mut self.values;
+        let start = types.len();
+        for (member_type, member_ref, role) in members {
+            types.push(member_type);
+            refs.push(member_ref);
+            roles.push(role.map(str::to_string));
+        }
+        self.list.push(types.len() - start, true);
+    }
+}
+
+/// The lengths and validity of the entries of a list array.
+#[derive(Default)]
+struct ListOffsets {
+    lengths: Vec<usize>,
+    valid: Vec<bool>,
+}
+
+impl ListOffsets {
+    fn push(&mut self, length: usize, valid: bool) {
+        self.lengths.push(length);
+        self.valid.push(valid);
+    }
+
+    fn finish(self, fields: Vec<Field>, arrays: Vec<ArrayRef>) -> ArrayRef {
+        let fields = Fields::from(fields);
+        let item = Field::new("item", DataType::Struct(fields.clone()), false);
+        let nulls = if self.valid.iter().all(|&valid| valid) {
+            None
+        } else {
+            Some(NullBuffer::from(self.valid))
+        };
+        Arc::new(ListArray::new(
+            Arc::new(item),
+            OffsetBuffer::from_lengths(self.lengths),
+            Arc::new(StructArray::new(fields, arrays, None)),
+            nulls,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::Array;
+
+    #[test]
+    fn test_schemas() {
+        let nodes = node_schema();
+        let names: Vec<&str> = nodes.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "id",
+                "lat",
+                "lon",
+                "tags",
+                "version",
+                "timestamp",
+                "changeset",
+                "uid",
+                "user",
+                "visible"
+            ]
+        );
+        assert!(matches!(
+            way_schema().field_with_name("refs").unwrap().data_type(),
+            DataType::List(_)
+        ));
+        assert!(matches!(
+            relation_schema()
+                .field_with_name("members")
+                .unwrap()
+                .data_type(),
+            DataType::List(_)
+        ));
+    }
+
+    #[test]
+    fn test_struct_list() {
+        let mut locations = StructList::<LocationValues>::default();
+        locations.append([(1.0, 2.0), (3.0, 4.0)].into_iter());
+        locations.append_null();
+        locations.append(std::iter::empty());
+        let (lats, lons) = locations.values;
+        let array = locations.list.finish(
+            vec![
+                Field::new("lat", DataType::Float64, false),
+                Field::new("lon", DataType::Float64, false),
+            ],
+            vec![
+                Arc::new(Float64Array::from(lats)),
+                Arc::new(Float64Array::from(lons)),
+            ],
+        );
+        let list = array.as_list::<i32>();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.value_offsets(), &[0, 2, 2, 2]);
+        assert!(list.is_valid(0) && list.is_null(1) && list.is_valid(2));
+    }
+}
@@ -0,0 +1,437 @@
+//! Read blobs and elements from asynchronous streams
+//!
+//! The readers in this module read the length-prefixed blob framing of a PBF stream from a
+//! [`tokio::io::AsyncRead`], for example a file opened with `tokio::fs` or the body of a response
+//! from an object storage service. Decompressing and decoding the blobs is CPU-bound work, so it
+//! is moved to the rayon thread pool and never blocks the async runtime.
+
+use crate::blob::{Blob, BlobDecode, ByteOffset};
+use crate::block::{BlockDecoding, PrimitiveBlock, TagDecoding};
+use crate::elements::Element;
+use crate::error::{new_blob_error, new_protobuf_error, BlobError, Error, Result};
+use crate::options::ReaderOptions;
+use crate::proto::fileformat;
+use byteorder::ByteOrder;
+use futures_util::{future, Stream, StreamExt};
+use protobuf::Message;
+use rayon::ThreadPool;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// A reader for PBF streams that reads [`Blob`]s from a [`tokio::io::AsyncRead`].
+///
+/// Unlike [`BlobReader`](crate::blob::BlobReader), this reader has no error recovery mode. The
+/// iteration stops after the first error, even if it is enabled in the [`ReaderOptions`].
+///
+/// # Example
+/// ```
+/// use futures_util::StreamExt;
+/// use osmpbf::*;
+///
+/// # async fn foo() -> Result<()> {
+/// let file = tokio::fs::File::open("tests/test.osm.pbf").await?;
+/// let reader = AsyncBlobReader::new(tokio::io::BufReader::new(file));
+///
+/// let mut blobs = reader.into_stream();
+/// while let Some(blob) = blobs.next().await {
+///     println!("{:?} blob", blob?.get_type());
+/// }
+/// # Ok(())
+/// # }
+/// # tokio::runtime::Runtime::new().unwrap().block_on(foo()).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct AsyncBlobReader<R: AsyncRead + Unpin> {
+    reader: R,
+    /// Current reader offset in bytes from the start of the stream.
+    offset: Option<ByteOffset>,
+    /// The ordinal of the next blob.
+    next_index: u64,
+    /// Is true after the end of the stream or after an error.
+    finished: bool,
+    options: ReaderOptions,
+}
+
+impl<R: AsyncRead + Unpin> AsyncBlobReader<R> {
+    /// Creates a new `AsyncBlobReader`. The offsets of the returned blobs are unknown, use
+    /// [`with_offset`](AsyncBlobReader::with_offset) if the position of the stream is known.
+    pub fn new(reader: R) -> AsyncBlobReader<R> {
+        AsyncBlobReader {
+            reader,
+            offset: None,
+            next_index: 0,
+            finished: false,
+            options: ReaderOptions::default(),
+        }
+    }
+
+    /// Creates a new `AsyncBlobReader` for a stream that starts at the given offset of the PBF
+    /// file, which is usually `ByteOffset(0)`. Each blob will have a valid ([`Some`]) offset.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # async fn foo() -> Result<()> {
+    /// let file = tokio::fs::File::open("tests/test.osm.pbf").await?;
+    /// let mut reader = AsyncBlobReader::with_offset(file, ByteOffset(0));
+    ///
+    /// let first_blob = reader.next_blob().await.unwrap()?;
+    /// assert_eq!(first_blob.offset(), Some(ByteOffset(0)));
+    /// # Ok(())
+    /// # }
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(foo()).unwrap();
+    /// ```
+    pub fn with_offset(reader: R, offset: ByteOffset) -> AsyncBlobReader<R> {
+        AsyncBlobReader {
+            offset: Some(offset),
+            ..Self::new(reader)
+        }
+    }
+
+    /// Replaces all options of the reader, including the ones that were changed with the other
+    /// setters. The error recovery mode is ignored.
+    pub fn set_options(&mut self, options: ReaderOptions) {
+        self.options = options;
+    }
+
+    /// Sets the policy for tags that cannot be decoded (default: [`TagDecoding::Unchecked`]). It
+    /// is applied when the returned blobs are decoded to [`PrimitiveBlock`]s.
+    pub fn set_tag_decoding(&mut self, tag_decoding: TagDecoding) {
+        self.options.tag_decoding = tag_decoding;
+    }
+
+    /// Sets how the returned blobs are decoded to [`PrimitiveBlock`]s (default:
+    /// [`BlockDecoding::Eager`]).
+    pub fn set_block_decoding(&mut self, block_decoding: BlockDecoding) {
+        self.options.block_decoding = block_decoding;
+    }
+
+    /// Reads the next blob. Returns [`None`] at the end of the stream and after an error.
+    pub async fn next_blob(&mut self) -> Option<Result<Blob>> {
+        if self.finished {
+            return None;
+        }
+        let offset = self.offset;
+        let index = self.next_index;
+        match self.read_blob(offset, index).await {
+            Ok(Some(blob)) => {
+                self.next_index += 1;
+                Some(Ok(blob))
+            }
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                self.offset = None;
+                Some(Err(e.with_blob(offset, Some(index))))
+            }
+        }
+    }
+
+    /// Turns the reader into a [`Stream`] of blobs.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Blob>> + Unpin {
+        Box::pin(futures_util::stream::unfold(
+            self,
+            |mut reader| async move {
+                let blob = reader.next_blob().await?;
+                Some((blob, reader))
+            },
+        ))
+    }
+
+    async fn read_blob(&mut self, offset: Option<ByteOffset>, index: u64) -> Result<Option<Blob>> {
+        let mut prefix = [0_u8; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.reader.read(&mut prefix[filled..]).await? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(new_blob_error(BlobError::InvalidHeaderSize)),
+                n => filled += n,
+            }
+        }
+        let header_size = u64::from(byteorder::BigEndian::read_u32(&prefix));
+        self.options.check_header_size(header_size)?;
+
+        let mut bytes = prefix.to_vec();
+        self.read_append(
+            &mut bytes,
+            header_size as usize,
+            "content too short for header",
+        )
+        .await?;
+        let header = fileformat::BlobHeader::parse_from_bytes(&bytes[4..])
+            .map_err(|e| new_protobuf_error(e, "blob header"))?;
+        self.options.check_blob_size(&header)?;
+
+        let datasize = header.datasize() as usize;
+        self.read_append(&mut bytes, datasize, "content too short for block data")
+            .await?;
+
+        self.offset = offset.map(|x| ByteOffset(x.0 + 4 + header_size + datasize as u64));
+        let blob = Blob::new(
+            header,
+            bytes,
+            offset,
+            Some(index),
+            self.options.decode_options(),
+        )?;
+        self.options
+            .check_blob(blob.get_type().as_str(), offset, || blob.to_headerblock())?;
+        Ok(Some(blob))
+    }
+
+    /// Reads exactly `len` bytes and appends them to `bytes`.
+    async fn read_append(
+        &mut self,
+        bytes: &mut Vec<u8>,
+        len: usize,
+        eof_message: &'static str,
+    ) -> Result<()> {
+        let start = bytes.len();
+        bytes.resize(start + len, 0);
+        match self.reader.read_exact(&mut bytes[start..]).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, eof_message).into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// A reader for PBF streams that gives access to the stored elements of a
+/// [`tokio::io::AsyncRead`].
+///
+/// The blobs are read on the async task and decoded in parallel on the rayon thread pool. The
+/// blocks are returned in the order of the stream.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # async fn foo() -> Result<()> {
+/// let file = tokio::fs::File::open("tests/test.osm.pbf").await?;
+/// let reader = AsyncElementReader::new(tokio::io::BufReader::new(file));
+///
+/// let mut ways = 0_u64;
+/// reader
+///     .for_each(|element| {
+///         if let Element::Way(_) = element {
+///             ways += 1;
+///         }
+///     })
+///     .await?;
+///
+/// println!("Number of ways: {ways}");
+/// # assert_eq!(ways, 1);
+/// # Ok(())
+/// # }
+/// # tokio::runtime::Runtime::new().unwrap().block_on(foo()).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct AsyncElementReader<R: AsyncRead + Unpin> {
+    blob_reader: AsyncBlobReader<R>,
+    concurrency: Option<usize>,
+    /// The pool for decoding or `None` for the global pool.
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncElementReader<R> {
+    /// Creates a new `AsyncElementReader`.
+    pub fn new(reader: R) -> AsyncElementReader<R> {
+        AsyncElementReader {
+            blob_reader: AsyncBlobReader::new(reader),
+            concurrency: None,
+            pool: None,
+        }
+    }
+
+    /// Replaces the options of the reader. The error recovery mode is ignored.
+    pub fn set_options(&mut self, options: ReaderOptions) {
+        self.blob_reader.set_options(options);
+    }
+
+    /// Sets the policy for tags that cannot be decoded (default: [`TagDecoding::Unchecked`]).
+    pub fn set_tag_decoding(&mut self, tag_decoding: TagDecoding) {
+        self.blob_reader.set_tag_decoding(tag_decoding);
+    }
+
+    /// Sets how blocks are decoded (default: [`BlockDecoding::Eager`]).
+    pub fn set_block_decoding(&mut self, block_decoding: BlockDecoding) {
+        self.blob_reader.set_block_decoding(block_decoding);
+    }
+
+    /// Sets the maximum number of blobs that are decoded at the same time (default: the number of
+    /// threads in the rayon thread pool). This also limits the number of decoded blocks that are
+    /// kept in memory while waiting for an earlier block. Values below one are treated as one.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = Some(concurrency.max(1));
+    }
+
+    /// Decodes the blobs on the given rayon thread pool instead of the global pool.
+    pub fn set_thread_pool(&mut self, pool: Arc<ThreadPool>) {
+        self.pool = Some(pool);
+    }
+
+    /// Turns the reader into a [`Stream`] of decoded [`PrimitiveBlock`]s. Header blocks and
+    /// blobs of unknown types are skipped. The stream ends after the first error.
+    ///
+    /// # Example
+    /// ```
+    /// use futures_util::StreamExt;
+    /// use osmpbf::*;
+    ///
+    /// # async fn foo() -> Result<()> {
+    /// let file = tokio::fs::File::open("tests/test.osm.pbf").await?;
+    /// let reader = AsyncElementReader::new(file);
+    ///
+    /// let mut blocks = reader.into_block_stream();
+    /// while let Some(block) = blocks.next().await {
+    ///     for element in block?.elements() {
+    ///         println!("{:?} {}", element.element_type(), element.id());
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(foo()).unwrap();
+    /// ```
+    pub fn into_block_stream(self) -> impl Stream<Item = Result<PrimitiveBlock>> + Unpin {
+        let concurrency = self.concurrency.unwrap_or_else(|| match &self.pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        });
+        let pool = self.pool;
+        let blocks = self
+            .blob_reader
+            .into_stream()
+            .map(move |blob| {
+                let pool = pool.clone();
+                async move { decode_on_pool(blob?, pool.as_deref()).await }
+            })
+            .buffered(concurrency)
+            .filter_map(|block| future::ready(block.transpose()));
+        Box::pin(blocks)
+    }
+
+    /// Decodes the stream and calls the given closure on each element in the order of the
+    /// stream. The closure is called on the async task, so it should not block.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while reading or parsing the PBF structure.
+    pub async fn for_each<F>(self, mut f: F) -> Result<()>
+    where
+        F: for<'a> FnMut(Element<'a>),
+    {
+        let mut blocks = self.into_block_stream();
+        while let Some(block) = blocks.next().await {
+            block?.for_each_element(&mut f);
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a blob on the given or the global rayon thread pool. Returns [`None`] for blobs that
+/// do not contain a [`PrimitiveBlock`]. A panic while decoding is returned as an error.
+async fn decode_on_pool(blob: Blob, pool: Option<&ThreadPool>) -> Result<Option<PrimitiveBlock>> {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    let task = move || {
+        let decode = || {
+            blob.decode().map(|decoded| match decoded {
+                BlobDecode::OsmData(block) => Some(block),
+                BlobDecode::OsmHeader(_) | BlobDecode::Unknown(_) => None,
+            })
+        };
+        let block = panic::catch_unwind(AssertUnwindSafe(decode)).unwrap_or_else(|payload| {
+            Err(task_error(format!(
+                "blob decoding task panicked: {}",
+                panic_message(&*payload)
+            )))
+        });
+        // The receiver is gone if the stream was dropped
+        let _ = sender.send(block);
+    };
+    match pool {
+        Some(pool) => pool.spawn(task),
+        None => rayon::spawn(task),
+    }
+    receiver
+        .await
+        .unwrap_or_else(|_| Err(task_error("blob decoding task was dropped".to_string())))
+}
+
+fn task_error(message: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::Other, message).into()
+}
+
+/// Returns the message of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::BlobReader;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Runtime::new().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn test_read_blobs() {
+        let data = std::fs::read("tests/test.osm.pbf").unwrap();
+        let expected: Vec<_> = BlobReader::new(data.as_slice())
+            .map(|blob| blob.unwrap().get_type().as_str().to_string())
+            .collect();
+
+        // Reads of a single byte exercise partial reads of the prefix
+        let reader = tokio::io::BufReader::with_capacity(1, data.as_slice());
+        let mut reader = AsyncBlobReader::with_offset(reader, ByteOffset(0));
+        let mut types = vec![];
+        let mut offsets = vec![];
+        while let Some(blob) = block_on(reader.next_blob()) {
+            let blob = blob.unwrap();
+            types.push(blob.get_type().as_str().to_string());
+            offsets.push(blob.offset().unwrap().0);
+        }
+        assert_eq!(types, expected);
+        assert_eq!(offsets[0], 0);
+        assert!(offsets[1] > 0);
+        assert_eq!(reader.offset, Some(ByteOffset(data.len() as u64)));
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("broken blob {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "broken blob 1");
+        let payload = panic::catch_unwind(|| panic!("broken blob")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "broken blob");
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let data = std::fs::read("tests/test.osm.pbf").unwrap();
+        for len in [2, 10, data.len() - 1] {
+            let mut reader = AsyncBlobReader::new(&data[..len]);
+            let results: Vec<_> = block_on(async {
+                let mut results = vec![];
+                while let Some(blob) = reader.next_blob().await {
+                    results.push(blob.is_ok());
+                }
+                results
+            });
+            assert_eq!(results.last(), Some(&false), "length {len}");
+            assert!(block_on(reader.next_blob()).is_none());
+        }
+    }
+}
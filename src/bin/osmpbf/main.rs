@@ -0,0 +1,388 @@
+// Inspect OpenStreetMap PBF files from the command line.
+//
+// Build with `cargo build --features cli` and run `osmpbf --help` for a list of subcommands.
+
+mod output;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use osmpbf::{
+    format_timestamp, BlobDecode, BlobReader, BlobType, ElementReader, ElementStats, ElementType,
+    FileInfo, HeaderBlock, IdReport, IndexedReader, IntegrityReport, OsmObject, TagFilter,
+    SUPPORTED_FEATURES,
+};
+use output::{write_element, Format};
+use std::error::Error;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "osmpbf", version, about = "Inspect OpenStreetMap PBF files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show header information and optionally statistics about the content
+    Info {
+        file: PathBuf,
+        /// Decode the whole file and show statistics about the elements
+        #[arg(short, long)]
+        extended: bool,
+    },
+    /// Print all elements
+    Cat {
+        file: PathBuf,
+        #[arg(short, long, value_enum, default_value_t = Format::Opl)]
+        format: Format,
+        /// Only print elements of the given types
+        #[arg(short = 't', long = "type", value_enum, value_delimiter = ',')]
+        types: Vec<TypeArg>,
+        /// Only print elements that match a tag filter expression, e.g. "highway and not area=yes"
+        #[arg(long)]
+        filter: Option<TagFilter>,
+    },
+    /// Count nodes, ways and relations
+    Count { file: PathBuf },
+    /// Print elements with the given ids, for example `n105 w107 r120`
+    Get {
+        file: PathBuf,
+        #[arg(required = true)]
+        ids: Vec<String>,
+        #[arg(short, long, value_enum, default_value_t = Format::Opl)]
+        format: Format,
+    },
+    /// List the blobs of the file with their offsets, types and sizes
+    Blobs { file: PathBuf },
+    /// Decode the whole file and report errors
+    Check {
+        file: PathBuf,
+        /// Also check that all referenced nodes, ways and relations are present
+        #[arg(short, long)]
+        refs: bool,
+    },
+}
+
+/// An element type as a command line argument.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum TypeArg {
+    Node,
+    Way,
+    Relation,
+}
+
+impl From<TypeArg> for ElementType {
+    fn from(arg: TypeArg) -> ElementType {
+        match arg {
+            TypeArg::Node => ElementType::Node,
+            TypeArg::Way => ElementType::Way,
+            TypeArg::Relation => ElementType::Relation,
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Info { file, extended } => info(file, extended),
+        Command::Cat {
+            file,
+            format,
+            types,
+            filter,
+        } => cat(
+            file,
+            format,
+            &types.into_iter().map(ElementType::from).collect::<Vec<_>>(),
+            filter.as_ref(),
+        ),
+        Command::Count { file } => count(file),
+        Command::Get { file, ids, format } => get(file, &ids, format),
+        Command::Blobs { file } => blobs(file),
+        Command::Check { file, refs } => check(file, refs),
+    };
+
+    if let Err(e) = result {
+        eprintln!("osmpbf: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn print_header(header: &HeaderBlock) {
+    if let Some(program) = header.writing_program() {
+        println!("  Writing program: {program}");
+    }
+    if let Some(source) = header.source() {
+        println!("  Source: {source}");
+    }
+    if let Some(bbox) = header.bbox() {
+        println!(
+            "  Bounding box: ({:.7}, {:.7}, {:.7}, {:.7})",
+            bbox.left, bbox.bottom, bbox.right, bbox.top
+        );
+    }
+    println!(
+        "  Required features: {}",
+        header.required_features().join(", ")
+    );
+    println!(
+        "  Optional features: {}",
+        header.optional_features().join(", ")
+    );
+    if let Some(timestamp) = header.osmosis_replication_timestamp() {
+        println!(
+            "  Replication timestamp: {}",
+            format_timestamp(timestamp * 1000)
+        );
+    }
+    if let Some(sequence_number) = header.osmosis_replication_sequence_number() {
+        println!("  Replication sequence number: {sequence_number}");
+    }
+    if let Some(url) = header.osmosis_replication_base_url() {
+        println!("  Replication base URL: {url}");
+    }
+}
+
+fn print_element_stats(name: &str, stats: &ElementStats) {
+    match stats.ids {
+        Some(ref ids) => println!(
+            "  {name}: {} (ids {}..={})",
+            stats.count,
+            ids.start(),
+            ids.end()
+        ),
+        None => println!("  {name}: 0"),
+    }
+}
+
+fn info(file: PathBuf, extended: bool) -> Result<(), Box<dyn Error>> {
+    println!("File: {}", file.display());
+    println!("  Size: {} bytes", std::fs::metadata(&file)?.len());
+
+    if !extended {
+        let reader = BlobReader::from_path(&file)?;
+        for blob in reader {
+            let blob = blob?;
+            if blob.get_type() == BlobType::OsmHeader {
+                println!("Header:");
+                print_header(&blob.to_headerblock()?);
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let info = FileInfo::from_path(&file)?;
+    if let Some(ref header) = info.header {
+        println!("Header:");
+        print_header(header);
+    }
+
+    println!("Blobs:");
+    println!("  Header blobs: {}", info.header_blobs);
+    println!("  Data blobs: {}", info.data_blobs);
+    println!("  Unknown blobs: {}", info.unknown_blobs);
+    println!("  Compressed size: {} bytes", info.compressed_size);
+    println!("  Uncompressed size: {} bytes", info.uncompressed_size);
+
+    println!("Data:");
+    print_element_stats("Nodes", &info.nodes);
+    print_element_stats("Ways", &info.ways);
+    print_element_stats("Relations", &info.relations);
+    if let Some(bbox) = info.bbox {
+        println!(
+            "  Bounding box: ({:.7}, {:.7}, {:.7}, {:.7})",
+            bbox.left, bbox.bottom, bbox.right, bbox.top
+        );
+    }
+    if let Some(timestamp) = info.first_timestamp {
+        println!("  First timestamp: {}", format_timestamp(timestamp));
+    }
+    if let Some(timestamp) = info.last_timestamp {
+        println!("  Last timestamp: {}", format_timestamp(timestamp));
+    }
+    println!("  Metadata: {}", yes_no(info.has_metadata));
+    println!("  Locations on ways: {}", yes_no(info.locations_on_ways));
+    println!("  Sorted by type, then id: {}", yes_no(info.sorted));
+
+    Ok(())
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn cat(
+    file: PathBuf,
+    format: Format,
+    types: &[ElementType],
+    filter: Option<&TagFilter>,
+) -> Result<(), Box<dyn Error>> {
+    let reader = BlobReader::from_path(file)?;
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    for blob in reader {
+        if let BlobDecode::OsmData(block) = blob?.decode()? {
+            let filter = filter.map(|filter| filter.for_block(&block));
+            for element in block.elements() {
+                if (types.is_empty() || types.contains(&element.element_type()))
+                    && filter
+                        .as_ref()
+                        .map_or(true, |filter| filter.matches(&element))
+                {
+                    write_element(&mut out, &element, format)?;
+                }
+            }
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn count(file: PathBuf) -> Result<(), Box<dyn Error>> {
+    let reader = ElementReader::from_path(file)?;
+    let (nodes, ways, relations) = reader.par_map_reduce(
+        |element| match element.element_type() {
+            ElementType::Node => (1, 0, 0),
+            ElementType::Way => (0, 1, 0),
+            ElementType::Relation => (0, 0, 1),
+        },
+        || (0u64, 0u64, 0u64),
+        |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2),
+    )?;
+
+    println!("Nodes: {nodes}");
+    println!("Ways: {ways}");
+    println!("Relations: {relations}");
+    Ok(())
+}
+
+/// Parses an id with a type prefix like `n105`, `w107` or `r120`.
+fn parse_id(id: &str) -> Result<(ElementType, i64), String> {
+    let element_type = match id.chars().next() {
+        Some('n') => ElementType::Node,
+        Some('w') => ElementType::Way,
+        Some('r') => ElementType::Relation,
+        _ => {
+            return Err(format!(
+                "invalid id '{id}', expected a prefix 'n', 'w' or 'r'"
+            ))
+        }
+    };
+    id[1..]
+        .parse()
+        .map(|id| (element_type, id))
+        .map_err(|e| format!("invalid id '{id}': {e}"))
+}
+
+fn get(file: PathBuf, ids: &[String], format: Format) -> Result<(), Box<dyn Error>> {
+    let ids = ids
+        .iter()
+        .map(|id| parse_id(id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut reader = IndexedReader::from_path(file)?;
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut write_result = Ok(());
+
+    reader.read_elements_by_id(&ids, |element| {
+        if write_result.is_ok() {
+            write_result = write_element(&mut out, &element, format);
+        }
+    })?;
+
+    write_result?;
+    out.flush()?;
+    Ok(())
+}
+
+fn blobs(file: PathBuf) -> Result<(), Box<dyn Error>> {
+    let mut reader = BlobReader::seekable_from_path(file)?;
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    writeln!(out, "{:>8} {:>14} {:>12} type", "index", "offset", "size")?;
+    let mut index = 0;
+    while let Some(result) = reader.next_header_skip_blob() {
+        let (header, offset) = result?;
+        let offset = offset.map_or(String::from("?"), |offset| offset.0.to_string());
+        writeln!(
+            out,
+            "{index:>8} {offset:>14} {:>12} {}",
+            header.get_blob_size(),
+            header.blob_type().as_str()
+        )?;
+        index += 1;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn check(file: PathBuf, refs: bool) -> Result<(), Box<dyn Error>> {
+    let info = FileInfo::from_path(&file)?;
+
+    let header = info.header.ok_or("file does not contain a header block")?;
+    for feature in header.required_features() {
+        if !SUPPORTED_FEATURES.contains(&feature.as_str()) {
+            return Err(format!("unsupported required feature '{feature}'").into());
+        }
+    }
+    if header
+        .optional_features()
+        .iter()
+        .any(|feature| feature == "Sort.Type_then_ID")
+        && !info.sorted
+    {
+        return Err("file declares 'Sort.Type_then_ID' but is not sorted".into());
+    }
+
+    if refs {
+        let report = IntegrityReport::from_path(&file)?;
+        print_id_report("Missing way nodes", &report.missing_way_nodes);
+        print_id_report(
+            "Missing relation member nodes",
+            &report.missing_relation_nodes,
+        );
+        print_id_report(
+            "Missing relation member ways",
+            &report.missing_relation_ways,
+        );
+        print_id_report(
+            "Missing relation member relations",
+            &report.missing_relation_relations,
+        );
+        print_id_report("Duplicate nodes", &report.duplicate_nodes);
+        print_id_report("Duplicate ways", &report.duplicate_ways);
+        print_id_report("Duplicate relations", &report.duplicate_relations);
+        print_id_report("Empty ways", &report.empty_ways);
+        if !report.is_ok() {
+            return Err("referential integrity check failed".into());
+        }
+    }
+
+    println!(
+        "OK: {} blobs, {} nodes, {} ways, {} relations",
+        info.header_blobs + info.data_blobs + info.unknown_blobs,
+        info.nodes.count,
+        info.ways.count,
+        info.relations.count
+    );
+    Ok(())
+}
+
+fn print_id_report(name: &str, report: &IdReport) {
+    if !report.is_empty() {
+        let samples: Vec<String> = report.samples.iter().map(|id| id.to_string()).collect();
+        println!("{name}: {} (e.g. {})", report.count, samples.join(", "));
+    }
+}
@@ -0,0 +1,234 @@
+//! Text representations of elements: OPL and JSON
+
+use osmpbf::{
+    format_timestamp, push_json_string, DenseNode, Element, Metadata, Node, OsmObject,
+    RelMemberType, Relation, Way,
+};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// The output format of elements.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// Object Per Line format, as used by osmium.
+    Opl,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Writes a single element followed by a newline.
+pub fn write_element<W: Write>(out: &mut W, element: &Element, format: Format) -> io::Result<()> {
+    let line = match format {
+        Format::Opl => opl_element(element),
+        Format::Json => json_element(element),
+    };
+    writeln!(out, "{line}")
+}
+
+fn opl_element(element: &Element) -> String {
+    let mut line = String::new();
+    match element {
+        Element::Node(node) => opl_node(&mut line, node),
+        Element::DenseNode(node) => opl_dense_node(&mut line, node),
+        Element::Way(way) => opl_way(&mut line, way),
+        Element::Relation(relation) => opl_relation(&mut line, relation),
+    }
+    line
+}
+
+fn opl_node(line: &mut String, node: &Node) {
+    let _ = write!(line, "n{}", node.id());
+    opl_meta(line, &node.metadata());
+    opl_tags(line, node.tags());
+    let _ = write!(line, " x{:.7} y{:.7}", node.lon(), node.lat());
+}
+
+fn opl_dense_node(line: &mut String, node: &DenseNode) {
+    let _ = write!(line, "n{}", node.id());
+    opl_meta(line, &node.metadata());
+    opl_tags(line, node.tags());
+    let _ = write!(line, " x{:.7} y{:.7}", node.lon(), node.lat());
+}
+
+fn opl_way(line: &mut String, way: &Way) {
+    let _ = write!(line, "w{}", way.id());
+    opl_meta(line, &way.metadata());
+    opl_tags(line, way.tags());
+    line.push_str(" N");
+    for (i, id) in way.refs().enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+        let _ = write!(line, "n{id}");
+    }
+}
+
+fn opl_relation(line: &mut String, relation: &Relation) {
+    let _ = write!(line, "r{}", relation.id());
+    opl_meta(line, &relation.metadata());
+    opl_tags(line, relation.tags());
+    line.push_str(" M");
+    for (i, member) in relation.members().enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+        let _ = write!(
+            line,
+            "{}{}@",
+            member_type_char(&member.member_type),
+            member.member_id
+        );
+        opl_escape(line, member.role().unwrap_or(""));
+    }
+}
+
+fn opl_meta(line: &mut String, meta: &Metadata) {
+    if let Some(version) = meta.version() {
+        let _ = write!(line, " v{version}");
+    }
+    line.push_str(if meta.visible() { " dV" } else { " dD" });
+    if let Some(changeset) = meta.changeset() {
+        let _ = write!(line, " c{changeset}");
+    }
+    if let Some(timestamp) = meta.milli_timestamp() {
+        let _ = write!(line, " t{}", format_timestamp(timestamp));
+    }
+    if let Some(uid) = meta.uid() {
+        let _ = write!(line, " i{uid}");
+    }
+    if let Some(Ok(user)) = meta.user() {
+        line.push_str(" u");
+        opl_escape(line, user);
+    }
+}
+
+fn opl_tags<'a, I: Iterator<Item = (&'a str, &'a str)>>(line: &mut String, tags: I) {
+    line.push_str(" T");
+    for (i, (key, value)) in tags.enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+        opl_escape(line, key);
+        line.push('=');
+        opl_escape(line, value);
+    }
+}
+
+/// Escapes characters with a special meaning in OPL as `%<hex code point>%`.
+fn opl_escape(line: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            ' ' | ',' | '=' | '@' | '%' => {
+                let _ = write!(line, "%{:x}%", c as u32);
+            }
+            c if c.is_control() => {
+                let _ = write!(line, "%{:x}%", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+}
+
+fn member_type_char(member_type: &RelMemberType) -> char {
+    match member_type {
+        RelMemberType::Node => 'n',
+        RelMemberType::Way => 'w',
+        RelMemberType::Relation => 'r',
+    }
+}
+
+fn member_type_name(member_type: &RelMemberType) -> &'static str {
+    match member_type {
+        RelMemberType::Node => "node",
+        RelMemberType::Way => "way",
+        RelMemberType::Relation => "relation",
+    }
+}
+
+fn json_element(element: &Element) -> String {
+    let mut line = String::new();
+    match element {
+        Element::Node(node) => {
+            let _ = write!(line, "{{\"type\":\"node\",\"id\":{}", node.id());
+            let _ = write!(line, ",\"lat\":{:.7},\"lon\":{:.7}", node.lat(), node.lon());
+            json_meta(&mut line, &node.metadata());
+            json_tags(&mut line, node.tags());
+        }
+        Element::DenseNode(node) => {
+            let _ = write!(line, "{{\"type\":\"node\",\"id\":{}", node.id());
+            let _ = write!(line, ",\"lat\":{:.7},\"lon\":{:.7}", node.lat(), node.lon());
+            json_meta(&mut line, &node.metadata());
+            json_tags(&mut line, node.tags());
+        }
+        Element::Way(way) => {
+            let _ = write!(line, "{{\"type\":\"way\",\"id\":{}", way.id());
+            json_meta(&mut line, &way.metadata());
+            json_tags(&mut line, way.tags());
+            line.push_str(",\"refs\":[");
+            for (i, id) in way.refs().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                let _ = write!(line, "{id}");
+            }
+            line.push(']');
+        }
+        Element::Relation(relation) => {
+            let _ = write!(line, "{{\"type\":\"relation\",\"id\":{}", relation.id());
+            json_meta(&mut line, &relation.metadata());
+            json_tags(&mut line, relation.tags());
+            line.push_str(",\"members\":[");
+            for (i, member) in relation.members().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                let _ = write!(
+                    line,
+                    "{{\"type\":\"{}\",\"ref\":{},\"role\":",
+                    member_type_name(&member.member_type),
+                    member.member_id
+                );
+                push_json_string(&mut line, member.role().unwrap_or(""));
+                line.push('}');
+            }
+            line.push(']');
+        }
+    }
+    line.push('}');
+    line
+}
+
+fn json_meta(line: &mut String, meta: &Metadata) {
+    if let Some(version) = meta.version() {
+        let _ = write!(line, ",\"version\":{version}");
+    }
+    if let Some(timestamp) = meta.milli_timestamp() {
+        let _ = write!(line, ",\"timestamp\":\"{}\"", format_timestamp(timestamp));
+    }
+    if let Some(changeset) = meta.changeset() {
+        let _ = write!(line, ",\"changeset\":{changeset}");
+    }
+    if let Some(uid) = meta.uid() {
+        let _ = write!(line, ",\"uid\":{uid}");
+    }
+    if let Some(Ok(user)) = meta.user() {
+        line.push_str(",\"user\":");
+        push_json_string(line, user);
+    }
+    if !meta.visible() {
+        line.push_str(",\"visible\":false");
+    }
+}
+
+fn json_tags<'a, I: Iterator<Item = (&'a str, &'a str)>>(line: &mut String, tags: I) {
+    line.push_str(",\"tags\":{");
+    for (i, (key, value)) in tags.enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+        push_json_string(line, key);
+        line.push(':');
+        push_json_string(line, value);
+    }
+    line.push('}');
+}
@@ -1,15 +1,19 @@
 //! Read and decode blobs
 
-use crate::block::{HeaderBlock, PrimitiveBlock};
+use crate::block::{BlockDecoding, HeaderBlock, PrimitiveBlock, TagDecoding};
+use crate::decode::DecodeContext;
 use crate::error::{new_blob_error, new_error, new_protobuf_error, BlobError, ErrorKind, Result};
+use crate::options::{DecodeOptions, ReaderOptions};
+use crate::progress::{Progress, ProgressTracker};
 use crate::proto::fileformat;
-use byteorder::ReadBytesExt;
+use byteorder::ByteOrder;
 use protobuf::Message;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::ops::Range;
 use std::path::Path;
-
-use flate2::read::ZlibDecoder;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Maximum allowed [`BlobHeader`] size in bytes.
 pub static MAX_BLOB_HEADER_SIZE: u64 = 64 * 1024;
@@ -52,6 +56,38 @@ pub enum BlobDecode<'a> {
     Unknown(&'a str),
 }
 
+/// The compression of the content of a blob.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BlobCompression {
+    /// The content is not compressed.
+    Raw,
+    /// The content is compressed with zlib.
+    Zlib,
+    /// The content is compressed with LZMA.
+    Lzma,
+    /// The content is compressed with bzip2, which is deprecated.
+    Bzip2,
+    /// The content is compressed with LZ4.
+    Lz4,
+    /// The content is compressed with Zstandard.
+    Zstd,
+}
+
+impl BlobCompression {
+    /// Returns the compression that is stored in the given field of a `Blob` message.
+    fn from_field_number(field_number: u32) -> Option<BlobCompression> {
+        match field_number {
+            1 => Some(BlobCompression::Raw),
+            3 => Some(BlobCompression::Zlib),
+            4 => Some(BlobCompression::Lzma),
+            5 => Some(BlobCompression::Bzip2),
+            6 => Some(BlobCompression::Lz4),
+            7 => Some(BlobCompression::Zstd),
+            _ => None,
+        }
+    }
+}
+
 /// The offset of a blob in bytes from stream start.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct ByteOffset(pub u64);
@@ -63,26 +99,83 @@ pub struct ByteOffset(pub u64);
 #[derive(Clone, Debug)]
 pub struct Blob {
     header: fileformat::BlobHeader,
-    blob: fileformat::Blob,
+    /// The encoded blob with size prefix, header and content.
+    bytes: Vec<u8>,
+    /// The position of the `Blob` message in `bytes`.
+    content_start: usize,
+    layout: BlobLayout,
     offset: Option<ByteOffset>,
+    index: Option<u64>,
+    decode: DecodeOptions,
 }
 
 impl Blob {
-    fn new(
+    /// Creates a blob from its encoded bytes, which start with the size prefix. Returns an error
+    /// if the `Blob` message cannot be parsed.
+    pub(crate) fn new(
         header: fileformat::BlobHeader,
-        blob: fileformat::Blob,
+        bytes: Vec<u8>,
         offset: Option<ByteOffset>,
+        index: Option<u64>,
+        decode: DecodeOptions,
+    ) -> Result<Blob> {
+        let content_start = 4 + byteorder::BigEndian::read_u32(&bytes) as usize;
+        let layout = parse_blob_layout(&bytes[content_start..])?;
+        Ok(Blob::from_parts(
+            header,
+            bytes,
+            content_start,
+            layout,
+            offset,
+            index,
+            decode,
+        ))
+    }
+
+    /// Creates a blob from its encoded bytes and the layout of the `Blob` message that starts at
+    /// `content_start`, which was already parsed by the caller.
+    pub(crate) fn from_parts(
+        header: fileformat::BlobHeader,
+        bytes: Vec<u8>,
+        content_start: usize,
+        layout: BlobLayout,
+        offset: Option<ByteOffset>,
+        index: Option<u64>,
+        decode: DecodeOptions,
     ) -> Blob {
         Blob {
             header,
-            blob,
+            bytes,
+            content_start,
+            layout,
             offset,
+            index,
+            decode,
         }
     }
 
+    /// Creates a blob from its header and the encoded `Blob` message. The `datasize` of the header
+    /// is set to the size of the content.
+    pub(crate) fn from_content(
+        mut header: fileformat::BlobHeader,
+        content: &[u8],
+        decode: DecodeOptions,
+    ) -> Result<Blob> {
+        header.set_datasize(message_size(content.len())?);
+        let header_bytes = header
+            .write_to_bytes()
+            .map_err(|e| new_protobuf_error(e, "blob header"))?;
+
+        let mut bytes = Vec::with_capacity(4 + header_bytes.len() + content.len());
+        bytes.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&header_bytes);
+        bytes.extend_from_slice(content);
+        Blob::new(header, bytes, None, None, decode)
+    }
+
     /// Decodes the Blob and tries to obtain the inner content (usually a [`HeaderBlock`] or a
     /// [`PrimitiveBlock`]). This operation might involve an expensive decompression step.
-    pub fn decode(&self) -> Result<BlobDecode> {
+    pub fn decode(&self) -> Result<BlobDecode<'_>> {
         match self.get_type() {
             BlobType::OsmHeader => {
                 let block = Box::new(self.to_headerblock()?);
@@ -97,7 +190,7 @@ impl Blob {
     }
 
     /// Returns the type of a blob without decoding its content.
-    pub fn get_type(&self) -> BlobType {
+    pub fn get_type(&self) -> BlobType<'_> {
         match self.header.type_() {
             x if x == BlobType::OsmHeader.as_str() => BlobType::OsmHeader,
             x if x == BlobType::OsmData.as_str() => BlobType::OsmData,
@@ -111,16 +204,140 @@ impl Blob {
         self.offset
     }
 
+    /// Returns the ordinal of the blob in its source stream, starting with 0 for the first blob
+    /// that was read. This is [`None`] if the position in the stream was changed by seeking.
+    pub fn index(&self) -> Option<u64> {
+        self.index
+    }
+
     /// Tries to decode the blob to a [`HeaderBlock`]. This operation might involve an expensive
     /// decompression step.
     pub fn to_headerblock(&self) -> Result<HeaderBlock> {
-        decode_blob(&self.blob).map(HeaderBlock::new)
+        decode_blob_ref(&self.content(), self.decode.max_blob_size)
+            .map(HeaderBlock::new)
+            .map_err(|e| e.with_blob(self.offset, self.index))
     }
 
     /// Tries to decode the blob to a [`PrimitiveBlock`]. This operation might involve an expensive
-    /// decompression step.
+    /// decompression step. The tag and block decoding policies of the reader are applied to the
+    /// block, see [`BlobReader::set_tag_decoding`] and [`BlobReader::set_block_decoding`].
     pub fn to_primitiveblock(&self) -> Result<PrimitiveBlock> {
-        decode_blob(&self.blob).map(PrimitiveBlock::new)
+        decode_primitive_block(&self.content(), &self.decode)
+            .map_err(|e| e.with_blob(self.offset, self.index))
+    }
+
+    /// Returns the encoded parts of the blob, which can be written to another file without
+    /// decoding the blob, see [`BlobWriter`](crate::writer::BlobWriter).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// for blob in BlobReader::from_path("tests/test.osm.pbf")? {
+    ///     let blob = blob?;
+    ///     let raw = blob.raw();
+    ///     println!(
+    ///         "{:?} blob: {} bytes, {:?} payload of {} bytes, {:?} bytes uncompressed",
+    ///         blob.get_type(),
+    ///         raw.bytes().len(),
+    ///         raw.compression(),
+    ///         raw.payload().map_or(0, |p| p.len()),
+    ///         raw.raw_size(),
+    ///     );
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn raw(&self) -> RawBlob<'_> {
+        RawBlob {
+            bytes: &self.bytes,
+            content_start: self.content_start,
+            layout: self.layout.clone(),
+        }
+    }
+
+    fn content(&self) -> BlobRef<'_> {
+        self.layout.blob_ref(&self.bytes[self.content_start..])
+    }
+
+    /// Returns the size of the (possibly compressed) blob content in bytes and, if known, the size
+    /// after decompression.
+    pub(crate) fn content_sizes(&self) -> (u64, Option<u64>) {
+        let raw_size = self.layout.raw_size.map(|s| s as u64);
+        match self.layout.payload {
+            Some((BlobCompression::Raw, ref range)) => {
+                (range.len() as u64, Some(range.len() as u64))
+            }
+            Some((_, ref range)) => (range.len() as u64, raw_size),
+            None => (0, raw_size),
+        }
+    }
+}
+
+/// The encoded parts of a [`Blob`] or an [`MmapBlob`](crate::mmap_blob::MmapBlob) that can be
+/// copied to another file without decompressing the content.
+#[derive(Clone, Debug)]
+pub struct RawBlob<'a> {
+    bytes: &'a [u8],
+    content_start: usize,
+    layout: BlobLayout,
+}
+
+impl<'a> RawBlob<'a> {
+    /// Creates the encoded parts of a blob that starts with the size prefix. The layout was
+    /// parsed from the `Blob` message at `content_start`.
+    pub(crate) fn new(bytes: &'a [u8], content_start: usize, layout: BlobLayout) -> RawBlob<'a> {
+        RawBlob {
+            bytes,
+            content_start,
+            layout,
+        }
+    }
+
+    /// Returns the content of the `Blob` message.
+    pub(crate) fn blob_ref(&self) -> BlobRef<'a> {
+        self.layout.blob_ref(self.content())
+    }
+
+    /// Returns the complete encoded blob: the size prefix, the `BlobHeader` message and the
+    /// `Blob` message. Writing these bytes to a file reproduces the blob exactly.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Returns the encoded `BlobHeader` message.
+    pub fn header(&self) -> &'a [u8] {
+        &self.bytes[4..self.content_start]
+    }
+
+    /// Returns the encoded `Blob` message with the (possibly compressed) payload.
+    pub fn content(&self) -> &'a [u8] {
+        &self.bytes[self.content_start..]
+    }
+
+    /// Returns the (possibly compressed) payload of the blob or [`None`] if the blob has no
+    /// payload.
+    pub fn payload(&self) -> Option<&'a [u8]> {
+        let content = self.content();
+        self.layout
+            .payload
+            .as_ref()
+            .map(|(_, range)| &content[range.clone()])
+    }
+
+    /// Returns the compression of the payload or [`None`] if the blob has no payload.
+    pub fn compression(&self) -> Option<BlobCompression> {
+        self.layout
+            .payload
+            .as_ref()
+            .map(|(compression, _)| *compression)
+    }
+
+    /// Returns the size of the payload after decompression, if it is stored in the blob.
+    pub fn raw_size(&self) -> Option<i32> {
+        self.layout.raw_size
     }
 }
 
@@ -138,7 +355,7 @@ impl BlobHeader {
     }
 
     /// Returns the type of the following blob.
-    pub fn blob_type(&self) -> BlobType {
+    pub fn blob_type(&self) -> BlobType<'_> {
         match self.header.type_() {
             "OSMHeader" => BlobType::OsmHeader,
             "OSMData" => BlobType::OsmData,
@@ -152,6 +369,79 @@ impl BlobHeader {
     }
 }
 
+/// A region of a PBF stream that was skipped while recovering from an error.
+///
+/// See [`BlobReader::set_error_recovery`] and [`MmapBlobReader::set_error_recovery`].
+///
+/// [`MmapBlobReader::set_error_recovery`]: crate::mmap_blob::MmapBlobReader::set_error_recovery
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Resync {
+    /// The offset of the first skipped byte. This might be [`None`] if the offset of the stream is
+    /// not known.
+    pub offset: Option<ByteOffset>,
+    /// The number of skipped bytes.
+    pub skipped_bytes: u64,
+}
+
+/// The encoded start of a valid [`BlobHeader`]: the `type` field (number 1) with one of the known
+/// blob types as a length-delimited string.
+const HEADER_SIGNATURES: [&[u8]; 2] = [b"\x0a\x07OSMData", b"\x0a\x09OSMHeader"];
+
+/// Maximum length of the blob size prefix and a header signature.
+const MAX_SIGNATURE_LEN: usize = 4 + 11;
+
+/// Number of bytes that are read at once while searching for the next blob in a stream.
+const RESYNC_BUFFER_SIZE: u64 = 64 * 1024;
+
+/// Returns the position of the size prefix of the first header signature in `data` and the length
+/// of the signature. Both signatures start with the same byte, so the search only compares the
+/// signatures at the positions of that byte.
+fn find_signature(data: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 4;
+    loop {
+        let pos = start + data.get(start..)?.iter().position(|&b| b == b'\x0a')?;
+        if let Some(sig) = HEADER_SIGNATURES
+            .iter()
+            .find(|sig| data[pos..].starts_with(sig))
+        {
+            return Some((pos - 4, sig.len()));
+        }
+        start = pos + 1;
+    }
+}
+
+/// Parses a blob header and checks that it looks like the start of a valid blob within the size
+/// limits of the options.
+fn parse_plausible_header(bytes: &[u8], options: &ReaderOptions) -> Option<fileformat::BlobHeader> {
+    let header = fileformat::BlobHeader::parse_from_bytes(bytes).ok()?;
+    let plausible = matches!(header.type_(), "OSMData" | "OSMHeader")
+        && options.check_blob_size(&header).is_ok();
+    plausible.then_some(header)
+}
+
+/// Searches for the next plausible and complete blob in the given slice and returns the position
+/// of its size prefix.
+pub(crate) fn find_plausible_blob(data: &[u8], options: &ReaderOptions) -> Option<usize> {
+    let mut start = 0;
+    while let Some((candidate, _)) = find_signature(&data[start..]) {
+        let pos = start + candidate;
+        start = pos + 1;
+        let after_prefix = &data[pos + 4..];
+        let header_size = byteorder::BigEndian::read_u32(&data[pos..]) as usize;
+        if options.check_header_size(header_size as u64).is_err()
+            || after_prefix.len() < header_size
+        {
+            continue;
+        }
+        if let Some(header) = parse_plausible_header(&after_prefix[..header_size], options) {
+            if after_prefix.len() - header_size >= header.datasize() as usize {
+                return Some(pos);
+            }
+        }
+    }
+    None
+}
+
 /// A reader for PBF files that allows iterating over [`Blob`]s.
 #[derive(Clone, Debug)]
 pub struct BlobReader<R: Read + Send> {
@@ -159,6 +449,25 @@ pub struct BlobReader<R: Read + Send> {
     /// Current reader offset in bytes from the start of the stream.
     offset: Option<ByteOffset>,
     last_blob_ok: bool,
+    /// Is true if the last error can be recovered from by resynchronizing.
+    resync_pending: bool,
+    resyncs: Vec<Resync>,
+    /// Bytes that have to be read again before continuing with `reader`.
+    replay: Cursor<Vec<u8>>,
+    /// The bytes of the current blob.
+    current: Vec<u8>,
+    /// The offset of the current blob.
+    blob_start: Option<ByteOffset>,
+    /// The ordinal of the current blob.
+    blob_index: Option<u64>,
+    /// The ordinal of the next blob or `None` if unknown after seeking.
+    next_index: Option<u64>,
+    options: ReaderOptions,
+    /// Number of bytes read from the stream, without bytes that were put back.
+    bytes_read: u64,
+    /// Size of the stream from the start offset, if known.
+    total_bytes: Option<u64>,
+    progress: Option<Arc<ProgressTracker>>,
 }
 
 impl<R: Read + Send> BlobReader<R> {
@@ -179,55 +488,410 @@ impl<R: Read + Send> BlobReader<R> {
     /// # foo().unwrap();
     /// ```
     pub fn new(reader: R) -> BlobReader<R> {
+        Self::with_offset(reader, None)
+    }
+
+    fn with_offset(reader: R, offset: Option<ByteOffset>) -> BlobReader<R> {
         BlobReader {
             reader,
-            offset: None,
+            offset,
             last_blob_ok: true,
+            resync_pending: false,
+            resyncs: vec![],
+            replay: Cursor::new(vec![]),
+            current: vec![],
+            blob_start: offset,
+            blob_index: None,
+            next_index: Some(0),
+            options: ReaderOptions::default(),
+            bytes_read: 0,
+            total_bytes: None,
+            progress: None,
+        }
+    }
+
+    /// Calls `callback` with the progress of the reader, at most once per `interval` and once
+    /// at the end of the stream. The total size of the stream is known for readers that were
+    /// created from a path or with [`new_seekable`](BlobReader::new_seekable), otherwise it can
+    /// be set with [`set_total_bytes`](BlobReader::set_total_bytes).
+    ///
+    /// The callback may be called from other threads if the reader is used by the parallel
+    /// methods of [`ElementReader`](crate::reader::ElementReader), which also report the decoded
+    /// blobs and processed elements. It should return quickly because it delays the reader.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_progress(Duration::from_secs(5), |progress| {
+    ///     if let Some(fraction) = progress.fraction() {
+    ///         eprintln!("{:.1}% of the file read", fraction * 100.0);
+    ///     }
+    /// });
+    ///
+    /// for blob in reader {
+    ///     let blob = blob?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_progress<F>(&mut self, interval: Duration, callback: F)
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(ProgressTracker::new(
+            Arc::new(callback),
+            interval,
+            self.total_bytes,
+        )));
+    }
+
+    /// Sets the size of the stream in bytes for the progress reports, for example the length of
+    /// a remote file.
+    pub fn set_total_bytes(&mut self, total_bytes: u64) {
+        self.total_bytes = Some(total_bytes);
+        if let Some(progress) = &self.progress {
+            progress.set_total_bytes(self.total_bytes);
+        }
+    }
+
+    /// Returns the progress tracker that the element readers update.
+    pub(crate) fn progress_tracker(&self) -> Option<&Arc<ProgressTracker>> {
+        self.progress.as_ref()
+    }
+
+    /// Replaces all options of the reader, including the ones that were changed with the other
+    /// setters.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_options(
+    ///     ReaderOptions::new()
+    ///         .unknown_blobs(UnknownBlobPolicy::Error)
+    ///         .validate_features(true),
+    /// );
+    ///
+    /// for blob in reader {
+    ///     // Fails for unknown blob types and unsupported required features
+    ///     let blob = blob?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_options(&mut self, options: ReaderOptions) {
+        self.options = options;
+    }
+
+    /// Returns the options of the reader.
+    pub fn options(&self) -> &ReaderOptions {
+        &self.options
+    }
+
+    /// Enables or disables the error recovery mode (disabled by default).
+    ///
+    /// Usually, the iteration stops after the first error. In recovery mode, the reader returns
+    /// the error and then scans the stream, starting after the first byte of the damaged blob, for
+    /// the next plausible blob (a valid size prefix followed by a `BlobHeader` of type `OSMData`
+    /// or `OSMHeader`). The skipped regions of the stream can be inspected with
+    /// [`resyncs`](BlobReader::resyncs). I/O errors of the underlying reader still stop the
+    /// iteration.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_error_recovery(true);
+    ///
+    /// let mut blobs = 0;
+    /// for blob in &mut reader {
+    ///     match blob {
+    ///         Ok(_) => blobs += 1,
+    ///         Err(e) => println!("skipping damaged blob: {e}"),
+    ///     }
+    /// }
+    ///
+    /// for resync in reader.resyncs() {
+    ///     println!("skipped {} bytes", resync.skipped_bytes);
+    /// }
+    /// # assert_eq!(blobs, 2);
+    /// # assert!(reader.resyncs().is_empty());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_error_recovery(&mut self, enabled: bool) {
+        self.options.error_recovery = enabled;
+    }
+
+    /// Returns the regions of the stream that were skipped to recover from errors. This is always
+    /// empty if the error recovery mode is disabled.
+    pub fn resyncs(&self) -> &[Resync] {
+        &self.resyncs
+    }
+
+    /// Sets the policy for tags that cannot be decoded (default: [`TagDecoding::Unchecked`]). It
+    /// is applied when the returned blobs are decoded to [`PrimitiveBlock`]s.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_tag_decoding(TagDecoding::Strict);
+    ///
+    /// for blob in reader {
+    ///     // Fails if a tag cannot be decoded
+    ///     let decoded = blob?.decode()?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_tag_decoding(&mut self, tag_decoding: TagDecoding) {
+        self.options.tag_decoding = tag_decoding;
+    }
+
+    /// Sets how the returned blobs are decoded to [`PrimitiveBlock`]s (default:
+    /// [`BlockDecoding::Eager`]).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_block_decoding(BlockDecoding::Lazy);
+    ///
+    /// for blob in reader {
+    ///     if let BlobDecode::OsmData(block) = blob?.decode()? {
+    ///         // Only the ids are decoded
+    ///         let max_id = block.elements().map(|e| e.id()).max();
+    ///         # assert_eq!(max_id, Some(120));
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_block_decoding(&mut self, block_decoding: BlockDecoding) {
+        self.options.block_decoding = block_decoding;
+    }
+
+    /// Stops the iteration or, in recovery mode, resynchronizes on the next call of `next`.
+    fn set_failed(&mut self) {
+        self.last_blob_ok = false;
+        if self.options.error_recovery && !self.current.is_empty() {
+            self.resync_pending = true;
+            let current = std::mem::take(&mut self.current);
+            self.unread(&current[1..]);
         }
     }
 
+    /// Stops the iteration, even in recovery mode.
+    fn set_stopped(&mut self) {
+        self.last_blob_ok = false;
+        self.resync_pending = false;
+    }
+
+    fn advance_offset(&mut self, bytes: u64) {
+        self.offset = self.offset.map(|x| ByteOffset(x.0 + bytes));
+    }
+
+    /// Reads up to `len` bytes and appends them to the current blob. Returns the number of bytes
+    /// that were read, which is only less than `len` at the end of the stream.
+    fn read_current(&mut self, len: u64) -> ::std::io::Result<u64> {
+        let n = (&mut self.replay)
+            .chain(&mut self.reader)
+            .take(len)
+            .read_to_end(&mut self.current)? as u64;
+        self.advance_offset(n);
+        self.bytes_read += n;
+        Ok(n)
+    }
+
+    /// Puts the given bytes back in front of the remaining stream.
+    fn unread(&mut self, bytes: &[u8]) {
+        let pos = self.replay.position() as usize;
+        let mut replay = bytes.to_vec();
+        replay.extend_from_slice(&self.replay.get_ref()[pos..]);
+        self.replay = Cursor::new(replay);
+        self.offset = self.offset.map(|x| ByteOffset(x.0 - bytes.len() as u64));
+        self.bytes_read -= bytes.len() as u64;
+    }
+
+    /// Marks the start of a new blob at the given offset.
+    fn start_blob(&mut self, offset: Option<ByteOffset>) {
+        self.blob_start = offset;
+        self.blob_index = self.next_index;
+        self.next_index = self.next_index.map(|i| i + 1);
+    }
+
     fn read_blob_header(&mut self) -> Option<Result<fileformat::BlobHeader>> {
-        let header_size: u64 = match self.reader.read_u32::<byteorder::BigEndian>() {
-            Ok(n) => {
-                self.offset = self.offset.map(|x| ByteOffset(x.0 + 4));
-                u64::from(n)
+        let offset = self.offset;
+        self.current.clear();
+
+        match self.read_current(4) {
+            Ok(4) => self.start_blob(offset),
+            Ok(_) => {
+                //TODO This also accepts corrupted files in the case of 1-3 available bytes
+                self.offset = None;
+                return None;
             }
-            Err(e) => {
+            Err(_) => {
+                self.start_blob(offset);
                 self.offset = None;
-                return match e.kind() {
-                    ::std::io::ErrorKind::UnexpectedEof => {
-                        //TODO This also accepts corrupted files in the case of 1-3 available bytes
-                        None
-                    }
-                    _ => {
-                        self.last_blob_ok = false;
-                        Some(Err(new_blob_error(BlobError::InvalidHeaderSize)))
-                    }
-                };
+                self.set_stopped();
+                return Some(Err(new_blob_error(BlobError::InvalidHeaderSize)));
             }
-        };
+        }
+        let header_size = u64::from(byteorder::BigEndian::read_u32(&self.current));
 
-        if header_size >= MAX_BLOB_HEADER_SIZE {
-            self.last_blob_ok = false;
-            return Some(Err(new_blob_error(BlobError::HeaderTooBig {
-                size: header_size,
-            })));
+        if let Err(e) = self.options.check_header_size(header_size) {
+            self.set_failed();
+            return Some(Err(e));
         }
 
-        let mut reader = self.reader.by_ref().take(header_size);
-        let header = match fileformat::BlobHeader::parse_from_reader(&mut reader) {
+        match self.read_current(header_size) {
+            Ok(n) if n == header_size => {}
+            Ok(_) => {
+                self.set_failed();
+                let io_error = ::std::io::Error::new(
+                    ::std::io::ErrorKind::UnexpectedEof,
+                    "content too short for header",
+                );
+                return Some(Err(io_error.into()));
+            }
+            Err(e) => {
+                self.set_stopped();
+                return Some(Err(e.into()));
+            }
+        }
+
+        let header = match fileformat::BlobHeader::parse_from_bytes(&self.current[4..]) {
             Ok(header) => header,
             Err(e) => {
-                self.offset = None;
-                self.last_blob_ok = false;
+                self.set_failed();
                 return Some(Err(new_protobuf_error(e, "blob header")));
             }
         };
 
-        self.offset = self.offset.map(|x| ByteOffset(x.0 + header_size));
+        if let Err(e) = self.options.check_blob_size(&header) {
+            self.set_failed();
+            return Some(Err(e));
+        }
 
         Some(Ok(header))
     }
+
+    /// Scans the stream for the next plausible blob header, starting after the first byte of the
+    /// failed blob.
+    fn resync(&mut self) -> Option<Result<fileformat::BlobHeader>> {
+        self.resync_pending = false;
+        let start = self.blob_start;
+        // Number of skipped bytes in front of `self.current`, which is used as a search window.
+        // The first byte of the failed blob is always skipped.
+        let mut skipped: u64 = 1;
+        // Position in `self.current` where the search for the next signature continues.
+        let mut search_from = 0;
+        self.current.clear();
+
+        loop {
+            let Some((candidate, sig_len)) = find_signature(&self.current[search_from..]) else {
+                // Keep the bytes that might be the start of a signature that is not complete yet
+                let keep = self.current.len().min(MAX_SIGNATURE_LEN - 1);
+                let dropped = self.current.len() - keep;
+                self.current.drain(..dropped);
+                skipped += dropped as u64;
+                search_from = 0;
+
+                match self.read_current(RESYNC_BUFFER_SIZE) {
+                    Ok(0) => {
+                        self.offset = None;
+                        return self.end_resync(start, skipped + self.current.len() as u64, None);
+                    }
+                    Ok(_) => continue,
+                    Err(e) => {
+                        self.offset = None;
+                        let skipped = skipped + self.current.len() as u64;
+                        return self.end_resync(start, skipped, Some(e));
+                    }
+                }
+            };
+
+            let prefix_pos = search_from + candidate;
+            let header_size =
+                u64::from(byteorder::BigEndian::read_u32(&self.current[prefix_pos..]));
+            if header_size < sig_len as u64 || self.options.check_header_size(header_size).is_err()
+            {
+                search_from = prefix_pos + 1;
+                continue;
+            }
+
+            self.current.drain(..prefix_pos);
+            skipped += prefix_pos as u64;
+            let header_end = 4 + header_size as usize;
+            if self.current.len() < header_end {
+                let rest = (header_end - self.current.len()) as u64;
+                match self.read_current(rest) {
+                    Ok(n) if n == rest => {}
+                    Ok(_) => {
+                        self.offset = None;
+                        return self.end_resync(start, skipped + self.current.len() as u64, None);
+                    }
+                    Err(e) => {
+                        self.offset = None;
+                        let skipped = skipped + self.current.len() as u64;
+                        return self.end_resync(start, skipped, Some(e));
+                    }
+                }
+            }
+
+            if let Some(header) =
+                parse_plausible_header(&self.current[4..header_end], &self.options)
+            {
+                // The bytes after the header belong to the content of the blob
+                let rest = self.current.split_off(header_end);
+                self.unread(&rest);
+                self.resyncs.push(Resync {
+                    offset: start,
+                    skipped_bytes: skipped,
+                });
+                self.start_blob(start.map(|x| ByteOffset(x.0 + skipped)));
+                self.last_blob_ok = true;
+                return Some(Ok(header));
+            }
+
+            // Not a valid header, continue scanning after the first byte of the candidate.
+            search_from = 1;
+        }
+    }
+
+    /// Records the skipped bytes if the stream ended while resynchronizing.
+    fn end_resync(
+        &mut self,
+        start: Option<ByteOffset>,
+        skipped_bytes: u64,
+        error: Option<::std::io::Error>,
+    ) -> Option<Result<fileformat::BlobHeader>> {
+        self.set_stopped();
+        self.resyncs.push(Resync {
+            offset: start,
+            skipped_bytes,
+        });
+        error.map(|e| Err(e.into()))
+    }
 }
 
 impl BlobReader<BufReader<File>> {
@@ -249,13 +913,12 @@ impl BlobReader<BufReader<File>> {
     /// ```
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         let f = File::open(path)?;
+        let total_bytes = f.metadata()?.len();
         let reader = BufReader::new(f);
 
-        Ok(BlobReader {
-            reader,
-            offset: Some(ByteOffset(0)),
-            last_blob_ok: true,
-        })
+        let mut reader = BlobReader::with_offset(reader, Some(ByteOffset(0)));
+        reader.total_bytes = Some(total_bytes);
+        Ok(reader)
     }
 }
 
@@ -263,34 +926,78 @@ impl<R: Read + Send> Iterator for BlobReader<R> {
     type Item = Result<Blob>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Stop iteration if there was an error.
-        if !self.last_blob_ok {
-            return None;
+        let blob = self.next_blob().map(|blob| {
+            let blob = blob?;
+            let checked = self
+                .options
+                .check_blob(blob.header.type_(), blob.offset, || blob.to_headerblock());
+            if checked.is_err() && !self.options.error_recovery {
+                self.set_stopped();
+            }
+            checked.map(|_| blob)
+        });
+        if let Some(progress) = &self.progress {
+            match blob {
+                Some(Ok(_)) => progress.blob_read(self.bytes_read),
+                None => progress.finish(self.bytes_read),
+                Some(Err(_)) => {}
+            }
         }
+        match blob {
+            Some(Err(e)) => Some(Err(e.with_blob(self.blob_start, self.blob_index))),
+            blob => blob,
+        }
+    }
+}
 
-        let prev_offset = self.offset;
-
-        let header = match self.read_blob_header() {
-            Some(Ok(header)) => header,
-            Some(Err(err)) => return Some(Err(err)),
-            None => return None,
+impl<R: Read + Send> BlobReader<R> {
+    fn next_blob(&mut self) -> Option<Result<Blob>> {
+        let header = if self.last_blob_ok {
+            self.read_blob_header()
+        } else if self.resync_pending {
+            self.resync()
+        } else {
+            // Stop iteration if there was an error.
+            None
+        };
+        let header = match header? {
+            Ok(header) => header,
+            Err(err) => return Some(Err(err)),
         };
 
-        let mut reader = self.reader.by_ref().take(header.datasize() as u64);
-        let blob = match fileformat::Blob::parse_from_reader(&mut reader) {
-            Ok(blob) => blob,
+        let content_start = self.current.len();
+        let datasize = header.datasize() as u64;
+        match self.read_current(datasize) {
+            Ok(n) if n == datasize => {}
+            Ok(_) => {
+                self.set_failed();
+                let io_error = ::std::io::Error::new(
+                    ::std::io::ErrorKind::UnexpectedEof,
+                    "content too short for block data",
+                );
+                return Some(Err(io_error.into()));
+            }
             Err(e) => {
-                self.offset = None;
-                self.last_blob_ok = false;
-                return Some(Err(new_protobuf_error(e, "blob content")));
+                self.set_stopped();
+                return Some(Err(e.into()));
             }
-        };
-
-        self.offset = self
-            .offset
-            .map(|x| ByteOffset(x.0 + header.datasize() as u64));
+        }
 
-        Some(Ok(Blob::new(header, blob, prev_offset)))
+        match parse_blob_layout(&self.current[content_start..]) {
+            Ok(layout) => Some(Ok(Blob::from_parts(
+                header,
+                std::mem::take(&mut self.current),
+                content_start,
+                layout,
+                self.blob_start,
+                self.blob_index,
+                self.options.decode_options(),
+            ))),
+            Err(e) => {
+                self.set_failed();
+                Some(Err(e))
+            }
+        }
     }
 }
 
@@ -316,12 +1023,12 @@ impl<R: Read + Seek + Send> BlobReader<R> {
     /// ```
     pub fn new_seekable(mut reader: R) -> Result<BlobReader<R>> {
         let pos = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(pos))?;
 
-        Ok(BlobReader {
-            reader,
-            offset: Some(ByteOffset(pos)),
-            last_blob_ok: true,
-        })
+        let mut reader = BlobReader::with_offset(reader, Some(ByteOffset(pos)));
+        reader.total_bytes = Some(end.saturating_sub(pos));
+        Ok(reader)
     }
 
     /// Read and return the [`Blob`] at the given offset. If successful, the cursor of the stream is
@@ -373,23 +1080,27 @@ impl<R: Read + Seek + Send> BlobReader<R> {
     /// # foo().unwrap();
     /// ```
     pub fn seek(&mut self, pos: ByteOffset) -> Result<()> {
-        match self.reader.seek(SeekFrom::Start(pos.0)) {
-            Ok(offset) => {
-                self.offset = Some(ByteOffset(offset));
-                Ok(())
-            }
-            Err(e) => {
-                self.offset = None;
-                Err(e.into())
-            }
-        }
+        self.seek_raw(SeekFrom::Start(pos.0)).map(|_| ())
     }
 
     /// Seek to an offset in bytes. (See `std::io::Seek`)
     pub fn seek_raw(&mut self, pos: SeekFrom) -> Result<u64> {
+        // Bytes that were put back for resynchronizing are not part of the reader's position.
+        let pos = match pos {
+            SeekFrom::Current(n) => {
+                let remaining = self.replay.get_ref().len() as u64 - self.replay.position();
+                SeekFrom::Current(n - remaining as i64)
+            }
+            pos => pos,
+        };
+        self.replay = Cursor::new(vec![]);
+        self.next_index = None;
+
         match self.reader.seek(pos) {
             Ok(offset) => {
                 self.offset = Some(ByteOffset(offset));
+                self.last_blob_ok = true;
+                self.resync_pending = false;
                 Ok(offset)
             }
             Err(e) => {
@@ -414,15 +1125,17 @@ impl<R: Read + Seek + Send> BlobReader<R> {
         // read header
         let header = match self.read_blob_header() {
             Some(Ok(header)) => header,
-            Some(Err(err)) => return Some(Err(err)),
+            Some(Err(err)) => return Some(Err(err.with_blob(prev_offset, self.blob_index))),
             None => return None,
         };
 
         // skip blob (which also adjusts self.offset)
+        let next_index = self.next_index;
         if let Err(err) = self.seek_raw(SeekFrom::Current(header.datasize() as i64)) {
             self.last_blob_ok = false;
-            return Some(Err(err));
+            return Some(Err(err.with_blob(prev_offset, self.blob_index)));
         }
+        self.next_index = next_index;
 
         Some(Ok((BlobHeader::new(header), prev_offset)))
     }
@@ -452,26 +1165,158 @@ impl BlobReader<BufReader<File>> {
     }
 }
 
-pub(crate) fn decode_blob<T: Message>(blob: &fileformat::Blob) -> Result<T> {
-    if blob.has_raw() {
-        let size = blob.raw().len() as u64;
-        if size < MAX_BLOB_MESSAGE_SIZE {
-            T::parse_from_bytes(blob.raw()).map_err(|e| new_protobuf_error(e, "raw blob data"))
-        } else {
-            Err(new_blob_error(BlobError::MessageTooBig { size }))
+/// The content of a blob message that borrows the (possibly compressed) payload from the encoded
+/// message or from a [`fileformat::Blob`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BlobRef<'a> {
+    pub(crate) raw_size: Option<i32>,
+    pub(crate) data: Option<BlobData<'a>>,
+}
+
+/// The payload of a blob.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum BlobData<'a> {
+    Raw(&'a [u8]),
+    Zlib(&'a [u8]),
+    #[cfg(feature = "zstd")]
+    Zstd(&'a [u8]),
+    #[cfg(feature = "lz4")]
+    Lz4(&'a [u8]),
+    /// A compression scheme that is not supported.
+    Unsupported(BlobCompression),
+}
+
+/// The positions of the fields of an encoded `Blob` message.
+#[derive(Clone, Debug)]
+pub(crate) struct BlobLayout {
+    raw_size: Option<i32>,
+    /// The compression and the position of the payload in the message.
+    payload: Option<(BlobCompression, Range<usize>)>,
+}
+
+impl BlobLayout {
+    /// Returns the content of the given encoded `Blob` message with this layout.
+    pub(crate) fn blob_ref<'a>(&self, bytes: &'a [u8]) -> BlobRef<'a> {
+        let data = self.payload.as_ref().map(|(compression, range)| {
+            let data = &bytes[range.clone()];
+            match compression {
+                BlobCompression::Raw => BlobData::Raw(data),
+                BlobCompression::Zlib => BlobData::Zlib(data),
+                #[cfg(feature = "zstd")]
+                BlobCompression::Zstd => BlobData::Zstd(data),
+                #[cfg(feature = "lz4")]
+                BlobCompression::Lz4 => BlobData::Lz4(data),
+                compression => BlobData::Unsupported(*compression),
+            }
+        });
+        BlobRef {
+            raw_size: self.raw_size,
+            data,
+        }
+    }
+}
+
+/// Parses the positions of the fields of an encoded `Blob` message.
+pub(crate) fn parse_blob_layout(bytes: &[u8]) -> Result<BlobLayout> {
+    use protobuf::rt::WireType;
+    use protobuf::CodedInputStream;
+
+    let protobuf_error = |e| new_protobuf_error(e, "blob content");
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut blob = BlobLayout {
+        raw_size: None,
+        payload: None,
+    };
+    while let Some(tag) = is.read_raw_tag_or_eof().map_err(protobuf_error)? {
+        let field_number = tag >> 3;
+        let wire_type = WireType::new(tag & 7).ok_or_else(|| {
+            protobuf_error(
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid wire type").into(),
+            )
+        })?;
+        match (field_number, wire_type) {
+            (2, WireType::Varint) => blob.raw_size = Some(is.read_int32().map_err(protobuf_error)?),
+            (1 | 3..=7, WireType::LengthDelimited) => {
+                let len = is.read_raw_varint32().map_err(protobuf_error)?;
+                let start = is.pos() as usize;
+                is.skip_raw_bytes(len).map_err(protobuf_error)?;
+                blob.payload = BlobCompression::from_field_number(field_number)
+                    .map(|compression| (compression, start..start + len as usize));
+            }
+            (_, wire_type) => is.skip_field(wire_type).map_err(protobuf_error)?,
         }
-    } else if blob.has_zlib_data() {
-        let mut decoder = ZlibDecoder::new(blob.zlib_data()).take(MAX_BLOB_MESSAGE_SIZE);
-        T::parse_from_reader(&mut decoder).map_err(|e| new_protobuf_error(e, "blob zlib data"))
-    } else {
-        Err(new_blob_error(BlobError::Empty))
     }
+    Ok(blob)
+}
+
+/// Decodes the content of a blob to a [`PrimitiveBlock`] with the given policies.
+pub(crate) fn decode_primitive_block(
+    blob: &BlobRef,
+    options: &DecodeOptions,
+) -> Result<PrimitiveBlock> {
+    let max_size = options.max_blob_size;
+    match (options.block_decoding, options.tag_decoding) {
+        (BlockDecoding::Lazy, TagDecoding::Unchecked) => {
+            PrimitiveBlock::lazy(DecodeContext::with(|context| {
+                context.decompress_owned(blob, max_size)
+            })?)
+        }
+        (_, tag_decoding) => {
+            PrimitiveBlock::with_tag_decoding(decode_blob_ref(blob, max_size)?, tag_decoding)
+        }
+    }
+}
+
+pub(crate) fn decode_blob_ref<T: Message>(blob: &BlobRef, max_size: u64) -> Result<T> {
+    DecodeContext::with(|context| {
+        let location = match blob.data {
+            Some(BlobData::Zlib(_)) => "blob zlib data",
+            #[cfg(feature = "zstd")]
+            Some(BlobData::Zstd(_)) => "blob zstd data",
+            #[cfg(feature = "lz4")]
+            Some(BlobData::Lz4(_)) => "blob lz4 data",
+            _ => "raw blob data",
+        };
+        T::parse_from_bytes(context.decompress(blob, max_size)?)
+            .map_err(|e| new_protobuf_error(e, location))
+    })
+}
+
+/// Converts the size of a blob message to the `int32` of the protobuf fields `datasize` and
+/// `raw_size`. Returns a [`BlobError::MessageTooBig`] error if it does not fit.
+pub(crate) fn message_size(len: usize) -> Result<i32> {
+    i32::try_from(len).map_err(|_| new_blob_error(BlobError::MessageTooBig { size: len as u64 }))
+}
+
+/// Returns the encoded blob with size prefix. The `datasize` of the header is set to the size of
+/// the blob.
+#[cfg(test)]
+pub(crate) fn encode_blob(header: &fileformat::BlobHeader, blob: &fileformat::Blob) -> Vec<u8> {
+    let blob = blob.write_to_bytes().unwrap();
+    let mut header = header.clone();
+    header.set_datasize(blob.len() as i32);
+    let header = header.write_to_bytes().unwrap();
+    let mut bytes = (header.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&blob);
+    bytes
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_message_size() {
+        assert_eq!(message_size(0).unwrap(), 0);
+        assert_eq!(message_size(i32::MAX as usize).unwrap(), i32::MAX);
+        let err = message_size(i32::MAX as usize + 1).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::Blob(BlobError::MessageTooBig { size: 0x8000_0000 })
+        ));
+    }
+
     #[test]
     fn test_get_type() {
         let pairs = [
@@ -484,10 +1329,71 @@ mod tests {
         for (string, blob_type) in &pairs {
             let mut ff_header = fileformat::BlobHeader::new();
             ff_header.set_type(string.to_string());
-            let ff_blob = fileformat::Blob::new();
+            let bytes = encode_blob(&ff_header, &fileformat::Blob::new());
 
-            let blob = Blob::new(ff_header, ff_blob, None);
+            let blob = Blob::new(ff_header, bytes, None, None, DecodeOptions::default()).unwrap();
             assert_eq!(blob.get_type(), *blob_type);
         }
     }
+
+    fn parse_blob_ref(bytes: &[u8]) -> Result<BlobRef<'_>> {
+        parse_blob_layout(bytes).map(|layout| layout.blob_ref(bytes))
+    }
+
+    #[test]
+    fn test_parse_blob_ref() {
+        let mut ff_blob = fileformat::Blob::new();
+        ff_blob.set_raw_size(42);
+        ff_blob.set_zlib_data(vec![1, 2, 3]);
+        let mut bytes = ff_blob.write_to_bytes().unwrap();
+        // Unknown fields are skipped
+        bytes.extend_from_slice(&[0x40, 0x01, 0x4a, 0x01, 0xff]);
+
+        let blob = parse_blob_ref(&bytes).unwrap();
+        assert_eq!(blob.raw_size, Some(42));
+        assert!(matches!(blob.data, Some(BlobData::Zlib(&[1, 2, 3]))));
+
+        ff_blob.set_lzma_data(vec![4]);
+        let bytes = ff_blob.write_to_bytes().unwrap();
+        let blob = parse_blob_ref(&bytes).unwrap();
+        assert!(matches!(
+            blob.data,
+            Some(BlobData::Unsupported(BlobCompression::Lzma))
+        ));
+
+        assert!(parse_blob_ref(&[0x1a, 0x05, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_raw_blob() {
+        let mut ff_header = fileformat::BlobHeader::new();
+        ff_header.set_type("OSMData".to_string());
+        let mut ff_blob = fileformat::Blob::new();
+        ff_blob.set_raw_size(42);
+        ff_blob.set_zstd_data(vec![1, 2, 3]);
+        ff_header.set_datasize(ff_blob.compute_size() as i32);
+        let bytes = encode_blob(&ff_header, &ff_blob);
+
+        let blob = Blob::new(
+            ff_header.clone(),
+            bytes.clone(),
+            None,
+            None,
+            Default::default(),
+        )
+        .unwrap();
+        let raw = blob.raw();
+        assert_eq!(raw.bytes(), bytes.as_slice());
+        assert_eq!(raw.header(), ff_header.write_to_bytes().unwrap().as_slice());
+        assert_eq!(raw.content(), ff_blob.write_to_bytes().unwrap().as_slice());
+        assert_eq!(raw.payload(), Some(&[1, 2, 3][..]));
+        assert_eq!(raw.compression(), Some(BlobCompression::Zstd));
+        assert_eq!(raw.raw_size(), Some(42));
+        assert_eq!(blob.content_sizes(), (3, Some(42)));
+
+        let bytes = encode_blob(&ff_header, &fileformat::Blob::new());
+        let blob = Blob::new(ff_header, bytes, None, None, Default::default()).unwrap();
+        assert_eq!(blob.raw().payload(), None);
+        assert_eq!(blob.raw().compression(), None);
+    }
 }
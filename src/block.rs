@@ -1,10 +1,19 @@
 //! `HeaderBlock`, `PrimitiveBlock` and `PrimitiveGroup`s
 
-use crate::dense::DenseNodeIter;
-use crate::elements::{Element, Node, Relation, Way};
-use crate::error::{new_error, ErrorKind, Result};
+use crate::dense::{DenseColumns, DenseNodeIter};
+use crate::elements::{Element, ElementType, Node, Relation, Way};
+use crate::error::{new_error, new_protobuf_error, ErrorKind, Result};
+use crate::lazy::{
+    parse_node, parse_relation, parse_way, DenseView, LazyBlock, LazyGroup, Messages, NodeView,
+    Packed, RelationView, WayView,
+};
 use crate::proto::osmformat;
+use protobuf::Message;
 use std;
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
 
 /// A `HeaderBlock`. It contains metadata about following [`PrimitiveBlock`]s.
 #[derive(Clone, Debug)]
@@ -46,6 +55,34 @@ impl HeaderBlock {
             None
         }
     }
+
+    /// Returns the source of the bounding box or `None` if unset.
+    pub fn source(&self) -> Option<&str> {
+        if self.header.has_source() {
+            Some(self.header.source())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the replication timestamp in seconds since the epoch or `None` if unset.
+    pub fn osmosis_replication_timestamp(&self) -> Option<i64> {
+        self.header.osmosis_replication_timestamp
+    }
+
+    /// Returns the replication sequence number or `None` if unset.
+    pub fn osmosis_replication_sequence_number(&self) -> Option<i64> {
+        self.header.osmosis_replication_sequence_number
+    }
+
+    /// Returns the replication base URL or `None` if unset.
+    pub fn osmosis_replication_base_url(&self) -> Option<&str> {
+        if self.header.has_osmosis_replication_base_url() {
+            Some(self.header.osmosis_replication_base_url())
+        } else {
+            None
+        }
+    }
 }
 
 /// A bounding box that is usually included in a [`HeaderBlock`].
@@ -62,25 +99,183 @@ pub struct HeaderBBox {
     pub bottom: f64,
 }
 
+/// The policy for tags with strings that cannot be decoded, either because they are not valid
+/// UTF-8 or because they refer to a missing stringtable entry.
+///
+/// The policy is applied when a [`PrimitiveBlock`] is decoded, see for example
+/// [`ElementReader::set_tag_decoding`](crate::reader::ElementReader::set_tag_decoding).
+/// Independent of the policy, the tags of single elements can also be checked with `checked_tags`
+/// or decoded with `lossy_tags`, for example [`Node::checked_tags`] and [`Node::lossy_tags`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TagDecoding {
+    /// Strings are decoded lazily. Tag iterators such as [`Node::tags`] end at the first tag that
+    /// cannot be decoded.
+    #[default]
+    Unchecked,
+    /// All tags are checked while decoding a block. A tag that cannot be decoded causes an error
+    /// that contains the affected element.
+    Strict,
+    /// Tags are repaired while decoding a block: invalid UTF-8 sequences are replaced with
+    /// `U+FFFD REPLACEMENT CHARACTER` and missing strings with a single replacement character.
+    Lossy,
+}
+
+/// The string that replaces a missing stringtable entry in lossy mode.
+pub(crate) const REPLACEMENT_STR: &str = "\u{FFFD}";
+
+/// How the content of a [`PrimitiveBlock`] is decoded.
+///
+/// The policy is applied when a blob is decoded, see for example
+/// [`ElementReader::set_block_decoding`](crate::reader::ElementReader::set_block_decoding).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BlockDecoding {
+    /// The whole block is decoded to owned protobuf messages.
+    #[default]
+    Eager,
+    /// Only the structure of the decompressed block is checked. Elements, packed fields and
+    /// strings are decoded on demand and borrow from the decompressed buffer, which saves
+    /// allocations when only a few fields of each element are used.
+    ///
+    /// Methods that return slices of decoded values, like [`PrimitiveBlock::raw_stringtable`] and
+    /// [`Way::raw_refs`], copy the values on their first call. Blocks are also
+    /// decoded eagerly if the [`TagDecoding`] policy is not [`TagDecoding::Unchecked`] or if they
+    /// use an unusual encoding, like unpacked repeated fields.
+    Lazy,
+}
+
 /// A `PrimitiveBlock`. It contains a sequence of groups.
 #[derive(Clone, Debug)]
 pub struct PrimitiveBlock {
-    block: osmformat::PrimitiveBlock,
+    data: BlockData,
+    string_ids: OnceLock<StringIds>,
+}
+
+/// The eagerly or lazily decoded content of a block.
+#[derive(Clone, Debug)]
+enum BlockData {
+    Proto(osmformat::PrimitiveBlock),
+    Lazy(Box<LazyBlock>),
+}
+
+/// Maps the strings of a stringtable to their indices without copying the strings.
+#[derive(Clone, Debug, Default)]
+struct StringIds {
+    hasher: RandomState,
+    /// The hashes of the strings and their indices, sorted by hash and index.
+    ids: Vec<(u64, u32)>,
+    /// True if at least one string occurs more than once in the stringtable.
+    has_duplicates: bool,
+}
+
+impl StringIds {
+    fn hash(&self, s: &[u8]) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(s);
+        hasher.finish()
+    }
+
+    /// Returns the indices of the given string in ascending order. `string` returns the strings
+    /// of the stringtable that the ids were built from.
+    fn indices<'a, 's>(
+        &'s self,
+        s: &'s [u8],
+        string: impl Fn(usize) -> Option<&'a [u8]> + 's,
+    ) -> impl Iterator<Item = u32> + 's {
+        let hash = self.hash(s);
+        let start = self.ids.partition_point(|&(h, _)| h < hash);
+        self.ids[start..]
+            .iter()
+            .take_while(move |&&(h, _)| h == hash)
+            .map(|&(_, index)| index)
+            .filter(move |&index| string(index as usize) == Some(s))
+    }
+}
+
+/// A string that was resolved against the stringtable of a block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ResolvedStr {
+    /// The string only occurs at the given index.
+    Id(u32),
+    /// The string occurs at all of the given indices.
+    Ids(Vec<u32>),
+}
+
+impl ResolvedStr {
+    fn matches(&self, index: u32) -> bool {
+        match self {
+            ResolvedStr::Id(id) => index == *id,
+            ResolvedStr::Ids(ids) => ids.contains(&index),
+        }
+    }
+}
+
+/// A tag key, or a key and a value, that was resolved to stringtable indices with
+/// [`PrimitiveBlock::resolve_tag`].
+///
+/// Matching a resolved tag only compares the indices of the raw tags, so an element method like
+/// [`Way::has_resolved_tag`](crate::elements::Way::has_resolved_tag) neither hashes nor decodes
+/// any string. A resolved tag is only valid for the elements of the block it was resolved with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResolvedTag {
+    key: ResolvedStr,
+    value: Option<ResolvedStr>,
+}
+
+impl ResolvedTag {
+    /// Returns the value index of the first raw tag that matches.
+    pub(crate) fn find<I>(&self, mut raw_tags: I) -> Option<u32>
+    where
+        I: Iterator<Item = (u32, u32)>,
+    {
+        raw_tags
+            .find(|&(k, v)| {
+                self.key.matches(k) && self.value.as_ref().map_or(true, |value| value.matches(v))
+            })
+            .map(|(_, v)| v)
+    }
 }
 
 impl PrimitiveBlock {
-    pub(crate) fn new(block: osmformat::PrimitiveBlock) -> PrimitiveBlock {
-        PrimitiveBlock { block }
+    /// Creates a block and applies the given tag decoding policy.
+    pub(crate) fn with_tag_decoding(
+        mut block: osmformat::PrimitiveBlock,
+        tag_decoding: TagDecoding,
+    ) -> Result<PrimitiveBlock> {
+        match tag_decoding {
+            TagDecoding::Unchecked => {}
+            TagDecoding::Strict => check_tags(&block)?,
+            TagDecoding::Lossy => repair_tags(&mut block),
+        }
+        Ok(PrimitiveBlock {
+            data: BlockData::Proto(block),
+            string_ids: OnceLock::new(),
+        })
+    }
+
+    /// Creates a lazily decoded block from the decompressed message.
+    pub(crate) fn lazy(buffer: Vec<u8>) -> Result<PrimitiveBlock> {
+        let data = match LazyBlock::new(buffer) {
+            Ok(block) => BlockData::Lazy(Box::new(block)),
+            // This also reports the error if the message is malformed
+            Err(buffer) => BlockData::Proto(
+                osmformat::PrimitiveBlock::parse_from_bytes(&buffer)
+                    .map_err(|e| new_protobuf_error(e, "primitive block"))?,
+            ),
+        };
+        Ok(PrimitiveBlock {
+            data,
+            string_ids: OnceLock::new(),
+        })
     }
 
     /// Returns an iterator over the elements in this `PrimitiveBlock`.
-    pub fn elements(&self) -> BlockElementsIter {
-        BlockElementsIter::new(&self.block)
+    pub fn elements(&self) -> BlockElementsIter<'_> {
+        BlockElementsIter::new(self)
     }
 
     /// Returns an iterator over the groups in this `PrimitiveBlock`.
-    pub fn groups(&self) -> GroupIter {
-        GroupIter::new(&self.block)
+    pub fn groups(&self) -> GroupIter<'_> {
+        GroupIter::new(self)
     }
 
     /// Calls the given closure on each element.
@@ -104,61 +299,335 @@ impl PrimitiveBlock {
         }
     }
 
+    /// Decodes the dense nodes of all groups into contiguous columns, see [`DenseColumns`].
+    pub fn dense_columns(&self) -> DenseColumns {
+        let mut columns = DenseColumns::new();
+        for group in self.groups() {
+            columns.extend(self, group.dense_view());
+        }
+        columns
+    }
+
+    /// Returns true if the content of this block is decoded on demand, see
+    /// [`BlockDecoding::Lazy`].
+    pub fn is_lazy(&self) -> bool {
+        matches!(self.data, BlockData::Lazy(_))
+    }
+
+    pub(crate) fn granularity(&self) -> i32 {
+        match &self.data {
+            BlockData::Proto(block) => block.granularity(),
+            BlockData::Lazy(block) => block.granularity(),
+        }
+    }
+
+    pub(crate) fn date_granularity(&self) -> i32 {
+        match &self.data {
+            BlockData::Proto(block) => block.date_granularity(),
+            BlockData::Lazy(block) => block.date_granularity(),
+        }
+    }
+
+    pub(crate) fn lat_offset(&self) -> i64 {
+        match &self.data {
+            BlockData::Proto(block) => block.lat_offset(),
+            BlockData::Lazy(block) => block.lat_offset(),
+        }
+    }
+
+    pub(crate) fn lon_offset(&self) -> i64 {
+        match &self.data {
+            BlockData::Proto(block) => block.lon_offset(),
+            BlockData::Lazy(block) => block.lon_offset(),
+        }
+    }
+
     /// Returns the raw stringtable. Elements in a `PrimitiveBlock` do not store strings
     /// themselves; instead, they just store indices to the stringtable. By convention, the
     /// contained strings are UTF-8 encoded but it is not safe to assume that (use
     /// `std::str::from_utf8`).
+    ///
+    /// Lazily decoded blocks copy the stringtable on the first call, see
+    /// [`BlockDecoding::Lazy`].
     pub fn raw_stringtable(&self) -> &[Vec<u8>] {
-        self.block.stringtable.s.as_slice()
+        match &self.data {
+            BlockData::Proto(block) => block.stringtable.s.as_slice(),
+            BlockData::Lazy(block) => block.raw_stringtable(),
+        }
+    }
+
+    /// Returns the string at the given stringtable index.
+    pub(crate) fn string(&self, index: usize) -> Option<&[u8]> {
+        match &self.data {
+            BlockData::Proto(block) => block.stringtable.s.get(index).map(Vec::as_slice),
+            BlockData::Lazy(block) => block.string(index),
+        }
+    }
+
+    pub(crate) fn string_count(&self) -> usize {
+        match &self.data {
+            BlockData::Proto(block) => block.stringtable.s.len(),
+            BlockData::Lazy(block) => block.string_count(),
+        }
+    }
+
+    /// Returns the delta coded node ids of the way at the given position. `refs` are the packed
+    /// ids of the same way.
+    pub(crate) fn raw_way_refs(&self, position: (usize, usize), refs: Packed<'_, i64>) -> &[i64] {
+        match &self.data {
+            BlockData::Proto(block) => block
+                .primitivegroup
+                .get(position.0)
+                .and_then(|group| group.ways.get(position.1))
+                .map_or(&[], |way| way.refs.as_slice()),
+            BlockData::Lazy(block) => block.way_refs(position, refs),
+        }
+    }
+
+    fn group(&self, index: usize) -> Option<PrimitiveGroup<'_>> {
+        let group = match &self.data {
+            BlockData::Proto(block) => GroupData::Proto(block.primitivegroup.get(index)?),
+            BlockData::Lazy(block) => GroupData::Lazy(block, block.groups().get(index)?),
+        };
+        Some(PrimitiveGroup {
+            block: self,
+            group,
+            index,
+        })
+    }
+
+    fn group_count(&self) -> usize {
+        match &self.data {
+            BlockData::Proto(block) => block.primitivegroup.len(),
+            BlockData::Lazy(block) => block.groups().len(),
+        }
+    }
+
+    /// Returns the stringtable index of the given string or `None` if the block does not contain
+    /// it. If the string occurs more than once, the smallest index is returned.
+    ///
+    /// A lookup table is built on the first call and shared by all elements of the block, so
+    /// resolving a string once and comparing it with the indices from `raw_tags` is much cheaper
+    /// than decoding every tag. See [`resolve_tag`](Self::resolve_tag) to match whole tags.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    ///
+    /// for blob in reader {
+    ///     if let BlobDecode::OsmData(block) = blob?.decode()? {
+    ///         if let Some(building) = block.string_id("building") {
+    ///             let buildings = block
+    ///                 .elements()
+    ///                 .filter(|e| match e {
+    ///                     Element::Way(way) => way.raw_tags().any(|(k, _)| k == building),
+    ///                     _ => false,
+    ///                 })
+    ///                 .count();
+    ///             println!("{buildings} buildings");
+    ///         }
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn string_id(&self, s: &str) -> Option<u32> {
+        self.string_ids()
+            .indices(s.as_bytes(), |index| self.string(index))
+            .next()
+    }
+
+    /// Resolves a tag key and, if given, a tag value to their stringtable indices. Returns `None`
+    /// if the block does not contain the key or the value, so none of its elements has the tag.
+    ///
+    /// Resolving hashes the strings, so it should be done once per block. The resolved tag is
+    /// then matched with the raw tags of each element, for example with
+    /// [`Way::has_resolved_tag`](crate::elements::Way::has_resolved_tag).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    ///
+    /// for blob in reader {
+    ///     if let BlobDecode::OsmData(block) = blob?.decode()? {
+    ///         if let Some(building) = block.resolve_tag("building", Some("yes")) {
+    ///             let buildings = block
+    ///                 .elements()
+    ///                 .filter(|e| match e {
+    ///                     Element::Way(way) => way.has_resolved_tag(&building),
+    ///                     _ => false,
+    ///                 })
+    ///                 .count();
+    ///             println!("{buildings} buildings");
+    ///         }
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn resolve_tag(&self, key: &str, value: Option<&str>) -> Option<ResolvedTag> {
+        Some(ResolvedTag {
+            key: self.resolve(key)?,
+            value: match value {
+                Some(value) => Some(self.resolve(value)?),
+                None => None,
+            },
+        })
+    }
+
+    fn string_ids(&self) -> &StringIds {
+        self.string_ids.get_or_init(|| {
+            let mut string_ids = StringIds::default();
+            let mut ids: Vec<(u64, u32)> = (0..self.string_count())
+                .filter_map(|index| Some((string_ids.hash(self.string(index)?), index as u32)))
+                .collect();
+            ids.sort_unstable();
+            // Strings with the same hash are adjacent, so duplicates are found by comparing them
+            string_ids.has_duplicates = ids.iter().enumerate().any(|(i, &(hash, index))| {
+                ids[..i]
+                    .iter()
+                    .rev()
+                    .take_while(|&&(h, _)| h == hash)
+                    .any(|&(_, other)| self.string(other as usize) == self.string(index as usize))
+            });
+            string_ids.ids = ids;
+            string_ids
+        })
+    }
+
+    /// Returns all stringtable indices of the given string in ascending order.
+    pub(crate) fn string_indices(&self, s: &str) -> Vec<u32> {
+        self.string_ids()
+            .indices(s.as_bytes(), |index| self.string(index))
+            .collect()
+    }
+
+    fn resolve(&self, s: &str) -> Option<ResolvedStr> {
+        let string_ids = self.string_ids();
+        let mut indices = string_ids.indices(s.as_bytes(), |index| self.string(index));
+        let id = indices.next()?;
+        if string_ids.has_duplicates {
+            Some(ResolvedStr::Ids(
+                std::iter::once(id).chain(indices).collect(),
+            ))
+        } else {
+            Some(ResolvedStr::Id(id))
+        }
     }
 }
 
 /// A `PrimitiveGroup` contains a sequence of elements of one type.
 #[derive(Clone, Debug)]
 pub struct PrimitiveGroup<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    group: &'a osmformat::PrimitiveGroup,
+    block: &'a PrimitiveBlock,
+    group: GroupData<'a>,
+    /// The index of the group in the block.
+    index: usize,
 }
 
-impl<'a> PrimitiveGroup<'a> {
-    fn new(
-        block: &'a osmformat::PrimitiveBlock,
-        group: &'a osmformat::PrimitiveGroup,
-    ) -> PrimitiveGroup<'a> {
-        PrimitiveGroup { block, group }
-    }
+#[derive(Clone, Copy, Debug)]
+enum GroupData<'a> {
+    Proto(&'a osmformat::PrimitiveGroup),
+    Lazy(&'a LazyBlock, &'a LazyGroup),
+}
 
+impl<'a> PrimitiveGroup<'a> {
     /// Returns an iterator over the nodes in this group.
     pub fn nodes(&self) -> GroupNodeIter<'a> {
-        GroupNodeIter::new(self.block, self.group)
+        let nodes = match self.group {
+            GroupData::Proto(group) => Source::Proto(group.nodes.iter()),
+            GroupData::Lazy(block, group) => Source::Lazy(block.nodes(group)),
+        };
+        GroupNodeIter {
+            block: self.block,
+            nodes,
+        }
     }
 
     /// Returns an iterator over the dense nodes in this group.
     pub fn dense_nodes(&self) -> DenseNodeIter<'a> {
-        DenseNodeIter::new(self.block, self.group.dense.get_or_default())
+        DenseNodeIter::new(self.block, self.dense_view())
+    }
+
+    /// Decodes the dense nodes in this group into contiguous columns, see [`DenseColumns`].
+    pub fn dense_columns(&self) -> DenseColumns {
+        let mut columns = DenseColumns::new();
+        columns.extend(self.block, self.dense_view());
+        columns
+    }
+
+    fn dense_view(&self) -> DenseView<'a> {
+        match self.group {
+            GroupData::Proto(group) => DenseView::from(group.dense.get_or_default()),
+            GroupData::Lazy(block, group) => block.dense(group),
+        }
     }
 
     /// Returns an iterator over the ways in this group.
     pub fn ways(&self) -> GroupWayIter<'a> {
-        GroupWayIter::new(self.block, self.group)
+        let ways = match self.group {
+            GroupData::Proto(group) => Source::Proto(group.ways.iter()),
+            GroupData::Lazy(block, group) => Source::Lazy(block.ways(group)),
+        };
+        GroupWayIter {
+            block: self.block,
+            ways,
+            group_index: self.index,
+            index: 0,
+        }
     }
 
     /// Returns an iterator over the relations in this group.
     pub fn relations(&self) -> GroupRelationIter<'a> {
-        GroupRelationIter::new(self.block, self.group)
+        let rels = match self.group {
+            GroupData::Proto(group) => Source::Proto(group.relations.iter()),
+            GroupData::Lazy(block, group) => Source::Lazy(block.relations(group)),
+        };
+        GroupRelationIter {
+            block: self.block,
+            rels,
+        }
+    }
+}
+
+/// The elements of a group, either decoded messages or encoded messages that are parsed on
+/// demand.
+#[derive(Clone, Debug)]
+enum Source<'a, T> {
+    Proto(std::slice::Iter<'a, T>),
+    Lazy(Messages<'a>),
+}
+
+impl<'a, T> Source<'a, T> {
+    fn empty() -> Source<'a, T> {
+        Source::Proto([].iter())
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Source::Proto(iter) => iter.len(),
+            Source::Lazy(iter) => iter.len(),
+        }
     }
 }
 
 /// An iterator over the elements in a [`PrimitiveGroup`].
 #[derive(Clone, Debug)]
 pub struct BlockElementsIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
     state: ElementsIterState,
-    groups: std::slice::Iter<'a, osmformat::PrimitiveGroup>,
+    groups: GroupIter<'a>,
     dense_nodes: DenseNodeIter<'a>,
-    nodes: std::slice::Iter<'a, osmformat::Node>,
-    ways: std::slice::Iter<'a, osmformat::Way>,
-    relations: std::slice::Iter<'a, osmformat::Relation>,
+    nodes: GroupNodeIter<'a>,
+    ways: GroupWayIter<'a>,
+    relations: GroupRelationIter<'a>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -171,15 +640,25 @@ enum ElementsIterState {
 }
 
 impl<'a> BlockElementsIter<'a> {
-    fn new(block: &'a osmformat::PrimitiveBlock) -> BlockElementsIter<'a> {
+    fn new(block: &'a PrimitiveBlock) -> BlockElementsIter<'a> {
         BlockElementsIter {
-            block,
             state: ElementsIterState::Group,
-            groups: block.primitivegroup.iter(),
+            groups: GroupIter::new(block),
             dense_nodes: DenseNodeIter::empty(block),
-            nodes: [].iter(),
-            ways: [].iter(),
-            relations: [].iter(),
+            nodes: GroupNodeIter {
+                block,
+                nodes: Source::empty(),
+            },
+            ways: GroupWayIter {
+                block,
+                ways: Source::empty(),
+                group_index: 0,
+                index: 0,
+            },
+            relations: GroupRelationIter {
+                block,
+                rels: Source::empty(),
+            },
         }
     }
 
@@ -192,10 +671,10 @@ impl<'a> BlockElementsIter<'a> {
             ElementsIterState::Group => match self.groups.next() {
                 Some(group) => {
                     self.state = ElementsIterState::DenseNode;
-                    self.dense_nodes = DenseNodeIter::new(self.block, group.dense.get_or_default());
-                    self.nodes = group.nodes.iter();
-                    self.ways = group.ways.iter();
-                    self.relations = group.relations.iter();
+                    self.dense_nodes = group.dense_nodes();
+                    self.nodes = group.nodes();
+                    self.ways = group.ways();
+                    self.relations = group.relations();
                     None
                 }
                 None => Some(None),
@@ -208,21 +687,21 @@ impl<'a> BlockElementsIter<'a> {
                 }
             },
             ElementsIterState::Node => match self.nodes.next() {
-                Some(node) => Some(Some(Element::Node(Node::new(self.block, node)))),
+                Some(node) => Some(Some(Element::Node(node))),
                 None => {
                     self.state = ElementsIterState::Way;
                     None
                 }
             },
             ElementsIterState::Way => match self.ways.next() {
-                Some(way) => Some(Some(Element::Way(Way::new(self.block, way)))),
+                Some(way) => Some(Some(Element::Way(way))),
                 None => {
                     self.state = ElementsIterState::Relation;
                     None
                 }
             },
             ElementsIterState::Relation => match self.relations.next() {
-                Some(rel) => Some(Some(Element::Relation(Relation::new(self.block, rel)))),
+                Some(rel) => Some(Some(Element::Relation(rel))),
                 None => {
                     self.state = ElementsIterState::Group;
                     None
@@ -248,15 +727,15 @@ impl<'a> Iterator for BlockElementsIter<'a> {
 /// An iterator over the groups in a [`PrimitiveBlock`].
 #[derive(Clone, Debug)]
 pub struct GroupIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    groups: std::slice::Iter<'a, osmformat::PrimitiveGroup>,
+    block: &'a PrimitiveBlock,
+    indices: std::ops::Range<usize>,
 }
 
 impl<'a> GroupIter<'a> {
-    fn new(block: &'a osmformat::PrimitiveBlock) -> GroupIter<'a> {
+    fn new(block: &'a PrimitiveBlock) -> GroupIter<'a> {
         GroupIter {
             block,
-            groups: block.primitivegroup.iter(),
+            indices: 0..block.group_count(),
         }
     }
 }
@@ -265,14 +744,13 @@ impl<'a> Iterator for GroupIter<'a> {
     type Item = PrimitiveGroup<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.groups.next() {
-            Some(g) => Some(PrimitiveGroup::new(self.block, g)),
-            None => None,
-        }
+        self.indices
+            .next()
+            .and_then(|index| self.block.group(index))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.groups.size_hint()
+        self.indices.size_hint()
     }
 }
 
@@ -281,34 +759,24 @@ impl<'a> ExactSizeIterator for GroupIter<'a> {}
 /// An iterator over the nodes in a [`PrimitiveGroup`].
 #[derive(Clone, Debug)]
 pub struct GroupNodeIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    nodes: std::slice::Iter<'a, osmformat::Node>,
-}
-
-impl<'a> GroupNodeIter<'a> {
-    fn new(
-        block: &'a osmformat::PrimitiveBlock,
-        group: &'a osmformat::PrimitiveGroup,
-    ) -> GroupNodeIter<'a> {
-        GroupNodeIter {
-            block,
-            nodes: group.nodes.iter(),
-        }
-    }
+    block: &'a PrimitiveBlock,
+    nodes: Source<'a, osmformat::Node>,
 }
 
 impl<'a> Iterator for GroupNodeIter<'a> {
     type Item = Node<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.nodes.next() {
-            Some(n) => Some(Node::new(self.block, n)),
-            None => None,
-        }
+        let node = match &mut self.nodes {
+            Source::Proto(iter) => NodeView::from(iter.next()?),
+            Source::Lazy(iter) => parse_node(iter.next()?)?,
+        };
+        Some(Node::new(self.block, node))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.nodes.size_hint()
+        let len = self.nodes.len();
+        (len, Some(len))
     }
 }
 
@@ -317,34 +785,28 @@ impl<'a> ExactSizeIterator for GroupNodeIter<'a> {}
 /// An iterator over the ways in a [`PrimitiveGroup`].
 #[derive(Clone, Debug)]
 pub struct GroupWayIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    ways: std::slice::Iter<'a, osmformat::Way>,
-}
-
-impl<'a> GroupWayIter<'a> {
-    fn new(
-        block: &'a osmformat::PrimitiveBlock,
-        group: &'a osmformat::PrimitiveGroup,
-    ) -> GroupWayIter<'a> {
-        GroupWayIter {
-            block,
-            ways: group.ways.iter(),
-        }
-    }
+    block: &'a PrimitiveBlock,
+    ways: Source<'a, osmformat::Way>,
+    group_index: usize,
+    index: usize,
 }
 
 impl<'a> Iterator for GroupWayIter<'a> {
     type Item = Way<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.ways.next() {
-            Some(way) => Some(Way::new(self.block, way)),
-            None => None,
-        }
+        let position = (self.group_index, self.index);
+        let way = match &mut self.ways {
+            Source::Proto(iter) => WayView::new(iter.next()?, position),
+            Source::Lazy(iter) => parse_way(iter.next()?, position)?,
+        };
+        self.index += 1;
+        Some(Way::new(self.block, way))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.ways.size_hint()
+        let len = self.ways.len();
+        (len, Some(len))
     }
 }
 
@@ -353,54 +815,171 @@ impl<'a> ExactSizeIterator for GroupWayIter<'a> {}
 /// An iterator over the relations in a [`PrimitiveGroup`].
 #[derive(Clone, Debug)]
 pub struct GroupRelationIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    rels: std::slice::Iter<'a, osmformat::Relation>,
-}
-
-impl<'a> GroupRelationIter<'a> {
-    fn new(
-        block: &'a osmformat::PrimitiveBlock,
-        group: &'a osmformat::PrimitiveGroup,
-    ) -> GroupRelationIter<'a> {
-        GroupRelationIter {
-            block,
-            rels: group.relations.iter(),
-        }
-    }
+    block: &'a PrimitiveBlock,
+    rels: Source<'a, osmformat::Relation>,
 }
 
 impl<'a> Iterator for GroupRelationIter<'a> {
     type Item = Relation<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.rels.next() {
-            Some(rel) => Some(Relation::new(self.block, rel)),
-            None => None,
-        }
+        let rel = match &mut self.rels {
+            Source::Proto(iter) => RelationView::from(iter.next()?),
+            Source::Lazy(iter) => parse_relation(iter.next()?)?,
+        };
+        Some(Relation::new(self.block, rel))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.rels.size_hint()
+        let len = self.rels.len();
+        (len, Some(len))
     }
 }
 
 impl<'a> ExactSizeIterator for GroupRelationIter<'a> {}
 
-pub(crate) fn str_from_stringtable(
-    block: &osmformat::PrimitiveBlock,
-    index: usize,
-) -> Result<&str> {
-    if let Some(vec) = block.stringtable.s.get(index) {
-        std::str::from_utf8(vec)
+pub(crate) fn str_from_stringtable(block: &PrimitiveBlock, index: usize) -> Result<&str> {
+    str_from_bytes(block.string(index), index)
+}
+
+fn str_from_raw_stringtable(stringtable: &[Vec<u8>], index: usize) -> Result<&str> {
+    str_from_bytes(stringtable.get(index).map(Vec::as_slice), index)
+}
+
+fn str_from_bytes(bytes: Option<&[u8]>, index: usize) -> Result<&str> {
+    if let Some(bytes) = bytes {
+        std::str::from_utf8(bytes)
             .map_err(|e| new_error(ErrorKind::StringtableUtf8 { err: e, index }))
     } else {
         Err(new_error(ErrorKind::StringtableIndexOutOfBounds { index }))
     }
 }
 
+/// Returns the string at the given stringtable index with invalid UTF-8 sequences replaced, or
+/// [`REPLACEMENT_STR`] if the index is out of bounds.
+pub(crate) fn lossy_str_from_stringtable(block: &PrimitiveBlock, index: usize) -> Cow<'_, str> {
+    match block.string(index) {
+        Some(bytes) => String::from_utf8_lossy(bytes),
+        None => Cow::Borrowed(REPLACEMENT_STR),
+    }
+}
+
+/// Calls the given closure with the element and the stringtable indices of each tag in the block.
+fn for_each_tag_index<F>(block: &osmformat::PrimitiveBlock, mut f: F) -> Result<()>
+where
+    F: FnMut(ElementType, i64, usize) -> Result<()>,
+{
+    for group in &block.primitivegroup {
+        for node in &group.nodes {
+            for &index in node.keys.iter().chain(node.vals.iter()) {
+                f(ElementType::Node, node.id(), index as usize)?;
+            }
+        }
+        let dense = group.dense.get_or_default();
+        let mut ids = dense.id.iter().scan(0_i64, |id, delta| {
+            *id += delta;
+            Some(*id)
+        });
+        let mut id = ids.next();
+        for &index in &dense.keys_vals {
+            // A zero delimits the tags of consecutive nodes
+            if index == 0 {
+                id = ids.next();
+            } else {
+                f(ElementType::Node, id.unwrap_or(0), index as usize)?;
+            }
+        }
+        for way in &group.ways {
+            for &index in way.keys.iter().chain(way.vals.iter()) {
+                f(ElementType::Way, way.id(), index as usize)?;
+            }
+        }
+        for relation in &group.relations {
+            for &index in relation.keys.iter().chain(relation.vals.iter()) {
+                f(ElementType::Relation, relation.id(), index as usize)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error for the first tag that cannot be decoded.
+fn check_tags(block: &osmformat::PrimitiveBlock) -> Result<()> {
+    let mut valid = vec![None; block.stringtable.s.len()];
+    for_each_tag_index(block, |element_type, id, index| {
+        let result = match valid.get_mut(index) {
+            Some(Some(true)) => return Ok(()),
+            Some(checked) => {
+                let result = str_from_raw_stringtable(&block.stringtable.s, index).map(|_| ());
+                *checked = Some(result.is_ok());
+                result
+            }
+            None => str_from_raw_stringtable(&block.stringtable.s, index).map(|_| ()),
+        };
+        result.map_err(|e| e.with_element(element_type, id))
+    })
+}
+
+/// Replaces invalid UTF-8 sequences in the stringtable and redirects out-of-bounds tag indices to
+/// an added [`REPLACEMENT_STR`] entry.
+fn repair_tags(block: &mut osmformat::PrimitiveBlock) {
+    for s in &mut block.stringtable.mut_or_insert_default().s {
+        if let Cow::Owned(repaired) = String::from_utf8_lossy(s) {
+            *s = repaired.into_bytes();
+        }
+    }
+
+    let len = block.stringtable.s.len();
+    let mut out_of_bounds = false;
+    let _ = for_each_tag_index(block, |_, _, index| {
+        out_of_bounds |= index >= len;
+        Ok(())
+    });
+    if !out_of_bounds {
+        return;
+    }
+
+    block
+        .stringtable
+        .mut_or_insert_default()
+        .s
+        .push(REPLACEMENT_STR.as_bytes().to_vec());
+    let replacement = len as u32;
+    for group in &mut block.primitivegroup {
+        for node in &mut group.nodes {
+            for index in node.keys.iter_mut().chain(node.vals.iter_mut()) {
+                if *index as usize >= len {
+                    *index = replacement;
+                }
+            }
+        }
+        if let Some(dense) = group.dense.as_mut() {
+            for index in &mut dense.keys_vals {
+                if *index != 0 && *index as usize >= len {
+                    *index = replacement as i32;
+                }
+            }
+        }
+        for way in &mut group.ways {
+            for index in way.keys.iter_mut().chain(way.vals.iter_mut()) {
+                if *index as usize >= len {
+                    *index = replacement;
+                }
+            }
+        }
+        for relation in &mut group.relations {
+            for index in relation.keys.iter_mut().chain(relation.vals.iter_mut()) {
+                if *index as usize >= len {
+                    *index = replacement;
+                }
+            }
+        }
+    }
+}
+
 /// Construct a key-value tuple from key/value indexes, using the stringtable from a block.
 pub(crate) fn get_stringtable_key_value(
-    block: &osmformat::PrimitiveBlock,
+    block: &PrimitiveBlock,
     key_index: Option<usize>,
     value_index: Option<usize>,
 ) -> Option<(&str, &str)> {
@@ -417,3 +996,165 @@ pub(crate) fn get_stringtable_key_value(
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A block with a way whose tags contain invalid UTF-8 and an out-of-bounds index.
+    fn broken_block() -> osmformat::PrimitiveBlock {
+        let mut block = osmformat::PrimitiveBlock::new();
+        block.stringtable.mut_or_insert_default().s = vec![
+            b"".to_vec(),
+            b"name".to_vec(),
+            b"a\xffb".to_vec(),
+            b"highway".to_vec(),
+        ];
+        let mut way = osmformat::Way::new();
+        way.set_id(7);
+        way.keys = vec![3, 1, 3];
+        way.vals = vec![1, 2, 42];
+        let mut group = osmformat::PrimitiveGroup::new();
+        group.ways.push(way);
+        block.primitivegroup.push(group);
+        block
+    }
+
+    fn way_tags(block: &PrimitiveBlock) -> Vec<(String, String)> {
+        let group = block.groups().next().unwrap();
+        let way = group.ways().next().unwrap();
+        way.tags()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_tag_decoding() {
+        let block =
+            PrimitiveBlock::with_tag_decoding(broken_block(), TagDecoding::Unchecked).unwrap();
+        assert_eq!(way_tags(&block).len(), 1);
+
+        let group = block.groups().next().unwrap();
+        let way = group.ways().next().unwrap();
+        let checked: Vec<_> = way.checked_tags().collect();
+        assert_eq!(checked.len(), 3);
+        assert_eq!(checked[0].as_ref().unwrap(), &("highway", "name"));
+        assert!(matches!(
+            checked[1].as_ref().unwrap_err().kind(),
+            ErrorKind::StringtableUtf8 { index: 2, .. }
+        ));
+        assert!(matches!(
+            checked[2].as_ref().unwrap_err().kind(),
+            ErrorKind::StringtableIndexOutOfBounds { index: 42 }
+        ));
+        assert_eq!(
+            checked[2].as_ref().unwrap_err().element(),
+            Some((ElementType::Way, 7))
+        );
+        let lossy: Vec<_> = way.lossy_tags().collect();
+        assert_eq!(lossy[1], ("name".into(), "a\u{FFFD}b".into()));
+        assert_eq!(lossy[2], ("highway".into(), "\u{FFFD}".into()));
+
+        let err =
+            PrimitiveBlock::with_tag_decoding(broken_block(), TagDecoding::Strict).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::StringtableUtf8 { index: 2, .. }
+        ));
+        assert_eq!(err.element(), Some((ElementType::Way, 7)));
+
+        let block = PrimitiveBlock::with_tag_decoding(broken_block(), TagDecoding::Lossy).unwrap();
+        let expected: Vec<(String, String)> = lossy
+            .into_iter()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        assert_eq!(way_tags(&block), expected);
+    }
+
+    #[test]
+    fn test_tag_lookup() {
+        let block =
+            PrimitiveBlock::with_tag_decoding(broken_block(), TagDecoding::Unchecked).unwrap();
+        assert_eq!(block.string_id("highway"), Some(3));
+        assert_eq!(block.string_id("building"), None);
+        let group = block.groups().next().unwrap();
+        let way = group.ways().next().unwrap();
+        assert_eq!(way.tag("highway"), Some("name"));
+        // The value of "name" is not valid UTF-8, which is only detected when it is decoded
+        assert_eq!(way.tag("name"), None);
+        assert!(way.has_tag("name"));
+        assert!(way.has_tag_value("highway", "name"));
+        assert!(!way.has_tag_value("name", "highway"));
+        assert!(!way.has_tag("building"));
+
+        let highway = block.resolve_tag("highway", None).unwrap();
+        assert_eq!(way.resolved_tag(&highway), Some("name"));
+        assert!(way.has_resolved_tag(&block.resolve_tag("highway", Some("name")).unwrap()));
+        assert!(!way.has_resolved_tag(&block.resolve_tag("name", Some("highway")).unwrap()));
+        assert_eq!(block.resolve_tag("building", None), None);
+        assert_eq!(block.resolve_tag("highway", Some("residential")), None);
+
+        // The same string at several indices
+        let mut duplicates = broken_block();
+        duplicates.stringtable.mut_or_insert_default().s = vec![
+            b"".to_vec(),
+            b"name".to_vec(),
+            b"highway".to_vec(),
+            b"name".to_vec(),
+            b"highway".to_vec(),
+        ];
+        duplicates.primitivegroup[0].ways[0].keys = vec![4, 3];
+        duplicates.primitivegroup[0].ways[0].vals = vec![2, 1];
+        let block = PrimitiveBlock::with_tag_decoding(duplicates, TagDecoding::Unchecked).unwrap();
+        assert_eq!(block.string_id("name"), Some(1));
+        let group = block.groups().next().unwrap();
+        let way = group.ways().next().unwrap();
+        assert_eq!(way.tag("highway"), Some("highway"));
+        assert_eq!(way.tag("name"), Some("name"));
+        assert!(way.has_tag_value("name", "name"));
+        assert!(!way.has_tag_value("highway", "name"));
+        let name = block.resolve_tag("name", Some("name")).unwrap();
+        assert_eq!(
+            name,
+            ResolvedTag {
+                key: ResolvedStr::Ids(vec![1, 3]),
+                value: Some(ResolvedStr::Ids(vec![1, 3])),
+            }
+        );
+        assert!(way.has_resolved_tag(&name));
+    }
+
+    #[test]
+    fn test_lazy_decoding() {
+        let mut proto = broken_block();
+        proto.primitivegroup[0].ways[0].refs = vec![5, -2, 10];
+        let mut group = proto.primitivegroup[0].clone();
+        group.ways[0].refs = vec![7, 1];
+        proto.primitivegroup.push(group);
+        let bytes = proto.write_to_bytes().unwrap();
+        let eager = PrimitiveBlock::with_tag_decoding(proto, TagDecoding::Unchecked).unwrap();
+        let block = PrimitiveBlock::lazy(bytes.clone()).unwrap();
+        assert!(block.is_lazy());
+        assert_eq!(way_tags(&block), way_tags(&eager));
+        assert_eq!(block.string_id("highway"), Some(3));
+        let way = block.groups().next().unwrap().ways().next().unwrap();
+        assert_eq!(way.refs().collect::<Vec<_>>(), vec![5, 3, 13]);
+        assert_eq!(way.raw_refs(), &[5, -2, 10]);
+        let way = block.groups().nth(1).unwrap().ways().next().unwrap();
+        assert_eq!(way.raw_refs(), &[7, 1]);
+        assert_eq!(block.raw_stringtable(), eager.raw_stringtable());
+
+        // Unpacked repeated fields are decoded eagerly
+        let mut unpacked = vec![0x0a, 0x11, 0x0a, 0x00, 0x0a, 0x07];
+        unpacked.extend_from_slice(b"highway");
+        unpacked.extend_from_slice(&[0x0a, 0x04]);
+        unpacked.extend_from_slice(b"name");
+        unpacked.extend_from_slice(&[0x12, 0x08, 0x1a, 0x06, 0x08, 0x07, 0x10, 0x01, 0x18, 0x02]);
+        let block = PrimitiveBlock::lazy(unpacked).unwrap();
+        assert!(!block.is_lazy());
+        assert_eq!(way_tags(&block), vec![("highway".into(), "name".into())]);
+
+        // A truncated varint in the granularity field
+        assert!(PrimitiveBlock::lazy(vec![0x0a, 0x00, 0x88, 0x01, 0x80]).is_err());
+    }
+}
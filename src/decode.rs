@@ -0,0 +1,358 @@
+//! Per-thread state for decompressing blobs
+//!
+//! Every thread that decodes blobs keeps a zlib decompressor, an output buffer for messages that
+//! are parsed right away and a few spare buffers for lazily decoded blocks. The buffers keep their
+//! capacity between blobs, so decoding a file does not allocate a new buffer for every blob.
+//! Buffers that grew beyond [`MAX_RETAINED_CAPACITY`] are freed after use, and
+//! [`release_decode_buffers`] frees all buffers of the current thread.
+
+use crate::blob::{BlobData, BlobRef};
+use crate::error::{new_blob_error, new_protobuf_error, BlobError, Result};
+use flate2::{Decompress, FlushDecompress, Status};
+use std::cell::RefCell;
+
+/// The number of spare buffers that are kept per thread.
+const MAX_SPARE_BUFFERS: usize = 4;
+
+/// The maximum capacity of a buffer that is kept for the next blob. Regular blobs are much
+/// smaller, so only the buffers of unusually large blobs are freed.
+const MAX_RETAINED_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// The size of the first allocation if the blob does not specify its decompressed size.
+const MIN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Reusable state for decompressing blobs.
+pub(crate) struct DecodeContext {
+    decompress: Decompress,
+    buffer: Vec<u8>,
+    spare: Vec<Vec<u8>>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<DecodeContext> = RefCell::new(DecodeContext::new());
+}
+
+impl DecodeContext {
+    fn new() -> DecodeContext {
+        DecodeContext {
+            decompress: Decompress::new(true),
+            buffer: vec![],
+            spare: vec![],
+        }
+    }
+
+    /// Calls the closure with the context of the current thread.
+    pub(crate) fn with<T>(f: impl FnOnce(&mut DecodeContext) -> T) -> T {
+        CONTEXT.with(|context| match context.try_borrow_mut() {
+            Ok(mut context) => {
+                let result = f(&mut context);
+                if context.buffer.capacity() > MAX_RETAINED_CAPACITY {
+                    context.buffer = vec![];
+                }
+                result
+            }
+            // Only happens if the closure decodes another blob
+            Err(_) => f(&mut DecodeContext::new()),
+        })
+    }
+
+    /// Returns the decompressed content of a blob that is smaller than `max_size` bytes. The
+    /// result is only valid until the next call.
+    pub(crate) fn decompress<'s>(
+        &'s mut self,
+        blob: &BlobRef<'s>,
+        max_size: u64,
+    ) -> Result<&'s [u8]> {
+        match blob.data {
+            Some(BlobData::Raw(data)) => check_size(data.len(), max_size).map(|_| data),
+            _ => {
+                let buffer = &mut self.buffer;
+                decompress_into(&mut self.decompress, blob, max_size, buffer)?;
+                Ok(&self.buffer)
+            }
+        }
+    }
+
+    /// Returns the decompressed content of a blob in a buffer that may have been used before.
+    /// Return it with [`recycle`] when it is no longer needed.
+    pub(crate) fn decompress_owned(&mut self, blob: &BlobRef, max_size: u64) -> Result<Vec<u8>> {
+        let mut buffer = self.spare.pop().unwrap_or_default();
+        decompress_into(&mut self.decompress, blob, max_size, &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Replaces the content of the buffer with the decompressed content of a blob that is smaller
+/// than `max_size` bytes.
+fn decompress_into(
+    decompress: &mut Decompress,
+    blob: &BlobRef,
+    max_size: u64,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    match blob.data {
+        Some(BlobData::Raw(data)) => {
+            check_size(data.len(), max_size)?;
+            buffer.clear();
+            buffer.extend_from_slice(data);
+            Ok(())
+        }
+        Some(BlobData::Zlib(data)) => inflate(decompress, data, blob.raw_size, max_size, buffer),
+        #[cfg(feature = "zstd")]
+        Some(BlobData::Zstd(data)) => zstd_decompress(data, blob.raw_size, max_size, buffer),
+        #[cfg(feature = "lz4")]
+        Some(BlobData::Lz4(data)) => lz4_decompress(data, blob.raw_size, max_size, buffer),
+        Some(BlobData::Unsupported(compression)) => {
+            Err(new_blob_error(BlobError::UnsupportedCompression {
+                compression,
+            }))
+        }
+        None => Err(new_blob_error(BlobError::Empty)),
+    }
+}
+
+/// Keeps a buffer from [`DecodeContext::decompress_owned`] for the next blob that is decoded on
+/// the current thread.
+pub(crate) fn recycle(buffer: Vec<u8>) {
+    if buffer.capacity() == 0 || buffer.capacity() > MAX_RETAINED_CAPACITY {
+        return;
+    }
+    // Ignore buffers that are dropped while the thread exits
+    let _ = CONTEXT.try_with(|context| {
+        if let Ok(mut context) = context.try_borrow_mut() {
+            if context.spare.len() < MAX_SPARE_BUFFERS {
+                context.spare.push(buffer);
+            }
+        }
+    });
+}
+
+/// Frees the buffers that the current thread keeps for decompressing the next blobs.
+///
+/// Every thread that decodes blobs keeps a few buffers of up to 16 MiB each, which are freed
+/// when the thread exits. Call this function to free them earlier, for example on a long-lived
+/// thread that is done with reading PBF files. The buffers of other threads, like the workers of
+/// a rayon thread pool, are not affected.
+pub fn release_decode_buffers() {
+    let _ = CONTEXT.try_with(|context| {
+        if let Ok(mut context) = context.try_borrow_mut() {
+            context.buffer = vec![];
+            context.spare = vec![];
+        }
+    });
+}
+
+/// Returns the initial capacity of the output buffer.
+fn expected_size(raw_size: Option<i32>, limit: usize) -> usize {
+    raw_size
+        .and_then(|size| usize::try_from(size).ok())
+        .unwrap_or(MIN_BUFFER_SIZE)
+        .min(limit)
+}
+
+fn check_size(size: usize, max_size: u64) -> Result<()> {
+    let size = size as u64;
+    if size < max_size {
+        Ok(())
+    } else {
+        Err(new_blob_error(BlobError::MessageTooBig { size }))
+    }
+}
+
+/// Decompresses zlib data into the buffer, which must stay smaller than `max_size` bytes. The
+/// buffer is sized with the decompressed size from the blob, if available.
+fn inflate(
+    decompress: &mut Decompress,
+    data: &[u8],
+    raw_size: Option<i32>,
+    max_size: u64,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    let limit = usize::try_from(max_size).unwrap_or(usize::MAX);
+    let expected = expected_size(raw_size, limit);
+    decompress.reset(true);
+    buffer.clear();
+    buffer.reserve(expected);
+
+    let zlib_error = |e: std::io::Error| new_protobuf_error(e.into(), "blob zlib data");
+    let too_big = |size: usize| new_blob_error(BlobError::MessageTooBig { size: size as u64 });
+    loop {
+        if buffer.len() >= limit {
+            return Err(too_big(buffer.len()));
+        }
+        if buffer.len() == buffer.capacity() {
+            let additional = buffer.capacity().max(MIN_BUFFER_SIZE);
+            buffer.reserve(additional.min(limit - buffer.len()));
+        }
+        let consumed = decompress.total_in() as usize;
+        let written = buffer.len();
+        let status = decompress
+            .decompress_vec(&data[consumed..], buffer, FlushDecompress::None)
+            .map_err(|e| zlib_error(e.into()))?;
+        match status {
+            // The buffer may have grown beyond the limit
+            Status::StreamEnd if buffer.len() >= limit => return Err(too_big(buffer.len())),
+            Status::StreamEnd => return Ok(()),
+            Status::Ok | Status::BufError => {
+                let progress =
+                    decompress.total_in() as usize != consumed || buffer.len() != written;
+                if !progress && buffer.len() < buffer.capacity() {
+                    return Err(zlib_error(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "unexpected end of zlib stream",
+                    )));
+                }
+            }
+        }
+    }
+}
+
+/// Decompresses Zstandard data into the buffer, which must stay smaller than `max_size` bytes.
+#[cfg(feature = "zstd")]
+fn zstd_decompress(
+    data: &[u8],
+    raw_size: Option<i32>,
+    max_size: u64,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    use std::io::Read;
+
+    let limit = usize::try_from(max_size).unwrap_or(usize::MAX);
+    buffer.clear();
+    buffer.reserve(expected_size(raw_size, limit));
+
+    let zstd_error = |e: std::io::Error| new_protobuf_error(e.into(), "blob zstd data");
+    zstd::stream::read::Decoder::with_buffer(data)
+        .and_then(|decoder| decoder.take(max_size).read_to_end(buffer))
+        .map_err(zstd_error)?;
+    check_size(buffer.len(), max_size)
+}
+
+/// Decompresses an LZ4 block into the buffer, which must stay smaller than `max_size` bytes. LZ4
+/// blocks do not store their decompressed size, so the blob has to provide it.
+#[cfg(feature = "lz4")]
+fn lz4_decompress(
+    data: &[u8],
+    raw_size: Option<i32>,
+    max_size: u64,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    let lz4_error = |message: String| {
+        let err = std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        new_protobuf_error(err.into(), "blob lz4 data")
+    };
+    let raw_size = raw_size
+        .and_then(|size| usize::try_from(size).ok())
+        .ok_or_else(|| lz4_error("missing decompressed size".to_string()))?;
+    check_size(raw_size, max_size)?;
+    buffer.clear();
+    buffer.resize(raw_size, 0);
+    let size =
+        lz4_flex::block::decompress_into(data, buffer).map_err(|e| lz4_error(e.to_string()))?;
+    buffer.truncate(size);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::MAX_BLOB_MESSAGE_SIZE;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data: Vec<u8> = (0..200_000_u32).map(|i| (i % 251) as u8).collect();
+        let compressed = zlib(&data);
+        let mut context = DecodeContext::new();
+
+        // Correct, missing and wrong decompressed sizes
+        for raw_size in [Some(data.len() as i32), None, Some(10), Some(-1)] {
+            let blob = BlobRef {
+                raw_size,
+                data: Some(BlobData::Zlib(&compressed)),
+            };
+            assert_eq!(
+                context.decompress(&blob, MAX_BLOB_MESSAGE_SIZE).unwrap(),
+                data.as_slice()
+            );
+            assert_eq!(
+                context
+                    .decompress_owned(&blob, MAX_BLOB_MESSAGE_SIZE)
+                    .unwrap(),
+                data
+            );
+        }
+        // The buffer is reused
+        assert!(context.buffer.capacity() >= data.len());
+
+        let blob = BlobRef {
+            raw_size: None,
+            data: Some(BlobData::Raw(&data)),
+        };
+        assert_eq!(
+            context.decompress(&blob, MAX_BLOB_MESSAGE_SIZE).unwrap(),
+            data.as_slice()
+        );
+
+        let truncated = BlobRef {
+            raw_size: None,
+            data: Some(BlobData::Zlib(&compressed[..compressed.len() / 2])),
+        };
+        assert!(context
+            .decompress(&truncated, MAX_BLOB_MESSAGE_SIZE)
+            .is_err());
+        let invalid = BlobRef {
+            raw_size: None,
+            data: Some(BlobData::Zlib(&[1, 2, 3])),
+        };
+        assert!(context.decompress(&invalid, MAX_BLOB_MESSAGE_SIZE).is_err());
+
+        // Blobs that exceed the size limit
+        let blob = BlobRef {
+            raw_size: None,
+            data: Some(BlobData::Zlib(&compressed)),
+        };
+        assert!(context.decompress(&blob, data.len() as u64 / 2).is_err());
+        assert!(context.decompress_owned(&blob, 1000).is_err());
+    }
+
+    #[test]
+    fn test_recycle() {
+        let buffer = DecodeContext::with(|context| {
+            let blob = BlobRef {
+                raw_size: None,
+                data: Some(BlobData::Raw(&[1, 2, 3])),
+            };
+            context
+                .decompress_owned(&blob, MAX_BLOB_MESSAGE_SIZE)
+                .unwrap()
+        });
+        let capacity = buffer.capacity();
+        let pointer = buffer.as_ptr();
+        recycle(buffer);
+        let reused = DecodeContext::with(|context| context.spare.pop().unwrap());
+        assert_eq!((reused.as_ptr(), reused.capacity()), (pointer, capacity));
+
+        // Large buffers are not kept
+        recycle(Vec::with_capacity(MAX_RETAINED_CAPACITY + 1));
+        assert!(DecodeContext::with(|context| context.spare.is_empty()));
+        DecodeContext::with(|context| context.buffer.reserve(MAX_RETAINED_CAPACITY + 1));
+        assert_eq!(DecodeContext::with(|context| context.buffer.capacity()), 0);
+
+        recycle(reused);
+        DecodeContext::with(|context| context.buffer.reserve(1000));
+        release_decode_buffers();
+        DecodeContext::with(|context| {
+            assert!(context.spare.is_empty());
+            assert_eq!(context.buffer.capacity(), 0);
+        });
+    }
+}
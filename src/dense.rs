@@ -1,23 +1,28 @@
 //! Iterate over the dense nodes in a `PrimitiveGroup`
 
-use crate::block::{get_stringtable_key_value, str_from_stringtable};
+use crate::block::{
+    get_stringtable_key_value, lossy_str_from_stringtable, str_from_stringtable, PrimitiveBlock,
+    ResolvedTag,
+};
+use crate::elements::ElementType;
 use crate::error::Result;
-use crate::proto::osmformat;
+use crate::lazy::{DenseInfoView, DenseView, Packed, PackedIter};
 use std;
+use std::borrow::Cow;
 
 //TODO Add getter functions for id, version, uid, ...
 /// An OpenStreetMap node element from a compressed array of dense nodes (See [OSM wiki](http://wiki.openstreetmap.org/wiki/Node)).
 #[derive(Clone, Debug)]
 pub struct DenseNode<'a> {
-    block: &'a osmformat::PrimitiveBlock,
+    block: &'a PrimitiveBlock,
 
     /// The node id. It should be unique between nodes and might be negative to indicate
     /// that the element has not yet been uploaded to a server.
     pub id: i64,
     lat: i64,
     lon: i64,
-    keys_vals_indices: &'a [i32],
-    info: Option<DenseNodeInfo<'a>>,
+    keys_vals_indices: Packed<'a, i32>,
+    pub(crate) info: Option<DenseNodeInfo<'a>>,
 }
 
 impl<'a> DenseNode<'a> {
@@ -70,6 +75,25 @@ impl<'a> DenseNode<'a> {
         }
     }
 
+    /// Returns an iterator over the tags of this node that yields an error for each tag that
+    /// cannot be decoded instead of ending the iteration.
+    pub fn checked_tags(&self) -> DenseCheckedTagIter<'a> {
+        DenseCheckedTagIter {
+            block: self.block,
+            id: self.id,
+            keys_vals_indices: self.keys_vals_indices.iter(),
+        }
+    }
+
+    /// Returns an iterator over the tags of this node that replaces invalid UTF-8 sequences with
+    /// `U+FFFD REPLACEMENT CHARACTER` and missing strings with a single replacement character.
+    pub fn lossy_tags(&self) -> DenseLossyTagIter<'a> {
+        DenseLossyTagIter {
+            block: self.block,
+            keys_vals_indices: self.keys_vals_indices.iter(),
+        }
+    }
+
     /// Returns an iterator over the tags of this node
     /// (See [OSM wiki](http://wiki.openstreetmap.org/wiki/Tags)).
     /// A tag is represented as a pair of indices (key and value) to the stringtable of the current
@@ -79,32 +103,65 @@ impl<'a> DenseNode<'a> {
             keys_vals_indices: self.keys_vals_indices.iter(),
         }
     }
+
+    /// Returns the value of the first tag with the given key. Only the value of that tag is
+    /// decoded, but the key is resolved against the stringtable on every call, see
+    /// [`resolved_tag`](Self::resolved_tag) to resolve it once per block.
+    ///
+    /// Returns `None` if the value is not valid UTF-8, which can only happen with
+    /// [`TagDecoding::Unchecked`](crate::block::TagDecoding::Unchecked). Use
+    /// [`has_tag`](Self::has_tag) to check if the tag exists regardless of its value.
+    pub fn tag(&self, key: &str) -> Option<&'a str> {
+        self.resolved_tag(&self.block.resolve_tag(key, None)?)
+    }
+
+    /// Returns true if this node has a tag with the given key.
+    pub fn has_tag(&self, key: &str) -> bool {
+        self.block
+            .resolve_tag(key, None)
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns true if this node has a tag with the given key and value.
+    pub fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        self.block
+            .resolve_tag(key, Some(value))
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns the value of the first tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this node, or `None` like
+    /// [`tag`](Self::tag).
+    pub fn resolved_tag(&self, tag: &ResolvedTag) -> Option<&'a str> {
+        let index = tag.find(self.raw_tags().map(|(k, v)| (k as u32, v as u32)))?;
+        str_from_stringtable(self.block, index as usize).ok()
+    }
+
+    /// Returns true if this node has a tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this node.
+    pub fn has_resolved_tag(&self, tag: &ResolvedTag) -> bool {
+        tag.find(self.raw_tags().map(|(k, v)| (k as u32, v as u32)))
+            .is_some()
+    }
 }
 
 /// An iterator over dense nodes. It decodes the delta encoded values.
 #[derive(Clone, Debug)]
 pub struct DenseNodeIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    dids: std::slice::Iter<'a, i64>,  // deltas
-    cid: i64,                         // current id
-    dlats: std::slice::Iter<'a, i64>, // deltas
+    block: &'a PrimitiveBlock,
+    dids: PackedIter<'a, i64>,  // deltas
+    cid: i64,                   // current id
+    dlats: PackedIter<'a, i64>, // deltas
     clat: i64,
-    dlons: std::slice::Iter<'a, i64>, // deltas
+    dlons: PackedIter<'a, i64>, // deltas
     clon: i64,
-    keys_vals_slice: &'a [i32],
-    keys_vals_index: usize,
+    keys_vals: PackedIter<'a, i32>,
     info_iter: Option<DenseNodeInfoIter<'a>>,
 }
 
 impl<'a> DenseNodeIter<'a> {
-    pub(crate) fn new(
-        block: &'a osmformat::PrimitiveBlock,
-        osmdense: &'a osmformat::DenseNodes,
-    ) -> DenseNodeIter<'a> {
-        let info_iter = Some(DenseNodeInfoIter::new(
-            block,
-            osmdense.denseinfo.get_or_default(),
-        ));
+    pub(crate) fn new(block: &'a PrimitiveBlock, osmdense: DenseView<'a>) -> DenseNodeIter<'a> {
+        let info_iter = Some(DenseNodeInfoIter::new(block, osmdense.info));
         DenseNodeIter {
             block,
             dids: osmdense.id.iter(),
@@ -113,24 +170,15 @@ impl<'a> DenseNodeIter<'a> {
             clat: 0,
             dlons: osmdense.lon.iter(),
             clon: 0,
-            keys_vals_slice: osmdense.keys_vals.as_slice(),
-            keys_vals_index: 0,
+            keys_vals: osmdense.keys_vals.iter(),
             info_iter,
         }
     }
 
-    pub(crate) fn empty(block: &'a osmformat::PrimitiveBlock) -> DenseNodeIter<'a> {
+    pub(crate) fn empty(block: &'a PrimitiveBlock) -> DenseNodeIter<'a> {
         DenseNodeIter {
-            block,
-            dids: [].iter(),
-            cid: 0,
-            dlats: [].iter(),
-            clat: 0,
-            dlons: [].iter(),
-            clon: 0,
-            keys_vals_slice: &[],
-            keys_vals_index: 0,
             info_iter: None,
+            ..DenseNodeIter::new(block, DenseView::default())
         }
     }
 }
@@ -146,28 +194,20 @@ impl<'a> Iterator for DenseNodeIter<'a> {
             self.info_iter.as_mut().and_then(|iter| iter.next()),
         ) {
             (Some(did), Some(dlat), Some(dlon), info) => {
-                self.cid += *did;
-                self.clat += *dlat;
-                self.clon += *dlon;
-
-                let start_index = self.keys_vals_index;
-                let mut end_index = start_index;
-                for chunk in self.keys_vals_slice[self.keys_vals_index..].chunks(2) {
-                    if chunk[0] != 0 && chunk.len() == 2 {
-                        end_index += 2;
-                        self.keys_vals_index += 2;
-                    } else {
-                        self.keys_vals_index += 1;
-                        break;
-                    }
-                }
+                self.cid += did;
+                let info = info.map(|info| DenseNodeInfo {
+                    id: self.cid,
+                    ..info
+                });
+                self.clat += dlat;
+                self.clon += dlon;
 
                 Some(DenseNode {
                     block: self.block,
                     id: self.cid,
                     lat: self.clat,
                     lon: self.clon,
-                    keys_vals_indices: &self.keys_vals_slice[start_index..end_index],
+                    keys_vals_indices: self.keys_vals.next_dense_tags(),
                     info,
                 })
             }
@@ -185,7 +225,9 @@ impl<'a> ExactSizeIterator for DenseNodeIter<'a> {}
 /// Optional metadata with non-geographic information about a dense node
 #[derive(Clone, Debug)]
 pub struct DenseNodeInfo<'a> {
-    block: &'a osmformat::PrimitiveBlock,
+    block: &'a PrimitiveBlock,
+    /// The node id, only used for errors.
+    id: i64,
     /// The version of this element.
     version: i32,
     /// Timestamp
@@ -219,6 +261,7 @@ impl<'a> DenseNodeInfo<'a> {
     /// Returns the user name.
     pub fn user(&self) -> Result<&'a str> {
         str_from_stringtable(self.block, self.user_sid as usize)
+            .map_err(|e| e.with_element(ElementType::Node, self.id))
     }
 
     /// Returns the time stamp in milliseconds since the epoch.
@@ -242,24 +285,21 @@ impl<'a> DenseNodeInfo<'a> {
 /// An iterator over dense nodes info. It decodes the delta encoded values.
 #[derive(Clone, Debug)]
 pub struct DenseNodeInfoIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    versions: std::slice::Iter<'a, i32>,
-    dtimestamps: std::slice::Iter<'a, i64>, // deltas
+    block: &'a PrimitiveBlock,
+    versions: PackedIter<'a, i32>,
+    dtimestamps: PackedIter<'a, i64>, // deltas
     ctimestamp: i64,
-    dchangesets: std::slice::Iter<'a, i64>, // deltas
+    dchangesets: PackedIter<'a, i64>, // deltas
     cchangeset: i64,
-    duids: std::slice::Iter<'a, i32>, // deltas
+    duids: PackedIter<'a, i32>, // deltas
     cuid: i32,
-    duser_sids: std::slice::Iter<'a, i32>, // deltas
+    duser_sids: PackedIter<'a, i32>, // deltas
     cuser_sid: i32,
-    visible: std::slice::Iter<'a, bool>,
+    visible: PackedIter<'a, bool>,
 }
 
 impl<'a> DenseNodeInfoIter<'a> {
-    fn new(
-        block: &'a osmformat::PrimitiveBlock,
-        info: &'a osmformat::DenseInfo,
-    ) -> DenseNodeInfoIter<'a> {
+    fn new(block: &'a PrimitiveBlock, info: DenseInfoView<'a>) -> DenseNodeInfoIter<'a> {
         DenseNodeInfoIter {
             block,
             versions: info.version.iter(),
@@ -289,25 +329,26 @@ impl<'a> Iterator for DenseNodeInfoIter<'a> {
             self.visible.next(),
         ) {
             (
-                Some(&version),
+                Some(version),
                 Some(dtimestamp),
                 Some(dchangeset),
                 Some(duid),
                 Some(duser_sid),
                 visible_opt,
             ) => {
-                self.ctimestamp += *dtimestamp;
-                self.cchangeset += *dchangeset;
-                self.cuid += *duid;
-                self.cuser_sid += *duser_sid;
+                self.ctimestamp += dtimestamp;
+                self.cchangeset += dchangeset;
+                self.cuid += duid;
+                self.cuser_sid += duser_sid;
                 Some(DenseNodeInfo {
                     block: self.block,
+                    id: 0,
                     version,
                     timestamp: self.ctimestamp,
                     changeset: self.cchangeset,
                     uid: self.cuid,
                     user_sid: self.cuser_sid,
-                    visible: *visible_opt.unwrap_or(&true),
+                    visible: visible_opt.unwrap_or(true),
                 })
             }
             _ => None,
@@ -316,21 +357,23 @@ impl<'a> Iterator for DenseNodeInfoIter<'a> {
 }
 
 /// An iterator over the tags in a dense node.
+///
+/// The iteration ends at the first tag that cannot be decoded. Use [`DenseCheckedTagIter`] or
+/// [`DenseLossyTagIter`] to handle such tags.
 #[derive(Clone, Debug)]
 pub struct DenseTagIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    keys_vals_indices: std::slice::Iter<'a, i32>,
+    block: &'a PrimitiveBlock,
+    keys_vals_indices: PackedIter<'a, i32>,
 }
 
-//TODO return Result
 impl<'a> Iterator for DenseTagIter<'a> {
     type Item = (&'a str, &'a str);
 
     fn next(&mut self) -> Option<Self::Item> {
         get_stringtable_key_value(
             self.block,
-            self.keys_vals_indices.next().map(|v| *v as usize),
-            self.keys_vals_indices.next().map(|v| *v as usize),
+            self.keys_vals_indices.next().map(|v| v as usize),
+            self.keys_vals_indices.next().map(|v| v as usize),
         )
     }
 
@@ -342,11 +385,73 @@ impl<'a> Iterator for DenseTagIter<'a> {
 
 impl<'a> ExactSizeIterator for DenseTagIter<'a> {}
 
+/// An iterator over the tags in a dense node. It returns a pair of strings (key and value) or an
+/// error if a string cannot be decoded.
+#[derive(Clone, Debug)]
+pub struct DenseCheckedTagIter<'a> {
+    block: &'a PrimitiveBlock,
+    id: i64,
+    keys_vals_indices: PackedIter<'a, i32>,
+}
+
+impl<'a> Iterator for DenseCheckedTagIter<'a> {
+    type Item = Result<(&'a str, &'a str)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.keys_vals_indices.next(), self.keys_vals_indices.next()) {
+            (Some(key_index), Some(val_index)) => Some(
+                str_from_stringtable(self.block, key_index as usize)
+                    .and_then(|k| {
+                        str_from_stringtable(self.block, val_index as usize).map(|v| (k, v))
+                    })
+                    .map_err(|e| e.with_element(ElementType::Node, self.id)),
+            ),
+            _ => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.keys_vals_indices.len() / 2;
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for DenseCheckedTagIter<'a> {}
+
+/// An iterator over the tags in a dense node. It returns a pair of strings (key and value) with
+/// invalid UTF-8 sequences replaced by `U+FFFD REPLACEMENT CHARACTER`.
+#[derive(Clone, Debug)]
+pub struct DenseLossyTagIter<'a> {
+    block: &'a PrimitiveBlock,
+    keys_vals_indices: PackedIter<'a, i32>,
+}
+
+impl<'a> Iterator for DenseLossyTagIter<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.keys_vals_indices.next(), self.keys_vals_indices.next()) {
+            (Some(key_index), Some(val_index)) => Some((
+                lossy_str_from_stringtable(self.block, key_index as usize),
+                lossy_str_from_stringtable(self.block, val_index as usize),
+            )),
+            _ => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.keys_vals_indices.len() / 2;
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for DenseLossyTagIter<'a> {}
+
 /// An iterator over the tags of a node. It returns a pair of indices (key and value) to the
 /// stringtable of the current [`PrimitiveBlock`](crate::block::PrimitiveBlock).
 #[derive(Clone, Debug)]
 pub struct DenseRawTagIter<'a> {
-    keys_vals_indices: std::slice::Iter<'a, i32>,
+    keys_vals_indices: PackedIter<'a, i32>,
 }
 
 //TODO return Result
@@ -355,7 +460,7 @@ impl<'a> Iterator for DenseRawTagIter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match (self.keys_vals_indices.next(), self.keys_vals_indices.next()) {
-            (Some(&key_index), Some(&val_index)) => Some((key_index, val_index)),
+            (Some(key_index), Some(val_index)) => Some((key_index, val_index)),
             _ => None,
         }
     }
@@ -367,3 +472,195 @@ impl<'a> Iterator for DenseRawTagIter<'a> {
 }
 
 impl<'a> ExactSizeIterator for DenseRawTagIter<'a> {}
+
+/// The dense nodes of a [`PrimitiveGroup`](crate::block::PrimitiveGroup) or
+/// [`PrimitiveBlock`](crate::block::PrimitiveBlock) decoded into contiguous columns.
+///
+/// All values are delta decoded and coordinates and timestamps are already scaled with the
+/// granularity and offsets of the block. The metadata columns (`versions`, `milli_timestamps`,
+/// `changesets`, `uids`, `user_sids` and `visible`) either have one value per node or are empty
+/// if not every node has metadata.
+///
+/// # Example
+/// ```
+/// use osmpbf::{BlobDecode, BlobReader};
+///
+/// # fn foo() -> osmpbf::Result<()> {
+/// let reader = BlobReader::from_path("tests/test.osm.pbf")?;
+///
+/// for blob in reader {
+///     if let BlobDecode::OsmData(block) = blob?.decode()? {
+///         let columns = block.dense_columns();
+///         let max_lat = columns.nano_lats.iter().max();
+///         println!("{} dense nodes, max. latitude {:?}", columns.len(), max_lat);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DenseColumns {
+    /// The node ids.
+    pub ids: Vec<i64>,
+    /// The latitudes in nanodegrees (10⁻⁹).
+    pub nano_lats: Vec<i64>,
+    /// The longitudes in nanodegrees (10⁻⁹).
+    pub nano_lons: Vec<i64>,
+    /// The versions of the nodes.
+    pub versions: Vec<i32>,
+    /// The time stamps in milliseconds since the epoch.
+    pub milli_timestamps: Vec<i64>,
+    /// The changeset ids.
+    pub changesets: Vec<i64>,
+    /// The user ids.
+    pub uids: Vec<i32>,
+    /// The stringtable indices of the user names.
+    pub user_sids: Vec<i32>,
+    /// The visibility of the nodes. Nodes are visible if the block does not contain visibility
+    /// information.
+    pub visible: Vec<bool>,
+    /// The key and value stringtable indices of all tags without the delimiters between nodes.
+    pub keys_vals: Vec<i32>,
+    /// The offsets of the tags of each node in `keys_vals`. There is one more offset than there
+    /// are nodes, so the tags of the node at `index` are
+    /// `keys_vals[tag_offsets[index]..tag_offsets[index + 1]]`.
+    pub tag_offsets: Vec<usize>,
+}
+
+impl Default for DenseColumns {
+    fn default() -> Self {
+        DenseColumns::new()
+    }
+}
+
+impl DenseColumns {
+    /// Creates empty columns.
+    pub fn new() -> DenseColumns {
+        DenseColumns {
+            ids: vec![],
+            nano_lats: vec![],
+            nano_lons: vec![],
+            versions: vec![],
+            milli_timestamps: vec![],
+            changesets: vec![],
+            uids: vec![],
+            user_sids: vec![],
+            visible: vec![],
+            keys_vals: vec![],
+            tag_offsets: vec![0],
+        }
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns true if there are no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns true if the metadata columns contain a value for every node.
+    pub fn has_info(&self) -> bool {
+        !self.is_empty() && self.versions.len() == self.len()
+    }
+
+    /// Returns the alternating key and value stringtable indices of the node at the given index.
+    pub fn raw_tags(&self, index: usize) -> &[i32] {
+        match (self.tag_offsets.get(index), self.tag_offsets.get(index + 1)) {
+            (Some(&start), Some(&end)) => &self.keys_vals[start..end],
+            _ => &[],
+        }
+    }
+
+    /// Appends the dense nodes of a group.
+    pub(crate) fn extend(&mut self, block: &PrimitiveBlock, dense: DenseView) {
+        let start = self.len();
+        let len = dense
+            .id
+            .iter()
+            .len()
+            .min(dense.lat.iter().len())
+            .min(dense.lon.iter().len());
+        if len == 0 {
+            return;
+        }
+
+        let mut id = 0;
+        self.ids.extend(dense.id.iter().take(len).map(|delta| {
+            id += delta;
+            id
+        }));
+        let granularity = i64::from(block.granularity());
+        for (column, deltas, offset) in [
+            (&mut self.nano_lats, dense.lat, block.lat_offset()),
+            (&mut self.nano_lons, dense.lon, block.lon_offset()),
+        ] {
+            let mut value = 0;
+            column.extend(deltas.iter().take(len).map(|delta| {
+                value += delta;
+                offset + granularity * value
+            }));
+        }
+
+        let mut keys_vals = dense.keys_vals.iter();
+        for _ in 0..len {
+            self.keys_vals.extend(keys_vals.next_dense_tags().iter());
+            self.tag_offsets.push(self.keys_vals.len());
+        }
+
+        let info = dense.info;
+        let has_info = self.versions.len() == start
+            && [
+                info.version.iter().len(),
+                info.timestamp.iter().len(),
+                info.changeset.iter().len(),
+                info.uid.iter().len(),
+                info.user_sid.iter().len(),
+            ]
+            .iter()
+            .all(|&info_len| info_len >= len);
+        if !has_info {
+            self.clear_info();
+            return;
+        }
+        self.versions.extend(info.version.iter().take(len));
+        let date_granularity = i64::from(block.date_granularity());
+        let mut timestamp = 0;
+        self.milli_timestamps
+            .extend(info.timestamp.iter().take(len).map(|delta| {
+                timestamp += delta;
+                timestamp * date_granularity
+            }));
+        let mut changeset = 0;
+        self.changesets
+            .extend(info.changeset.iter().take(len).map(|delta| {
+                changeset += delta;
+                changeset
+            }));
+        let mut uid = 0;
+        self.uids.extend(info.uid.iter().take(len).map(|delta| {
+            uid += delta;
+            uid
+        }));
+        let mut user_sid = 0;
+        self.user_sids
+            .extend(info.user_sid.iter().take(len).map(|delta| {
+                user_sid += delta;
+                user_sid
+            }));
+        let visible = info.visible.iter().chain(std::iter::repeat(true));
+        self.visible.extend(visible.take(len));
+    }
+
+    fn clear_info(&mut self) {
+        self.versions.clear();
+        self.milli_timestamps.clear();
+        self.changesets.clear();
+        self.uids.clear();
+        self.user_sids.clear();
+        self.visible.clear();
+    }
+}
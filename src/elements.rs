@@ -1,12 +1,16 @@
 //! Nodes, ways and relations
 
-use crate::block::{get_stringtable_key_value, str_from_stringtable};
+use crate::block::{
+    get_stringtable_key_value, lossy_str_from_stringtable, str_from_stringtable, PrimitiveBlock,
+    ResolvedTag,
+};
 use crate::dense::DenseNode;
 use crate::error::Result;
+use crate::lazy::{InfoView, NodeView, Packed, PackedIter, RelationView, WayView};
 use crate::proto::osmformat;
-use crate::proto::osmformat::PrimitiveBlock;
 use osmformat::relation::MemberType;
 use protobuf::EnumOrUnknown;
+use std::borrow::Cow;
 
 /// An enum with the OSM core elements: nodes, ways and relations.
 #[derive(Clone, Debug)]
@@ -26,22 +30,41 @@ pub enum Element<'a> {
     Relation(Relation<'a>),
 }
 
+/// The type of an OSM element.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ElementType {
+    Node,
+    Way,
+    Relation,
+}
+
+impl ElementType {
+    /// Returns the lowercase name of the element type, for example `"node"`.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ElementType::Node => "node",
+            ElementType::Way => "way",
+            ElementType::Relation => "relation",
+        }
+    }
+}
+
 /// An OpenStreetMap node element (See [OSM wiki](http://wiki.openstreetmap.org/wiki/Node)).
 #[derive(Clone, Debug)]
 pub struct Node<'a> {
     block: &'a PrimitiveBlock,
-    osmnode: &'a osmformat::Node,
+    osmnode: NodeView<'a>,
 }
 
 impl<'a> Node<'a> {
-    pub(crate) fn new(block: &'a PrimitiveBlock, osmnode: &'a osmformat::Node) -> Node<'a> {
+    pub(crate) fn new(block: &'a PrimitiveBlock, osmnode: NodeView<'a>) -> Node<'a> {
         Node { block, osmnode }
     }
 
     /// Returns the node id. It should be unique between nodes and might be negative to indicate
     /// that the element has not yet been uploaded to a server.
     pub fn id(&self) -> i64 {
-        self.osmnode.id()
+        self.osmnode.id
     }
 
     /// Returns an iterator over the tags of this node
@@ -75,9 +98,34 @@ impl<'a> Node<'a> {
         }
     }
 
+    /// Returns an iterator over the tags of this node that yields an error for each tag that
+    /// cannot be decoded instead of ending the iteration.
+    pub fn checked_tags(&self) -> CheckedTagIter<'a> {
+        CheckedTagIter {
+            block: self.block,
+            element: (ElementType::Node, self.id()),
+            key_indices: self.osmnode.keys.iter(),
+            val_indices: self.osmnode.vals.iter(),
+        }
+    }
+
+    /// Returns an iterator over the tags of this node that replaces invalid UTF-8 sequences with
+    /// `U+FFFD REPLACEMENT CHARACTER` and missing strings with a single replacement character.
+    pub fn lossy_tags(&self) -> LossyTagIter<'a> {
+        LossyTagIter {
+            block: self.block,
+            key_indices: self.osmnode.keys.iter(),
+            val_indices: self.osmnode.vals.iter(),
+        }
+    }
+
     /// Returns additional metadata for this element.
     pub fn info(&self) -> Info<'a> {
-        Info::new(self.block, self.osmnode.info.get_or_default())
+        Info::new(
+            self.block,
+            self.osmnode.info,
+            (ElementType::Node, self.id()),
+        )
     }
 
     /// Returns the latitude coordinate in degrees.
@@ -87,7 +135,7 @@ impl<'a> Node<'a> {
 
     /// Returns the latitude coordinate in nanodegrees (10⁻⁹).
     pub fn nano_lat(&self) -> i64 {
-        self.block.lat_offset() + i64::from(self.block.granularity()) * self.osmnode.lat()
+        self.block.lat_offset() + i64::from(self.block.granularity()) * self.osmnode.lat
     }
 
     /// Returns the latitude coordinate in decimicrodegrees (10⁻⁷).
@@ -102,7 +150,7 @@ impl<'a> Node<'a> {
 
     /// Returns the longitude in nanodegrees (10⁻⁹).
     pub fn nano_lon(&self) -> i64 {
-        self.block.lon_offset() + i64::from(self.block.granularity()) * self.osmnode.lon()
+        self.block.lon_offset() + i64::from(self.block.granularity()) * self.osmnode.lon
     }
 
     /// Returns the longitude coordinate in decimicrodegrees (10⁻⁷).
@@ -121,12 +169,51 @@ impl<'a> Node<'a> {
         }
     }
 
+    /// Returns the value of the first tag with the given key. Only the value of that tag is
+    /// decoded, but the key is resolved against the stringtable on every call, see
+    /// [`resolved_tag`](Self::resolved_tag) to resolve it once per block.
+    ///
+    /// Returns `None` if the value is not valid UTF-8, which can only happen with
+    /// [`TagDecoding::Unchecked`](crate::block::TagDecoding::Unchecked). Use
+    /// [`has_tag`](Self::has_tag) to check if the tag exists regardless of its value.
+    pub fn tag(&self, key: &str) -> Option<&'a str> {
+        self.resolved_tag(&self.block.resolve_tag(key, None)?)
+    }
+
+    /// Returns true if this node has a tag with the given key.
+    pub fn has_tag(&self, key: &str) -> bool {
+        self.block
+            .resolve_tag(key, None)
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns true if this node has a tag with the given key and value.
+    pub fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        self.block
+            .resolve_tag(key, Some(value))
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns the value of the first tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this node, or `None` like
+    /// [`tag`](Self::tag).
+    pub fn resolved_tag(&self, tag: &ResolvedTag) -> Option<&'a str> {
+        let index = tag.find(self.raw_tags())?;
+        str_from_stringtable(self.block, index as usize).ok()
+    }
+
+    /// Returns true if this node has a tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this node.
+    pub fn has_resolved_tag(&self, tag: &ResolvedTag) -> bool {
+        tag.find(self.raw_tags()).is_some()
+    }
+
     /// Returns the raw stringtable. Elements in a `PrimitiveBlock` do not store strings
     /// themselves; instead, they just store indices to a common stringtable. By convention, the
     /// contained strings are UTF-8 encoded but it is not safe to assume that (use
     /// `std::str::from_utf8`).
     pub fn raw_stringtable(&self) -> &[Vec<u8>] {
-        self.block.stringtable.s.as_slice()
+        self.block.raw_stringtable()
     }
 }
 
@@ -137,17 +224,17 @@ impl<'a> Node<'a> {
 #[derive(Clone, Debug)]
 pub struct Way<'a> {
     block: &'a PrimitiveBlock,
-    osmway: &'a osmformat::Way,
+    osmway: WayView<'a>,
 }
 
 impl<'a> Way<'a> {
-    pub(crate) fn new(block: &'a PrimitiveBlock, osmway: &'a osmformat::Way) -> Way<'a> {
+    pub(crate) fn new(block: &'a PrimitiveBlock, osmway: WayView<'a>) -> Way<'a> {
         Way { block, osmway }
     }
 
     /// Returns the way id.
     pub fn id(&self) -> i64 {
-        self.osmway.id()
+        self.osmway.id
     }
 
     /// Returns an iterator over the tags of this way
@@ -181,9 +268,30 @@ impl<'a> Way<'a> {
         }
     }
 
+    /// Returns an iterator over the tags of this way that yields an error for each tag that
+    /// cannot be decoded instead of ending the iteration.
+    pub fn checked_tags(&self) -> CheckedTagIter<'a> {
+        CheckedTagIter {
+            block: self.block,
+            element: (ElementType::Way, self.id()),
+            key_indices: self.osmway.keys.iter(),
+            val_indices: self.osmway.vals.iter(),
+        }
+    }
+
+    /// Returns an iterator over the tags of this way that replaces invalid UTF-8 sequences with
+    /// `U+FFFD REPLACEMENT CHARACTER` and missing strings with a single replacement character.
+    pub fn lossy_tags(&self) -> LossyTagIter<'a> {
+        LossyTagIter {
+            block: self.block,
+            key_indices: self.osmway.keys.iter(),
+            val_indices: self.osmway.vals.iter(),
+        }
+    }
+
     /// Returns additional metadata for this element.
     pub fn info(&self) -> Info<'a> {
-        Info::new(self.block, self.osmway.info.get_or_default())
+        Info::new(self.block, self.osmway.info, (ElementType::Way, self.id()))
     }
 
     /// Returns an iterator over the references of this way. Each reference should correspond to a
@@ -216,8 +324,14 @@ impl<'a> Way<'a> {
     }
 
     /// Returns a slice of delta coded node ids.
+    ///
+    /// Lazily decoded ways decode the ids on the first call, see
+    /// [`BlockDecoding::Lazy`](crate::block::BlockDecoding::Lazy).
     pub fn raw_refs(&self) -> &[i64] {
-        self.osmway.refs.as_slice()
+        match self.osmway.refs {
+            Packed::Slice(refs) => refs,
+            refs @ Packed::Varint(..) => self.block.raw_way_refs(self.osmway.position, refs),
+        }
     }
 
     /// Returns an iterator over the tags of this way
@@ -231,12 +345,51 @@ impl<'a> Way<'a> {
         }
     }
 
+    /// Returns the value of the first tag with the given key. Only the value of that tag is
+    /// decoded, but the key is resolved against the stringtable on every call, see
+    /// [`resolved_tag`](Self::resolved_tag) to resolve it once per block.
+    ///
+    /// Returns `None` if the value is not valid UTF-8, which can only happen with
+    /// [`TagDecoding::Unchecked`](crate::block::TagDecoding::Unchecked). Use
+    /// [`has_tag`](Self::has_tag) to check if the tag exists regardless of its value.
+    pub fn tag(&self, key: &str) -> Option<&'a str> {
+        self.resolved_tag(&self.block.resolve_tag(key, None)?)
+    }
+
+    /// Returns true if this way has a tag with the given key.
+    pub fn has_tag(&self, key: &str) -> bool {
+        self.block
+            .resolve_tag(key, None)
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns true if this way has a tag with the given key and value.
+    pub fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        self.block
+            .resolve_tag(key, Some(value))
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns the value of the first tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this way, or `None` like
+    /// [`tag`](Self::tag).
+    pub fn resolved_tag(&self, tag: &ResolvedTag) -> Option<&'a str> {
+        let index = tag.find(self.raw_tags())?;
+        str_from_stringtable(self.block, index as usize).ok()
+    }
+
+    /// Returns true if this way has a tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this way.
+    pub fn has_resolved_tag(&self, tag: &ResolvedTag) -> bool {
+        tag.find(self.raw_tags()).is_some()
+    }
+
     /// Returns the raw stringtable. Elements in a `PrimitiveBlock` do not store strings
     /// themselves; instead, they just store indices to a common stringtable. By convention, the
     /// contained strings are UTF-8 encoded but it is not safe to assume that (use
     /// `std::str::from_utf8`).
     pub fn raw_stringtable(&self) -> &[Vec<u8>] {
-        self.block.stringtable.s.as_slice()
+        self.block.raw_stringtable()
     }
 }
 
@@ -246,17 +399,17 @@ impl<'a> Way<'a> {
 #[derive(Clone, Debug)]
 pub struct Relation<'a> {
     block: &'a PrimitiveBlock,
-    osmrel: &'a osmformat::Relation,
+    osmrel: RelationView<'a>,
 }
 
 impl<'a> Relation<'a> {
-    pub(crate) fn new(block: &'a PrimitiveBlock, osmrel: &'a osmformat::Relation) -> Relation<'a> {
+    pub(crate) fn new(block: &'a PrimitiveBlock, osmrel: RelationView<'a>) -> Relation<'a> {
         Relation { block, osmrel }
     }
 
     /// Returns the relation id.
     pub fn id(&self) -> i64 {
-        self.osmrel.id()
+        self.osmrel.id
     }
 
     /// Returns an iterator over the tags of this relation
@@ -290,14 +443,39 @@ impl<'a> Relation<'a> {
         }
     }
 
+    /// Returns an iterator over the tags of this relation that yields an error for each tag that
+    /// cannot be decoded instead of ending the iteration.
+    pub fn checked_tags(&self) -> CheckedTagIter<'a> {
+        CheckedTagIter {
+            block: self.block,
+            element: (ElementType::Relation, self.id()),
+            key_indices: self.osmrel.keys.iter(),
+            val_indices: self.osmrel.vals.iter(),
+        }
+    }
+
+    /// Returns an iterator over the tags of this relation that replaces invalid UTF-8 sequences with
+    /// `U+FFFD REPLACEMENT CHARACTER` and missing strings with a single replacement character.
+    pub fn lossy_tags(&self) -> LossyTagIter<'a> {
+        LossyTagIter {
+            block: self.block,
+            key_indices: self.osmrel.keys.iter(),
+            val_indices: self.osmrel.vals.iter(),
+        }
+    }
+
     /// Returns additional metadata for this element.
     pub fn info(&self) -> Info<'a> {
-        Info::new(self.block, self.osmrel.info.get_or_default())
+        Info::new(
+            self.block,
+            self.osmrel.info,
+            (ElementType::Relation, self.id()),
+        )
     }
 
     /// Returns an iterator over the members of this relation.
     pub fn members(&self) -> RelMemberIter<'a> {
-        RelMemberIter::new(self.block, self.osmrel)
+        RelMemberIter::new(self.block, &self.osmrel)
     }
 
     /// Returns an iterator over the tags of this relation
@@ -311,12 +489,51 @@ impl<'a> Relation<'a> {
         }
     }
 
+    /// Returns the value of the first tag with the given key. Only the value of that tag is
+    /// decoded, but the key is resolved against the stringtable on every call, see
+    /// [`resolved_tag`](Self::resolved_tag) to resolve it once per block.
+    ///
+    /// Returns `None` if the value is not valid UTF-8, which can only happen with
+    /// [`TagDecoding::Unchecked`](crate::block::TagDecoding::Unchecked). Use
+    /// [`has_tag`](Self::has_tag) to check if the tag exists regardless of its value.
+    pub fn tag(&self, key: &str) -> Option<&'a str> {
+        self.resolved_tag(&self.block.resolve_tag(key, None)?)
+    }
+
+    /// Returns true if this relation has a tag with the given key.
+    pub fn has_tag(&self, key: &str) -> bool {
+        self.block
+            .resolve_tag(key, None)
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns true if this relation has a tag with the given key and value.
+    pub fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        self.block
+            .resolve_tag(key, Some(value))
+            .is_some_and(|tag| self.has_resolved_tag(&tag))
+    }
+
+    /// Returns the value of the first tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this relation, or `None` like
+    /// [`tag`](Self::tag).
+    pub fn resolved_tag(&self, tag: &ResolvedTag) -> Option<&'a str> {
+        let index = tag.find(self.raw_tags())?;
+        str_from_stringtable(self.block, index as usize).ok()
+    }
+
+    /// Returns true if this relation has a tag that matches a tag resolved with
+    /// [`PrimitiveBlock::resolve_tag`] for the block of this relation.
+    pub fn has_resolved_tag(&self, tag: &ResolvedTag) -> bool {
+        tag.find(self.raw_tags()).is_some()
+    }
+
     /// Returns the raw stringtable. Elements in a `PrimitiveBlock` do not store strings
     /// themselves; instead, they just store indices to a common stringtable. By convention, the
     /// contained strings are UTF-8 encoded but it is not safe to assume that (use
     /// `std::str::from_utf8`).
     pub fn raw_stringtable(&self) -> &[Vec<u8>] {
-        self.block.stringtable.s.as_slice()
+        self.block.raw_stringtable()
     }
 }
 
@@ -325,7 +542,7 @@ impl<'a> Relation<'a> {
 /// Each reference corresponds to a node id.
 #[derive(Clone, Debug)]
 pub struct WayRefIter<'a> {
-    deltas: std::slice::Iter<'a, i64>,
+    deltas: PackedIter<'a, i64>,
     current: i64,
 }
 
@@ -334,7 +551,7 @@ impl<'a> Iterator for WayRefIter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.deltas.next() {
-            Some(&d) => {
+            Some(d) => {
                 self.current += d;
                 Some(self.current)
             }
@@ -391,9 +608,9 @@ impl WayNodeLocation {
 /// Each element is a pair of coordinates consisting of latitude and longitude.
 #[derive(Clone, Debug)]
 pub struct WayNodeLocationsIter<'a> {
-    block: &'a osmformat::PrimitiveBlock,
-    dlats: std::slice::Iter<'a, i64>,
-    dlons: std::slice::Iter<'a, i64>,
+    block: &'a PrimitiveBlock,
+    dlats: PackedIter<'a, i64>,
+    dlons: PackedIter<'a, i64>,
     clat: i64,
     clon: i64,
 }
@@ -403,7 +620,7 @@ impl<'a> Iterator for WayNodeLocationsIter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match (self.dlats.next(), self.dlons.next()) {
-            (Some(&dlat), Some(&dlon)) => {
+            (Some(dlat), Some(dlon)) => {
                 self.clat += dlat;
                 self.clon += dlon;
                 Some(WayNodeLocation {
@@ -447,6 +664,7 @@ impl From<EnumOrUnknown<MemberType>> for RelMemberType {
 #[derive(Clone, Debug)]
 pub struct RelMember<'a> {
     block: &'a PrimitiveBlock,
+    relation_id: i64,
     pub role_sid: i32,
     pub member_id: i64,
     pub member_type: RelMemberType,
@@ -456,6 +674,7 @@ impl<'a> RelMember<'a> {
     /// Returns the role of a relation member.
     pub fn role(&self) -> Result<&'a str> {
         str_from_stringtable(self.block, self.role_sid as usize)
+            .map_err(|e| e.with_element(ElementType::Relation, self.relation_id))
     }
 }
 
@@ -463,16 +682,18 @@ impl<'a> RelMember<'a> {
 #[derive(Clone, Debug)]
 pub struct RelMemberIter<'a> {
     block: &'a PrimitiveBlock,
-    role_sids: std::slice::Iter<'a, i32>,
-    member_id_deltas: std::slice::Iter<'a, i64>,
-    member_types: std::slice::Iter<'a, EnumOrUnknown<MemberType>>,
+    relation_id: i64,
+    role_sids: PackedIter<'a, i32>,
+    member_id_deltas: PackedIter<'a, i64>,
+    member_types: PackedIter<'a, EnumOrUnknown<MemberType>>,
     current_member_id: i64,
 }
 
 impl<'a> RelMemberIter<'a> {
-    fn new(block: &'a PrimitiveBlock, osmrel: &'a osmformat::Relation) -> RelMemberIter<'a> {
+    fn new(block: &'a PrimitiveBlock, osmrel: &RelationView<'a>) -> RelMemberIter<'a> {
         RelMemberIter {
             block,
+            relation_id: osmrel.id,
             role_sids: osmrel.roles_sid.iter(),
             member_id_deltas: osmrel.memids.iter(),
             member_types: osmrel.types.iter(),
@@ -491,12 +712,13 @@ impl<'a> Iterator for RelMemberIter<'a> {
             self.member_types.next(),
         ) {
             (Some(role_sid), Some(mem_id_delta), Some(member_type)) => {
-                self.current_member_id += *mem_id_delta;
+                self.current_member_id += mem_id_delta;
                 Some(RelMember {
                     block: self.block,
-                    role_sid: *role_sid,
+                    relation_id: self.relation_id,
+                    role_sid,
                     member_id: self.current_member_id,
-                    member_type: RelMemberType::from(*member_type),
+                    member_type: RelMemberType::from(member_type),
                 })
             }
             _ => None,
@@ -511,22 +733,24 @@ impl<'a> Iterator for RelMemberIter<'a> {
 impl<'a> ExactSizeIterator for RelMemberIter<'a> {}
 
 /// An iterator over the tags of an element. It returns a pair of strings (key and value).
+///
+/// The iteration ends at the first tag that cannot be decoded. Use [`CheckedTagIter`] or
+/// [`LossyTagIter`] to handle such tags.
 #[derive(Clone, Debug)]
 pub struct TagIter<'a> {
     block: &'a PrimitiveBlock,
-    key_indices: std::slice::Iter<'a, u32>,
-    val_indices: std::slice::Iter<'a, u32>,
+    key_indices: PackedIter<'a, u32>,
+    val_indices: PackedIter<'a, u32>,
 }
 
-//TODO return Result?
 impl<'a> Iterator for TagIter<'a> {
     type Item = (&'a str, &'a str);
 
     fn next(&mut self) -> Option<Self::Item> {
         get_stringtable_key_value(
             self.block,
-            self.key_indices.next().map(|v| *v as usize),
-            self.val_indices.next().map(|v| *v as usize),
+            self.key_indices.next().map(|v| v as usize),
+            self.val_indices.next().map(|v| v as usize),
         )
     }
 
@@ -537,12 +761,77 @@ impl<'a> Iterator for TagIter<'a> {
 
 impl<'a> ExactSizeIterator for TagIter<'a> {}
 
+/// An iterator over the tags of an element. It returns a pair of strings (key and value) or an
+/// error if a string cannot be decoded.
+#[derive(Clone, Debug)]
+pub struct CheckedTagIter<'a> {
+    block: &'a PrimitiveBlock,
+    element: (ElementType, i64),
+    key_indices: PackedIter<'a, u32>,
+    val_indices: PackedIter<'a, u32>,
+}
+
+impl<'a> Iterator for CheckedTagIter<'a> {
+    type Item = Result<(&'a str, &'a str)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.key_indices.next(), self.val_indices.next()) {
+            (Some(key_index), Some(val_index)) => {
+                let (element_type, id) = self.element;
+                Some(
+                    str_from_stringtable(self.block, key_index as usize)
+                        .and_then(|k| {
+                            str_from_stringtable(self.block, val_index as usize).map(|v| (k, v))
+                        })
+                        .map_err(|e| e.with_element(element_type, id)),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.key_indices.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for CheckedTagIter<'a> {}
+
+/// An iterator over the tags of an element. It returns a pair of strings (key and value) with
+/// invalid UTF-8 sequences replaced by `U+FFFD REPLACEMENT CHARACTER`.
+#[derive(Clone, Debug)]
+pub struct LossyTagIter<'a> {
+    block: &'a PrimitiveBlock,
+    key_indices: PackedIter<'a, u32>,
+    val_indices: PackedIter<'a, u32>,
+}
+
+impl<'a> Iterator for LossyTagIter<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.key_indices.next(), self.val_indices.next()) {
+            (Some(key_index), Some(val_index)) => Some((
+                lossy_str_from_stringtable(self.block, key_index as usize),
+                lossy_str_from_stringtable(self.block, val_index as usize),
+            )),
+            _ => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.key_indices.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for LossyTagIter<'a> {}
+
 /// An iterator over the tags of an element. It returns a pair of indices (key and value) to the
 /// stringtable of the current [`PrimitiveBlock`](crate::block::PrimitiveBlock).
 #[derive(Clone, Debug)]
 pub struct RawTagIter<'a> {
-    key_indices: std::slice::Iter<'a, u32>,
-    val_indices: std::slice::Iter<'a, u32>,
+    key_indices: PackedIter<'a, u32>,
+    val_indices: PackedIter<'a, u32>,
 }
 
 //TODO return Result?
@@ -551,7 +840,7 @@ impl<'a> Iterator for RawTagIter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match (self.key_indices.next(), self.val_indices.next()) {
-            (Some(&key_index), Some(&val_index)) => Some((key_index, val_index)),
+            (Some(key_index), Some(val_index)) => Some((key_index, val_index)),
             _ => None,
         }
     }
@@ -567,12 +856,18 @@ impl<'a> ExactSizeIterator for RawTagIter<'a> {}
 #[derive(Clone, Debug)]
 pub struct Info<'a> {
     block: &'a PrimitiveBlock,
-    info: &'a osmformat::Info,
+    info: InfoView,
+    /// The type and id of the element, only used for errors.
+    element: (ElementType, i64),
 }
 
 impl<'a> Info<'a> {
-    fn new(block: &'a PrimitiveBlock, info: &'a osmformat::Info) -> Info<'a> {
-        Info { block, info }
+    fn new(block: &'a PrimitiveBlock, info: InfoView, element: (ElementType, i64)) -> Info<'a> {
+        Info {
+            block,
+            info,
+            element,
+        }
     }
 
     /// Returns the version of this element.
@@ -582,11 +877,9 @@ impl<'a> Info<'a> {
 
     /// Returns the time stamp in milliseconds since the epoch.
     pub fn milli_timestamp(&self) -> Option<i64> {
-        if self.info.has_timestamp() {
-            Some(self.info.timestamp() * i64::from(self.block.date_granularity()))
-        } else {
-            None
-        }
+        self.info
+            .timestamp
+            .map(|timestamp| timestamp * i64::from(self.block.date_granularity()))
     }
 
     /// Returns the changeset id.
@@ -601,14 +894,11 @@ impl<'a> Info<'a> {
 
     /// Returns the user name.
     pub fn user(&self) -> Option<Result<&'a str>> {
-        if self.info.has_user_sid() {
-            Some(str_from_stringtable(
-                self.block,
-                self.info.user_sid() as usize,
-            ))
-        } else {
-            None
-        }
+        let (element_type, id) = self.element;
+        self.info.user_sid.map(|user_sid| {
+            str_from_stringtable(self.block, user_sid as usize)
+                .map_err(|e| e.with_element(element_type, id))
+        })
     }
 
     /// Returns the visibility status of an element. This is only relevant if the PBF file contains
@@ -7,36 +7,86 @@ use std::str::Utf8Error;
 
 use protobuf::Error as ProtobufError;
 
+use crate::blob::{BlobCompression, ByteOffset};
+use crate::elements::ElementType;
+use crate::geometry::InvalidArea;
+
 // Error data structures are modeled just like in the `csv` crate by BurntSushi.
 
 pub(crate) fn new_error(kind: ErrorKind) -> Error {
-    Error(Box::new(kind))
+    Error(Box::new(ErrorImpl {
+        kind,
+        blob_offset: None,
+        blob_index: None,
+        element: None,
+    }))
 }
 
 pub(crate) fn new_blob_error(kind: BlobError) -> Error {
-    Error(Box::new(ErrorKind::Blob(kind)))
+    new_error(ErrorKind::Blob(kind))
 }
 
 pub(crate) fn new_protobuf_error(err: ProtobufError, location: &'static str) -> Error {
-    Error(Box::new(ErrorKind::Protobuf { err, location }))
+    new_error(ErrorKind::Protobuf { err, location })
 }
 
 /// A type alias for `Result<T, osmpbf::Error>`.
 pub type Result<T> = result::Result<T, Error>;
 
 /// An error that can occur when reading PBF files.
+///
+/// Besides the specific [`ErrorKind`], an error might carry some context about where it occurred:
+/// the offset and ordinal of the affected blob and the element that was being decoded.
 #[derive(Debug)]
-pub struct Error(Box<ErrorKind>);
+pub struct Error(Box<ErrorImpl>);
+
+#[derive(Debug)]
+struct ErrorImpl {
+    kind: ErrorKind,
+    blob_offset: Option<ByteOffset>,
+    blob_index: Option<u64>,
+    element: Option<(ElementType, i64)>,
+}
 
 impl Error {
     /// Return the specific type of this error.
     pub fn kind(&self) -> &ErrorKind {
-        &self.0
+        &self.0.kind
     }
 
     /// Unwrap this error into its underlying type.
     pub fn into_kind(self) -> ErrorKind {
-        *self.0
+        self.0.kind
+    }
+
+    /// Returns the byte offset of the blob where the error occurred, if known.
+    pub fn blob_offset(&self) -> Option<ByteOffset> {
+        self.0.blob_offset
+    }
+
+    /// Returns the ordinal of the blob where the error occurred (starting with 0 for the first
+    /// blob of the stream), if known.
+    pub fn blob_index(&self) -> Option<u64> {
+        self.0.blob_index
+    }
+
+    /// Returns the type and id of the element that was being decoded when the error occurred,
+    /// if known.
+    pub fn element(&self) -> Option<(ElementType, i64)> {
+        self.0.element
+    }
+
+    /// Adds the blob offset and ordinal to the error unless they are already set.
+    pub(crate) fn with_blob(mut self, offset: Option<ByteOffset>, index: Option<u64>) -> Error {
+        self.0.blob_offset = self.0.blob_offset.or(offset);
+        self.0.blob_index = self.0.blob_index.or(index);
+        self
+    }
+
+    /// Adds the element type and id to the error unless they are already set.
+    pub(crate) fn with_element(mut self, element_type: ElementType, id: i64) -> Error {
+        self.0.element = self.0.element.or(Some((element_type, id)));
+        self
     }
 }
 
@@ -58,6 +108,28 @@ pub enum ErrorKind {
     StringtableIndexOutOfBounds { index: usize },
     /// An error that occurs when decoding `Blob`s.
     Blob(BlobError),
+    /// The file requires a feature that is not supported by this library. Only returned if
+    /// feature validation is enabled with
+    /// [`ReaderOptions::validate_features`](crate::options::ReaderOptions::validate_features).
+    UnsupportedFeature { feature: String },
+    /// A [`TagFilter`](crate::filter::TagFilter) expression could not be parsed. `position` is the
+    /// byte offset in the expression where the error was detected.
+    InvalidFilter { message: String, position: usize },
+    /// A multipolygon relation could not be assembled to an area. Only returned by exporters with
+    /// [`InvalidAreaPolicy::Error`](crate::InvalidAreaPolicy::Error).
+    InvalidArea { relation: i64, reason: InvalidArea },
+    /// A [`BlobEncoding`](crate::recompress::BlobEncoding) has a compression level that is not
+    /// supported by its compression.
+    InvalidCompressionLevel {
+        compression: BlobCompression,
+        level: i64,
+    },
+    /// An error that occurs when converting elements to Apache Arrow arrays.
+    #[cfg(feature = "arrow")]
+    Arrow(arrow_schema::ArrowError),
+    /// An error that occurs when writing a Parquet file.
+    #[cfg(feature = "geoparquet")]
+    Parquet(parquet::errors::ParquetError),
     //TODO add UnexpectedPrimitiveBlock
 }
 
@@ -67,18 +139,40 @@ pub enum ErrorKind {
 pub enum BlobError {
     /// Header size could not be decoded to a u32.
     InvalidHeaderSize,
-    /// Blob header is bigger than [`MAX_BLOB_HEADER_SIZE`](blob/MAX_BLOB_HEADER_SIZE.v.html).
+    /// Blob header is bigger than [`MAX_BLOB_HEADER_SIZE`](blob/MAX_BLOB_HEADER_SIZE.v.html) or
+    /// the limit set with
+    /// [`ReaderOptions::max_header_size`](crate::options::ReaderOptions::max_header_size).
     HeaderTooBig {
         /// Blob header size in bytes.
         size: u64,
     },
-    /// Blob content is bigger than [`MAX_BLOB_MESSAGE_SIZE`](blob/MAX_BLOB_MESSAGE_SIZE.v.html).
+    /// Blob content is bigger than [`MAX_BLOB_MESSAGE_SIZE`](blob/MAX_BLOB_MESSAGE_SIZE.v.html) or
+    /// the limit set with
+    /// [`ReaderOptions::max_blob_size`](crate::options::ReaderOptions::max_blob_size).
     MessageTooBig {
         /// Blob content size in bytes.
         size: u64,
     },
+    /// The `datasize` field of the blob header is negative.
+    NegativeMessageSize {
+        /// The `datasize` value of the blob header.
+        size: i32,
+    },
     /// The blob is empty because the `raw` and `zlib-data` fields are missing.
     Empty,
+    /// The blob has an unknown type. Only returned with [`UnknownBlobPolicy::Error`].
+    ///
+    /// [`UnknownBlobPolicy::Error`]: crate::options::UnknownBlobPolicy::Error
+    UnknownType {
+        /// The type string of the blob header.
+        blob_type: String,
+    },
+    /// The content of the blob is compressed with a scheme that is not supported. Zstandard and
+    /// LZ4 are supported with the `zstd` and `lz4` features.
+    UnsupportedCompression {
+        /// The compression of the blob content.
+        compression: BlobCompression,
+    },
 }
 
 impl From<io::Error> for Error {
@@ -87,6 +181,20 @@ impl From<io::Error> for Error {
     }
 }
 
+#[cfg(feature = "arrow")]
+impl From<arrow_schema::ArrowError> for Error {
+    fn from(err: arrow_schema::ArrowError) -> Error {
+        new_error(ErrorKind::Arrow(err))
+    }
+}
+
+#[cfg(feature = "geoparquet")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(err: parquet::errors::ParquetError) -> Error {
+        new_error(ErrorKind::Parquet(err))
+    }
+}
+
 impl From<Error> for io::Error {
     fn from(err: Error) -> io::Error {
         io::Error::new(io::ErrorKind::Other, err)
@@ -95,7 +203,7 @@ impl From<Error> for io::Error {
 
 impl StdError for Error {
     fn description(&self) -> &str {
-        match *self.0 {
+        match self.0.kind {
             ErrorKind::Io(ref err, ..) => {
                 use std::io::ErrorKind;
                 match err.kind() {
@@ -128,12 +236,27 @@ impl StdError for Error {
             }
             ErrorKind::Blob(BlobError::HeaderTooBig { .. }) => "blob header is too big",
             ErrorKind::Blob(BlobError::MessageTooBig { .. }) => "blob message is too big",
+            ErrorKind::Blob(BlobError::NegativeMessageSize { .. }) => {
+                "blob message size is negative"
+            }
             ErrorKind::Blob(BlobError::Empty) => "blob is missing fields 'raw' and 'zlib_data",
+            ErrorKind::Blob(BlobError::UnknownType { .. }) => "blob has an unknown type",
+            ErrorKind::Blob(BlobError::UnsupportedCompression { .. }) => {
+                "blob compression is not supported"
+            }
+            ErrorKind::UnsupportedFeature { .. } => "unsupported required feature",
+            ErrorKind::InvalidFilter { .. } => "invalid tag filter expression",
+            ErrorKind::InvalidCompressionLevel { .. } => "invalid compression level",
+            ErrorKind::InvalidArea { .. } => "invalid multipolygon relation",
+            #[cfg(feature = "arrow")]
+            ErrorKind::Arrow(..) => "arrow error",
+            #[cfg(feature = "geoparquet")]
+            ErrorKind::Parquet(..) => "parquet error",
         }
     }
 
     fn cause(&self) -> Option<&dyn StdError> {
-        match *self.0 {
+        match self.0.kind {
             ErrorKind::Io(ref err) => Some(err),
             ErrorKind::Protobuf { ref err, .. } => Some(err),
             ErrorKind::StringtableUtf8 { ref err, .. } => Some(err),
@@ -141,14 +264,25 @@ impl StdError for Error {
             ErrorKind::Blob(BlobError::InvalidHeaderSize) => None,
             ErrorKind::Blob(BlobError::HeaderTooBig { .. }) => None,
             ErrorKind::Blob(BlobError::MessageTooBig { .. }) => None,
+            ErrorKind::Blob(BlobError::NegativeMessageSize { .. }) => None,
             ErrorKind::Blob(BlobError::Empty) => None,
+            ErrorKind::Blob(BlobError::UnknownType { .. }) => None,
+            ErrorKind::Blob(BlobError::UnsupportedCompression { .. }) => None,
+            ErrorKind::UnsupportedFeature { .. } => None,
+            ErrorKind::InvalidFilter { .. } => None,
+            ErrorKind::InvalidCompressionLevel { .. } => None,
+            ErrorKind::InvalidArea { .. } => None,
+            #[cfg(feature = "arrow")]
+            ErrorKind::Arrow(ref err) => Some(err),
+            #[cfg(feature = "geoparquet")]
+            ErrorKind::Parquet(ref err) => Some(err),
         }
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self.0 {
+        match self.0.kind {
             ErrorKind::Io(ref err) => err.fmt(f),
             ErrorKind::Protobuf { ref err, location } => {
                 write!(f, "protobuf error at '{location}': {err}")
@@ -168,9 +302,52 @@ impl fmt::Display for Error {
             ErrorKind::Blob(BlobError::MessageTooBig { size }) => {
                 write!(f, "blob message is too big: {size} bytes")
             }
+            ErrorKind::Blob(BlobError::NegativeMessageSize { size }) => {
+                write!(f, "blob message size is negative: {size}")
+            }
             ErrorKind::Blob(BlobError::Empty) => {
                 write!(f, "blob is missing fields 'raw' and 'zlib_data'")
             }
+            ErrorKind::Blob(BlobError::UnknownType { ref blob_type }) => {
+                write!(f, "blob has an unknown type: '{blob_type}'")
+            }
+            ErrorKind::Blob(BlobError::UnsupportedCompression { compression }) => {
+                write!(f, "blob compression is not supported: {compression:?}")
+            }
+            ErrorKind::UnsupportedFeature { ref feature } => {
+                write!(f, "unsupported required feature: '{feature}'")
+            }
+            ErrorKind::InvalidFilter {
+                ref message,
+                position,
+            } => {
+                write!(f, "invalid tag filter at position {position}: {message}")
+            }
+            ErrorKind::InvalidCompressionLevel { compression, level } => {
+                write!(f, "invalid {compression:?} compression level: {level}")
+            }
+            ErrorKind::InvalidArea { relation, reason } => {
+                write!(f, "invalid multipolygon relation {relation}: {reason}")
+            }
+            #[cfg(feature = "arrow")]
+            ErrorKind::Arrow(ref err) => write!(f, "arrow error: {err}"),
+            #[cfg(feature = "geoparquet")]
+            ErrorKind::Parquet(ref err) => write!(f, "parquet error: {err}"),
+        }?;
+
+        let mut context = vec![];
+        if let Some(index) = self.0.blob_index {
+            context.push(format!("blob #{index}"));
+        }
+        if let Some(ByteOffset(offset)) = self.0.blob_offset {
+            context.push(format!("offset {offset}"));
+        }
+        if let Some((element_type, id)) = self.0.element {
+            context.push(format!("{} {id}", element_type.as_str()));
+        }
+        if !context.is_empty() {
+            write!(f, " ({})", context.join(", "))?;
         }
+        Ok(())
     }
 }
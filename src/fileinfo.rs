@@ -0,0 +1,379 @@
+//! Collect statistics about a whole PBF file
+
+use crate::blob::{Blob, BlobDecode, BlobReader};
+use crate::block::{HeaderBBox, HeaderBlock, PrimitiveBlock};
+use crate::elements::Element;
+use crate::error::Result;
+use crate::pipeline::{par_map_reduce_blobs, PipelineOptions};
+use crate::reader::ElementReader;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// The number of elements of one type and the range of their ids.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ElementStats {
+    /// The number of elements.
+    pub count: u64,
+    /// The minimum and maximum id or `None` if there are no elements of this type.
+    pub ids: Option<RangeInclusive<i64>>,
+}
+
+impl ElementStats {
+    fn add(&mut self, id: i64) {
+        self.count += 1;
+        self.ids = Some(match self.ids.take() {
+            Some(range) => (*range.start()).min(id)..=(*range.end()).max(id),
+            None => id..=id,
+        });
+    }
+
+    fn merge(self, other: ElementStats) -> ElementStats {
+        let ids = match (self.ids, other.ids) {
+            (Some(a), Some(b)) => Some((*a.start()).min(*b.start())..=(*a.end()).max(*b.end())),
+            (a, b) => a.or(b),
+        };
+        ElementStats {
+            count: self.count + other.count,
+            ids,
+        }
+    }
+}
+
+/// Statistics about a PBF file, similar to the output of `osmium fileinfo --extended`.
+///
+/// Computing the statistics decodes every blob of the file in parallel.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let info = FileInfo::from_path("tests/test.osm.pbf")?;
+///
+/// println!("Nodes: {}", info.nodes.count);
+/// println!("Ways: {}", info.ways.count);
+/// println!("Relations: {}", info.relations.count);
+/// # assert_eq!(info.nodes.count, 3);
+/// # assert_eq!(info.ways.ids, Some(107..=107));
+/// # assert!(info.sorted);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FileInfo {
+    /// The first [`HeaderBlock`] of the file or `None` if there is none.
+    pub header: Option<HeaderBlock>,
+    /// The number of blobs that contain a [`HeaderBlock`].
+    pub header_blobs: u64,
+    /// The number of blobs that contain a [`PrimitiveBlock`].
+    pub data_blobs: u64,
+    /// The number of blobs with an unknown type.
+    pub unknown_blobs: u64,
+    /// The sum of the (possibly compressed) blob content sizes in bytes.
+    pub compressed_size: u64,
+    /// The sum of the uncompressed blob content sizes in bytes. Compressed blobs that do not
+    /// declare their uncompressed size (`raw_size`) are not included.
+    pub uncompressed_size: u64,
+    /// Statistics about all nodes (including dense nodes).
+    pub nodes: ElementStats,
+    /// Statistics about all ways.
+    pub ways: ElementStats,
+    /// Statistics about all relations.
+    pub relations: ElementStats,
+    /// The bounding box of all node locations (including locations on ways) or `None` if there
+    /// are no locations in the file. In contrast to [`HeaderBlock::bbox`], this is computed from
+    /// the actual data.
+    pub bbox: Option<HeaderBBox>,
+    /// The earliest element timestamp in milliseconds since the epoch.
+    pub first_timestamp: Option<i64>,
+    /// The latest element timestamp in milliseconds since the epoch.
+    pub last_timestamp: Option<i64>,
+    /// Is true if at least one element contains metadata (version, timestamp, ...).
+    pub has_metadata: bool,
+    /// Is true if at least one way stores the locations of its nodes (`LocationsOnWays`).
+    pub locations_on_ways: bool,
+    /// Is true if the elements are sorted by type (nodes, ways, relations) and then by id.
+    /// Elements with the same id (as in history files) are allowed.
+    pub sorted: bool,
+}
+
+impl FileInfo {
+    /// Computes the statistics of the PBF file at the given path.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<FileInfo> {
+        Self::from_element_reader(ElementReader::<BufReader<File>>::from_path(path)?)
+    }
+
+    /// Computes the statistics of all blobs of the given reader. The blobs are decoded with the
+    /// thread pool, the in-flight limits and the progress callback of the reader.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_threads(2);
+    /// let info = FileInfo::from_element_reader(reader)?;
+    ///
+    /// assert_eq!(info.nodes.count, 3);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn from_element_reader<R: Read + Send>(reader: ElementReader<R>) -> Result<FileInfo> {
+        let partial = reader.par_map_reduce_blobs(
+            |blob| {
+                let partial = Partial::from_blob(&blob)?;
+                let elements = partial.info.nodes.count
+                    + partial.info.ways.count
+                    + partial.info.relations.count;
+                Ok((partial, elements))
+            },
+            Partial::default,
+            Partial::merge,
+        )?;
+
+        Ok(partial.finish())
+    }
+
+    /// Computes the statistics of all blobs of the given reader.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let reader = BlobReader::from_path("tests/test.osm.pbf")?;
+    /// let info = FileInfo::from_blob_reader(reader)?;
+    ///
+    /// assert_eq!(info.header_blobs, 1);
+    /// assert_eq!(info.data_blobs, 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn from_blob_reader<R: Read + Send>(reader: BlobReader<R>) -> Result<FileInfo> {
+        let partial = par_map_reduce_blobs(
+            reader,
+            &PipelineOptions::default(),
+            |blob| Partial::from_blob(&blob),
+            Partial::default,
+            Partial::merge,
+        )?;
+
+        Ok(partial.finish())
+    }
+}
+
+/// The order of the elements in one data blob.
+#[derive(Clone, Debug)]
+struct BlockOrder {
+    position: u64,
+    first: (u8, i64),
+    last: (u8, i64),
+    sorted: bool,
+}
+
+/// Intermediate statistics of a subset of blobs.
+#[derive(Debug, Default)]
+struct Partial {
+    /// The header with the smallest blob position.
+    header: Option<(u64, HeaderBlock)>,
+    info: FileInfo,
+    /// Minimum and maximum (latitude, longitude) in nanodegrees.
+    bbox: Option<((i64, i64), (i64, i64))>,
+    orders: Vec<BlockOrder>,
+}
+
+impl Partial {
+    fn from_blob(blob: &Blob) -> Result<Partial> {
+        let mut partial = Partial::default();
+        // Offsets are missing for streams that cannot seek, indices are missing after seeking
+        let position = blob
+            .offset()
+            .map(|offset| offset.0)
+            .or(blob.index())
+            .unwrap_or(0);
+
+        let (compressed, uncompressed) = blob.content_sizes();
+        partial.info.compressed_size = compressed;
+        partial.info.uncompressed_size = uncompressed.unwrap_or(0);
+
+        match blob.decode()? {
+            BlobDecode::OsmHeader(header) => {
+                partial.info.header_blobs = 1;
+                partial.header = Some((position, *header));
+            }
+            BlobDecode::OsmData(block) => {
+                partial.info.data_blobs = 1;
+                partial.add_block(position, &block);
+            }
+            BlobDecode::Unknown(_) => {
+                partial.info.unknown_blobs = 1;
+            }
+        }
+
+        Ok(partial)
+    }
+
+    fn add_location(&mut self, nano_lat: i64, nano_lon: i64) {
+        self.bbox = Some(match self.bbox {
+            Some(((min_lat, min_lon), (max_lat, max_lon))) => (
+                (min_lat.min(nano_lat), min_lon.min(nano_lon)),
+                (max_lat.max(nano_lat), max_lon.max(nano_lon)),
+            ),
+            None => ((nano_lat, nano_lon), (nano_lat, nano_lon)),
+        });
+    }
+
+    fn add_timestamp(&mut self, timestamp: Option<i64>) {
+        if let Some(t) = timestamp {
+            self.info.has_metadata = true;
+            self.info.first_timestamp = Some(self.info.first_timestamp.map_or(t, |x| x.min(t)));
+            self.info.last_timestamp = Some(self.info.last_timestamp.map_or(t, |x| x.max(t)));
+        }
+    }
+
+    fn add_block(&mut self, position: u64, block: &PrimitiveBlock) {
+        let mut order: Option<BlockOrder> = None;
+
+        for element in block.elements() {
+            let key = match element {
+                Element::Node(ref node) => {
+                    self.info.nodes.add(node.id());
+                    self.add_location(node.nano_lat(), node.nano_lon());
+                    let info = node.info();
+                    self.info.has_metadata |= info.version().is_some();
+                    self.add_timestamp(info.milli_timestamp());
+                    (0, node.id())
+                }
+                Element::DenseNode(ref node) => {
+                    self.info.nodes.add(node.id());
+                    self.add_location(node.nano_lat(), node.nano_lon());
+                    if let Some(info) = node.info() {
+                        self.add_timestamp(Some(info.milli_timestamp()));
+                    }
+                    (0, node.id())
+                }
+                Element::Way(ref way) => {
+                    self.info.ways.add(way.id());
+                    for location in way.node_locations() {
+                        self.info.locations_on_ways = true;
+                        self.add_location(location.nano_lat(), location.nano_lon());
+                    }
+                    let info = way.info();
+                    self.info.has_metadata |= info.version().is_some();
+                    self.add_timestamp(info.milli_timestamp());
+                    (1, way.id())
+                }
+                Element::Relation(ref relation) => {
+                    self.info.relations.add(relation.id());
+                    let info = relation.info();
+                    self.info.has_metadata |= info.version().is_some();
+                    self.add_timestamp(info.milli_timestamp());
+                    (2, relation.id())
+                }
+            };
+
+            match order {
+                Some(ref mut order) => {
+                    order.sorted &= order.last.cmp(&key) != Ordering::Greater;
+                    order.last = key;
+                }
+                None => {
+                    order = Some(BlockOrder {
+                        position,
+                        first: key,
+                        last: key,
+                        sorted: true,
+                    })
+                }
+            }
+        }
+
+        self.orders.extend(order);
+    }
+
+    fn merge(mut self, other: Partial) -> Partial {
+        self.header = match (self.header, other.header) {
+            (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+            (a, b) => a.or(b),
+        };
+
+        let a = self.info;
+        let b = other.info;
+        self.info = FileInfo {
+            header: None,
+            header_blobs: a.header_blobs + b.header_blobs,
+            data_blobs: a.data_blobs + b.data_blobs,
+            unknown_blobs: a.unknown_blobs + b.unknown_blobs,
+            compressed_size: a.compressed_size + b.compressed_size,
+            uncompressed_size: a.uncompressed_size + b.uncompressed_size,
+            nodes: a.nodes.merge(b.nodes),
+            ways: a.ways.merge(b.ways),
+            relations: a.relations.merge(b.relations),
+            bbox: None,
+            first_timestamp: min_option(a.first_timestamp, b.first_timestamp),
+            last_timestamp: max_option(a.last_timestamp, b.last_timestamp),
+            has_metadata: a.has_metadata || b.has_metadata,
+            locations_on_ways: a.locations_on_ways || b.locations_on_ways,
+            sorted: false,
+        };
+
+        if let Some(((min_lat, min_lon), (max_lat, max_lon))) = other.bbox {
+            self.add_location(min_lat, min_lon);
+            self.add_location(max_lat, max_lon);
+        }
+
+        self.orders.extend(other.orders);
+        self
+    }
+
+    fn finish(mut self) -> FileInfo {
+        self.orders.sort_unstable_by_key(|order| order.position);
+
+        let mut info = self.info;
+        info.header = self.header.map(|(_, header)| header);
+        info.bbox = self
+            .bbox
+            .map(|((min_lat, min_lon), (max_lat, max_lon))| HeaderBBox {
+                left: (min_lon as f64) * 1.0_e-9,
+                right: (max_lon as f64) * 1.0_e-9,
+                top: (max_lat as f64) * 1.0_e-9,
+                bottom: (min_lat as f64) * 1.0_e-9,
+            });
+        info.sorted = self.orders.iter().all(|order| order.sorted)
+            && self
+                .orders
+                .windows(2)
+                .all(|pair| pair[0].last.cmp(&pair[1].first) != Ordering::Greater);
+        info
+    }
+}
+
+fn min_option(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn max_option(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
@@ -0,0 +1,691 @@
+//! Filter elements with tag expressions
+//!
+//! A [`TagFilter`] is parsed once from an expression like `highway and not area=yes`. To match the
+//! elements of a block, it is compiled against the stringtable of the block with
+//! [`TagFilter::for_block`]: the keys and values are resolved to stringtable indices once, and the
+//! values that match a wildcard pattern or a regular expression are collected by testing each
+//! string of the stringtable once. The resulting [`BlockFilter`] only compares the raw indices of
+//! the tags, so no tag is decoded. [`TagFilter::matches`] can be used as a predicate for single
+//! elements, but resolves the keys and values again for every element.
+//!
+//! # Syntax
+//!
+//! | Expression             | Matches elements that ...                                        |
+//! |------------------------|------------------------------------------------------------------|
+//! | `key`, `key=*`         | have a tag with the given key                                    |
+//! | `key=value`            | have the tag `key=value`                                         |
+//! | `key!=value`           | do not have the tag `key=value`                                  |
+//! | `key in (a, b, c)`     | have one of the tags `key=a`, `key=b` or `key=c`                 |
+//! | `key=pre*`             | have a value for `key` that matches the wildcard pattern         |
+//! | `key~"regex"`          | have a value for `key` that matches the regular expression       |
+//! | `not e`                | do not match `e`                                                 |
+//! | `e1 and e2`            | match both `e1` and `e2`                                         |
+//! | `e1 or e2`             | match `e1` or `e2`                                               |
+//! | `w/e`, `nr/e`          | are of one of the given types (`n`ode, `w`ay, `r`elation) and match `e` |
+//!
+//! `not` binds stronger than `and`, which binds stronger than `or`. Parentheses can be used for
+//! grouping. Keys and values that contain whitespace, special characters or one of the keywords
+//! have to be put in double quotes; quoted values never act as wildcard patterns. Regular
+//! expressions require the `regex` feature.
+
+use crate::block::PrimitiveBlock;
+use crate::elements::{Element, ElementType};
+use crate::error::{new_error, ErrorKind, Result};
+use crate::object::OsmObject;
+use std::str::FromStr;
+
+/// A compiled tag filter expression. See the [module documentation](crate::filter) for the
+/// syntax.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let filter = TagFilter::new("building or amenity in (school, hospital)")?;
+/// let reader = ElementReader::from_path("tests/test.osm.pbf")?;
+/// let mut matches = 0_u64;
+///
+/// reader.for_each(|element| {
+///     if filter.matches(&element) {
+///         matches += 1;
+///     }
+/// })?;
+///
+/// println!("Number of matching elements: {matches}");
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// A filter can also select the ways for
+/// [`IndexedReader::read_ways_and_deps`](crate::indexed::IndexedReader::read_ways_and_deps):
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let filter = TagFilter::new("highway=* and not area=yes")?;
+/// let mut reader = IndexedReader::from_path("tests/test.osm.pbf")?;
+///
+/// reader.read_ways_and_deps(|way| filter.matches(way), |element| {
+///     println!("{:?} {}", element.element_type(), element.id());
+/// })?;
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TagFilter {
+    expr: Expr,
+}
+
+impl TagFilter {
+    /// Parses the given filter expression.
+    pub fn new(expr: &str) -> Result<TagFilter> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: expr.len(),
+        };
+        let expr = parser.parse_or()?;
+        if let Some(&(position, _)) = parser.tokens.get(parser.pos) {
+            return Err(filter_error("unexpected token", position));
+        }
+        Ok(TagFilter { expr })
+    }
+
+    /// Returns true if the given element matches the filter. The keys and values are looked up
+    /// with the tag methods of the element on every call, see [`for_block`](Self::for_block) to
+    /// match all elements of a block.
+    pub fn matches<'a, O: OsmObject<'a>>(&self, object: &O) -> bool {
+        self.expr.matches(object)
+    }
+
+    /// Compiles the filter against the stringtable of the given block.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let filter = TagFilter::new("building=y*")?;
+    /// let mut matches = 0_u64;
+    ///
+    /// for blob in BlobReader::from_path("tests/test.osm.pbf")? {
+    ///     if let BlobDecode::OsmData(block) = blob?.decode()? {
+    ///         let block_filter = filter.for_block(&block);
+    ///         matches += block.elements().filter(|e| block_filter.matches(e)).count() as u64;
+    ///     }
+    /// }
+    ///
+    /// println!("Number of matching elements: {matches}");
+    /// # assert_eq!(matches, 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn for_block(&self, block: &PrimitiveBlock) -> BlockFilter {
+        BlockFilter {
+            expr: self.expr.compile(block),
+        }
+    }
+}
+
+/// A [`TagFilter`] that was compiled against the stringtable of a block with
+/// [`TagFilter::for_block`]. It is only valid for the elements of that block.
+#[derive(Clone, Debug)]
+pub struct BlockFilter {
+    expr: BlockExpr,
+}
+
+impl BlockFilter {
+    /// Returns true if the given element of the block matches the filter.
+    pub fn matches(&self, element: &Element) -> bool {
+        match element {
+            Element::Node(node) => self.expr.matches(ElementType::Node, &node.raw_tags()),
+            Element::DenseNode(node) => self.expr.matches(
+                ElementType::Node,
+                &node.raw_tags().map(|(k, v)| (k as u32, v as u32)),
+            ),
+            Element::Way(way) => self.expr.matches(ElementType::Way, &way.raw_tags()),
+            Element::Relation(relation) => self
+                .expr
+                .matches(ElementType::Relation, &relation.raw_tags()),
+        }
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<TagFilter> {
+        TagFilter::new(s)
+    }
+}
+
+fn filter_error(message: &str, position: usize) -> crate::error::Error {
+    new_error(ErrorKind::InvalidFilter {
+        message: message.to_string(),
+        position,
+    })
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Tag { key: String, value: ValueMatch },
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Scoped { types: TypeSet, expr: Box<Expr> },
+}
+
+impl Expr {
+    fn matches<'a, O: OsmObject<'a>>(&self, object: &O) -> bool {
+        match self {
+            Expr::Tag { key, value } => match value {
+                ValueMatch::Any => object.has_tag(key),
+                ValueMatch::Exact(value) => object.has_tag_value(key, value),
+                ValueMatch::List(values) => values.iter().any(|v| object.has_tag_value(key, v)),
+                ValueMatch::Wildcard(pattern) => {
+                    object.tag(key).is_some_and(|v| pattern.matches(v))
+                }
+                #[cfg(feature = "regex")]
+                ValueMatch::Regex(regex) => object.tag(key).is_some_and(|v| regex.is_match(v)),
+            },
+            Expr::Not(expr) => !expr.matches(object),
+            Expr::And(exprs) => exprs.iter().all(|e| e.matches(object)),
+            Expr::Or(exprs) => exprs.iter().any(|e| e.matches(object)),
+            Expr::Scoped { types, expr } => {
+                types.contains(object.element_type()) && expr.matches(object)
+            }
+        }
+    }
+
+    fn compile(&self, block: &PrimitiveBlock) -> BlockExpr {
+        match self {
+            Expr::Tag { key, value } => {
+                let keys = block.string_indices(key);
+                let values = if keys.is_empty() {
+                    // No element of the block has the key, so the values do not matter
+                    ValueIds::Ids(vec![])
+                } else {
+                    match value {
+                        ValueMatch::Any => ValueIds::Any,
+                        ValueMatch::Exact(value) => ValueIds::Ids(block.string_indices(value)),
+                        ValueMatch::List(values) => {
+                            let mut ids: Vec<u32> = values
+                                .iter()
+                                .flat_map(|v| block.string_indices(v))
+                                .collect();
+                            ids.sort_unstable();
+                            ids.dedup();
+                            ValueIds::Ids(ids)
+                        }
+                        ValueMatch::Wildcard(pattern) => {
+                            ValueIds::matching(block, |v| pattern.matches(v))
+                        }
+                        #[cfg(feature = "regex")]
+                        ValueMatch::Regex(regex) => {
+                            ValueIds::matching(block, |v| regex.is_match(v))
+                        }
+                    }
+                };
+                BlockExpr::Tag { keys, values }
+            }
+            Expr::Not(expr) => BlockExpr::Not(Box::new(expr.compile(block))),
+            Expr::And(exprs) => BlockExpr::And(exprs.iter().map(|e| e.compile(block)).collect()),
+            Expr::Or(exprs) => BlockExpr::Or(exprs.iter().map(|e| e.compile(block)).collect()),
+            Expr::Scoped { types, expr } => BlockExpr::Scoped {
+                types: *types,
+                expr: Box::new(expr.compile(block)),
+            },
+        }
+    }
+}
+
+/// An [`Expr`] with keys and values resolved to the stringtable indices of a block.
+#[derive(Clone, Debug)]
+enum BlockExpr {
+    /// Matches a tag with one of the key indices and a matching value index.
+    Tag {
+        keys: Vec<u32>,
+        values: ValueIds,
+    },
+    Not(Box<BlockExpr>),
+    And(Vec<BlockExpr>),
+    Or(Vec<BlockExpr>),
+    Scoped {
+        types: TypeSet,
+        expr: Box<BlockExpr>,
+    },
+}
+
+impl BlockExpr {
+    fn matches<I>(&self, element_type: ElementType, raw_tags: &I) -> bool
+    where
+        I: Iterator<Item = (u32, u32)> + Clone,
+    {
+        match self {
+            BlockExpr::Tag { keys, values } => raw_tags
+                .clone()
+                .any(|(k, v)| keys.contains(&k) && values.contains(v)),
+            BlockExpr::Not(expr) => !expr.matches(element_type, raw_tags),
+            BlockExpr::And(exprs) => exprs.iter().all(|e| e.matches(element_type, raw_tags)),
+            BlockExpr::Or(exprs) => exprs.iter().any(|e| e.matches(element_type, raw_tags)),
+            BlockExpr::Scoped { types, expr } => {
+                types.contains(element_type) && expr.matches(element_type, raw_tags)
+            }
+        }
+    }
+}
+
+/// The stringtable indices of the values that a tag expression accepts.
+#[derive(Clone, Debug)]
+enum ValueIds {
+    Any,
+    /// Sorted indices.
+    Ids(Vec<u32>),
+    /// Whether the string at each index matches.
+    Mask(Vec<bool>),
+}
+
+impl ValueIds {
+    /// Tests each valid UTF-8 string of the stringtable with the predicate.
+    fn matching(block: &PrimitiveBlock, predicate: impl Fn(&str) -> bool) -> ValueIds {
+        ValueIds::Mask(
+            (0..block.string_count())
+                .map(|index| {
+                    block
+                        .string(index)
+                        .and_then(|s| std::str::from_utf8(s).ok())
+                        .is_some_and(&predicate)
+                })
+                .collect(),
+        )
+    }
+
+    fn contains(&self, index: u32) -> bool {
+        match self {
+            ValueIds::Any => true,
+            ValueIds::Ids(ids) => ids.binary_search(&index).is_ok(),
+            ValueIds::Mask(mask) => mask.get(index as usize).copied().unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum ValueMatch {
+    Any,
+    Exact(String),
+    List(Vec<String>),
+    Wildcard(Wildcard),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+/// A pattern where `*` matches any sequence of characters.
+#[derive(Clone, Debug)]
+struct Wildcard {
+    /// The literal parts between the asterisks. There are at least two parts.
+    parts: Vec<String>,
+}
+
+impl Wildcard {
+    fn matches(&self, s: &str) -> bool {
+        let (first, rest) = self.parts.split_first().unwrap();
+        let (last, middle) = rest.split_last().unwrap();
+        let Some(s) = s.strip_prefix(first.as_str()) else {
+            return false;
+        };
+        let Some(mut s) = s.strip_suffix(last.as_str()) else {
+            return false;
+        };
+        for part in middle {
+            match s.find(part.as_str()) {
+                Some(i) => s = &s[i + part.len()..],
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TypeSet {
+    node: bool,
+    way: bool,
+    relation: bool,
+}
+
+impl TypeSet {
+    fn parse(s: &str) -> Option<TypeSet> {
+        let mut types = TypeSet {
+            node: false,
+            way: false,
+            relation: false,
+        };
+        for c in s.chars() {
+            match c {
+                'n' => types.node = true,
+                'w' => types.way = true,
+                'r' => types.relation = true,
+                _ => return None,
+            }
+        }
+        Some(types)
+    }
+
+    fn contains(&self, element_type: ElementType) -> bool {
+        match element_type {
+            ElementType::Node => self.node,
+            ElementType::Way => self.way,
+            ElementType::Relation => self.relation,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Comma,
+    Eq,
+    NotEq,
+    Tilde,
+    Slash,
+    Word(String),
+    Quoted(String),
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | ',' | '=' | '!' | '~' | '/' | '"')
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '=' => Token::Eq,
+            '~' => Token::Tilde,
+            '/' => Token::Slash,
+            '!' => match chars.next() {
+                Some((_, '=')) => Token::NotEq,
+                _ => return Err(filter_error("expected '=' after '!'", pos)),
+            },
+            '"' => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => quoted.push(c),
+                            None => return Err(filter_error("unterminated string", pos)),
+                        },
+                        Some((_, c)) => quoted.push(c),
+                        None => return Err(filter_error("unterminated string", pos)),
+                    }
+                }
+                Token::Quoted(quoted)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if !is_word_char(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push((pos, token));
+    }
+    Ok(tokens)
+}
+
+fn is_keyword(word: &str) -> bool {
+    matches!(word, "and" | "or" | "not" | "in")
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Length of the expression, used as position for errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(pos, _)| pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    fn expect(&mut self, expected: Token, message: &str) -> Result<()> {
+        if self.peek() == Some(&expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(filter_error(message, self.position()))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut exprs = vec![self.parse_and()?];
+        while self.is_word("or") {
+            self.pos += 1;
+            exprs.push(self.parse_and()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::Or(exprs)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut exprs = vec![self.parse_not()?];
+        while self.is_word("and") {
+            self.pos += 1;
+            exprs.push(self.parse_not()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::And(exprs)
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.is_word("not") {
+            self.pos += 1;
+            Ok(Expr::Not(Box::new(self.parse_not()?)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen, "expected ')'")?;
+                Ok(expr)
+            }
+            Some(Token::Word(word)) if self.peek() == Some(&Token::Slash) => {
+                let types = TypeSet::parse(&word).ok_or_else(|| {
+                    filter_error("expected element types ('n', 'w' or 'r')", position)
+                })?;
+                self.pos += 1;
+                Ok(Expr::Scoped {
+                    types,
+                    expr: Box::new(self.parse_not()?),
+                })
+            }
+            Some(Token::Word(key)) if !is_keyword(&key) => self.parse_tag(key),
+            Some(Token::Quoted(key)) => self.parse_tag(key),
+            _ => Err(filter_error("expected a key or '('", position)),
+        }
+    }
+
+    fn parse_tag(&mut self, key: String) -> Result<Expr> {
+        let value = match self.peek() {
+            Some(Token::Eq) => {
+                self.pos += 1;
+                self.parse_value()?
+            }
+            Some(Token::NotEq) => {
+                self.pos += 1;
+                let value = self.parse_value()?;
+                return Ok(Expr::Not(Box::new(Expr::Tag { key, value })));
+            }
+            Some(Token::Tilde) => {
+                self.pos += 1;
+                self.parse_regex()?
+            }
+            Some(Token::Word(w)) if w == "in" => {
+                self.pos += 1;
+                self.expect(Token::LParen, "expected '(' after 'in'")?;
+                let mut values = vec![self.parse_literal()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    values.push(self.parse_literal()?);
+                }
+                self.expect(Token::RParen, "expected ',' or ')'")?;
+                ValueMatch::List(values)
+            }
+            _ => ValueMatch::Any,
+        };
+        Ok(Expr::Tag { key, value })
+    }
+
+    fn parse_literal(&mut self) -> Result<String> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => Ok(value),
+            _ => Err(filter_error("expected a value", position)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<ValueMatch> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Word(value)) if value == "*" => Ok(ValueMatch::Any),
+            Some(Token::Word(value)) if value.contains('*') => Ok(ValueMatch::Wildcard(Wildcard {
+                parts: value.split('*').map(str::to_string).collect(),
+            })),
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => Ok(ValueMatch::Exact(value)),
+            _ => Err(filter_error("expected a value", position)),
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    fn parse_regex(&mut self) -> Result<ValueMatch> {
+        let position = self.position();
+        let pattern = self.parse_literal()?;
+        regex::Regex::new(&pattern)
+            .map(ValueMatch::Regex)
+            .map_err(|e| filter_error(&e.to_string(), position))
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn parse_regex(&mut self) -> Result<ValueMatch> {
+        Err(filter_error(
+            "regular expressions require the 'regex' feature",
+            self.position(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::{BlobDecode, BlobReader};
+    use crate::reader::ElementReader;
+
+    fn count(expr: &str) -> usize {
+        let filter = TagFilter::new(expr).unwrap();
+        let reader = ElementReader::from_path("tests/test.osm.pbf").unwrap();
+        let count = reader
+            .par_map_reduce(|e| usize::from(filter.matches(&e)), || 0, |a, b| a + b)
+            .unwrap();
+
+        // The compiled filter matches the same elements
+        let mut block_count = 0;
+        for blob in BlobReader::from_path("tests/test.osm.pbf").unwrap() {
+            if let BlobDecode::OsmData(block) = blob.unwrap().decode().unwrap() {
+                let block_filter = filter.for_block(&block);
+                block_count += block.elements().filter(|e| block_filter.matches(e)).count();
+            }
+        }
+        assert_eq!(block_count, count, "{expr}");
+        count
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(count("building"), 1);
+        assert_eq!(count("building=yes"), 1);
+        assert_eq!(count("building!=yes"), 4);
+        assert_eq!(count("n/building"), 0);
+        assert_eq!(count("wr/building=*"), 1);
+        assert_eq!(count("building in (no, \"yes\")"), 1);
+        assert_eq!(count("building=y*s"), 1);
+        assert_eq!(count("building=\"y*s\""), 0);
+        assert_eq!(count("not building and not (w/foo or r/bar)"), 4);
+        assert_eq!(count("building or not building"), 5);
+        assert_eq!(count("unknown or building=n*"), 0);
+        #[cfg(feature = "regex")]
+        assert_eq!(count("name~\"^tri.*e$\" and w/name"), 1);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let wildcard = Wildcard {
+            parts: "a*b*c".split('*').map(str::to_string).collect(),
+        };
+        assert!(wildcard.matches("abc"));
+        assert!(wildcard.matches("a-b-b-c"));
+        assert!(!wildcard.matches("ac"));
+        assert!(!wildcard.matches("abcd"));
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        for (expr, position) in [
+            ("", 0),
+            ("highway and", 11),
+            ("(highway", 8),
+            ("x/highway", 0),
+            ("highway in (a b)", 14),
+            ("name=\"a", 5),
+            ("highway )", 8),
+        ] {
+            let err = TagFilter::new(expr).unwrap_err();
+            match err.kind() {
+                ErrorKind::InvalidFilter { position: p, .. } => assert_eq!(*p, position, "{expr}"),
+                _ => panic!("unexpected error kind"),
+            }
+        }
+    }
+}
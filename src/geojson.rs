@@ -0,0 +1,315 @@
+//! Export points, lines and areas as GeoJSON
+//!
+//! Nodes are exported as `Point`s, ways as `LineString`s or, if they are closed and tagged as an
+//! area, as `Polygon`s, and multipolygon relations as `MultiPolygon`s. The tags are the properties
+//! of a feature. Metadata is added with the property names `@version`, `@timestamp`,
+//! `@changeset`, `@uid` and `@user`. The feature id is the element type and id, for example
+//! `"way/107"`.
+//!
+//! As required by RFC 7946, the outer rings of polygons are written counterclockwise and the inner
+//! rings clockwise, independent of the direction of the ways.
+
+use crate::error::Result;
+use crate::geometry::{
+    read_features, Coord, ExportOptions, Feature, Geometry, NodeLocationStore, SortedNodeLocations,
+};
+use crate::util::{format_timestamp, push_json_string};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The output format of a [`GeoJsonExporter`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum GeoJsonFormat {
+    /// A single `FeatureCollection` object.
+    #[default]
+    FeatureCollection,
+    /// One `Feature` object per line (GeoJSONSeq), which can be processed as a stream.
+    Sequence,
+}
+
+/// Writes the nodes, ways and multipolygon relations of a PBF file as GeoJSON.
+///
+/// Features are written as soon as they are read, so the output does not have to fit into
+/// memory.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let mut exporter = GeoJsonExporter::new();
+/// exporter.set_options(ExportOptions::new().filter(TagFilter::new("building")?));
+/// exporter.set_format(GeoJsonFormat::Sequence);
+///
+/// let mut buffer = vec![];
+/// let features = exporter.export("tests/test.osm.pbf", &mut buffer)?;
+///
+/// println!("{}", String::from_utf8_lossy(&buffer));
+/// # assert_eq!(features, 1);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct GeoJsonExporter {
+    options: ExportOptions,
+    format: GeoJsonFormat,
+}
+
+impl Default for GeoJsonExporter {
+    fn default() -> Self {
+        GeoJsonExporter::new()
+    }
+}
+
+impl GeoJsonExporter {
+    /// Creates an exporter that writes all tagged nodes, ways and multipolygon relations as a
+    /// `FeatureCollection` without metadata.
+    pub fn new() -> GeoJsonExporter {
+        GeoJsonExporter {
+            options: ExportOptions::new(),
+            format: GeoJsonFormat::default(),
+        }
+    }
+
+    /// Sets the options that select the exported features (default: [`ExportOptions::new`]).
+    /// With [`ExportOptions::metadata`], the metadata is added to the properties.
+    pub fn set_options(&mut self, options: ExportOptions) {
+        self.options = options;
+    }
+
+    /// Sets the output format (default: [`GeoJsonFormat::FeatureCollection`]).
+    pub fn set_format(&mut self, format: GeoJsonFormat) {
+        self.format = format;
+    }
+
+    /// Reads the PBF file at `path` and writes the features to `writer`. Returns the number of
+    /// exported features.
+    ///
+    /// Node locations are kept in a [`SortedNodeLocations`] store to build the way geometries,
+    /// unless the file stores the locations on the ways (see
+    /// [`Way::node_locations`](crate::elements::Way::node_locations)).
+    pub fn export<P, W>(&self, path: P, writer: W) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        W: Write,
+    {
+        self.export_with_store(path, writer, &mut SortedNodeLocations::new())
+    }
+
+    /// Like [`export`](GeoJsonExporter::export), but keeps the node locations in the given store.
+    pub fn export_with_store<P, W, S>(&self, path: P, mut writer: W, store: &mut S) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        W: Write,
+        S: NodeLocationStore + ?Sized,
+    {
+        let mut count = 0;
+        let mut line = String::new();
+        if self.format == GeoJsonFormat::FeatureCollection {
+            writer.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[")?;
+        }
+        read_features(path, &self.options, store, |feature| {
+            line.clear();
+            if self.format == GeoJsonFormat::FeatureCollection {
+                line.push_str(if count == 0 { "\n" } else { ",\n" });
+            }
+            write_feature(&mut line, feature);
+            if self.format == GeoJsonFormat::Sequence {
+                line.push('\n');
+            }
+            writer.write_all(line.as_bytes())?;
+            count += 1;
+            Ok(())
+        })?;
+        if self.format == GeoJsonFormat::FeatureCollection {
+            writer.write_all(b"\n]}\n")?;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Reads the PBF file at `path` and writes the features to a new file at `output_path`.
+    /// Returns the number of exported features.
+    pub fn export_to_path<P, Q>(&self, path: P, output_path: Q) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let file = File::create(output_path)?;
+        self.export(path, io::BufWriter::new(file))
+    }
+}
+
+/// Appends a feature as a GeoJSON object.
+fn write_feature(out: &mut String, feature: &Feature) {
+    out.push_str("{\"type\":\"Feature\",\"id\":\"");
+    out.push_str(feature.element_type.as_str());
+    out.push('/');
+    out.push_str(&feature.id.to_string());
+    out.push_str("\",\"geometry\":");
+    write_geometry(out, &feature.geometry);
+    out.push_str(",\"properties\":{");
+    let mut first = true;
+    let mut property = |out: &mut String, key: &str| {
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        push_json_string(out, key);
+        out.push(':');
+    };
+    for (key, value) in &feature.tags {
+        property(out, key);
+        push_json_string(out, value);
+    }
+    if let Some(metadata) = &feature.metadata {
+        let numbers = [
+            ("@version", metadata.version.map(i64::from)),
+            ("@changeset", metadata.changeset),
+            ("@uid", metadata.uid.map(i64::from)),
+        ];
+        for (key, value) in numbers {
+            if let Some(value) = value {
+                property(out, key);
+                out.push_str(&value.to_string());
+            }
+        }
+        if let Some(timestamp) = metadata.milli_timestamp {
+            property(out, "@timestamp");
+            push_json_string(out, &format_timestamp(timestamp));
+        }
+        if let Some(user) = &metadata.user {
+            property(out, "@user");
+            push_json_string(out, user);
+        }
+    }
+    out.push_str("}}");
+}
+
+fn write_geometry(out: &mut String, geometry: &Geometry) {
+    fn coord(out: &mut String, &(x, y): &Coord) {
+        out.push_str(&format!("[{x},{y}]"));
+    }
+    fn list<T>(out: &mut String, items: &[T], mut f: impl FnMut(&mut String, &T)) {
+        out.push('[');
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            f(out, item);
+        }
+        out.push(']');
+    }
+    fn rings(out: &mut String, rings: &[Vec<Coord>]) {
+        out.push('[');
+        for (i, ring) in rings.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            // The outer ring is counterclockwise, the inner rings are clockwise
+            if (signed_area(ring) < 0.0) == (i == 0) {
+                let reversed: Vec<Coord> = ring.iter().rev().copied().collect();
+                list(out, &reversed, coord);
+            } else {
+                list(out, ring, coord);
+            }
+        }
+        out.push(']');
+    }
+
+    let name = match geometry {
+        Geometry::Point(_) => "Point",
+        Geometry::LineString(_) => "LineString",
+        Geometry::Polygon(_) => "Polygon",
+        Geometry::MultiPolygon(_) => "MultiPolygon",
+    };
+    out.push_str("{\"type\":\"");
+    out.push_str(name);
+    out.push_str("\",\"coordinates\":");
+    match geometry {
+        Geometry::Point(point) => coord(out, point),
+        Geometry::LineString(line) => list(out, line, coord),
+        Geometry::Polygon(polygon) => rings(out, polygon),
+        Geometry::MultiPolygon(polygons) => list(out, polygons, |out, polygon| rings(out, polygon)),
+    }
+    out.push('}');
+}
+
+/// Returns twice the area of a closed ring, which is positive if it is counterclockwise.
+fn signed_area(ring: &[Coord]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elements::ElementType;
+    use crate::geometry::FeatureMetadata;
+
+    #[test]
+    fn test_write_feature() {
+        let feature = Feature {
+            element_type: ElementType::Node,
+            id: 42,
+            tags: vec![
+                ("name".to_string(), "\"Quote\" \\ \n".to_string()),
+                ("amenity".to_string(), "cafe".to_string()),
+            ],
+            metadata: Some(FeatureMetadata {
+                version: Some(2),
+                user: Some("user".to_string()),
+                ..Default::default()
+            }),
+            geometry: Geometry::Point((11.5, -52.25)),
+        };
+        let mut out = String::new();
+        write_feature(&mut out, &feature);
+        assert_eq!(
+            out,
+            r#"{"type":"Feature","id":"node/42","geometry":{"type":"Point","coordinates":[11.5,-52.25]},"properties":{"name":"\"Quote\" \\ \n","amenity":"cafe","@version":2,"@user":"user"}}"#
+        );
+
+        out.clear();
+        let ring = vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)];
+        write_geometry(&mut out, &Geometry::MultiPolygon(vec![vec![ring]]));
+        assert_eq!(
+            out,
+            r#"{"type":"MultiPolygon","coordinates":[[[[0,0],[1,0],[0,1],[0,0]]]]}"#
+        );
+    }
+
+    #[test]
+    fn test_ring_orientation() {
+        let clockwise = vec![(0.0, 0.0), (0.0, 2.0), (2.0, 2.0), (2.0, 0.0), (0.0, 0.0)];
+        let counterclockwise = vec![(0.5, 0.5), (1.0, 0.5), (1.0, 1.0), (0.5, 0.5)];
+        assert!(signed_area(&clockwise) < 0.0);
+        assert!(signed_area(&counterclockwise) > 0.0);
+
+        // Both rings are reversed
+        let mut out = String::new();
+        write_geometry(
+            &mut out,
+            &Geometry::Polygon(vec![clockwise, counterclockwise]),
+        );
+        assert_eq!(
+            out,
+            r#"{"type":"Polygon","coordinates":[[[0,0],[2,0],[2,2],[0,2],[0,0]],[[0.5,0.5],[1,1],[1,0.5],[0.5,0.5]]]}"#
+        );
+
+        // Rings with the right orientation are unchanged
+        let polygon = vec![
+            vec![(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 0.0)],
+            vec![(0.5, 0.25), (1.5, 1.25), (1.5, 0.25), (0.5, 0.25)],
+        ];
+        out.clear();
+        write_geometry(&mut out, &Geometry::MultiPolygon(vec![polygon]));
+        assert_eq!(
+            out,
+            r#"{"type":"MultiPolygon","coordinates":[[[[0,0],[2,0],[2,2],[0,0]],[[0.5,0.25],[1.5,1.25],[1.5,0.25],[0.5,0.25]]]]}"#
+        );
+    }
+}
@@ -0,0 +1,851 @@
+//! Build point, line and area geometries from elements
+//!
+//! This is the common part of the exporters for geographic file formats. Elements are read in up
+//! to four parallel passes over the file:
+//!
+//! 1. collect the multipolygon relations,
+//! 2. collect the ids of the nodes of the ways that are exported or are members of a multipolygon,
+//! 3. build the points and store the locations of the collected node ids,
+//! 4. build the lines and areas of the ways and keep the members of the multipolygons.
+//!
+//! The second pass and the stored locations are skipped if the file stores the node locations on
+//! the ways (`LocationsOnWays`).
+
+use crate::blob::{Blob, BlobDecode, BlobReader};
+use crate::block::PrimitiveBlock;
+use crate::elements::{Element, ElementType, RelMemberType, Relation, Way};
+use crate::error::{new_error, ErrorKind, Result};
+use crate::filter::{BlockFilter, TagFilter};
+use crate::idset::IdSet;
+use crate::object::OsmObject;
+use crate::pipeline::{par_map_blobs_ordered, par_map_reduce_blobs, PipelineOptions};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A coordinate as longitude and latitude in degrees.
+pub(crate) type Coord = (f64, f64);
+
+/// The keys of tags that turn a closed way into an area, unless it is tagged with `area=no`.
+const AREA_KEYS: &[&str] = &[
+    "aeroway",
+    "amenity",
+    "building",
+    "building:part",
+    "craft",
+    "historic",
+    "landuse",
+    "leisure",
+    "man_made",
+    "military",
+    "natural",
+    "office",
+    "place",
+    "shop",
+    "sport",
+    "tourism",
+    "water",
+];
+
+/// The geometry of a feature.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Geometry {
+    Point(Coord),
+    LineString(Vec<Coord>),
+    /// An outer ring followed by the inner rings.
+    Polygon(Vec<Vec<Coord>>),
+    MultiPolygon(Vec<Vec<Vec<Coord>>>),
+}
+
+/// The reason why a multipolygon relation could not be assembled to an area.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InvalidArea {
+    /// The member way with the given id is missing or has nodes without a location.
+    MissingWay(i64),
+    /// The outer or inner ways cannot be joined to closed rings.
+    OpenRing,
+    /// The relation has no outer ways.
+    NoOuterRing,
+    /// An inner ring is not inside of any outer ring.
+    InnerRingOutside,
+}
+
+impl fmt::Display for InvalidArea {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidArea::MissingWay(id) => write!(f, "member way {id} is missing or incomplete"),
+            InvalidArea::OpenRing => write!(f, "the member ways do not form closed rings"),
+            InvalidArea::NoOuterRing => write!(f, "no outer ring"),
+            InvalidArea::InnerRingOutside => {
+                write!(f, "an inner ring is outside of the outer rings")
+            }
+        }
+    }
+}
+
+/// What an exporter does with multipolygon relations that cannot be assembled to an area. Invalid
+/// areas are never exported.
+#[derive(Clone, Default)]
+pub enum InvalidAreaPolicy {
+    /// Invalid areas are skipped.
+    #[default]
+    Skip,
+    /// The export stops with an [`ErrorKind::InvalidArea`] error.
+    Error,
+    /// The callback is called with the relation id and the reason of each invalid area, which is
+    /// then skipped.
+    Callback(InvalidAreaCallback),
+}
+
+/// A callback for [`InvalidAreaPolicy::Callback`] that receives the relation id and the reason.
+pub type InvalidAreaCallback = Arc<dyn Fn(i64, InvalidArea) + Send + Sync>;
+
+impl fmt::Debug for InvalidAreaPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidAreaPolicy::Skip => write!(f, "Skip"),
+            InvalidAreaPolicy::Error => write!(f, "Error"),
+            InvalidAreaPolicy::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+impl InvalidAreaPolicy {
+    /// Applies the policy to an invalid area.
+    fn report(&self, relation: i64, reason: InvalidArea) -> Result<()> {
+        match self {
+            InvalidAreaPolicy::Skip => Ok(()),
+            InvalidAreaPolicy::Error => Err(new_error(ErrorKind::InvalidArea { relation, reason })),
+            InvalidAreaPolicy::Callback(callback) => {
+                callback(relation, reason);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Optional metadata of a feature.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FeatureMetadata {
+    pub(crate) version: Option<i32>,
+    pub(crate) milli_timestamp: Option<i64>,
+    pub(crate) changeset: Option<i64>,
+    pub(crate) uid: Option<i32>,
+    pub(crate) user: Option<String>,
+}
+
+impl FeatureMetadata {
+    fn new<'a>(object: &impl OsmObject<'a>) -> FeatureMetadata {
+        let metadata = object.metadata();
+        FeatureMetadata {
+            version: metadata.version(),
+            milli_timestamp: metadata.milli_timestamp(),
+            changeset: metadata.changeset(),
+            uid: metadata.uid(),
+            user: metadata
+                .user()
+                .and_then(|user| user.ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// An element with its geometry.
+#[derive(Clone, Debug)]
+pub(crate) struct Feature {
+    pub(crate) element_type: ElementType,
+    pub(crate) id: i64,
+    pub(crate) tags: Vec<(String, String)>,
+    /// Only set if metadata was requested.
+    pub(crate) metadata: Option<FeatureMetadata>,
+    pub(crate) geometry: Geometry,
+}
+
+/// Options that select the features of an exporter, for example with
+/// [`GeoJsonExporter::set_options`](crate::geojson::GeoJsonExporter::set_options).
+///
+/// The options are built by chaining the setters, starting with the defaults of
+/// [`ExportOptions::new`].
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let options = ExportOptions::new()
+///     .filter(TagFilter::new("building")?)
+///     .points(false)
+///     .metadata(true);
+///
+/// let mut exporter = GeoJsonExporter::new();
+/// exporter.set_options(options);
+/// let features = exporter.export("tests/test.osm.pbf", std::io::sink())?;
+/// # assert_eq!(features, 1);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    pub(crate) filter: Option<TagFilter>,
+    pub(crate) points: bool,
+    pub(crate) lines: bool,
+    pub(crate) areas: bool,
+    pub(crate) metadata: bool,
+    pub(crate) invalid_areas: InvalidAreaPolicy,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions::new()
+    }
+}
+
+impl ExportOptions {
+    /// Creates the default options: all tagged nodes, ways and multipolygon relations without
+    /// metadata and [`InvalidAreaPolicy::Skip`].
+    pub fn new() -> ExportOptions {
+        ExportOptions {
+            filter: None,
+            points: true,
+            lines: true,
+            areas: true,
+            metadata: false,
+            invalid_areas: InvalidAreaPolicy::default(),
+        }
+    }
+
+    /// Only exports elements that match the given filter (default: all elements with tags).
+    /// Multipolygon relations have to match the filter, their member ways do not.
+    pub fn filter(mut self, filter: TagFilter) -> ExportOptions {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Sets whether nodes are exported as points (default: true).
+    pub fn points(mut self, points: bool) -> ExportOptions {
+        self.points = points;
+        self
+    }
+
+    /// Sets whether ways that are not areas are exported as line strings (default: true).
+    pub fn lines(mut self, lines: bool) -> ExportOptions {
+        self.lines = lines;
+        self
+    }
+
+    /// Sets whether closed ways that are areas are exported as polygons and multipolygon
+    /// relations as multipolygons (default: true). Multipolygon relations need an additional pass
+    /// over the file.
+    pub fn areas(mut self, areas: bool) -> ExportOptions {
+        self.areas = areas;
+        self
+    }
+
+    /// Sets whether the version, timestamp, changeset, uid and user of the elements are exported
+    /// (default: false).
+    pub fn metadata(mut self, metadata: bool) -> ExportOptions {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets what happens with multipolygon relations that cannot be assembled to an area, for
+    /// example because an inner ring is outside of the outer rings (default:
+    /// [`InvalidAreaPolicy::Skip`]). Invalid areas are never exported.
+    pub fn invalid_areas(mut self, policy: InvalidAreaPolicy) -> ExportOptions {
+        self.invalid_areas = policy;
+        self
+    }
+
+    /// Compiles the filter against the stringtable of a block.
+    fn block_filter(&self, block: &PrimitiveBlock) -> Option<BlockFilter> {
+        self.filter.as_ref().map(|filter| filter.for_block(block))
+    }
+
+    fn feature<'a>(&self, object: &impl OsmObject<'a>, geometry: Geometry) -> Feature {
+        Feature {
+            element_type: object.element_type(),
+            id: object.id(),
+            tags: object
+                .tags()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            metadata: self.metadata.then(|| FeatureMetadata::new(object)),
+            geometry,
+        }
+    }
+
+    /// Returns true if lines or areas are built from ways.
+    fn way_geometries(&self) -> bool {
+        self.lines || self.areas
+    }
+}
+
+/// Returns true if the element matches the compiled filter or, without a filter, has tags.
+fn matches(filter: &Option<BlockFilter>, element: &Element) -> bool {
+    match filter {
+        Some(filter) => filter.matches(element),
+        None => element.tags().len() > 0,
+    }
+}
+
+/// A multipolygon relation from the first pass.
+struct AreaRelation {
+    id: i64,
+    tags: Vec<(String, String)>,
+    metadata: Option<FeatureMetadata>,
+    outer: Vec<i64>,
+    inner: Vec<i64>,
+}
+
+impl AreaRelation {
+    fn feature(&self, geometry: Geometry) -> Feature {
+        Feature {
+            element_type: ElementType::Relation,
+            id: self.id,
+            tags: self.tags.clone(),
+            metadata: self.metadata.clone(),
+            geometry,
+        }
+    }
+}
+
+/// The node ids and locations of a way.
+#[derive(Clone, Debug, Default)]
+struct WayLine {
+    refs: Vec<i64>,
+    coords: Vec<Coord>,
+}
+
+impl WayLine {
+    fn is_closed(&self) -> bool {
+        self.refs.len() >= 4 && self.refs.first() == self.refs.last()
+    }
+}
+
+/// Stores the node locations that an exporter needs to build the geometries of ways.
+///
+/// Only the locations of the nodes of exported ways and of the members of multipolygons are
+/// inserted, in the order of the file. All locations are inserted before the first call to
+/// [`get`](NodeLocationStore::get), which can happen from multiple threads. The default store is
+/// [`SortedNodeLocations`], other implementations can keep the locations in a memory-mapped file
+/// or an external database, for example.
+pub trait NodeLocationStore: Sync {
+    /// Stores the location of a node as latitude and longitude in decimicrodegrees.
+    fn insert(&mut self, id: i64, lat: i32, lon: i32) -> Result<()>;
+
+    /// Is called after the last location was inserted.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the latitude and longitude of a node in decimicrodegrees, or `None` if the
+    /// location is unknown.
+    fn get(&self, id: i64) -> Option<(i32, i32)>;
+}
+
+/// A [`NodeLocationStore`] that keeps the locations in a flat vector sorted by node id, which
+/// needs 16 bytes per node.
+///
+/// The vector is only sorted if the nodes of the file are not sorted by id.
+#[derive(Clone, Debug, Default)]
+pub struct SortedNodeLocations {
+    locations: Vec<(i64, (i32, i32))>,
+}
+
+impl SortedNodeLocations {
+    /// Creates an empty store.
+    pub fn new() -> SortedNodeLocations {
+        SortedNodeLocations::default()
+    }
+
+    /// Returns the number of stored locations.
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Returns true if no locations are stored.
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
+
+impl NodeLocationStore for SortedNodeLocations {
+    fn insert(&mut self, id: i64, lat: i32, lon: i32) -> Result<()> {
+        self.locations.push((id, (lat, lon)));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if !self.locations.windows(2).all(|w| w[0].0 < w[1].0) {
+            self.locations.sort_by_key(|&(id, _)| id);
+            self.locations.dedup_by_key(|&mut (id, _)| id);
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: i64) -> Option<(i32, i32)> {
+        self.locations
+            .binary_search_by_key(&id, |&(id, _)| id)
+            .ok()
+            .map(|index| self.locations[index].1)
+    }
+}
+
+/// Reads the file at the given path and calls the closure with each feature that is selected by
+/// the options. Points are passed first, followed by lines and areas of ways and the areas of
+/// multipolygon relations, each in the order of the file. The node locations for the ways are
+/// kept in `locations`.
+pub(crate) fn read_features<P, S, F>(
+    path: P,
+    options: &ExportOptions,
+    locations: &mut S,
+    mut f: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    S: NodeLocationStore + ?Sized,
+    F: FnMut(&Feature) -> Result<()>,
+{
+    let path = path.as_ref();
+    let pipeline = PipelineOptions::default();
+    let relations = if options.areas {
+        collect_area_relations(path, options, &pipeline)?
+    } else {
+        vec![]
+    };
+    let mut member_ids = IdSet::new();
+    for relation in &relations {
+        for &id in relation.outer.iter().chain(&relation.inner) {
+            member_ids.insert(id);
+        }
+    }
+
+    let node_ids = if options.way_geometries() && !has_locations_on_ways(path)? {
+        Some(collect_node_ids(path, options, &member_ids, &pipeline)?)
+    } else {
+        None
+    };
+
+    if options.points || node_ids.is_some() {
+        let reader = BlobReader::from_path(path)?;
+        let map_op = |blob: Blob| {
+            let mut points = vec![];
+            let mut node_locations = vec![];
+            if let BlobDecode::OsmData(block) = blob.decode()? {
+                let filter = options.block_filter(&block);
+                for element in block.elements() {
+                    let (id, lat, lon, point) = match element {
+                        Element::Node(ref node) => (
+                            node.id(),
+                            node.decimicro_lat(),
+                            node.decimicro_lon(),
+                            (node.lon(), node.lat()),
+                        ),
+                        Element::DenseNode(ref node) => (
+                            node.id(),
+                            node.decimicro_lat(),
+                            node.decimicro_lon(),
+                            (node.lon(), node.lat()),
+                        ),
+                        _ => continue,
+                    };
+                    if node_ids.as_ref().is_some_and(|ids| ids.contains(id)) {
+                        node_locations.push((id, (lat, lon)));
+                    }
+                    if options.points && matches(&filter, &element) {
+                        points.push(options.feature(&element, Geometry::Point(point)));
+                    }
+                }
+            }
+            Ok((points, node_locations))
+        };
+        par_map_blobs_ordered(reader, &pipeline, map_op, |(points, node_locations)| {
+            for (id, (lat, lon)) in node_locations {
+                locations.insert(id, lat, lon)?;
+            }
+            points.iter().try_for_each(&mut f)
+        })?;
+        locations.finish()?;
+    }
+
+    let mut members: HashMap<i64, WayLine> = HashMap::new();
+    if options.way_geometries() {
+        let reader = BlobReader::from_path(path)?;
+        let locations = &*locations;
+        let map_op = |blob: Blob| {
+            let mut features = vec![];
+            let mut lines = vec![];
+            if let BlobDecode::OsmData(block) = blob.decode()? {
+                let filter = options.block_filter(&block);
+                for element in block.elements() {
+                    if let Element::Way(ref way) = element {
+                        let is_member = member_ids.contains(way.id());
+                        let matches = matches(&filter, &element);
+                        if !is_member && !matches {
+                            continue;
+                        }
+                        let line = match way_line(way, locations) {
+                            Some(line) => line,
+                            None => continue,
+                        };
+                        if matches {
+                            if let Some(geometry) = way_geometry(way, &line, options) {
+                                features.push(options.feature(&element, geometry));
+                            }
+                        }
+                        if is_member {
+                            lines.push((way.id(), line));
+                        }
+                    }
+                }
+            }
+            Ok((features, lines))
+        };
+        par_map_blobs_ordered(reader, &pipeline, map_op, |(features, lines)| {
+            members.extend(lines);
+            features.iter().try_for_each(&mut f)
+        })?;
+    }
+
+    for relation in &relations {
+        match assemble_area(relation, &members) {
+            Ok(polygons) => f(&relation.feature(Geometry::MultiPolygon(polygons)))?,
+            Err(reason) => options.invalid_areas.report(relation.id, reason)?,
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the header of the file declares that the ways store the node locations.
+fn has_locations_on_ways(path: &Path) -> Result<bool> {
+    match BlobReader::from_path(path)?.next() {
+        Some(blob) => match blob?.decode()? {
+            BlobDecode::OsmHeader(header) => Ok(header
+                .optional_features()
+                .iter()
+                .any(|feature| feature == "LocationsOnWays")),
+            _ => Ok(false),
+        },
+        None => Ok(false),
+    }
+}
+
+/// Collects the multipolygon relations that match the filter in parallel.
+fn collect_area_relations(
+    path: &Path,
+    options: &ExportOptions,
+    pipeline: &PipelineOptions,
+) -> Result<Vec<AreaRelation>> {
+    let mut relations = par_map_reduce_blobs(
+        BlobReader::from_path(path)?,
+        pipeline,
+        |blob| {
+            let mut relations = vec![];
+            if let BlobDecode::OsmData(block) = blob.decode()? {
+                let filter = options.block_filter(&block);
+                let multipolygon = block.resolve_tag("type", Some("multipolygon"));
+                for element in block.elements() {
+                    match element {
+                        Element::Relation(ref relation)
+                            if multipolygon
+                                .as_ref()
+                                .is_some_and(|tag| relation.has_resolved_tag(tag))
+                                && matches(&filter, &element) =>
+                        {
+                            relations.push(area_relation(relation, &element, options));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Ok(relations)
+        },
+        Vec::new,
+        |mut a, b| {
+            a.extend(b);
+            a
+        },
+    )?;
+    relations.sort_by_key(|relation| relation.id);
+    Ok(relations)
+}
+
+/// Returns the tags, metadata and member ways of a multipolygon relation.
+fn area_relation(relation: &Relation, element: &Element, options: &ExportOptions) -> AreaRelation {
+    let mut area = AreaRelation {
+        id: relation.id(),
+        tags: relation
+            .tags()
+            .filter(|(key, _)| *key != "type")
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        metadata: options.metadata.then(|| FeatureMetadata::new(element)),
+        outer: vec![],
+        inner: vec![],
+    };
+    for member in relation.members() {
+        if member.member_type == RelMemberType::Way {
+            match member.role() {
+                Ok("inner") => area.inner.push(member.member_id),
+                _ => area.outer.push(member.member_id),
+            }
+        }
+    }
+    area
+}
+
+/// Collects the ids of the nodes of the ways that are exported or are members of a multipolygon
+/// in parallel.
+fn collect_node_ids(
+    path: &Path,
+    options: &ExportOptions,
+    member_ids: &IdSet,
+    pipeline: &PipelineOptions,
+) -> Result<IdSet> {
+    par_map_reduce_blobs(
+        BlobReader::from_path(path)?,
+        pipeline,
+        |blob| {
+            let mut node_ids = IdSet::new();
+            if let BlobDecode::OsmData(block) = blob.decode()? {
+                let filter = options.block_filter(&block);
+                for element in block.elements() {
+                    if let Element::Way(ref way) = element {
+                        if member_ids.contains(way.id()) || matches(&filter, &element) {
+                            for id in way.refs() {
+                                node_ids.insert(id);
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(node_ids)
+        },
+        IdSet::new,
+        |mut a, b| {
+            a.union_with(&b);
+            a
+        },
+    )
+}
+
+/// Returns the node ids and locations of a way or `None` if a location is missing.
+fn way_line<S: NodeLocationStore + ?Sized>(way: &Way, locations: &S) -> Option<WayLine> {
+    let refs: Vec<i64> = way.refs().collect();
+    let coords: Vec<Coord> = if way.node_locations().len() > 0 {
+        way.node_locations()
+            .map(|location| (location.lon(), location.lat()))
+            .collect()
+    } else {
+        refs.iter()
+            .map(|&id| {
+                locations
+                    .get(id)
+                    .map(|(lat, lon)| (1e-7 * f64::from(lon), 1e-7 * f64::from(lat)))
+            })
+            .collect::<Option<_>>()?
+    };
+    (coords.len() == refs.len() && coords.len() >= 2).then_some(WayLine { refs, coords })
+}
+
+/// Returns the geometry of a way that matches the filter, if its type is selected.
+fn way_geometry(way: &Way, line: &WayLine, options: &ExportOptions) -> Option<Geometry> {
+    if line.is_closed() && is_area(way) {
+        options
+            .areas
+            .then(|| Geometry::Polygon(vec![line.coords.clone()]))
+    } else {
+        options
+            .lines
+            .then(|| Geometry::LineString(line.coords.clone()))
+    }
+}
+
+/// Returns the polygons of a multipolygon relation from the lines of its member ways.
+fn assemble_area(
+    relation: &AreaRelation,
+    members: &HashMap<i64, WayLine>,
+) -> std::result::Result<Vec<Vec<Vec<Coord>>>, InvalidArea> {
+    let lines = |ids: &[i64]| {
+        ids.iter()
+            .map(|&id| members.get(&id).cloned().ok_or(InvalidArea::MissingWay(id)))
+            .collect::<std::result::Result<Vec<_>, _>>()
+    };
+    assemble_multipolygon(lines(&relation.outer)?, lines(&relation.inner)?)
+}
+
+/// Returns true if a closed way with the given tags is an area.
+fn is_area(way: &Way) -> bool {
+    match way.tag("area") {
+        Some("no") => false,
+        Some("yes") => true,
+        _ => AREA_KEYS.iter().any(|key| way.has_tag(key)),
+    }
+}
+
+/// Joins the outer and inner ways of a multipolygon to polygons. Each inner ring is added to the
+/// first outer ring that contains it.
+fn assemble_multipolygon(
+    outer: Vec<WayLine>,
+    inner: Vec<WayLine>,
+) -> std::result::Result<Vec<Vec<Vec<Coord>>>, InvalidArea> {
+    let mut polygons: Vec<Vec<Vec<Coord>>> = join_rings(outer)
+        .ok_or(InvalidArea::OpenRing)?
+        .into_iter()
+        .map(|ring| vec![ring])
+        .collect();
+    if polygons.is_empty() {
+        return Err(InvalidArea::NoOuterRing);
+    }
+    for ring in join_rings(inner).ok_or(InvalidArea::OpenRing)? {
+        let polygon = polygons
+            .iter_mut()
+            .find(|polygon| contains(&polygon[0], ring[0]))
+            .ok_or(InvalidArea::InnerRingOutside)?;
+        polygon.push(ring);
+    }
+    Ok(polygons)
+}
+
+/// Joins ways at their end points to closed rings.
+fn join_rings(mut lines: Vec<WayLine>) -> Option<Vec<Vec<Coord>>> {
+    // Start with the first line
+    lines.reverse();
+    let mut rings = vec![];
+    while let Some(mut ring) = lines.pop() {
+        while !ring.is_closed() {
+            let last = *ring.refs.last()?;
+            let index = lines.iter().position(|line| {
+                line.refs.first() == Some(&last) || line.refs.last() == Some(&last)
+            })?;
+            let mut line = lines.swap_remove(index);
+            if line.refs.first() != Some(&last) {
+                line.refs.reverse();
+                line.coords.reverse();
+            }
+            ring.refs.extend_from_slice(&line.refs[1..]);
+            ring.coords.extend_from_slice(&line.coords[1..]);
+        }
+        rings.push(ring.coords);
+    }
+    Some(rings)
+}
+
+/// Returns true if the point is inside of the ring (even-odd rule).
+fn contains(ring: &[Coord], (x, y): Coord) -> bool {
+    let mut inside = false;
+    for (&(x1, y1), &(x2, y2)) in ring.iter().zip(ring.iter().skip(1)) {
+        if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(refs: &[i64]) -> WayLine {
+        WayLine {
+            refs: refs.to_vec(),
+            coords: refs.iter().map(|&id| (id as f64, 0.0)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_join_rings() {
+        // A ring from three ways, one of them reversed
+        let rings = join_rings(vec![line(&[1, 2, 3]), line(&[5, 4, 3]), line(&[5, 6, 1])]).unwrap();
+        assert_eq!(rings.len(), 1);
+        let ids: Vec<f64> = rings[0].iter().map(|c| c.0).collect();
+        assert_eq!(ids, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 1.0]);
+
+        assert_eq!(
+            join_rings(vec![line(&[1, 2, 3, 1]), line(&[4, 5, 6, 4])])
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(join_rings(vec![line(&[1, 2, 3]), line(&[3, 4])]).is_none());
+    }
+
+    #[test]
+    fn test_assemble_multipolygon() {
+        let square = |refs: [i64; 5], size: f64, offset: f64| WayLine {
+            refs: refs.to_vec(),
+            coords: vec![
+                (offset, offset),
+                (offset + size, offset),
+                (offset + size, offset + size),
+                (offset, offset + size),
+                (offset, offset),
+            ],
+        };
+        let outer = vec![
+            square([1, 2, 3, 4, 1], 10.0, 0.0),
+            square([5, 6, 7, 8, 5], 1.0, 20.0),
+        ];
+        let inner = vec![
+            square([9, 10, 11, 12, 9], 1.0, 21.5),
+            square([13, 14, 15, 16, 13], 2.0, 1.0),
+        ];
+        // The first inner ring is outside of both outer rings
+        assert_eq!(
+            assemble_multipolygon(outer.clone(), inner.clone()),
+            Err(InvalidArea::InnerRingOutside)
+        );
+
+        let polygons = assemble_multipolygon(outer, inner[1..].to_vec()).unwrap();
+        assert_eq!(polygons.iter().map(Vec::len).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(polygons[0][1][0], (1.0, 1.0));
+        assert_eq!(
+            assemble_multipolygon(vec![], vec![]),
+            Err(InvalidArea::NoOuterRing)
+        );
+        assert_eq!(
+            assemble_multipolygon(vec![line(&[1, 2, 3])], vec![]),
+            Err(InvalidArea::OpenRing)
+        );
+    }
+
+    #[test]
+    fn test_invalid_area_policy() {
+        let reason = InvalidArea::InnerRingOutside;
+        assert!(InvalidAreaPolicy::Skip.report(1, reason).is_ok());
+        let err = InvalidAreaPolicy::Error.report(1, reason).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::InvalidArea {
+                relation: 1,
+                reason: InvalidArea::InnerRingOutside
+            }
+        ));
+
+        let reported = Arc::new(std::sync::Mutex::new(vec![]));
+        let callback = {
+            let reported = reported.clone();
+            InvalidAreaPolicy::Callback(Arc::new(move |id, reason| {
+                reported.lock().unwrap().push((id, reason));
+            }))
+        };
+        assert!(callback.report(2, reason).is_ok());
+        assert_eq!(*reported.lock().unwrap(), [(2, reason)]);
+    }
+
+    #[test]
+    fn test_sorted_node_locations() {
+        let mut locations = SortedNodeLocations::new();
+        for (id, lat, lon) in [(5, 50, 51), (1, 10, 11), (3, 30, 31), (1, 10, 11)] {
+            locations.insert(id, lat, lon).unwrap();
+        }
+        locations.finish().unwrap();
+        assert_eq!(locations.len(), 3);
+        assert_eq!(locations.get(1), Some((10, 11)));
+        assert_eq!(locations.get(5), Some((50, 51)));
+        assert_eq!(locations.get(2), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! Export points, lines and areas as GeoParquet files
+//!
+//! The exported file contains one row per feature with these columns:
+//!
+//! | column      | type                              | nullable |
+//! |-------------|-----------------------------------|----------|
+//! | `osm_type`  | `Utf8` (`node`, `way`, `relation`) | no      |
+//! | `osm_id`    | `Int64`                           | no       |
+//! | ...         | `Utf8`, see [`GeoParquetExporter::set_tag_columns`] | yes |
+//! | `tags`      | `Map<Utf8, Utf8>`                 | no       |
+//! | `version`   | `Int32`                           | yes      |
+//! | `timestamp` | `Timestamp(Millisecond, "UTC")`   | yes      |
+//! | `changeset` | `Int64`                           | yes      |
+//! | `uid`       | `Int32`                           | yes      |
+//! | `user`      | `Utf8`                            | yes      |
+//! | `geometry`  | `Binary` (WKB)                    | no       |
+//!
+//! The metadata columns are only included if enabled with [`ExportOptions::metadata`], which
+//! [`GeoParquetExporter::new`] does. Nodes are exported as points, ways as line strings or, if
+//! they are closed and tagged as an area, as polygons, and multipolygon relations as multipolygons.
+
+use crate::error::Result;
+use crate::geometry::{
+    read_features, Coord, ExportOptions, Feature, Geometry, NodeLocationStore, SortedNodeLocations,
+};
+use arrow_array::builder::{
+    BinaryBuilder, Int32Builder, Int64Builder, MapBuilder, StringBuilder,
+    TimestampMillisecondBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The names of the columns that cannot be used for tags.
+const RESERVED_COLUMNS: &[&str] = &[
+    "osm_type",
+    "osm_id",
+    "tags",
+    "version",
+    "timestamp",
+    "changeset",
+    "uid",
+    "user",
+    "geometry",
+];
+
+/// Writes the nodes, ways and multipolygon relations of a PBF file as a GeoParquet file.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let mut exporter = GeoParquetExporter::new();
+/// exporter.set_options(
+///     ExportOptions::new()
+///         .filter(TagFilter::new("building or highway")?)
+///         .metadata(false),
+/// );
+/// exporter.set_tag_columns(["building", "name"]);
+///
+/// let mut buffer = vec![];
+/// let rows = exporter.export("tests/test.osm.pbf", &mut buffer)?;
+///
+/// println!("{rows} features, {} bytes", buffer.len());
+/// # assert_eq!(rows, 1);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct GeoParquetExporter {
+    options: ExportOptions,
+    tag_columns: Vec<String>,
+    batch_size: usize,
+}
+
+impl Default for GeoParquetExporter {
+    fn default() -> Self {
+        GeoParquetExporter::new()
+    }
+}
+
+impl GeoParquetExporter {
+    /// Creates an exporter that writes all tagged nodes, ways and multipolygon relations including
+    /// their metadata.
+    pub fn new() -> GeoParquetExporter {
+        GeoParquetExporter {
+            options: ExportOptions::new().metadata(true),
+            tag_columns: vec![],
+            batch_size: 64 * 1024,
+        }
+    }
+
+    /// Sets the options that select the exported features (default: [`ExportOptions::new`] with
+    /// [`ExportOptions::metadata`] enabled). The metadata determines whether the version,
+    /// timestamp, changeset, uid and user columns are included.
+    pub fn set_options(&mut self, options: ExportOptions) {
+        self.options = options;
+    }
+
+    /// Adds a nullable column for each given tag key with the value of the tag. All tags are still
+    /// included in the `tags` column.
+    pub fn set_tag_columns<I, S>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tag_columns = keys.into_iter().map(Into::into).collect();
+    }
+
+    /// Sets the maximum number of rows in a record batch (default: 65536).
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.max(1);
+    }
+
+    /// Reads the PBF file at `path` and writes the features to `writer`. Returns the number of
+    /// exported features.
+    ///
+    /// Node locations are kept in a [`SortedNodeLocations`] store to build the way geometries,
+    /// unless the file stores the locations on the ways (see
+    /// [`Way::node_locations`](crate::elements::Way::node_locations)).
+    ///
+    /// # Errors
+    /// Returns an error if the PBF file cannot be read, if the Parquet file cannot be written or
+    /// if a tag column has the name of another column.
+    pub fn export<P, W>(&self, path: P, writer: W) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        W: Write + Send,
+    {
+        self.export_with_store(path, writer, &mut SortedNodeLocations::new())
+    }
+
+    /// Like [`export`](GeoParquetExporter::export), but keeps the node locations in the given
+    /// store.
+    pub fn export_with_store<P, W, S>(&self, path: P, writer: W, store: &mut S) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        W: Write + Send,
+        S: NodeLocationStore + ?Sized,
+    {
+        if let Some(key) = self
+            .tag_columns
+            .iter()
+            .enumerate()
+            .find(|&(i, key)| {
+                RESERVED_COLUMNS.contains(&key.as_str()) || self.tag_columns[..i].contains(key)
+            })
+            .map(|(_, key)| key)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid tag column name: {key}"),
+            )
+            .into());
+        }
+
+        let mut columns = FeatureColumns::new(&self.tag_columns, self.options.metadata);
+        let schema = columns.finish()?.schema();
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let mut writer = ArrowWriter::try_new(writer, schema, Some(properties))?;
+        let mut stats = GeometryStats::default();
+        let mut rows = 0;
+
+        read_features(path, &self.options, store, |feature| {
+            stats.add(&feature.geometry);
+            columns.append(feature);
+            rows += 1;
+            if columns.len == self.batch_size {
+                writer.write(&columns.finish()?)?;
+            }
+            Ok(())
+        })?;
+        if columns.len > 0 {
+            writer.write(&columns.finish()?)?;
+        }
+        writer.append_key_value_metadata(KeyValue::new("geo".to_string(), stats.metadata()));
+        writer.close()?;
+        Ok(rows)
+    }
+
+    /// Reads the PBF file at `path` and writes the features to a new file at `output_path`.
+    /// Returns the number of exported features.
+    pub fn export_to_path<P, Q>(&self, path: P, output_path: Q) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let file = File::create(output_path)?;
+        self.export(path, io::BufWriter::new(file))
+    }
+}
+
+/// The columns of the exported features.
+struct FeatureColumns {
+    len: usize,
+    osm_types: StringBuilder,
+    osm_ids: Int64Builder,
+    tag_keys: Vec<String>,
+    tag_columns: Vec<StringBuilder>,
+    tags: MapBuilder<StringBuilder, StringBuilder>,
+    metadata: Option<MetadataColumns>,
+    geometries: BinaryBuilder,
+    wkb: Vec<u8>,
+}
+
+struct MetadataColumns {
+    versions: Int32Builder,
+    timestamps: TimestampMillisecondBuilder,
+    changesets: Int64Builder,
+    uids: Int32Builder,
+    users: StringBuilder,
+}
+
+impl FeatureColumns {
+    fn new(tag_keys: &[String], metadata: bool) -> FeatureColumns {
+        FeatureColumns {
+            len: 0,
+            osm_types: StringBuilder::new(),
+            osm_ids: Int64Builder::new(),
+            tag_keys: tag_keys.to_vec(),
+            tag_columns: tag_keys.iter().map(|_| StringBuilder::new()).collect(),
+            tags: MapBuilder::new(None, StringBuilder::new(), StringBuilder::new()),
+            metadata: metadata.then(|| MetadataColumns {
+                versions: Int32Builder::new(),
+                timestamps: TimestampMillisecondBuilder::new().with_timezone("UTC"),
+                changesets: Int64Builder::new(),
+                uids: Int32Builder::new(),
+                users: StringBuilder::new(),
+            }),
+            geometries: BinaryBuilder::new(),
+            wkb: vec![],
+        }
+    }
+
+    fn append(&mut self, feature: &Feature) {
+        self.len += 1;
+        self.osm_types.append_value(feature.element_type.as_str());
+        self.osm_ids.append_value(feature.id);
+        for (key, column) in self.tag_keys.iter().zip(&mut self.tag_columns) {
+            column.append_option(feature.tags.iter().find(|(k, _)| k == key).map(|(_, v)| v));
+        }
+        for (key, value) in &feature.tags {
+            self.tags.keys().append_value(key);
+            self.tags.values().append_value(value);
+        }
+        // Only fails if the number of keys and values differs
+        let _ = self.tags.append(true);
+        if let Some(columns) = &mut self.metadata {
+            let metadata = feature.metadata.clone().unwrap_or_default();
+            columns.versions.append_option(metadata.version);
+            columns.timestamps.append_option(metadata.milli_timestamp);
+            columns.changesets.append_option(metadata.changeset);
+            columns.uids.append_option(metadata.uid);
+            columns.users.append_option(metadata.user.as_deref());
+        }
+        self.wkb.clear();
+        write_wkb(&mut self.wkb, &feature.geometry);
+        self.geometries.append_value(&self.wkb);
+    }
+
+    fn finish(&mut self) -> Result<RecordBatch> {
+        self.len = 0;
+        let mut columns: Vec<(&str, ArrayRef, bool)> = vec![
+            ("osm_type", Arc::new(self.osm_types.finish()), false),
+            ("osm_id", Arc::new(self.osm_ids.finish()), false),
+        ];
+        for (key, column) in self.tag_keys.iter().zip(&mut self.tag_columns) {
+            columns.push((key, Arc::new(column.finish()), true));
+        }
+        columns.push(("tags", Arc::new(self.tags.finish()), false));
+        if let Some(metadata) = &mut self.metadata {
+            columns.extend([
+                (
+                    "version",
+                    Arc::new(metadata.versions.finish()) as ArrayRef,
+                    true,
+                ),
+                ("timestamp", Arc::new(metadata.timestamps.finish()), true),
+                ("changeset", Arc::new(metadata.changesets.finish()), true),
+                ("uid", Arc::new(metadata.uids.finish()), true),
+                ("user", Arc::new(metadata.users.finish()), true),
+            ]);
+        }
+        columns.push(("geometry", Arc::new(self.geometries.finish()), false));
+        Ok(RecordBatch::try_from_iter_with_nullable(columns)?)
+    }
+}
+
+/// The geometry types and the bounding box of the exported features.
+#[derive(Default)]
+struct GeometryStats {
+    types: [bool; 4],
+    bbox: Option<[f64; 4]>,
+}
+
+impl GeometryStats {
+    const TYPE_NAMES: [&'static str; 4] = ["Point", "LineString", "Polygon", "MultiPolygon"];
+
+    fn add(&mut self, geometry: &Geometry) {
+        let (index, coords): (usize, Box<dyn Iterator<Item = &Coord>>) = match geometry {
+            Geometry::Point(coord) => (0, Box::new(std::iter::once(coord))),
+            Geometry::LineString(coords) => (1, Box::new(coords.iter())),
+            // The inner rings are inside of the outer ring
+            Geometry::Polygon(rings) => (2, Box::new(rings.iter().take(1).flatten())),
+            Geometry::MultiPolygon(polygons) => (
+                3,
+                Box::new(
+                    polygons
+                        .iter()
+                        .flat_map(|rings| rings.iter().take(1).flatten()),
+                ),
+            ),
+        };
+        self.types[index] = true;
+        for &(x, y) in coords {
+            let bbox = self.bbox.get_or_insert([x, y, x, y]);
+            *bbox = [
+                bbox[0].min(x),
+                bbox[1].min(y),
+                bbox[2].max(x),
+                bbox[3].max(y),
+            ];
+        }
+    }
+
+    /// Returns the GeoParquet file metadata.
+    fn metadata(&self) -> String {
+        let types: Vec<String> = Self::TYPE_NAMES
+            .iter()
+            .zip(self.types)
+            .filter(|&(_, used)| used)
+            .map(|(name, _)| format!("\"{name}\""))
+            .collect();
+        let bbox = match self.bbox {
+            Some([x1, y1, x2, y2]) => format!(",\"bbox\":[{x1},{y1},{x2},{y2}]"),
+            None => String::new(),
+        };
+        format!(
+            "{{\"version\":\"1.0.0\",\"primary_column\":\"geometry\",\"columns\":{{\"geometry\":\
+            {{\"encoding\":\"WKB\",\"geometry_types\":[{}]{bbox}}}}}}}",
+            types.join(",")
+        )
+    }
+}
+
+/// Appends the geometry as little endian well-known binary.
+fn write_wkb(buffer: &mut Vec<u8>, geometry: &Geometry) {
+    fn header(buffer: &mut Vec<u8>, geometry_type: u32) {
+        buffer.push(1);
+        buffer.extend_from_slice(&geometry_type.to_le_bytes());
+    }
+    fn coords(buffer: &mut Vec<u8>, coords: &[Coord]) {
+        buffer.extend_from_slice(&(coords.len() as u32).to_le_bytes());
+        for &(x, y) in coords {
+            buffer.extend_from_slice(&x.to_le_bytes());
+            buffer.extend_from_slice(&y.to_le_bytes());
+        }
+    }
+    fn polygon(buffer: &mut Vec<u8>, rings: &[Vec<Coord>]) {
+        header(buffer, 3);
+        buffer.extend_from_slice(&(rings.len() as u32).to_le_bytes());
+        for ring in rings {
+            coords(buffer, ring);
+        }
+    }
+
+    match geometry {
+        Geometry::Point((x, y)) => {
+            header(buffer, 1);
+            buffer.extend_from_slice(&x.to_le_bytes());
+            buffer.extend_from_slice(&y.to_le_bytes());
+        }
+        Geometry::LineString(line) => {
+            header(buffer, 2);
+            coords(buffer, line);
+        }
+        Geometry::Polygon(rings) => polygon(buffer, rings),
+        Geometry::MultiPolygon(polygons) => {
+            header(buffer, 6);
+            buffer.extend_from_slice(&(polygons.len() as u32).to_le_bytes());
+            for rings in polygons {
+                polygon(buffer, rings);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wkb() {
+        let mut buffer = vec![];
+        write_wkb(&mut buffer, &Geometry::Point((1.0, 2.0)));
+        let mut expected = vec![1, 1, 0, 0, 0];
+        expected.extend_from_slice(&1.0_f64.to_le_bytes());
+        expected.extend_from_slice(&2.0_f64.to_le_bytes());
+        assert_eq!(buffer, expected);
+
+        buffer.clear();
+        let ring = vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)];
+        write_wkb(&mut buffer, &Geometry::MultiPolygon(vec![vec![ring]]));
+        assert_eq!(&buffer[..9], &[1, 6, 0, 0, 0, 1, 0, 0, 0]);
+        // Polygon header, one ring with four points
+        assert_eq!(&buffer[9..18], &[1, 3, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(&buffer[18..22], &[4, 0, 0, 0]);
+        assert_eq!(buffer.len(), 22 + 4 * 16);
+    }
+
+    #[test]
+    fn test_geo_metadata() {
+        let mut stats = GeometryStats::default();
+        assert_eq!(
+            stats.metadata(),
+            r#"{"version":"1.0.0","primary_column":"geometry","columns":{"geometry":{"encoding":"WKB","geometry_types":[]}}}"#
+        );
+        stats.add(&Geometry::Point((1.0, 2.0)));
+        stats.add(&Geometry::LineString(vec![(-1.0, 3.0), (0.5, 0.5)]));
+        assert!(stats
+            .metadata()
+            .ends_with(r#""geometry_types":["Point","LineString"],"bbox":[-1,0.5,1,3]}}}"#));
+    }
+}
@@ -0,0 +1,503 @@
+//! Read PBF files over HTTP with range requests
+//!
+//! [`HttpRangeReader`] implements [`Read`] and [`Seek`] by fetching byte ranges of a remote file,
+//! so a file on a static file server can be used with [`BlobReader::new_seekable`] or
+//! [`IndexedReader::new`] without downloading it completely.
+//!
+//! [`BlobReader::new_seekable`]: crate::blob::BlobReader::new_seekable
+//! [`IndexedReader::new`]: crate::indexed::IndexedReader::new
+
+use crate::error::Result;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// The default size of the cached blocks in bytes.
+const DEFAULT_BLOCK_SIZE: u64 = 256 * 1024;
+
+/// The default number of cached blocks.
+const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+/// The default number of blocks that are fetched ahead of sequential reads.
+const DEFAULT_READAHEAD_BLOCKS: u64 = 3;
+
+/// A seekable reader for a file on an HTTP server that supports range requests.
+///
+/// The file is read in blocks of a fixed size that are kept in a least recently used cache.
+/// Missing blocks that are needed by the same read are fetched with a single request. If a read
+/// continues where the previous read ended, a few of the following blocks are fetched with the
+/// same request, so reading the header and the content of consecutive blobs does not cost a
+/// request per blob.
+///
+/// The `ETag` or the `Last-Modified` date of the first response is sent as `If-Range` with the
+/// following requests. Reads fail with an error if the remote file was changed in the meantime.
+///
+/// # Example
+/// ```no_run
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let reader = HttpRangeReader::new("https://example.com/region.osm.pbf")?;
+/// let mut reader = IndexedReader::new(reader)?;
+///
+/// reader.for_each_way(|way| {
+///     println!("way {}", way.id());
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct HttpRangeReader {
+    agent: ureq::Agent,
+    url: String,
+    /// The size of the remote file in bytes.
+    len: u64,
+    /// The current position of the reader.
+    pos: u64,
+    /// The position after the last read, used to detect sequential reads.
+    last_end: Option<u64>,
+    block_size: u64,
+    cache_blocks: usize,
+    readahead_blocks: u64,
+    /// Cached blocks by block index.
+    blocks: HashMap<u64, Vec<u8>>,
+    /// Block indices from least to most recently used.
+    lru: VecDeque<u64>,
+    requests: u64,
+    /// The strong `ETag` or the `Last-Modified` date of the remote file, if the server sent one.
+    validator: Option<String>,
+}
+
+impl HttpRangeReader {
+    /// Creates a reader for the file at the given URL. The first block of the file is fetched to
+    /// determine its size and to check that the server supports range requests.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails or if the server ignores the `Range` header.
+    pub fn new(url: &str) -> Result<HttpRangeReader> {
+        Self::with_agent(ureq::Agent::new(), url)
+    }
+
+    /// Creates a reader that uses the given agent for its requests, for example to configure
+    /// timeouts or a proxy.
+    pub fn with_agent(agent: ureq::Agent, url: &str) -> Result<HttpRangeReader> {
+        let mut reader = HttpRangeReader {
+            agent,
+            url: url.to_string(),
+            len: 0,
+            pos: 0,
+            last_end: None,
+            block_size: DEFAULT_BLOCK_SIZE,
+            cache_blocks: DEFAULT_CACHE_BLOCKS,
+            readahead_blocks: DEFAULT_READAHEAD_BLOCKS,
+            blocks: HashMap::new(),
+            lru: VecDeque::new(),
+            requests: 0,
+            validator: None,
+        };
+        let (data, len) = reader.fetch(0, DEFAULT_BLOCK_SIZE)?;
+        reader.len = len;
+        reader.insert_block(0, data);
+        Ok(reader)
+    }
+
+    /// Sets the size of the fetched and cached blocks in bytes (default: 256 KiB). Clears the
+    /// cache.
+    pub fn set_block_size(&mut self, block_size: u64) {
+        self.block_size = block_size.max(1);
+        self.blocks.clear();
+        self.lru.clear();
+    }
+
+    /// Sets the maximum number of cached blocks (default: 64).
+    pub fn set_cache_blocks(&mut self, cache_blocks: usize) {
+        self.cache_blocks = cache_blocks.max(1);
+        self.evict();
+    }
+
+    /// Sets the number of blocks that are fetched ahead of sequential reads (default: 3).
+    pub fn set_readahead_blocks(&mut self, readahead_blocks: u64) {
+        self.readahead_blocks = readahead_blocks;
+    }
+
+    /// Returns the size of the remote file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the remote file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of HTTP requests that were sent so far.
+    pub fn request_count(&self) -> u64 {
+        self.requests
+    }
+
+    /// Fetches `len` bytes at `start`, or less at the end of the file. Returns the bytes and the
+    /// size of the file.
+    fn fetch(&mut self, start: u64, len: u64) -> io::Result<(Vec<u8>, u64)> {
+        self.requests += 1;
+        let range = format!("bytes={}-{}", start, start + len - 1);
+        let mut request = self.agent.get(&self.url).set("Range", &range);
+        if let Some(validator) = &self.validator {
+            request = request.set("If-Range", validator);
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            // The range starts at or after the end of the file
+            Err(ureq::Error::Status(416, response)) => {
+                let total = response
+                    .header("Content-Range")
+                    .and_then(parse_unsatisfied_range)
+                    .ok_or_else(|| invalid_data("missing size of the remote file"))?;
+                return Ok((vec![], total));
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+        };
+        if response.status() != 206 && self.validator.is_some() {
+            // The server sends the whole file if the validator does not match
+            return Err(changed());
+        }
+        if response.status() != 206 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "server does not support range requests",
+            ));
+        }
+        let validator = response
+            .header("ETag")
+            .filter(|etag| !etag.starts_with("W/"))
+            .or_else(|| response.header("Last-Modified"))
+            .map(str::to_string);
+        match (&self.validator, validator) {
+            (None, validator) if self.requests == 1 => self.validator = validator,
+            (Some(expected), Some(validator)) if *expected != validator => return Err(changed()),
+            _ => {}
+        }
+        let (first, last, total) = response
+            .header("Content-Range")
+            .and_then(parse_content_range)
+            .ok_or_else(|| invalid_data("invalid Content-Range header"))?;
+        let expected = (start + len).min(total) - start;
+        if first != start || last + 1 - first != expected {
+            return Err(invalid_data("server returned an unexpected range"));
+        }
+
+        let mut data = Vec::with_capacity(expected as usize);
+        response
+            .into_reader()
+            .take(expected)
+            .read_to_end(&mut data)?;
+        if data.len() as u64 != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "response too short for requested range",
+            ));
+        }
+        Ok((data, total))
+    }
+
+    /// Makes sure that the blocks `first..=last` are cached. Runs of missing blocks are fetched
+    /// with one request each.
+    fn load_blocks(&mut self, first: u64, last: u64) -> io::Result<()> {
+        let mut block = first;
+        while block <= last {
+            if self.blocks.contains_key(&block) {
+                self.touch(block);
+                block += 1;
+                continue;
+            }
+            let mut end = block;
+            while end < last && !self.blocks.contains_key(&(end + 1)) {
+                end += 1;
+            }
+            let start = block * self.block_size;
+            let (data, len) = self.fetch(start, (end - block + 1) * self.block_size)?;
+            if len != self.len {
+                return Err(changed());
+            }
+            for (i, chunk) in data.chunks(self.block_size as usize).enumerate() {
+                self.insert_block(block + i as u64, chunk.to_vec());
+            }
+            block = end + 1;
+        }
+        Ok(())
+    }
+
+    fn insert_block(&mut self, block: u64, data: Vec<u8>) {
+        self.blocks.insert(block, data);
+        self.touch(block);
+        self.evict();
+    }
+
+    /// Marks a block as most recently used.
+    fn touch(&mut self, block: u64) {
+        if let Some(pos) = self.lru.iter().position(|&b| b == block) {
+            self.lru.remove(pos);
+        }
+        self.lru.push_back(block);
+    }
+
+    fn evict(&mut self) {
+        while self.lru.len() > self.cache_blocks {
+            if let Some(block) = self.lru.pop_front() {
+                self.blocks.remove(&block);
+            }
+        }
+    }
+}
+
+impl Read for HttpRangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        let end = (self.pos + buf.len() as u64).min(self.len);
+        let first = self.pos / self.block_size;
+        let mut last = (end - 1) / self.block_size;
+        if self.last_end == Some(self.pos) && !self.blocks.contains_key(&last) {
+            let last_block = (self.len - 1) / self.block_size;
+            last = (last + self.readahead_blocks).min(last_block);
+        }
+        // Blocks of this read must not be evicted before they are copied
+        let last = last.min(first + self.cache_blocks as u64 - 1);
+        let end = end.min((last + 1) * self.block_size);
+        self.load_blocks(first, last)?;
+
+        let mut read = 0;
+        while self.pos + (read as u64) < end {
+            let pos = self.pos + read as u64;
+            let block = self
+                .blocks
+                .get(&(pos / self.block_size))
+                .ok_or_else(|| invalid_data("remote file is shorter than expected"))?;
+            let offset = (pos % self.block_size) as usize;
+            let n = (block.len() - offset).min((end - pos) as usize);
+            if n == 0 {
+                return Err(invalid_data("remote file is shorter than expected"));
+            }
+            buf[read..read + n].copy_from_slice(&block[offset..offset + n]);
+            read += n;
+        }
+        self.pos = end;
+        self.last_end = Some(end);
+        Ok(read)
+    }
+}
+
+impl Seek for HttpRangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        match new_pos {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn changed() -> io::Error {
+    invalid_data("remote file was changed while reading")
+}
+
+/// Parses a header like `bytes 0-99/1234` into the first and last byte and the total size.
+fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+    (first <= last).then_some((first, last, total.parse().ok()?))
+}
+
+/// Parses the total size from a header like `bytes */1234`.
+fn parse_unsatisfied_range(value: &str) -> Option<u64> {
+    value.trim().strip_prefix("bytes */")?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlobReader, ByteOffset, IndexedReader};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Serves `data` with range requests on a local port. Returns the URL and a counter of the
+    /// served requests.
+    fn serve(data: Vec<u8>) -> (String, Arc<AtomicU64>) {
+        serve_versions(Arc::new(Mutex::new((data, "\"v1\"".to_string()))))
+    }
+
+    /// Serves the current version of a file, which is its content and its `ETag`, with range
+    /// requests. Requests with an outdated `If-Range` get the whole file.
+    fn serve_versions(version: Arc<Mutex<(Vec<u8>, String)>>) -> (String, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/test.osm.pbf", listener.local_addr().unwrap());
+        let counter = Arc::new(AtomicU64::new(0));
+        let requests = counter.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                let mut if_range = None;
+                for line in BufReader::new(&mut stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Range: bytes=") {
+                        let (first, last) = value.split_once('-').unwrap();
+                        range = Some((first.parse::<u64>().unwrap(), last.parse::<u64>().unwrap()));
+                    }
+                    if let Some(value) = line.strip_prefix("If-Range: ") {
+                        if_range = Some(value.to_string());
+                    }
+                }
+                requests.fetch_add(1, Ordering::SeqCst);
+                let (data, etag) = version.lock().unwrap().clone();
+                if if_range.is_some_and(|if_range| if_range != etag) {
+                    range = None;
+                }
+                let len = data.len() as u64;
+                let response = match range {
+                    Some((first, _)) if first >= len => {
+                        format!("HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{len}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").into_bytes()
+                    }
+                    Some((first, last)) => {
+                        let last = last.min(len - 1);
+                        let body = &data[first as usize..=last as usize];
+                        let mut response = format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {first}-{last}/{len}\r\nContent-Length: {}\r\nETag: {etag}\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+                        response.extend_from_slice(body);
+                        response
+                    }
+                    None => {
+                        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {len}\r\nETag: {etag}\r\nConnection: close\r\n\r\n").into_bytes();
+                        response.extend_from_slice(&data);
+                        response
+                    }
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+        (url, counter)
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 0-99/1234"), Some((0, 99, 1234)));
+        assert_eq!(parse_content_range("bytes 5-4/1234"), None);
+        assert_eq!(parse_content_range("bytes */1234"), None);
+        assert_eq!(parse_unsatisfied_range("bytes */1234"), Some(1234));
+    }
+
+    #[test]
+    fn test_read_and_seek() {
+        let data: Vec<u8> = (0..10_000_u32).map(|i| (i % 251) as u8).collect();
+        let (url, requests) = serve(data.clone());
+        let mut reader = HttpRangeReader::new(&url).unwrap();
+        reader.set_block_size(1000);
+        reader.set_cache_blocks(4);
+        reader.set_readahead_blocks(2);
+        assert_eq!(reader.len(), 10_000);
+
+        // One request for the blocks 1 to 3
+        let mut buf = vec![0; 2500];
+        reader.seek(SeekFrom::Start(1500)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &data[1500..4000]);
+        assert_eq!(reader.request_count(), 2);
+
+        // A sequential read also fetches the following two blocks
+        reader.read_exact(&mut buf[..100]).unwrap();
+        assert_eq!(&buf[..100], &data[4000..4100]);
+        assert_eq!(reader.request_count(), 3);
+        reader.read_exact(&mut buf[..1900]).unwrap();
+        assert_eq!(&buf[..1900], &data[4100..6000]);
+        assert_eq!(reader.request_count(), 3);
+
+        // The blocks 1 and 2 were evicted
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        reader.read_exact(&mut buf[..10]).unwrap();
+        assert_eq!(reader.request_count(), 4);
+
+        let mut rest = vec![];
+        reader.seek(SeekFrom::End(-50)).unwrap();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[9950..]);
+        assert!(reader.seek(SeekFrom::Current(-20_000)).is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), reader.request_count());
+    }
+
+    #[test]
+    fn test_indexed_reader() {
+        let data = std::fs::read("tests/test.osm.pbf").unwrap();
+        let (url, _) = serve(data);
+
+        let mut reader = HttpRangeReader::new(&url).unwrap();
+        reader.set_block_size(64);
+        let mut blobs = BlobReader::new_seekable(reader).unwrap();
+        let offsets: Vec<_> = (&mut blobs).map(|blob| blob.unwrap().offset()).collect();
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(offsets[0], Some(ByteOffset(0)));
+
+        let mut reader = IndexedReader::new(HttpRangeReader::new(&url).unwrap()).unwrap();
+        let mut ways = vec![];
+        reader.for_each_way(|way| ways.push(way.id())).unwrap();
+        assert_eq!(ways, [107]);
+    }
+
+    #[test]
+    fn test_changed_file() {
+        let data: Vec<u8> = (0..10_000_u32).map(|i| (i % 251) as u8).collect();
+        let version = Arc::new(Mutex::new((data.clone(), "\"v1\"".to_string())));
+        let (url, _) = serve_versions(version.clone());
+        let mut reader = HttpRangeReader::new(&url).unwrap();
+        reader.set_block_size(1000);
+        let mut buf = vec![0; 100];
+        reader.seek(SeekFrom::Start(5000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &data[5000..5100]);
+
+        *version.lock().unwrap() = (data, "\"v2\"".to_string());
+        reader.seek(SeekFrom::Start(8000)).unwrap();
+        let err = reader.read_exact(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("changed"));
+    }
+
+    #[test]
+    fn test_no_range_support() {
+        let (url, _) = serve(vec![1, 2, 3]);
+        // Ask for a file of three bytes, which the test server answers with a range
+        assert_eq!(HttpRangeReader::new(&url).unwrap().len(), 3);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut line = String::new();
+            let mut reader = BufReader::new(&mut stream);
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\nabc");
+        });
+        let err = HttpRangeReader::new(&url).unwrap_err();
+        assert!(err.to_string().contains("range requests"));
+    }
+}
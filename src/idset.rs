@@ -0,0 +1,285 @@
+//! A compact set of element ids
+
+use std::collections::btree_map::{self, BTreeMap};
+
+/// Number of ids per chunk (must be a multiple of 64).
+const CHUNK_BITS: i64 = 4096;
+const CHUNK_WORDS: usize = (CHUNK_BITS / 64) as usize;
+
+type Chunk = Box<[u64; CHUNK_WORDS]>;
+
+/// A set of element ids that is stored as a sparse bitmap.
+///
+/// Ids are grouped into chunks of 4096 consecutive values that are stored as bit fields. This
+/// makes the set much more compact than a `BTreeSet<i64>` or `HashSet<i64>` for the mostly
+/// dense ids of OpenStreetMap elements.
+///
+/// # Example
+/// ```
+/// use osmpbf::IdSet;
+///
+/// let mut set = IdSet::new();
+/// assert!(set.insert(105));
+/// assert!(set.insert(-3));
+/// assert!(!set.insert(105));
+///
+/// assert!(set.contains(105));
+/// assert!(!set.contains(106));
+/// assert_eq!(set.len(), 2);
+/// assert_eq!(set.iter().collect::<Vec<_>>(), [-3, 105]);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IdSet {
+    chunks: BTreeMap<i64, Chunk>,
+    len: u64,
+}
+
+fn split(id: i64) -> (i64, usize, u64) {
+    let bit = id.rem_euclid(CHUNK_BITS) as usize;
+    (id.div_euclid(CHUNK_BITS), bit / 64, 1 << (bit % 64))
+}
+
+impl IdSet {
+    /// Creates an empty set.
+    pub fn new() -> IdSet {
+        IdSet::default()
+    }
+
+    /// Adds an id to the set. Returns true if the id was not present before.
+    pub fn insert(&mut self, id: i64) -> bool {
+        let (key, word, mask) = split(id);
+        let chunk = self
+            .chunks
+            .entry(key)
+            .or_insert_with(|| Box::new([0; CHUNK_WORDS]));
+        if chunk[word] & mask == 0 {
+            chunk[word] |= mask;
+            self.len += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if the set contains the given id.
+    pub fn contains(&self, id: i64) -> bool {
+        let (key, word, mask) = split(id);
+        self.chunks
+            .get(&key)
+            .is_some_and(|chunk| chunk[word] & mask != 0)
+    }
+
+    /// Returns the number of ids in the set.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the set contains no ids.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the ids in ascending order.
+    pub fn iter(&self) -> IdSetIter<'_> {
+        IdSetIter {
+            chunks: self.chunks.iter(),
+            current: None,
+        }
+    }
+
+    /// Adds all ids of `other` to this set.
+    pub fn union_with(&mut self, other: &IdSet) {
+        for (key, other_chunk) in &other.chunks {
+            let chunk = self
+                .chunks
+                .entry(*key)
+                .or_insert_with(|| Box::new([0; CHUNK_WORDS]));
+            for (word, other_word) in chunk.iter_mut().zip(other_chunk.iter()) {
+                self.len += u64::from((other_word & !*word).count_ones());
+                *word |= other_word;
+            }
+        }
+    }
+
+    /// Adds all ids of `other` to this set and the ids that were already present to
+    /// `duplicates`. This is cheaper than computing the intersection first, because it does not
+    /// allocate a temporary set and only visits the chunks of `other`.
+    pub fn union_with_duplicates(&mut self, other: &IdSet, duplicates: &mut IdSet) {
+        for (key, other_chunk) in &other.chunks {
+            let chunk = self
+                .chunks
+                .entry(*key)
+                .or_insert_with(|| Box::new([0; CHUNK_WORDS]));
+            if chunk
+                .iter()
+                .zip(other_chunk.iter())
+                .any(|(w, o)| w & o != 0)
+            {
+                let duplicate_chunk = duplicates
+                    .chunks
+                    .entry(*key)
+                    .or_insert_with(|| Box::new([0; CHUNK_WORDS]));
+                for ((word, other_word), duplicate_word) in chunk
+                    .iter()
+                    .zip(other_chunk.iter())
+                    .zip(duplicate_chunk.iter_mut())
+                {
+                    let common = word & other_word;
+                    duplicates.len += u64::from((common & !*duplicate_word).count_ones());
+                    *duplicate_word |= common;
+                }
+            }
+            for (word, other_word) in chunk.iter_mut().zip(other_chunk.iter()) {
+                self.len += u64::from((other_word & !*word).count_ones());
+                *word |= other_word;
+            }
+        }
+    }
+
+    /// Returns a new set with the ids that are contained in both sets.
+    pub fn intersection(&self, other: &IdSet) -> IdSet {
+        self.combine(other, |a, b| a & b)
+    }
+
+    /// Returns a new set with the ids that are contained in this set but not in `other`.
+    pub fn difference(&self, other: &IdSet) -> IdSet {
+        let mut result = IdSet::new();
+        for (key, chunk) in &self.chunks {
+            match other.chunks.get(key) {
+                Some(other_chunk) => result.insert_chunk(*key, chunk, other_chunk, |a, b| a & !b),
+                None => result.insert_chunk(*key, chunk, chunk, |a, _| a),
+            }
+        }
+        result
+    }
+
+    fn combine(&self, other: &IdSet, op: impl Fn(u64, u64) -> u64) -> IdSet {
+        let mut result = IdSet::new();
+        for (key, chunk) in &self.chunks {
+            if let Some(other_chunk) = other.chunks.get(key) {
+                result.insert_chunk(*key, chunk, other_chunk, &op);
+            }
+        }
+        result
+    }
+
+    fn insert_chunk(&mut self, key: i64, a: &Chunk, b: &Chunk, op: impl Fn(u64, u64) -> u64) {
+        let mut chunk: Chunk = Box::new([0; CHUNK_WORDS]);
+        let mut count = 0;
+        for ((word, a), b) in chunk.iter_mut().zip(a.iter()).zip(b.iter()) {
+            *word = op(*a, *b);
+            count += u64::from(word.count_ones());
+        }
+        if count > 0 {
+            self.chunks.insert(key, chunk);
+            self.len += count;
+        }
+    }
+}
+
+impl Extend<i64> for IdSet {
+    fn extend<T: IntoIterator<Item = i64>>(&mut self, iter: T) {
+        for id in iter {
+            self.insert(id);
+        }
+    }
+}
+
+impl FromIterator<i64> for IdSet {
+    fn from_iter<T: IntoIterator<Item = i64>>(iter: T) -> IdSet {
+        let mut set = IdSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+/// An iterator over the ids of an [`IdSet`] in ascending order.
+#[derive(Clone, Debug)]
+pub struct IdSetIter<'a> {
+    chunks: btree_map::Iter<'a, i64, Chunk>,
+    /// The current chunk key, chunk and the index of the next bit to check.
+    current: Option<(i64, &'a Chunk, usize)>,
+}
+
+impl<'a> Iterator for IdSetIter<'a> {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.current {
+                Some((key, chunk, ref mut bit)) => {
+                    while (*bit as i64) < CHUNK_BITS {
+                        let word = chunk[*bit / 64] >> (*bit % 64);
+                        if word == 0 {
+                            // Skip to the next word
+                            *bit = (*bit / 64 + 1) * 64;
+                            continue;
+                        }
+                        let found = *bit + word.trailing_zeros() as usize;
+                        *bit = found + 1;
+                        return Some(key * CHUNK_BITS + found as i64);
+                    }
+                    self.current = None;
+                }
+                None => {
+                    let (key, chunk) = self.chunks.next()?;
+                    self.current = Some((*key, chunk, 0));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a IdSet {
+    type Item = i64;
+    type IntoIter = IdSetIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_iterate() {
+        let ids = [-4097, -4096, -1, 0, 1, 63, 64, 4095, 4096, 1 << 40];
+        let set: IdSet = ids.iter().rev().copied().collect();
+
+        assert_eq!(set.len(), ids.len() as u64);
+        assert_eq!(set.iter().collect::<Vec<_>>(), ids);
+        for id in ids {
+            assert!(set.contains(id));
+        }
+        for id in [-4098, -2, 2, 62, 65, 4094, 4097, (1 << 40) + 1] {
+            assert!(!set.contains(id));
+        }
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let a: IdSet = [1, 2, 3, 5000, 9000].into_iter().collect();
+        let b: IdSet = [2, 3, 4, 9000, -7].into_iter().collect();
+
+        assert_eq!(a.intersection(&b).iter().collect::<Vec<_>>(), [2, 3, 9000]);
+        assert_eq!(a.intersection(&b).len(), 3);
+        assert_eq!(a.difference(&b).iter().collect::<Vec<_>>(), [1, 5000]);
+        assert_eq!(a.difference(&b).len(), 2);
+        assert!(a.difference(&a).is_empty());
+
+        let mut c = a.clone();
+        c.union_with(&b);
+        assert_eq!(c.iter().collect::<Vec<_>>(), [-7, 1, 2, 3, 4, 5000, 9000]);
+        assert_eq!(c.len(), 7);
+
+        let mut c = a.clone();
+        let mut duplicates: IdSet = [1, 4].into_iter().collect();
+        c.union_with_duplicates(&b, &mut duplicates);
+        assert_eq!(c.iter().collect::<Vec<_>>(), [-7, 1, 2, 3, 4, 5000, 9000]);
+        assert_eq!(c.len(), 7);
+        assert_eq!(duplicates.iter().collect::<Vec<_>>(), [1, 2, 3, 4, 9000]);
+        assert_eq!(duplicates.len(), 5);
+    }
+}
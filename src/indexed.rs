@@ -1,12 +1,18 @@
 //! Speed up searches by using an index
 
+use crate::elements::ElementType;
 use crate::error::Result;
-use crate::{BlobReader, BlobType, ByteOffset, Element, PrimitiveBlock, Way};
+use crate::object::OsmObject;
+use crate::options::ReaderOptions;
+use crate::progress::{Progress, ProgressTracker};
+use crate::{BlobReader, BlobType, ByteOffset, Element, PrimitiveBlock, Relation, Way};
 use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::{Read, Seek};
 use std::ops::RangeInclusive;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum SimpleBlobType {
@@ -27,6 +33,18 @@ fn range_included(range: RangeInclusive<i64>, node_ids: &BTreeSet<i64>) -> bool
     node_ids.range(range).next().is_some()
 }
 
+/// An element type and id in the order of files that are sorted by type and then by id.
+type SortKey = (u8, i64);
+
+fn sort_key(element_type: ElementType, id: i64) -> SortKey {
+    let order = match element_type {
+        ElementType::Node => 0,
+        ElementType::Way => 1,
+        ElementType::Relation => 2,
+    };
+    (order, id)
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum RangeIncluded {
     Yes(RangeInclusive<i64>),
@@ -39,8 +57,6 @@ enum RangeIncluded {
 pub struct IdRanges {
     node_ids: Option<RangeInclusive<i64>>,
     way_ids: Option<RangeInclusive<i64>>,
-    //TODO actually use this field
-    #[allow(dead_code)]
     relation_ids: Option<RangeInclusive<i64>>,
 }
 
@@ -75,16 +91,62 @@ impl BlobInfo {
         }
     }
 
-    /*
     /// Is there at least one relation in this blob?
     fn relations_available(&self) -> ElementsAvailable {
         match self.id_ranges {
-            Some(IdRanges {relation_ids: Some(_), ..}) => ElementsAvailable::Yes,
-            Some(IdRanges {relation_ids: None, ..}) => ElementsAvailable::No,
+            Some(IdRanges {
+                relation_ids: Some(_),
+                ..
+            }) => ElementsAvailable::Yes,
+            Some(IdRanges {
+                relation_ids: None, ..
+            }) => ElementsAvailable::No,
             None => ElementsAvailable::Unknown,
         }
     }
-    */
+
+    /// Returns the first and the last element of this blob in the sort order of sorted files or
+    /// `None` if the blob has no elements. The outer `None` means that the ranges are unknown.
+    fn key_range(&self) -> Option<Option<(SortKey, SortKey)>> {
+        let ranges = self.id_ranges.as_ref()?;
+        let typed = [
+            (ElementType::Node, &ranges.node_ids),
+            (ElementType::Way, &ranges.way_ids),
+            (ElementType::Relation, &ranges.relation_ids),
+        ];
+        let mut present = typed
+            .iter()
+            .filter_map(|(element_type, ids)| ids.as_ref().map(|ids| (*element_type, ids)));
+        let Some(first) = present.next() else {
+            return Some(None);
+        };
+        let last = present.next_back().unwrap_or(first);
+        Some(Some((
+            sort_key(first.0, *first.1.start()),
+            sort_key(last.0, *last.1.end()),
+        )))
+    }
+
+    /// Is there at least one of the given elements in the id ranges of this blob? Returns true if
+    /// the ranges are unknown.
+    fn may_contain(&self, keys: &BTreeSet<SortKey>) -> bool {
+        let Some(ranges) = self.id_ranges.as_ref() else {
+            return true;
+        };
+        [
+            (ElementType::Node, &ranges.node_ids),
+            (ElementType::Way, &ranges.way_ids),
+            (ElementType::Relation, &ranges.relation_ids),
+        ]
+        .iter()
+        .any(|(element_type, ids)| {
+            ids.as_ref().is_some_and(|ids| {
+                let start = sort_key(*element_type, *ids.start());
+                let end = sort_key(*element_type, *ids.end());
+                keys.range(start..=end).next().is_some()
+            })
+        })
+    }
 
     /// Compute if the range of node IDs of this blob (min and max ID value) is included in the
     /// given set of IDs with at least one ID inside of this range.
@@ -139,15 +201,79 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
         })
     }
 
+    /// Replaces the options of the underlying [`BlobReader`], see [`ReaderOptions`]. The index is
+    /// created again with the new options. Error recovery is not supported by this reader.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = IndexedReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_options(
+    ///     ReaderOptions::new()
+    ///         .unknown_blobs(UnknownBlobPolicy::Error)
+    ///         .validate_features(true),
+    /// );
+    ///
+    /// // Fails for unknown blob types and unsupported required features
+    /// reader.create_index()?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_options(&mut self, options: ReaderOptions) {
+        self.reader.set_options(options);
+        self.index.clear();
+    }
+
+    /// Calls `callback` with the progress of the reader, at most once per `interval` and once
+    /// at the end of each method. The progress counts the bytes and blobs that are actually read,
+    /// so blobs that are skipped with the help of the index are not included.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = IndexedReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_progress(Duration::from_secs(1), |progress| {
+    ///     eprintln!("{} blobs decoded", progress.blobs_decoded);
+    /// });
+    ///
+    /// reader.for_each_way(|way| println!("way {}", way.id()))?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_progress<F>(&mut self, interval: Duration, callback: F)
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.reader.set_progress(interval, callback);
+    }
+
     /// Initializes the index of the PBF structure without decompressing the blobs.
     /// You do not need to call this method explicitly as the other methods already take care of
     /// it.
+    ///
+    /// The unknown blob policy of the [`ReaderOptions`] is applied while the index is created. If
+    /// the features are validated, the header blobs are decoded as well.
     pub fn create_index(&mut self) -> Result<()> {
         if !self.index.is_empty() {
             // Index is already present -> Do nothing
             return Ok(());
         }
 
+        let result = self.read_index();
+        if result.is_err() {
+            self.index.clear();
+        }
+        result
+    }
+
+    fn read_index(&mut self) -> Result<()> {
         // Seek to the beginning of the reader.
         self.reader.seek(ByteOffset(0))?;
 
@@ -158,7 +284,14 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
             let blob_type = match header.blob_type() {
                 BlobType::OsmHeader => SimpleBlobType::Header,
                 BlobType::OsmData => SimpleBlobType::Primitive,
-                BlobType::Unknown(_) => SimpleBlobType::Unknown,
+                BlobType::Unknown(x) => {
+                    let index = Some(self.index.len() as u64);
+                    self.reader
+                        .options()
+                        .check_unknown_blob(x, Some(offset))
+                        .map_err(|e| e.with_blob(Some(offset), index))?;
+                    SimpleBlobType::Unknown
+                }
             };
 
             self.index.push(BlobInfo {
@@ -168,9 +301,36 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
             });
         }
 
+        if self.reader.options().validate_features {
+            let header_offsets: Vec<_> = self
+                .index
+                .iter()
+                .filter(|info| info.blob_type == SimpleBlobType::Header)
+                .map(|info| info.offset)
+                .collect();
+            for offset in header_offsets {
+                // The reader validates the features of header blobs
+                self.reader.blob_from_offset(offset)?;
+            }
+        }
+
         Ok(())
     }
 
+    /// Reports a decoded block with the given number of processed elements.
+    fn block_decoded(progress: Option<&Arc<ProgressTracker>>, elements: u64) {
+        if let Some(progress) = progress {
+            progress.blob_decoded(elements);
+        }
+    }
+
+    /// Reports the end of a method.
+    fn finish(&self) {
+        if let Some(progress) = self.reader.progress_tracker() {
+            progress.finish_elements();
+        }
+    }
+
     /// Check element IDs of this block. Record min and max for every node, way and relation.
     fn update_element_id_ranges(info: &mut BlobInfo, block: &PrimitiveBlock) {
         if info.id_ranges.is_some() {
@@ -240,7 +400,7 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
     /// reader.read_ways_and_deps(
     ///     |way| {
     ///         // Filter ways. Return true if tags contain "building": "yes".
-    ///         way.tags().any(|key_value| key_value == ("building", "yes"))
+    ///         way.has_tag_value("building", "yes")
     ///     },
     ///     |element| {
     ///         // Increment counter
@@ -269,6 +429,7 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
         self.create_index()?;
 
         let mut node_ids: BTreeSet<i64> = BTreeSet::new();
+        let progress = self.reader.progress_tracker().cloned();
 
         // First pass:
         //   * Filter ways and store their dependencies as node IDs
@@ -283,6 +444,7 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
                     .to_primitiveblock()?;
                 Self::update_element_id_ranges(info, &block);
 
+                let mut elements = 0;
                 for group in block.groups() {
                     // filter ways and record node IDs
                     for way in group.ways() {
@@ -293,9 +455,11 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
 
                             // Return way
                             element_callback(&Element::Way(way));
+                            elements += 1;
                         }
                     }
                 }
+                Self::block_decoded(progress.as_ref(), elements);
             }
         }
 
@@ -309,26 +473,182 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
                     .reader
                     .blob_from_offset(info.offset)?
                     .to_primitiveblock()?;
+                let mut elements = 0;
                 for group in block.groups() {
                     for node in group.nodes() {
                         if node_ids.binary_search(&node.id()).is_ok() {
                             // ID found, return node
                             element_callback(&Element::Node(node));
+                            elements += 1;
                         }
                     }
                     for node in group.dense_nodes() {
                         if node_ids.binary_search(&node.id).is_ok() {
                             // ID found, return dense node
                             element_callback(&Element::DenseNode(node));
+                            elements += 1;
                         }
                     }
                 }
+                Self::block_decoded(progress.as_ref(), elements);
             }
         }
 
+        self.finish();
         Ok(())
     }
 
+    /// Calls the closure on the elements with the given types and ids. Ids that are not in the
+    /// file are ignored.
+    ///
+    /// If the header declares that the file is sorted by type and then by id
+    /// (`Sort.Type_then_ID`), the blobs that contain the ids are found with a binary search over
+    /// the index, so only a few blobs are decoded for each id. Otherwise every blob is decoded
+    /// once to record its id ranges, and later calls only decode the blobs whose ranges include
+    /// one of the ids.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = IndexedReader::from_path("tests/test.osm.pbf")?;
+    /// let mut found = vec![];
+    ///
+    /// reader.read_elements_by_id(
+    ///     &[(ElementType::Node, 105), (ElementType::Way, 107)],
+    ///     |element| found.push(element.id()),
+    /// )?;
+    ///
+    /// # assert_eq!(found, [105, 107]);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn read_elements_by_id<F>(&mut self, ids: &[(ElementType, i64)], mut f: F) -> Result<()>
+    where
+        F: for<'a> FnMut(Element<'a>),
+    {
+        self.create_index()?;
+        let keys: BTreeSet<SortKey> = ids
+            .iter()
+            .map(|&(element_type, id)| sort_key(element_type, id))
+            .collect();
+        let progress = self.reader.progress_tracker().cloned();
+
+        let candidates = if self.is_sorted()? {
+            self.search_sorted(&keys)?
+        } else {
+            (0..self.index.len())
+                .filter(|&i| {
+                    self.index[i].blob_type == SimpleBlobType::Primitive
+                        && self.index[i].may_contain(&keys)
+                })
+                .collect()
+        };
+
+        for i in candidates {
+            let block = self
+                .reader
+                .blob_from_offset(self.index[i].offset)?
+                .to_primitiveblock()?;
+            Self::update_element_id_ranges(&mut self.index[i], &block);
+
+            let mut elements = 0;
+            for element in block.elements() {
+                if keys.contains(&sort_key(element.element_type(), element.id())) {
+                    f(element);
+                    elements += 1;
+                }
+            }
+            Self::block_decoded(progress.as_ref(), elements);
+        }
+
+        self.finish();
+        Ok(())
+    }
+
+    /// Returns true if a header declares that the elements are sorted by type and then by id.
+    fn is_sorted(&mut self) -> Result<bool> {
+        let header_offsets: Vec<_> = self
+            .index
+            .iter()
+            .filter(|info| info.blob_type == SimpleBlobType::Header)
+            .map(|info| info.offset)
+            .collect();
+        for offset in header_offsets {
+            let header = self.reader.blob_from_offset(offset)?.to_headerblock()?;
+            if header
+                .optional_features()
+                .iter()
+                .any(|feature| feature == "Sort.Type_then_ID")
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the first and the last element of the blob at the given index position, see
+    /// [`BlobInfo::key_range`]. Decodes the blob if its id ranges are unknown.
+    fn blob_key_range(&mut self, i: usize) -> Result<Option<(SortKey, SortKey)>> {
+        if let Some(range) = self.index[i].key_range() {
+            return Ok(range);
+        }
+        let block = self
+            .reader
+            .blob_from_offset(self.index[i].offset)?
+            .to_primitiveblock()?;
+        Self::update_element_id_ranges(&mut self.index[i], &block);
+        Ok(self.index[i].key_range().flatten())
+    }
+
+    /// Returns the index positions of the blobs that contain the given elements in a sorted file.
+    fn search_sorted(&mut self, keys: &BTreeSet<SortKey>) -> Result<BTreeSet<usize>> {
+        let primitive: Vec<usize> = (0..self.index.len())
+            .filter(|&i| self.index[i].blob_type == SimpleBlobType::Primitive)
+            .collect();
+        let mut candidates = BTreeSet::new();
+
+        for key in keys {
+            // Find the first blob whose last element is not smaller than the key
+            let (mut low, mut high) = (0, primitive.len());
+            while low < high {
+                let mid = low + (high - low) / 2;
+                // Blobs without elements do not tell where to continue, use the next one
+                let mut probe = mid;
+                let mut range = None;
+                while probe < high {
+                    range = self.blob_key_range(primitive[probe])?;
+                    if range.is_some() {
+                        break;
+                    }
+                    probe += 1;
+                }
+                match range {
+                    Some((_, last)) if last < *key => low = probe + 1,
+                    _ => high = mid,
+                }
+            }
+
+            // Elements with the same id (as in history files) may continue in the next blobs
+            for &i in &primitive[low..] {
+                match self.blob_key_range(i)? {
+                    Some((first, _)) if first > *key => break,
+                    Some(_) => {
+                        candidates.insert(i);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        Ok(candidates)
+    }
+
     /// Decodes the PBF structure sequentially and calls the given closure on each node.
     /// This method also creates a lightweight in-memory index that speeds up future invocations of
     /// this or any other method of `IndexedReader`.
@@ -366,6 +686,7 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
         F: for<'a> FnMut(Element<'a>),
     {
         self.create_index()?;
+        let progress = self.reader.progress_tracker().cloned();
 
         for info in &mut self.index {
             // Skip header blobs and blobs where there are certainly no nodes available.
@@ -378,17 +699,136 @@ impl<R: Read + Seek + Send> IndexedReader<R> {
                     .to_primitiveblock()?;
                 Self::update_element_id_ranges(info, &block);
 
+                let mut elements = 0;
                 for group in block.groups() {
                     for node in group.nodes() {
                         f(Element::Node(node));
+                        elements += 1;
                     }
                     for dense_node in group.dense_nodes() {
                         f(Element::DenseNode(dense_node));
+                        elements += 1;
+                    }
+                }
+                Self::block_decoded(progress.as_ref(), elements);
+            }
+        }
+
+        self.finish();
+        Ok(())
+    }
+
+    /// Decodes the PBF structure sequentially and calls the given closure on each way.
+    /// This method also creates a lightweight in-memory index that speeds up future invocations of
+    /// this or any other method of `IndexedReader`.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = IndexedReader::from_path("tests/test.osm.pbf")?;
+    /// let mut ways = 0;
+    ///
+    /// reader.for_each_way(|way| ways += 1)?;
+    ///
+    /// println!("ways: {ways}");
+    ///
+    /// # assert_eq!(ways, 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn for_each_way<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: for<'a> FnMut(Way<'a>),
+    {
+        self.create_index()?;
+        let progress = self.reader.progress_tracker().cloned();
+
+        for info in &mut self.index {
+            // Skip header blobs and blobs where there are certainly no ways available.
+            if info.blob_type == SimpleBlobType::Primitive
+                && info.ways_available() != ElementsAvailable::No
+            {
+                let block = self
+                    .reader
+                    .blob_from_offset(info.offset)?
+                    .to_primitiveblock()?;
+                Self::update_element_id_ranges(info, &block);
+
+                let mut elements = 0;
+                for group in block.groups() {
+                    for way in group.ways() {
+                        f(way);
+                        elements += 1;
                     }
                 }
+                Self::block_decoded(progress.as_ref(), elements);
             }
         }
 
+        self.finish();
+        Ok(())
+    }
+
+    /// Decodes the PBF structure sequentially and calls the given closure on each relation.
+    /// This method also creates a lightweight in-memory index that speeds up future invocations of
+    /// this or any other method of `IndexedReader`.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = IndexedReader::from_path("tests/test.osm.pbf")?;
+    /// let mut relations = 0;
+    ///
+    /// reader.for_each_relation(|relation| relations += 1)?;
+    ///
+    /// println!("relations: {relations}");
+    ///
+    /// # assert_eq!(relations, 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn for_each_relation<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: for<'a> FnMut(Relation<'a>),
+    {
+        self.create_index()?;
+        let progress = self.reader.progress_tracker().cloned();
+
+        for info in &mut self.index {
+            // Skip header blobs and blobs where there are certainly no relations available.
+            if info.blob_type == SimpleBlobType::Primitive
+                && info.relations_available() != ElementsAvailable::No
+            {
+                let block = self
+                    .reader
+                    .blob_from_offset(info.offset)?
+                    .to_primitiveblock()?;
+                Self::update_element_id_ranges(info, &block);
+
+                let mut elements = 0;
+                for group in block.groups() {
+                    for relation in group.relations() {
+                        f(relation);
+                        elements += 1;
+                    }
+                }
+                Self::block_decoded(progress.as_ref(), elements);
+            }
+        }
+
+        self.finish();
         Ok(())
     }
 }
@@ -417,6 +857,120 @@ impl IndexedReader<File> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::blob::encode_blob;
+    use crate::proto::{fileformat, osmformat};
+    use protobuf::{Message, MessageField};
+    use std::io::Cursor;
+
+    fn raw_blob(blob_type: &str, content: Vec<u8>) -> Vec<u8> {
+        let mut blob = fileformat::Blob::new();
+        blob.set_raw(content);
+        let mut header = fileformat::BlobHeader::new();
+        header.set_type(blob_type.to_string());
+        encode_blob(&header, &blob)
+    }
+
+    /// Returns a file with one block for each list of elements.
+    fn create_file(sorted: bool, blocks: &[Vec<(ElementType, i64)>]) -> Vec<u8> {
+        let mut header = osmformat::HeaderBlock::new();
+        header.required_features.push("OsmSchema-V0.6".to_string());
+        if sorted {
+            header
+                .optional_features
+                .push("Sort.Type_then_ID".to_string());
+        }
+        let mut data = raw_blob("OSMHeader", header.write_to_bytes().unwrap());
+
+        for elements in blocks {
+            let mut block = osmformat::PrimitiveBlock::new();
+            block.stringtable = MessageField::some(osmformat::StringTable::new());
+            for &(element_type, id) in elements {
+                let mut group = osmformat::PrimitiveGroup::new();
+                match element_type {
+                    ElementType::Node => {
+                        let mut node = osmformat::Node::new();
+                        node.set_id(id);
+                        node.set_lat(0);
+                        node.set_lon(0);
+                        group.nodes.push(node);
+                    }
+                    ElementType::Way => {
+                        let mut way = osmformat::Way::new();
+                        way.set_id(id);
+                        group.ways.push(way);
+                    }
+                    ElementType::Relation => {
+                        let mut relation = osmformat::Relation::new();
+                        relation.set_id(id);
+                        group.relations.push(relation);
+                    }
+                }
+                block.primitivegroup.push(group);
+            }
+            data.extend(raw_blob("OSMData", block.write_to_bytes().unwrap()));
+        }
+        data
+    }
+
+    fn read_ids(
+        reader: &mut IndexedReader<Cursor<Vec<u8>>>,
+        ids: &[(ElementType, i64)],
+    ) -> Vec<(ElementType, i64)> {
+        let mut found = vec![];
+        reader
+            .read_elements_by_id(ids, |element| {
+                found.push((element.element_type(), element.id()))
+            })
+            .unwrap();
+        found
+    }
+
+    #[test]
+    fn test_read_elements_by_id() {
+        let mut blocks: Vec<_> = (0..32)
+            .map(|i| vec![(ElementType::Node, 2 * i), (ElementType::Node, 2 * i + 1)])
+            .collect();
+        // The same node in two blocks, like in history files
+        blocks.push(vec![(ElementType::Node, 63), (ElementType::Node, 64)]);
+        blocks.push(vec![]);
+        blocks.push(vec![(ElementType::Way, 1), (ElementType::Way, 2)]);
+        blocks.push(vec![(ElementType::Relation, 1)]);
+
+        for sorted in [true, false] {
+            let data = create_file(sorted, &blocks);
+            let mut reader = IndexedReader::new(Cursor::new(data)).unwrap();
+
+            let found = read_ids(&mut reader, &[(ElementType::Node, 63)]);
+            assert_eq!(found, [(ElementType::Node, 63), (ElementType::Node, 63)]);
+            let decoded = reader
+                .index
+                .iter()
+                .filter(|info| info.id_ranges.is_some())
+                .count();
+            if sorted {
+                assert!(decoded < 10, "{decoded} blobs were decoded");
+            } else {
+                assert_eq!(decoded, blocks.len());
+            }
+
+            let ids = [
+                (ElementType::Relation, 1),
+                (ElementType::Way, 2),
+                (ElementType::Node, 0),
+                (ElementType::Node, 1000),
+                (ElementType::Way, 3),
+            ];
+            let found = read_ids(&mut reader, &ids);
+            assert_eq!(
+                found,
+                [
+                    (ElementType::Node, 0),
+                    (ElementType::Way, 2),
+                    (ElementType::Relation, 1)
+                ]
+            );
+        }
+    }
 
     #[test]
     fn test_range_included_set() {
@@ -0,0 +1,253 @@
+//! Check the referential integrity of a PBF file
+
+use crate::blob::{Blob, BlobDecode, BlobReader};
+use crate::block::PrimitiveBlock;
+use crate::elements::{Element, RelMemberType};
+use crate::error::Result;
+use crate::idset::IdSet;
+use crate::pipeline::{par_map_reduce_blobs, PipelineOptions};
+use crate::reader::ElementReader;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Maximum number of sample ids that are stored in an [`IdReport`].
+pub const MAX_SAMPLE_IDS: usize = 10;
+
+/// The number of ids that were found for a specific problem and a few samples of them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IdReport {
+    /// The number of distinct ids.
+    pub count: u64,
+    /// The smallest ids (up to [`MAX_SAMPLE_IDS`]) in ascending order.
+    pub samples: Vec<i64>,
+}
+
+impl IdReport {
+    fn new(set: &IdSet) -> IdReport {
+        IdReport {
+            count: set.len(),
+            samples: set.iter().take(MAX_SAMPLE_IDS).collect(),
+        }
+    }
+
+    /// Returns true if no ids were found.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// The result of a referential integrity check of a PBF file.
+///
+/// Files with a broken referential integrity are usually produced by cutting extracts
+/// incorrectly, and they cause gaps in geometries that are built from them. Note that history
+/// files contain multiple versions of the same element, which are reported as duplicates.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let report = IntegrityReport::from_path("tests/test.osm.pbf")?;
+///
+/// if !report.is_ok() {
+///     println!("missing way nodes: {:?}", report.missing_way_nodes.samples);
+/// }
+/// # assert!(report.is_ok());
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IntegrityReport {
+    /// Nodes that are referenced by ways but are not contained in the file.
+    pub missing_way_nodes: IdReport,
+    /// Nodes that are members of relations but are not contained in the file.
+    pub missing_relation_nodes: IdReport,
+    /// Ways that are members of relations but are not contained in the file.
+    pub missing_relation_ways: IdReport,
+    /// Relations that are members of relations but are not contained in the file.
+    pub missing_relation_relations: IdReport,
+    /// Node ids that occur more than once.
+    pub duplicate_nodes: IdReport,
+    /// Way ids that occur more than once.
+    pub duplicate_ways: IdReport,
+    /// Relation ids that occur more than once.
+    pub duplicate_relations: IdReport,
+    /// Ways without any node references.
+    pub empty_ways: IdReport,
+}
+
+impl IntegrityReport {
+    /// Checks the referential integrity of the PBF file at the given path.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<IntegrityReport> {
+        Self::from_element_reader(ElementReader::<BufReader<File>>::from_path(path)?)
+    }
+
+    /// Checks the referential integrity of all blobs of the given reader.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    pub fn from_blob_reader<R: Read + Send>(reader: BlobReader<R>) -> Result<IntegrityReport> {
+        let sets = par_map_reduce_blobs(
+            reader,
+            &PipelineOptions::default(),
+            |blob| Ok(IdSets::from_blob(&blob)?.0),
+            IdSets::default,
+            IdSets::merge,
+        )?;
+        Ok(Self::from_sets(&sets))
+    }
+
+    /// Checks the referential integrity of all blobs of the given reader. The blobs are decoded
+    /// with the thread pool, the in-flight limits and the progress callback of the reader.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while parsing the PBF structure.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_threads(2);
+    /// let report = IntegrityReport::from_element_reader(reader)?;
+    ///
+    /// assert!(report.is_ok());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn from_element_reader<R: Read + Send>(
+        reader: ElementReader<R>,
+    ) -> Result<IntegrityReport> {
+        let sets = reader.par_map_reduce_blobs(
+            |blob| IdSets::from_blob(&blob),
+            IdSets::default,
+            IdSets::merge,
+        )?;
+        Ok(Self::from_sets(&sets))
+    }
+
+    fn from_sets(sets: &IdSets) -> IntegrityReport {
+        IntegrityReport {
+            missing_way_nodes: IdReport::new(&sets.way_nodes.difference(&sets.nodes)),
+            missing_relation_nodes: IdReport::new(&sets.member_nodes.difference(&sets.nodes)),
+            missing_relation_ways: IdReport::new(&sets.member_ways.difference(&sets.ways)),
+            missing_relation_relations: IdReport::new(
+                &sets.member_relations.difference(&sets.relations),
+            ),
+            duplicate_nodes: IdReport::new(&sets.duplicate_nodes),
+            duplicate_ways: IdReport::new(&sets.duplicate_ways),
+            duplicate_relations: IdReport::new(&sets.duplicate_relations),
+            empty_ways: IdReport::new(&sets.empty_ways),
+        }
+    }
+
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.missing_way_nodes.is_empty()
+            && self.missing_relation_nodes.is_empty()
+            && self.missing_relation_ways.is_empty()
+            && self.missing_relation_relations.is_empty()
+            && self.duplicate_nodes.is_empty()
+            && self.duplicate_ways.is_empty()
+            && self.duplicate_relations.is_empty()
+            && self.empty_ways.is_empty()
+    }
+}
+
+/// Ids of the elements and references of a subset of blobs.
+#[derive(Debug, Default)]
+struct IdSets {
+    nodes: IdSet,
+    ways: IdSet,
+    relations: IdSet,
+    way_nodes: IdSet,
+    member_nodes: IdSet,
+    member_ways: IdSet,
+    member_relations: IdSet,
+    duplicate_nodes: IdSet,
+    duplicate_ways: IdSet,
+    duplicate_relations: IdSet,
+    empty_ways: IdSet,
+}
+
+impl IdSets {
+    /// Returns the ids of the blob and the number of its elements.
+    fn from_blob(blob: &Blob) -> Result<(IdSets, u64)> {
+        match blob.decode()? {
+            BlobDecode::OsmData(block) => Ok(IdSets::from_block(&block)),
+            BlobDecode::OsmHeader(_) | BlobDecode::Unknown(_) => Ok((IdSets::default(), 0)),
+        }
+    }
+
+    fn from_block(block: &PrimitiveBlock) -> (IdSets, u64) {
+        let mut sets = IdSets::default();
+        let mut elements = 0;
+
+        for element in block.elements() {
+            elements += 1;
+            match element {
+                Element::Node(node) => {
+                    if !sets.nodes.insert(node.id()) {
+                        sets.duplicate_nodes.insert(node.id());
+                    }
+                }
+                Element::DenseNode(node) => {
+                    if !sets.nodes.insert(node.id()) {
+                        sets.duplicate_nodes.insert(node.id());
+                    }
+                }
+                Element::Way(way) => {
+                    if !sets.ways.insert(way.id()) {
+                        sets.duplicate_ways.insert(way.id());
+                    }
+                    if way.refs().len() == 0 {
+                        sets.empty_ways.insert(way.id());
+                    }
+                    sets.way_nodes.extend(way.refs());
+                }
+                Element::Relation(relation) => {
+                    if !sets.relations.insert(relation.id()) {
+                        sets.duplicate_relations.insert(relation.id());
+                    }
+                    for member in relation.members() {
+                        match member.member_type {
+                            RelMemberType::Node => sets.member_nodes.insert(member.member_id),
+                            RelMemberType::Way => sets.member_ways.insert(member.member_id),
+                            RelMemberType::Relation => {
+                                sets.member_relations.insert(member.member_id)
+                            }
+                        };
+                    }
+                }
+            }
+        }
+
+        (sets, elements)
+    }
+
+    fn merge(mut self, other: IdSets) -> IdSets {
+        self.nodes
+            .union_with_duplicates(&other.nodes, &mut self.duplicate_nodes);
+        self.ways
+            .union_with_duplicates(&other.ways, &mut self.duplicate_ways);
+        self.relations
+            .union_with_duplicates(&other.relations, &mut self.duplicate_relations);
+        self.duplicate_nodes.union_with(&other.duplicate_nodes);
+        self.duplicate_ways.union_with(&other.duplicate_ways);
+        self.duplicate_relations
+            .union_with(&other.duplicate_relations);
+        self.way_nodes.union_with(&other.way_nodes);
+        self.member_nodes.union_with(&other.member_nodes);
+        self.member_ways.union_with(&other.member_ways);
+        self.member_relations.union_with(&other.member_relations);
+        self.empty_ways.union_with(&other.empty_ways);
+        self
+    }
+}
@@ -0,0 +1,965 @@
+//! Lazy decoding of `PrimitiveBlock`s directly from the protobuf wire format
+//!
+//! A lazily decoded block keeps the decompressed message and only records where the strings and
+//! groups are stored. Elements are parsed when they are visited and packed fields are decoded
+//! while iterating. The element types work on the views in this module, which can also be built
+//! from eagerly decoded protobuf messages.
+
+use crate::decode::recycle;
+use crate::proto::osmformat;
+use osmformat::relation::MemberType;
+use protobuf::EnumOrUnknown;
+use std::fmt;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+/// The values of a repeated scalar field.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Packed<'a, T> {
+    /// Values that were decoded by the protobuf crate.
+    Slice(&'a [T]),
+    /// Packed varints, their number and the function that converts each varint to a value.
+    Varint(&'a [u8], usize, fn(u64) -> T),
+}
+
+impl<'a, T> Packed<'a, T> {
+    pub(crate) fn iter(&self) -> PackedIter<'a, T> {
+        match *self {
+            Packed::Slice(values) => PackedIter::Slice(values.iter()),
+            Packed::Varint(data, len, decode) => PackedIter::Varint(data, len, decode),
+        }
+    }
+}
+
+impl<'a, T> Default for Packed<'a, T> {
+    fn default() -> Self {
+        Packed::Slice(&[])
+    }
+}
+
+/// An iterator over the values of a [`Packed`] field.
+#[derive(Clone, Debug)]
+pub(crate) enum PackedIter<'a, T> {
+    Slice(std::slice::Iter<'a, T>),
+    /// The remaining varints and their number.
+    Varint(&'a [u8], usize, fn(u64) -> T),
+}
+
+impl<'a, T: Copy> Iterator for PackedIter<'a, T> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            PackedIter::Slice(values) => values.next().copied(),
+            PackedIter::Varint(data, len, decode) => {
+                let mut pos = 0;
+                match read_varint(data, &mut pos) {
+                    Some(value) => {
+                        *data = &data[pos..];
+                        *len = len.saturating_sub(1);
+                        Some(decode(value))
+                    }
+                    None => {
+                        *data = &[];
+                        *len = 0;
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self {
+            PackedIter::Slice(values) => values.len(),
+            PackedIter::Varint(_, len, _) => *len,
+        };
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: Copy> ExactSizeIterator for PackedIter<'a, T> {}
+
+impl<'a> PackedIter<'a, i32> {
+    /// Splits off the key/value indices of the next dense node. The tags of consecutive nodes are
+    /// delimited by a zero.
+    pub(crate) fn next_dense_tags(&mut self) -> Packed<'a, i32> {
+        match self {
+            PackedIter::Slice(values) => {
+                let slice = values.as_slice();
+                let mut end = 0;
+                while end + 1 < slice.len() && slice[end] != 0 {
+                    end += 2;
+                }
+                // Skip the delimiter or a key without value
+                *values = slice[(end + 1).min(slice.len())..].iter();
+                Packed::Slice(&slice[..end])
+            }
+            PackedIter::Varint(data, len, decode) => {
+                let mut pos = 0;
+                let mut end = 0;
+                // The number of varints up to `pos` and up to `end`
+                let mut read = 0;
+                let mut tags = 0;
+                while let Some(key) = read_varint(data, &mut pos) {
+                    read += 1;
+                    if decode(key) == 0 || read_varint(data, &mut pos).is_none() {
+                        break;
+                    }
+                    read += 1;
+                    end = pos;
+                    tags = read;
+                }
+                let packed = Packed::Varint(&data[..end], tags, *decode);
+                *data = &data[pos.min(data.len())..];
+                *len = len.saturating_sub(read);
+                packed
+            }
+        }
+    }
+}
+
+/// The metadata of a node, way or relation.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct InfoView {
+    pub(crate) version: Option<i32>,
+    pub(crate) timestamp: Option<i64>,
+    pub(crate) changeset: Option<i64>,
+    pub(crate) uid: Option<i32>,
+    pub(crate) user_sid: Option<u32>,
+    pub(crate) visible: Option<bool>,
+}
+
+impl From<&osmformat::Info> for InfoView {
+    fn from(info: &osmformat::Info) -> InfoView {
+        InfoView {
+            version: info.version,
+            timestamp: info.timestamp,
+            changeset: info.changeset,
+            uid: info.uid,
+            user_sid: info.user_sid,
+            visible: info.visible,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct NodeView<'a> {
+    pub(crate) id: i64,
+    pub(crate) keys: Packed<'a, u32>,
+    pub(crate) vals: Packed<'a, u32>,
+    pub(crate) info: InfoView,
+    pub(crate) lat: i64,
+    pub(crate) lon: i64,
+}
+
+impl<'a> From<&'a osmformat::Node> for NodeView<'a> {
+    fn from(node: &'a osmformat::Node) -> NodeView<'a> {
+        NodeView {
+            id: node.id(),
+            keys: Packed::Slice(&node.keys),
+            vals: Packed::Slice(&node.vals),
+            info: node.info.as_ref().map(InfoView::from).unwrap_or_default(),
+            lat: node.lat(),
+            lon: node.lon(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct WayView<'a> {
+    pub(crate) id: i64,
+    pub(crate) keys: Packed<'a, u32>,
+    pub(crate) vals: Packed<'a, u32>,
+    pub(crate) info: InfoView,
+    pub(crate) refs: Packed<'a, i64>,
+    pub(crate) lat: Packed<'a, i64>,
+    pub(crate) lon: Packed<'a, i64>,
+    /// The index of the group and of the way in the group.
+    pub(crate) position: (usize, usize),
+}
+
+impl<'a> WayView<'a> {
+    pub(crate) fn new(way: &'a osmformat::Way, position: (usize, usize)) -> WayView<'a> {
+        WayView {
+            id: way.id(),
+            keys: Packed::Slice(&way.keys),
+            vals: Packed::Slice(&way.vals),
+            info: way.info.as_ref().map(InfoView::from).unwrap_or_default(),
+            refs: Packed::Slice(&way.refs),
+            lat: Packed::Slice(&way.lat),
+            lon: Packed::Slice(&way.lon),
+            position,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RelationView<'a> {
+    pub(crate) id: i64,
+    pub(crate) keys: Packed<'a, u32>,
+    pub(crate) vals: Packed<'a, u32>,
+    pub(crate) info: InfoView,
+    pub(crate) roles_sid: Packed<'a, i32>,
+    pub(crate) memids: Packed<'a, i64>,
+    pub(crate) types: Packed<'a, EnumOrUnknown<MemberType>>,
+}
+
+impl<'a> From<&'a osmformat::Relation> for RelationView<'a> {
+    fn from(relation: &'a osmformat::Relation) -> RelationView<'a> {
+        RelationView {
+            id: relation.id(),
+            keys: Packed::Slice(&relation.keys),
+            vals: Packed::Slice(&relation.vals),
+            info: relation
+                .info
+                .as_ref()
+                .map(InfoView::from)
+                .unwrap_or_default(),
+            roles_sid: Packed::Slice(&relation.roles_sid),
+            memids: Packed::Slice(&relation.memids),
+            types: Packed::Slice(&relation.types),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DenseView<'a> {
+    pub(crate) id: Packed<'a, i64>,
+    pub(crate) info: DenseInfoView<'a>,
+    pub(crate) lat: Packed<'a, i64>,
+    pub(crate) lon: Packed<'a, i64>,
+    pub(crate) keys_vals: Packed<'a, i32>,
+}
+
+impl<'a> From<&'a osmformat::DenseNodes> for DenseView<'a> {
+    fn from(dense: &'a osmformat::DenseNodes) -> DenseView<'a> {
+        DenseView {
+            id: Packed::Slice(&dense.id),
+            info: dense
+                .denseinfo
+                .as_ref()
+                .map(DenseInfoView::from)
+                .unwrap_or_default(),
+            lat: Packed::Slice(&dense.lat),
+            lon: Packed::Slice(&dense.lon),
+            keys_vals: Packed::Slice(&dense.keys_vals),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DenseInfoView<'a> {
+    pub(crate) version: Packed<'a, i32>,
+    pub(crate) timestamp: Packed<'a, i64>,
+    pub(crate) changeset: Packed<'a, i64>,
+    pub(crate) uid: Packed<'a, i32>,
+    pub(crate) user_sid: Packed<'a, i32>,
+    pub(crate) visible: Packed<'a, bool>,
+}
+
+impl<'a> From<&'a osmformat::DenseInfo> for DenseInfoView<'a> {
+    fn from(info: &'a osmformat::DenseInfo) -> DenseInfoView<'a> {
+        DenseInfoView {
+            version: Packed::Slice(&info.version),
+            timestamp: Packed::Slice(&info.timestamp),
+            changeset: Packed::Slice(&info.changeset),
+            uid: Packed::Slice(&info.uid),
+            user_sid: Packed::Slice(&info.user_sid),
+            visible: Packed::Slice(&info.visible),
+        }
+    }
+}
+
+/// A decompressed `PrimitiveBlock` message with the positions of its strings and groups.
+#[derive(Clone)]
+pub(crate) struct LazyBlock {
+    buffer: Vec<u8>,
+    strings: Vec<Range<usize>>,
+    groups: Vec<LazyGroup>,
+    granularity: i32,
+    date_granularity: i32,
+    lat_offset: i64,
+    lon_offset: i64,
+    /// The copied stringtable for methods that return it as a slice.
+    stringtable: OnceLock<Vec<Vec<u8>>>,
+    /// The decoded node ids of each way for methods that return them as a slice, in the order of
+    /// the groups and of the ways in each group.
+    way_refs: OnceLock<Vec<OnceLock<Vec<i64>>>>,
+}
+
+/// The position of an encoded `PrimitiveGroup` and the number of contained elements.
+#[derive(Clone, Debug)]
+pub(crate) struct LazyGroup {
+    range: Range<usize>,
+    nodes: usize,
+    ways: usize,
+    relations: usize,
+    /// The packed fields of the dense nodes, if the group contains any.
+    dense: Option<DenseRanges>,
+}
+
+/// The positions of the packed fields of a `DenseNodes` message in the buffer of the block.
+#[derive(Clone, Debug, Default)]
+struct DenseRanges {
+    id: PackedRange,
+    lat: PackedRange,
+    lon: PackedRange,
+    keys_vals: PackedRange,
+    version: PackedRange,
+    timestamp: PackedRange,
+    changeset: PackedRange,
+    uid: PackedRange,
+    user_sid: PackedRange,
+    visible: PackedRange,
+}
+
+impl DenseRanges {
+    fn new(buffer: &[u8], dense: &DenseView) -> DenseRanges {
+        DenseRanges {
+            id: PackedRange::new(buffer, dense.id),
+            lat: PackedRange::new(buffer, dense.lat),
+            lon: PackedRange::new(buffer, dense.lon),
+            keys_vals: PackedRange::new(buffer, dense.keys_vals),
+            version: PackedRange::new(buffer, dense.info.version),
+            timestamp: PackedRange::new(buffer, dense.info.timestamp),
+            changeset: PackedRange::new(buffer, dense.info.changeset),
+            uid: PackedRange::new(buffer, dense.info.uid),
+            user_sid: PackedRange::new(buffer, dense.info.user_sid),
+            visible: PackedRange::new(buffer, dense.info.visible),
+        }
+    }
+}
+
+/// The position of the encoded values of a packed field in the buffer of the block and the
+/// number of values.
+#[derive(Clone, Debug, Default)]
+struct PackedRange {
+    range: Range<usize>,
+    len: usize,
+}
+
+impl PackedRange {
+    fn new<T>(buffer: &[u8], packed: Packed<'_, T>) -> PackedRange {
+        match packed {
+            Packed::Varint(data, len, _) if !data.is_empty() => PackedRange {
+                range: range_of(buffer, data),
+                len,
+            },
+            _ => PackedRange::default(),
+        }
+    }
+
+    fn packed<'a, T>(&self, buffer: &'a [u8], decode: fn(u64) -> T) -> Packed<'a, T> {
+        Packed::Varint(&buffer[self.range.clone()], self.len, decode)
+    }
+}
+
+impl LazyBlock {
+    /// Checks the structure of the encoded block and records the positions of its strings and
+    /// groups. Returns the buffer if the block is malformed or uses an encoding that is not
+    /// supported lazily, like unpacked repeated fields. Such blocks have to be decoded eagerly.
+    pub(crate) fn new(buffer: Vec<u8>) -> std::result::Result<LazyBlock, Vec<u8>> {
+        let mut block = LazyBlock {
+            buffer: vec![],
+            strings: vec![],
+            groups: vec![],
+            granularity: 100,
+            date_granularity: 1000,
+            lat_offset: 0,
+            lon_offset: 0,
+            stringtable: OnceLock::new(),
+            way_refs: OnceLock::new(),
+        };
+        if block.scan(&buffer).is_none() {
+            return Err(buffer);
+        }
+        block.buffer = buffer;
+        Ok(block)
+    }
+
+    fn scan(&mut self, buffer: &[u8]) -> Option<()> {
+        let mut has_stringtable = false;
+        for field in Fields::new(buffer) {
+            match field.ok()? {
+                (1, Value::Bytes(stringtable)) => {
+                    has_stringtable = true;
+                    for field in Fields::new(stringtable) {
+                        match field.ok()? {
+                            (1, Value::Bytes(s)) => self.strings.push(range_of(buffer, s)),
+                            (1, _) => return None,
+                            _ => {}
+                        }
+                    }
+                }
+                (2, Value::Bytes(group)) => self.groups.push(scan_group(buffer, group)?),
+                (17, Value::Varint(v)) => self.granularity = v as i32,
+                (18, Value::Varint(v)) => self.date_granularity = v as i32,
+                (19, Value::Varint(v)) => self.lat_offset = v as i64,
+                (20, Value::Varint(v)) => self.lon_offset = v as i64,
+                (1 | 2 | 17..=20, _) => return None,
+                _ => {}
+            }
+        }
+        // The stringtable is a required field
+        has_stringtable.then_some(())
+    }
+
+    pub(crate) fn granularity(&self) -> i32 {
+        self.granularity
+    }
+
+    pub(crate) fn date_granularity(&self) -> i32 {
+        self.date_granularity
+    }
+
+    pub(crate) fn lat_offset(&self) -> i64 {
+        self.lat_offset
+    }
+
+    pub(crate) fn lon_offset(&self) -> i64 {
+        self.lon_offset
+    }
+
+    pub(crate) fn string(&self, index: usize) -> Option<&[u8]> {
+        self.strings
+            .get(index)
+            .map(|range| &self.buffer[range.clone()])
+    }
+
+    pub(crate) fn string_count(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub(crate) fn groups(&self) -> &[LazyGroup] {
+        &self.groups
+    }
+
+    /// Returns an iterator over the encoded nodes of a group.
+    pub(crate) fn nodes(&self, group: &LazyGroup) -> Messages<'_> {
+        Messages::new(&self.buffer[group.range.clone()], 1, group.nodes)
+    }
+
+    /// Returns the dense nodes of a group from the positions that were recorded by the scan.
+    pub(crate) fn dense(&self, group: &LazyGroup) -> DenseView<'_> {
+        let Some(dense) = &group.dense else {
+            return DenseView::default();
+        };
+        let buffer = self.buffer.as_slice();
+        DenseView {
+            id: dense.id.packed(buffer, decode_sint64),
+            info: DenseInfoView {
+                version: dense.version.packed(buffer, decode_int32),
+                timestamp: dense.timestamp.packed(buffer, decode_sint64),
+                changeset: dense.changeset.packed(buffer, decode_sint64),
+                uid: dense.uid.packed(buffer, decode_sint32),
+                user_sid: dense.user_sid.packed(buffer, decode_sint32),
+                visible: dense.visible.packed(buffer, decode_bool),
+            },
+            lat: dense.lat.packed(buffer, decode_sint64),
+            lon: dense.lon.packed(buffer, decode_sint64),
+            keys_vals: dense.keys_vals.packed(buffer, decode_int32),
+        }
+    }
+
+    /// Returns an iterator over the encoded ways of a group.
+    pub(crate) fn ways(&self, group: &LazyGroup) -> Messages<'_> {
+        Messages::new(&self.buffer[group.range.clone()], 3, group.ways)
+    }
+
+    /// Returns an iterator over the encoded relations of a group.
+    pub(crate) fn relations(&self, group: &LazyGroup) -> Messages<'_> {
+        Messages::new(&self.buffer[group.range.clone()], 4, group.relations)
+    }
+
+    /// Copies the stringtable on the first call.
+    pub(crate) fn raw_stringtable(&self) -> &[Vec<u8>] {
+        self.stringtable.get_or_init(|| {
+            self.strings
+                .iter()
+                .map(|range| self.buffer[range.clone()].to_vec())
+                .collect()
+        })
+    }
+
+    /// Decodes the delta coded node ids of the way at the given position on the first call for
+    /// that way. `refs` are the packed ids of the same way.
+    pub(crate) fn way_refs(&self, (group, index): (usize, usize), refs: Packed<'_, i64>) -> &[i64] {
+        let way_refs = self.way_refs.get_or_init(|| {
+            let ways = self.groups.iter().map(|group| group.ways).sum();
+            (0..ways).map(|_| OnceLock::new()).collect()
+        });
+        let position = self.groups[..group]
+            .iter()
+            .map(|group| group.ways)
+            .sum::<usize>()
+            + index;
+        way_refs[position].get_or_init(|| refs.iter().collect())
+    }
+}
+
+impl Drop for LazyBlock {
+    fn drop(&mut self) {
+        recycle(std::mem::take(&mut self.buffer));
+    }
+}
+
+impl fmt::Debug for LazyBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyBlock")
+            .field("size", &self.buffer.len())
+            .field("strings", &self.strings.len())
+            .field("groups", &self.groups)
+            .field("granularity", &self.granularity)
+            .field("date_granularity", &self.date_granularity)
+            .field("lat_offset", &self.lat_offset)
+            .field("lon_offset", &self.lon_offset)
+            .finish()
+    }
+}
+
+fn scan_group(buffer: &[u8], group: &[u8]) -> Option<LazyGroup> {
+    let mut lazy_group = LazyGroup {
+        range: range_of(buffer, group),
+        nodes: 0,
+        ways: 0,
+        relations: 0,
+        dense: None,
+    };
+    for field in Fields::new(group) {
+        match field.ok()? {
+            (1, Value::Bytes(node)) => {
+                parse_node(node)?;
+                lazy_group.nodes += 1;
+            }
+            // Repeated occurrences of a message field would have to be merged
+            (2, Value::Bytes(dense)) if lazy_group.dense.is_none() => {
+                lazy_group.dense = Some(DenseRanges::new(buffer, &parse_dense(dense)?));
+            }
+            (3, Value::Bytes(way)) => {
+                parse_way(way, (0, 0))?;
+                lazy_group.ways += 1;
+            }
+            (4, Value::Bytes(relation)) => {
+                parse_relation(relation)?;
+                lazy_group.relations += 1;
+            }
+            (5, Value::Bytes(changeset)) => {
+                // Changesets are not exposed, but the id is required
+                let mut has_id = false;
+                for field in Fields::new(changeset) {
+                    match field.ok()? {
+                        (1, Value::Varint(_)) => has_id = true,
+                        (1, _) => return None,
+                        _ => {}
+                    }
+                }
+                has_id.then_some(())?;
+            }
+            (1..=5, _) => return None,
+            _ => {}
+        }
+    }
+    Some(lazy_group)
+}
+
+/// Returns the position of `inner` in `outer`.
+fn range_of(outer: &[u8], inner: &[u8]) -> Range<usize> {
+    let start = inner.as_ptr() as usize - outer.as_ptr() as usize;
+    start..start + inner.len()
+}
+
+/// An iterator over the encoded messages of a repeated field.
+#[derive(Clone, Debug)]
+pub(crate) struct Messages<'a> {
+    fields: Fields<'a>,
+    number: u32,
+    remaining: usize,
+}
+
+impl<'a> Messages<'a> {
+    fn new(data: &'a [u8], number: u32, count: usize) -> Messages<'a> {
+        Messages {
+            fields: Fields::new(data),
+            number,
+            remaining: count,
+        }
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            match self.fields.next()? {
+                Ok((number, Value::Bytes(bytes))) if number == self.number => {
+                    self.remaining -= 1;
+                    return Some(bytes);
+                }
+                Ok(_) => {}
+                Err(Malformed) => return None,
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Messages<'a> {}
+
+pub(crate) fn parse_node(bytes: &[u8]) -> Option<NodeView<'_>> {
+    let (mut id, mut lat, mut lon) = (None, None, None);
+    let (mut keys, mut vals, mut info) = (None, None, None);
+    for field in Fields::new(bytes) {
+        match field.ok()? {
+            (1, Value::Varint(v)) => id = Some(decode_sint64(v)),
+            (2, Value::Bytes(b)) => set_packed(&mut keys, b)?,
+            (3, Value::Bytes(b)) => set_packed(&mut vals, b)?,
+            (4, Value::Bytes(b)) if info.is_none() => info = Some(parse_info(b)?),
+            (8, Value::Varint(v)) => lat = Some(decode_sint64(v)),
+            (9, Value::Varint(v)) => lon = Some(decode_sint64(v)),
+            (1..=4 | 8 | 9, _) => return None,
+            _ => {}
+        }
+    }
+    Some(NodeView {
+        id: id?,
+        keys: packed(keys, decode_uint32),
+        vals: packed(vals, decode_uint32),
+        info: info.unwrap_or_default(),
+        lat: lat?,
+        lon: lon?,
+    })
+}
+
+pub(crate) fn parse_way(bytes: &[u8], position: (usize, usize)) -> Option<WayView<'_>> {
+    let mut id = None;
+    let (mut keys, mut vals, mut info) = (None, None, None);
+    let (mut refs, mut lat, mut lon) = (None, None, None);
+    for field in Fields::new(bytes) {
+        match field.ok()? {
+            (1, Value::Varint(v)) => id = Some(decode_int64(v)),
+            (2, Value::Bytes(b)) => set_packed(&mut keys, b)?,
+            (3, Value::Bytes(b)) => set_packed(&mut vals, b)?,
+            (4, Value::Bytes(b)) if info.is_none() => info = Some(parse_info(b)?),
+            (8, Value::Bytes(b)) => set_packed(&mut refs, b)?,
+            (9, Value::Bytes(b)) => set_packed(&mut lat, b)?,
+            (10, Value::Bytes(b)) => set_packed(&mut lon, b)?,
+            (1..=4 | 8..=10, _) => return None,
+            _ => {}
+        }
+    }
+    Some(WayView {
+        id: id?,
+        keys: packed(keys, decode_uint32),
+        vals: packed(vals, decode_uint32),
+        info: info.unwrap_or_default(),
+        refs: packed(refs, decode_sint64),
+        lat: packed(lat, decode_sint64),
+        lon: packed(lon, decode_sint64),
+        position,
+    })
+}
+
+pub(crate) fn parse_relation(bytes: &[u8]) -> Option<RelationView<'_>> {
+    let mut id = None;
+    let (mut keys, mut vals, mut info) = (None, None, None);
+    let (mut roles_sid, mut memids, mut types) = (None, None, None);
+    for field in Fields::new(bytes) {
+        match field.ok()? {
+            (1, Value::Varint(v)) => id = Some(decode_int64(v)),
+            (2, Value::Bytes(b)) => set_packed(&mut keys, b)?,
+            (3, Value::Bytes(b)) => set_packed(&mut vals, b)?,
+            (4, Value::Bytes(b)) if info.is_none() => info = Some(parse_info(b)?),
+            (8, Value::Bytes(b)) => set_packed(&mut roles_sid, b)?,
+            (9, Value::Bytes(b)) => set_packed(&mut memids, b)?,
+            (10, Value::Bytes(b)) => set_packed(&mut types, b)?,
+            (1..=4 | 8..=10, _) => return None,
+            _ => {}
+        }
+    }
+    Some(RelationView {
+        id: id?,
+        keys: packed(keys, decode_uint32),
+        vals: packed(vals, decode_uint32),
+        info: info.unwrap_or_default(),
+        roles_sid: packed(roles_sid, decode_int32),
+        memids: packed(memids, decode_sint64),
+        types: packed(types, decode_member_type),
+    })
+}
+
+fn parse_dense(bytes: &[u8]) -> Option<DenseView<'_>> {
+    let (mut id, mut info, mut lat, mut lon, mut keys_vals) = (None, None, None, None, None);
+    for field in Fields::new(bytes) {
+        match field.ok()? {
+            (1, Value::Bytes(b)) => set_packed(&mut id, b)?,
+            (5, Value::Bytes(b)) if info.is_none() => info = Some(parse_dense_info(b)?),
+            (8, Value::Bytes(b)) => set_packed(&mut lat, b)?,
+            (9, Value::Bytes(b)) => set_packed(&mut lon, b)?,
+            (10, Value::Bytes(b)) => set_packed(&mut keys_vals, b)?,
+            (1 | 5 | 8..=10, _) => return None,
+            _ => {}
+        }
+    }
+    Some(DenseView {
+        id: packed(id, decode_sint64),
+        info: info.unwrap_or_default(),
+        lat: packed(lat, decode_sint64),
+        lon: packed(lon, decode_sint64),
+        keys_vals: packed(keys_vals, decode_int32),
+    })
+}
+
+fn parse_dense_info(bytes: &[u8]) -> Option<DenseInfoView<'_>> {
+    let (mut version, mut timestamp, mut changeset) = (None, None, None);
+    let (mut uid, mut user_sid, mut visible) = (None, None, None);
+    for field in Fields::new(bytes) {
+        match field.ok()? {
+            (1, Value::Bytes(b)) => set_packed(&mut version, b)?,
+            (2, Value::Bytes(b)) => set_packed(&mut timestamp, b)?,
+            (3, Value::Bytes(b)) => set_packed(&mut changeset, b)?,
+            (4, Value::Bytes(b)) => set_packed(&mut uid, b)?,
+            (5, Value::Bytes(b)) => set_packed(&mut user_sid, b)?,
+            (6, Value::Bytes(b)) => set_packed(&mut visible, b)?,
+            (1..=6, _) => return None,
+            _ => {}
+        }
+    }
+    Some(DenseInfoView {
+        version: packed(version, decode_int32),
+        timestamp: packed(timestamp, decode_sint64),
+        changeset: packed(changeset, decode_sint64),
+        uid: packed(uid, decode_sint32),
+        user_sid: packed(user_sid, decode_sint32),
+        visible: packed(visible, decode_bool),
+    })
+}
+
+fn parse_info(bytes: &[u8]) -> Option<InfoView> {
+    let mut info = InfoView::default();
+    for field in Fields::new(bytes) {
+        match field.ok()? {
+            (1, Value::Varint(v)) => info.version = Some(decode_int32(v)),
+            (2, Value::Varint(v)) => info.timestamp = Some(decode_int64(v)),
+            (3, Value::Varint(v)) => info.changeset = Some(decode_int64(v)),
+            (4, Value::Varint(v)) => info.uid = Some(decode_int32(v)),
+            (5, Value::Varint(v)) => info.user_sid = Some(decode_uint32(v)),
+            (6, Value::Varint(v)) => info.visible = Some(decode_bool(v)),
+            (1..=6, _) => return None,
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+/// Stores the content of a packed field. Fields that occur more than once have to be
+/// concatenated, which is left to the eager decoding.
+/// Stores the bytes of a packed field and the number of its values.
+fn set_packed<'a>(field: &mut Option<(&'a [u8], usize)>, bytes: &'a [u8]) -> Option<()> {
+    if field.is_some() {
+        return None;
+    }
+    *field = Some((bytes, packed_len(bytes)?));
+    Some(())
+}
+
+/// Returns the number of varints in a packed field or `None` if a varint is invalid.
+fn packed_len(mut data: &[u8]) -> Option<usize> {
+    let mut len = 0;
+    while !data.is_empty() {
+        let mut pos = 0;
+        read_varint(data, &mut pos)?;
+        data = &data[pos..];
+        len += 1;
+    }
+    Some(len)
+}
+
+fn packed<T>(field: Option<(&[u8], usize)>, decode: fn(u64) -> T) -> Packed<'_, T> {
+    let (bytes, len) = field.unwrap_or((&[], 0));
+    Packed::Varint(bytes, len, decode)
+}
+
+fn decode_int32(v: u64) -> i32 {
+    v as i32
+}
+
+fn decode_int64(v: u64) -> i64 {
+    v as i64
+}
+
+fn decode_uint32(v: u64) -> u32 {
+    v as u32
+}
+
+fn decode_sint32(v: u64) -> i32 {
+    decode_sint64(v) as i32
+}
+
+fn decode_sint64(v: u64) -> i64 {
+    (v >> 1) as i64 ^ -((v & 1) as i64)
+}
+
+fn decode_bool(v: u64) -> bool {
+    v != 0
+}
+
+fn decode_member_type(v: u64) -> EnumOrUnknown<MemberType> {
+    EnumOrUnknown::from_i32(v as i32)
+}
+
+/// Reads a varint at the given position and advances the position. Returns `None` if the data
+/// ends within the varint or if the varint is longer than ten bytes.
+#[inline]
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// A field value. Fixed size values are not used by the OSM messages and only skipped.
+#[derive(Clone, Copy, Debug)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Malformed;
+
+/// An iterator over the fields of an encoded message.
+#[derive(Clone, Debug)]
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Fields<'a> {
+        Fields { data }
+    }
+
+    fn read_field(&self) -> Option<(u32, Value<'a>, usize)> {
+        let data = self.data;
+        let mut pos = 0;
+        let key = read_varint(data, &mut pos)?;
+        let number = u32::try_from(key >> 3).ok().filter(|&n| n != 0)?;
+        let value = match key & 7 {
+            0 => Value::Varint(read_varint(data, &mut pos)?),
+            1 => {
+                pos += 8;
+                Value::Fixed
+            }
+            2 => {
+                let len = usize::try_from(read_varint(data, &mut pos)?).ok()?;
+                let bytes = data.get(pos..pos.checked_add(len)?)?;
+                pos += len;
+                Value::Bytes(bytes)
+            }
+            5 => {
+                pos += 4;
+                Value::Fixed
+            }
+            _ => return None,
+        };
+        (pos <= data.len()).then_some((number, value, pos))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = std::result::Result<(u32, Value<'a>), Malformed>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match self.read_field() {
+            Some((number, value, len)) => {
+                self.data = &self.data[len..];
+                Some(Ok((number, value)))
+            }
+            None => {
+                self.data = &[];
+                Some(Err(Malformed))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed() {
+        // 1, 300, -1 as int32 and a trailing incomplete varint
+        let data = [
+            0x01, 0xac, 0x02, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x80,
+        ];
+        assert_eq!(packed_len(&data), None);
+        assert_eq!(packed_len(&data[..13]), Some(3));
+        let values = Packed::Varint(&data, 3, decode_int32);
+        let mut iter = values.iter();
+        assert_eq!(iter.len(), 3);
+        assert_eq!(iter.next(), Some(1));
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.collect::<Vec<_>>(), vec![300, -1]);
+
+        let zigzag = [0x00, 0x01, 0x02, 0x03];
+        let values = Packed::Varint(&zigzag, 4, decode_sint64);
+        assert_eq!(values.iter().collect::<Vec<_>>(), vec![0, -1, 1, -2]);
+    }
+
+    #[test]
+    fn test_dense_tags() {
+        let slice = [1, 2, 3, 4, 0, 0, 5, 6, 0, 7];
+        let data = [1, 2, 3, 4, 0, 0, 5, 6, 0, 7];
+        let mut iters = [
+            Packed::Slice(&slice).iter(),
+            Packed::Varint(&data, data.len(), decode_int32).iter(),
+        ];
+        for iter in &mut iters {
+            let tags: Vec<Vec<i32>> = (0..4)
+                .map(|_| {
+                    let tags = iter.next_dense_tags().iter();
+                    let len = tags.len();
+                    let tags: Vec<i32> = tags.collect();
+                    assert_eq!(len, tags.len());
+                    tags
+                })
+                .collect();
+            assert_eq!(tags, vec![vec![1, 2, 3, 4], vec![], vec![5, 6], vec![]]);
+            assert_eq!(iter.len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_fields() {
+        // Field 1 varint, field 2 bytes, field 3 fixed32
+        let data = [0x08, 0x96, 0x01, 0x12, 0x02, 0xaa, 0xbb, 0x1d, 0, 0, 0, 0];
+        let fields: Vec<_> = Fields::new(&data).map(|f| f.unwrap()).collect();
+        assert!(matches!(fields[0], (1, Value::Varint(150))));
+        assert!(matches!(fields[1], (2, Value::Bytes(&[0xaa, 0xbb]))));
+        assert!(matches!(fields[2], (3, Value::Fixed)));
+
+        // Length exceeds the data
+        assert!(Fields::new(&[0x12, 0x05, 0x00]).any(|f| f.is_err()));
+        // Groups are not supported
+        assert!(Fields::new(&[0x0b]).any(|f| f.is_err()));
+    }
+}
@@ -73,23 +73,73 @@ std::compile_error!(
     for example by using these cargo flags: --no-default-features --features zlib-ng"
 );
 
+#[cfg(feature = "arrow")]
+pub use arrow::*;
+#[cfg(feature = "async")]
+pub use async_reader::*;
 pub use blob::*;
 pub use block::*;
+pub use decode::release_decode_buffers;
 pub use dense::*;
 pub use elements::*;
 pub use error::{BlobError, Error, ErrorKind, Result};
+pub use fileinfo::*;
+pub use filter::*;
+pub use geojson::*;
+pub use geometry::{
+    ExportOptions, InvalidArea, InvalidAreaCallback, InvalidAreaPolicy, NodeLocationStore,
+    SortedNodeLocations,
+};
+#[cfg(feature = "geoparquet")]
+pub use geoparquet::*;
+#[cfg(feature = "http")]
+pub use http::*;
+pub use idset::*;
 pub use indexed::*;
+pub use integrity::*;
 pub use mmap_blob::*;
+pub use mmap_reader::*;
+pub use object::*;
+pub use options::*;
+pub use progress::*;
 pub use reader::*;
-
+pub use recompress::*;
+pub use util::{format_timestamp, push_json_string};
+pub use writer::*;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "async")]
+pub mod async_reader;
 pub mod blob;
 pub mod block;
+mod decode;
 pub mod dense;
 pub mod elements;
 mod error;
+pub mod fileinfo;
+pub mod filter;
+pub mod geojson;
+mod geometry;
+#[cfg(feature = "geoparquet")]
+pub mod geoparquet;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod idset;
 pub mod indexed;
+pub mod integrity;
+mod lazy;
 pub mod mmap_blob;
+pub mod mmap_reader;
+mod normalize;
+pub mod object;
+pub mod options;
+mod pipeline;
+pub mod progress;
 pub mod reader;
+pub mod recompress;
+mod util;
+pub mod writer;
 
 mod proto {
     include!(concat!(env!("OUT_DIR"), "/mod.rs"));
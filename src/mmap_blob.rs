@@ -1,11 +1,14 @@
-//! Iterate over blobs from a memory map
+//! Iterate over blobs from a memory map or a byte slice
 
 use self::fileformat::BlobHeader;
-use crate::blob::{decode_blob, BlobDecode, BlobType, ByteOffset};
-use crate::block::{HeaderBlock, PrimitiveBlock};
+use crate::blob::{
+    decode_blob_ref, decode_primitive_block, find_plausible_blob, parse_blob_layout, BlobDecode,
+    BlobLayout, BlobRef, BlobType, ByteOffset, RawBlob, Resync,
+};
+use crate::block::{BlockDecoding, HeaderBlock, TagDecoding};
 use crate::error::{new_blob_error, new_protobuf_error, BlobError, Result};
-use crate::proto::{fileformat, osmformat};
-use crate::MAX_BLOB_HEADER_SIZE;
+use crate::options::{DecodeOptions, ReaderOptions};
+use crate::proto::fileformat;
 use byteorder::ByteOrder;
 use protobuf::Message;
 use std::fs::File;
@@ -67,7 +70,7 @@ impl Mmap {
     }
 
     /// Returns an iterator over the blobs in this memory map.
-    pub fn blob_iter(&self) -> MmapBlobReader {
+    pub fn blob_iter(&self) -> MmapBlobReader<'_> {
         MmapBlobReader::new(self)
     }
 
@@ -76,35 +79,78 @@ impl Mmap {
     }
 }
 
-/// A PBF blob from a memory map.
+/// A PBF blob from a memory map or a byte slice. It borrows its content from the underlying data
+/// without copying it.
 #[derive(Clone, Debug)]
 pub struct MmapBlob<'a> {
     header: BlobHeader,
-    data: &'a [u8],
+    /// The encoded blob with size prefix, header and content.
+    bytes: &'a [u8],
+    /// The position of the `Blob` message in `bytes`.
+    content_start: usize,
+    /// The positions of the fields of the `Blob` message.
+    layout: BlobLayout,
     offset: ByteOffset,
+    index: Option<u64>,
+    decode: DecodeOptions,
 }
 
+/// A PBF blob from a byte slice, see [`SliceBlobReader`]. This is the same type as [`MmapBlob`].
+pub type SliceBlob<'a> = MmapBlob<'a>;
+
 impl<'a> MmapBlob<'a> {
     /// Decodes the blob and tries to obtain the inner content (usually a [`HeaderBlock`] or a
     /// [`PrimitiveBlock`]). This operation might involve an expensive decompression step.
     pub fn decode(&'a self) -> Result<BlobDecode<'a>> {
-        let blob = fileformat::Blob::parse_from_bytes(self.data)
-            .map_err(|e| new_protobuf_error(e, "blob content"))?;
+        self.decode_content()
+            .map_err(|e| e.with_blob(Some(self.offset), self.index))
+    }
+
+    fn decode_content(&'a self) -> Result<BlobDecode<'a>> {
+        let blob = self.content();
         match self.header.type_() {
             "OSMHeader" => {
-                let block = Box::new(HeaderBlock::new(decode_blob(&blob)?));
+                let block = HeaderBlock::new(decode_blob_ref(&blob, self.decode.max_blob_size)?);
+                let block = Box::new(block);
                 Ok(BlobDecode::OsmHeader(block))
             }
             "OSMData" => {
-                let block: osmformat::PrimitiveBlock = decode_blob(&blob)?;
-                Ok(BlobDecode::OsmData(PrimitiveBlock::new(block)))
+                let block = decode_primitive_block(&blob, &self.decode)?;
+                Ok(BlobDecode::OsmData(block))
             }
             x => Ok(BlobDecode::Unknown(x)),
         }
     }
 
+    /// Returns the encoded parts of the blob, which can be written to another file without
+    /// decoding the blob, see [`BlobWriter`](crate::writer::BlobWriter).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let data = std::fs::read("tests/test.osm.pbf")?;
+    /// for blob in MmapBlobReader::from_slice(&data) {
+    ///     let blob = blob?;
+    ///     let raw = blob.raw();
+    ///     println!("{:?}: {:?}", raw.compression(), raw.raw_size());
+    ///     # assert_eq!(raw.compression(), Some(BlobCompression::Zlib));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn raw(&self) -> RawBlob<'a> {
+        RawBlob::new(self.bytes, self.content_start, self.layout.clone())
+    }
+
+    fn content(&self) -> BlobRef<'a> {
+        self.layout.blob_ref(&self.bytes[self.content_start..])
+    }
+
     /// Returns the type of a blob without decoding its content.
-    pub fn get_type(&self) -> BlobType {
+    pub fn get_type(&self) -> BlobType<'_> {
         match self.header.type_() {
             "OSMHeader" => BlobType::OsmHeader,
             "OSMData" => BlobType::OsmData,
@@ -112,20 +158,43 @@ impl<'a> MmapBlob<'a> {
         }
     }
 
-    /// Returns the byte offset of the blob from the start of its memory map.
+    /// Returns the byte offset of the blob from the start of its memory map or slice.
     pub fn offset(&self) -> ByteOffset {
         self.offset
     }
+
+    /// Returns the ordinal of the blob in its memory map or slice, starting with 0 for the first
+    /// blob that was read. This is [`None`] if the reader was moved with [`MmapBlobReader::seek`].
+    pub fn index(&self) -> Option<u64> {
+        self.index
+    }
+
+    pub(crate) fn set_tag_decoding(&mut self, tag_decoding: TagDecoding) {
+        self.decode.tag_decoding = tag_decoding;
+    }
+
+    pub(crate) fn set_block_decoding(&mut self, block_decoding: BlockDecoding) {
+        self.decode.block_decoding = block_decoding;
+    }
 }
 
-/// A reader for memory mapped PBF files that allows iterating over [`MmapBlob`]s.
+/// A reader for memory mapped PBF files or PBF data in a byte slice that allows iterating over
+/// [`MmapBlob`]s.
 #[derive(Clone, Debug)]
 pub struct MmapBlobReader<'a> {
-    mmap: &'a Mmap,
+    data: &'a [u8],
     offset: usize,
     last_blob_ok: bool,
+    resyncs: Vec<Resync>,
+    /// The ordinal of the next blob or `None` if unknown after seeking.
+    next_index: Option<u64>,
+    options: ReaderOptions,
 }
 
+/// A reader for PBF data in a byte slice, created with [`MmapBlobReader::from_slice`]. This is
+/// the same type as [`MmapBlobReader`].
+pub type SliceBlobReader<'a> = MmapBlobReader<'a>;
+
 impl<'a> MmapBlobReader<'a> {
     /// Creates a new `MmapBlobReader`.
     ///
@@ -142,52 +211,139 @@ impl<'a> MmapBlobReader<'a> {
     /// # }
     /// # foo().unwrap();
     /// ```
-    pub fn new(mmap: &Mmap) -> MmapBlobReader {
+    pub fn new(mmap: &Mmap) -> MmapBlobReader<'_> {
+        MmapBlobReader::from_slice(mmap.as_slice())
+    }
+
+    /// Creates a new `MmapBlobReader` for PBF data in memory, for example a `Vec<u8>` or a
+    /// `bytes::Bytes` buffer that was received over the network. The returned blobs borrow from
+    /// `data`.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let data: Vec<u8> = std::fs::read("tests/test.osm.pbf")?;
+    /// let reader = SliceBlobReader::from_slice(&data);
+    ///
+    /// for blob in reader {
+    ///     if let BlobDecode::OsmData(block) = blob?.decode()? {
+    ///         println!("{} elements", block.elements().count());
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn from_slice(data: &'a [u8]) -> MmapBlobReader<'a> {
         MmapBlobReader {
-            mmap,
+            data,
             offset: 0,
             last_blob_ok: true,
+            resyncs: vec![],
+            next_index: Some(0),
+            options: ReaderOptions::default(),
         }
     }
 
-    /// Move the cursor to the given byte offset.
+    /// Replaces all options of the reader, including the ones that were changed with the other
+    /// setters.
     ///
     /// # Example
     /// ```
     /// use osmpbf::*;
     ///
     /// # fn foo() -> Result<()> {
+    /// let data = std::fs::read("tests/test.osm.pbf")?;
+    /// let mut reader = MmapBlobReader::from_slice(&data);
+    /// reader.set_options(ReaderOptions::new().max_blob_size(128));
     ///
-    /// let mmap = unsafe { Mmap::from_path("tests/test.osm.pbf")? };
-    /// let mut reader = MmapBlobReader::new(&mmap);
+    /// // The data blob of the test file is larger than the limit
+    /// let blobs: Vec<_> = reader.collect();
+    /// assert!(blobs[0].is_ok());
+    /// assert!(blobs[1].is_err());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_options(&mut self, options: ReaderOptions) {
+        self.options = options;
+    }
+
+    /// Returns the options of the reader.
+    pub fn options(&self) -> &ReaderOptions {
+        &self.options
+    }
+
+    /// Enables or disables the error recovery mode (disabled by default).
     ///
-    /// let first_blob = reader.next().unwrap()?;
-    /// let second_blob = reader.next().unwrap()?;
+    /// Usually, the iteration stops after the first error. In recovery mode, the reader returns
+    /// the error and then continues with the next plausible blob after the current offset. The
+    /// skipped regions can be inspected with [`resyncs`](MmapBlobReader::resyncs).
     ///
-    /// reader.seek(first_blob.offset());
-    /// let first_blob_again = reader.next().unwrap()?;
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
     ///
-    /// assert_eq!(first_blob.offset(), first_blob_again.offset());
+    /// # fn foo() -> Result<()> {
+    /// let mmap = unsafe { Mmap::from_path("tests/test.osm.pbf")? };
+    /// let mut reader = MmapBlobReader::new(&mmap);
+    /// reader.set_error_recovery(true);
+    ///
+    /// let blobs = reader.by_ref().filter(|blob| blob.is_ok()).count();
     ///
+    /// # assert_eq!(blobs, 2);
+    /// # assert!(reader.resyncs().is_empty());
     /// # Ok(())
     /// # }
     /// # foo().unwrap();
     /// ```
-    pub fn seek(&mut self, pos: ByteOffset) {
-        self.offset = pos.0 as usize;
+    pub fn set_error_recovery(&mut self, enabled: bool) {
+        self.options.error_recovery = enabled;
     }
-}
 
-impl<'a> Iterator for MmapBlobReader<'a> {
-    type Item = Result<MmapBlob<'a>>;
+    /// Returns the regions of the data that were skipped to recover from errors. This is
+    /// always empty if the error recovery mode is disabled.
+    pub fn resyncs(&self) -> &[Resync] {
+        &self.resyncs
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let slice = &self.mmap.as_slice()[self.offset..];
+    /// Sets the policy for tags that cannot be decoded (default: [`TagDecoding::Unchecked`]). It
+    /// is applied when the returned blobs are decoded to [`PrimitiveBlock`]s.
+    pub fn set_tag_decoding(&mut self, tag_decoding: TagDecoding) {
+        self.options.tag_decoding = tag_decoding;
+    }
+
+    /// Sets how the returned blobs are decoded to [`PrimitiveBlock`]s (default:
+    /// [`BlockDecoding::Eager`]).
+    pub fn set_block_decoding(&mut self, block_decoding: BlockDecoding) {
+        self.options.block_decoding = block_decoding;
+    }
+
+    /// Moves the offset to the next plausible blob after the current offset, or to the end of the
+    /// data.
+    fn resync(&mut self) {
+        let data = self.data;
+        let start = (self.offset + 1).min(data.len());
+        let next = match find_plausible_blob(&data[start..], &self.options) {
+            Some(pos) => start + pos,
+            None => data.len(),
+        };
+        self.resyncs.push(Resync {
+            offset: Some(ByteOffset(self.offset as u64)),
+            skipped_bytes: (next - self.offset) as u64,
+        });
+        self.offset = next;
+        self.last_blob_ok = true;
+    }
+
+    fn read_blob(&mut self) -> Option<Result<MmapBlob<'a>>> {
+        let slice = self.data.get(self.offset..).unwrap_or_default();
 
         match slice.len() {
             0 => return None,
             1..=3 => {
-                self.last_blob_ok = false;
                 return Some(Err(new_blob_error(BlobError::InvalidHeaderSize)));
             }
             _ => {}
@@ -195,15 +351,11 @@ impl<'a> Iterator for MmapBlobReader<'a> {
 
         let header_size = byteorder::BigEndian::read_u32(slice) as usize;
 
-        if header_size as u64 >= MAX_BLOB_HEADER_SIZE {
-            self.last_blob_ok = false;
-            return Some(Err(new_blob_error(BlobError::HeaderTooBig {
-                size: header_size as u64,
-            })));
+        if let Err(e) = self.options.check_header_size(header_size as u64) {
+            return Some(Err(e));
         }
 
         if slice.len() < 4 + header_size {
-            self.last_blob_ok = false;
             let io_error = ::std::io::Error::new(
                 ::std::io::ErrorKind::UnexpectedEof,
                 "content too short for header",
@@ -214,16 +366,18 @@ impl<'a> Iterator for MmapBlobReader<'a> {
         let header = match BlobHeader::parse_from_bytes(&slice[4..(4 + header_size)]) {
             Ok(x) => x,
             Err(e) => {
-                self.last_blob_ok = false;
                 return Some(Err(new_protobuf_error(e, "blob header")));
             }
         };
 
+        if let Err(e) = self.options.check_blob_size(&header) {
+            return Some(Err(e));
+        }
+
         let data_size = header.datasize() as usize;
         let chunk_size = 4 + header_size + data_size;
 
         if slice.len() < chunk_size {
-            self.last_blob_ok = false;
             let io_error = ::std::io::Error::new(
                 ::std::io::ErrorKind::UnexpectedEof,
                 "content too short for block data",
@@ -231,13 +385,93 @@ impl<'a> Iterator for MmapBlobReader<'a> {
             return Some(Err(io_error.into()));
         }
 
+        let content_start = 4 + header_size;
+        let layout = match parse_blob_layout(&slice[content_start..chunk_size]) {
+            Ok(layout) => layout,
+            Err(e) => return Some(Err(e)),
+        };
+
         let prev_offset = self.offset;
         self.offset += chunk_size;
 
         Some(Ok(MmapBlob {
             header,
-            data: &slice[(4 + header_size)..chunk_size],
+            bytes: &slice[..chunk_size],
+            content_start,
+            layout,
             offset: ByteOffset(prev_offset as u64),
+            index: self.next_index,
+            decode: self.options.decode_options(),
         }))
     }
+
+    /// Move the cursor to the given byte offset.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    ///
+    /// let mmap = unsafe { Mmap::from_path("tests/test.osm.pbf")? };
+    /// let mut reader = MmapBlobReader::new(&mmap);
+    ///
+    /// let first_blob = reader.next().unwrap()?;
+    /// let second_blob = reader.next().unwrap()?;
+    ///
+    /// reader.seek(first_blob.offset());
+    /// let first_blob_again = reader.next().unwrap()?;
+    ///
+    /// assert_eq!(first_blob.offset(), first_blob_again.offset());
+    ///
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn seek(&mut self, pos: ByteOffset) {
+        self.offset = pos.0 as usize;
+        self.last_blob_ok = true;
+        self.next_index = None;
+    }
+}
+
+impl<'a> Iterator for MmapBlobReader<'a> {
+    type Item = Result<MmapBlob<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.last_blob_ok {
+            if !self.options.error_recovery {
+                // Stop iteration if there was an error.
+                return None;
+            }
+            self.resync();
+        }
+
+        let offset = ByteOffset(self.offset as u64);
+        let index = self.next_index;
+        let blob = self.read_blob()?;
+        self.next_index = self.next_index.map(|i| i + 1);
+        match blob {
+            Ok(blob) => {
+                let checked = self
+                    .options
+                    .check_blob(blob.header.type_(), Some(offset), || {
+                        decode_blob_ref(&blob.content(), blob.decode.max_blob_size)
+                            .map(HeaderBlock::new)
+                    });
+                match checked {
+                    Ok(()) => Some(Ok(blob)),
+                    Err(e) => {
+                        // The blob is intact, so recovery continues with the next blob
+                        self.last_blob_ok = self.options.error_recovery;
+                        Some(Err(e.with_blob(Some(offset), index)))
+                    }
+                }
+            }
+            Err(e) => {
+                self.last_blob_ok = false;
+                Some(Err(e.with_blob(Some(offset), index)))
+            }
+        }
+    }
 }
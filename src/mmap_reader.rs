@@ -0,0 +1,337 @@
+//! Read elements from a memory map, a byte slice or an owned buffer in parallel
+
+use crate::blob::BlobDecode;
+use crate::block::{BlockDecoding, TagDecoding};
+use crate::elements::Element;
+use crate::error::Result;
+use crate::mmap_blob::{Mmap, MmapBlob, MmapBlobReader, SliceBlobReader};
+use crate::pipeline::WorkerPool;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::sync::Arc;
+
+/// A reader for memory mapped PBF files or PBF data in a byte slice that gives access to the
+/// stored elements.
+///
+/// The boundaries of all blobs are determined once when the reader is created. This only parses
+/// the small blob headers, the content of the blobs stays in the memory map or slice until it is
+/// decoded.
+/// The blobs can then be processed with an indexed rayon [`ParallelIterator`] that splits the
+/// work evenly between threads without reading ahead of the workers.
+#[derive(Clone, Debug)]
+pub struct MmapElementReader<'a> {
+    blobs: Vec<MmapBlob<'a>>,
+    pool: WorkerPool,
+}
+
+/// A reader for the elements of PBF data in a byte slice, created with
+/// [`MmapElementReader::from_slice`]. This is the same type as [`MmapElementReader`].
+pub type SliceElementReader<'a> = MmapElementReader<'a>;
+
+impl<'a> MmapElementReader<'a> {
+    /// Creates a new `MmapElementReader` and scans the boundaries of all blobs in the memory map.
+    ///
+    /// # Errors
+    /// Returns the first error encountered while reading the blob headers.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mmap = unsafe { Mmap::from_path("tests/test.osm.pbf")? };
+    /// let reader = MmapElementReader::new(&mmap)?;
+    ///
+    /// # assert_eq!(reader.blobs().len(), 2);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn new(mmap: &'a Mmap) -> Result<MmapElementReader<'a>> {
+        let blobs = mmap.blob_iter().collect::<Result<Vec<_>>>()?;
+        Ok(MmapElementReader {
+            blobs,
+            pool: WorkerPool::default(),
+        })
+    }
+
+    /// Creates a new `MmapElementReader` for PBF data in memory and scans the boundaries of all
+    /// blobs. Unlike a memory map, this needs no `unsafe` code, so it is useful for files that
+    /// were received over the network or are embedded in a program. `Vec<u8>` and `bytes::Bytes`
+    /// buffers can be passed as slices, or moved into a [`MemoryReader`].
+    ///
+    /// # Errors
+    /// Returns the first error encountered while reading the blob headers.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let data = std::fs::read("tests/test.osm.pbf")?;
+    /// let reader = SliceElementReader::from_slice(&data)?;
+    ///
+    /// let nodes = reader.par_map_reduce(
+    ///     |element| matches!(element, Element::DenseNode(_)) as u64,
+    ///     || 0_u64,
+    ///     |a, b| a + b,
+    /// )?;
+    /// # assert_eq!(nodes, 3);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn from_slice(data: &'a [u8]) -> Result<MmapElementReader<'a>> {
+        let blobs = MmapBlobReader::from_slice(data).collect::<Result<Vec<_>>>()?;
+        Ok(MmapElementReader {
+            blobs,
+            pool: WorkerPool::default(),
+        })
+    }
+
+    /// Sets the policy for tags that cannot be decoded (default: [`TagDecoding::Unchecked`]).
+    pub fn set_tag_decoding(&mut self, tag_decoding: TagDecoding) {
+        for blob in &mut self.blobs {
+            blob.set_tag_decoding(tag_decoding);
+        }
+    }
+
+    /// Sets how blocks are decoded (default: [`BlockDecoding::Eager`]).
+    pub fn set_block_decoding(&mut self, block_decoding: BlockDecoding) {
+        for blob in &mut self.blobs {
+            blob.set_block_decoding(block_decoding);
+        }
+    }
+
+    /// Sets the number of worker threads for [`par_map_reduce`](MmapElementReader::par_map_reduce)
+    /// (default: 0). With 0, the current rayon thread pool is used, otherwise a thread pool of the
+    /// given size is created for each call. Replaces a thread pool that was set with
+    /// [`set_thread_pool`](MmapElementReader::set_thread_pool).
+    pub fn set_threads(&mut self, threads: usize) {
+        self.pool = WorkerPool::with_threads(threads);
+    }
+
+    /// Runs [`par_map_reduce`](MmapElementReader::par_map_reduce) on the given rayon thread pool
+    /// instead of the global pool. Replaces the number of threads set with
+    /// [`set_threads`](MmapElementReader::set_threads).
+    pub fn set_thread_pool(&mut self, pool: Arc<ThreadPool>) {
+        self.pool = WorkerPool::Pool(pool);
+    }
+
+    /// Returns all blobs of the memory map or slice in file order.
+    pub fn blobs(&self) -> &[MmapBlob<'a>] {
+        &self.blobs
+    }
+
+    /// Returns an indexed parallel iterator that decodes the blobs. The results can be collected
+    /// in file order, for example with [`ParallelIterator::collect`] or
+    /// [`IndexedParallelIterator::enumerate`]. The iterator runs in the thread pool of the caller,
+    /// use [`ThreadPool::install`] to run it in a different pool.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    /// use rayon::prelude::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mmap = unsafe { Mmap::from_path("tests/test.osm.pbf")? };
+    /// let reader = MmapElementReader::new(&mmap)?;
+    ///
+    /// // Number of elements per blob in file order
+    /// let counts = reader
+    ///     .par_decode()
+    ///     .map(|decoded| match decoded? {
+    ///         BlobDecode::OsmData(block) => Ok(block.elements().count()),
+    ///         _ => Ok(0),
+    ///     })
+    ///     .collect::<Result<Vec<_>>>()?;
+    ///
+    /// # assert_eq!(counts, vec![0, 5]);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn par_decode(&self) -> impl IndexedParallelIterator<Item = Result<BlobDecode<'_>>> + '_ {
+        let blobs: &[MmapBlob<'_>] = &self.blobs;
+        blobs.par_iter().map(MmapBlob::decode)
+    }
+
+    /// Decodes the blobs sequentially and calls the given closure on each element.
+    /// Consider using `par_map_reduce` instead if you need better performance.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while decoding the blobs.
+    pub fn for_each<F>(&self, mut f: F) -> Result<()>
+    where
+        F: for<'b> FnMut(Element<'b>),
+    {
+        for blob in &self.blobs {
+            if let BlobDecode::OsmData(block) = blob.decode()? {
+                block.for_each_element(&mut f);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parallel map/reduce. Decodes the blobs in parallel, calls the closure `map_op` on each
+    /// element and then reduces the number of results to one item with the closure `reduce_op`.
+    /// See [`ElementReader::par_map_reduce`](crate::reader::ElementReader::par_map_reduce) for a
+    /// description of the arguments.
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while decoding the blobs.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mmap = unsafe { Mmap::from_path("tests/test.osm.pbf")? };
+    /// let reader = MmapElementReader::new(&mmap)?;
+    ///
+    /// // Count the ways
+    /// let ways = reader.par_map_reduce(
+    ///     |element| {
+    ///         match element {
+    ///             Element::Way(_) => 1,
+    ///             _ => 0,
+    ///         }
+    ///     },
+    ///     || 0_u64,      // Zero is the identity value for addition
+    ///     |a, b| a + b   // Sum the partial results
+    /// )?;
+    ///
+    /// println!("Number of ways: {ways}");
+    /// # assert_eq!(ways, 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn par_map_reduce<MP, RD, ID, T>(
+        &self,
+        map_op: MP,
+        identity: ID,
+        reduce_op: RD,
+    ) -> Result<T>
+    where
+        MP: for<'b> Fn(Element<'b>) -> T + Sync + Send,
+        RD: Fn(T, T) -> T + Sync + Send,
+        ID: Fn() -> T + Sync + Send,
+        T: Send,
+    {
+        self.pool.install(|| {
+            self.par_decode()
+                .map(|decoded| match decoded? {
+                    BlobDecode::OsmData(block) => {
+                        Ok(block.elements().map(&map_op).fold(identity(), &reduce_op))
+                    }
+                    BlobDecode::OsmHeader(_) | BlobDecode::Unknown(_) => Ok(identity()),
+                })
+                .reduce(
+                    || Ok(identity()),
+                    |a, b| match (a, b) {
+                        (Ok(x), Ok(y)) => Ok(reduce_op(x, y)),
+                        (x, y) => x.and(y),
+                    },
+                )
+        })?
+    }
+}
+
+/// A reader for PBF data in memory that owns its buffer, for example a `Vec<u8>` or a
+/// `bytes::Bytes` buffer that was received over the network.
+///
+/// Unlike the readers for byte slices, this reader can be stored or moved to another thread
+/// together with its data. Blobs and elements are read with borrowing readers that parse the
+/// buffer without copying it.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let data: Vec<u8> = std::fs::read("tests/test.osm.pbf")?;
+/// let reader = MemoryReader::new(data);
+///
+/// let ways = std::thread::spawn(move || {
+///     reader.par_map_reduce(
+///         |element| matches!(element, Element::Way(_)) as u64,
+///         || 0_u64,
+///         |a, b| a + b,
+///     )
+/// })
+/// .join()
+/// .unwrap()?;
+/// # assert_eq!(ways, 1);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct MemoryReader<D: AsRef<[u8]>> {
+    data: D,
+}
+
+impl<D: AsRef<[u8]>> MemoryReader<D> {
+    /// Creates a new `MemoryReader` that owns the given buffer.
+    pub fn new(data: D) -> MemoryReader<D> {
+        MemoryReader { data }
+    }
+
+    /// Returns the PBF data.
+    pub fn data(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    /// Returns the buffer.
+    pub fn into_inner(self) -> D {
+        self.data
+    }
+
+    /// Returns an iterator over the blobs in the buffer.
+    pub fn blob_iter(&self) -> SliceBlobReader<'_> {
+        SliceBlobReader::from_slice(self.data())
+    }
+
+    /// Scans the boundaries of all blobs and returns a reader for their elements, see
+    /// [`MmapElementReader::from_slice`].
+    ///
+    /// # Errors
+    /// Returns the first error encountered while reading the blob headers.
+    pub fn element_reader(&self) -> Result<SliceElementReader<'_>> {
+        SliceElementReader::from_slice(self.data())
+    }
+
+    /// Decodes the blobs sequentially and calls the given closure on each element, see
+    /// [`MmapElementReader::for_each`].
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while reading or decoding the blobs.
+    pub fn for_each<F>(&self, f: F) -> Result<()>
+    where
+        F: for<'b> FnMut(Element<'b>),
+    {
+        self.element_reader()?.for_each(f)
+    }
+
+    /// Parallel map/reduce on the elements of the current rayon thread pool, see
+    /// [`MmapElementReader::par_map_reduce`].
+    ///
+    /// # Errors
+    /// Returns the first Error encountered while reading or decoding the blobs.
+    pub fn par_map_reduce<MP, RD, ID, T>(
+        &self,
+        map_op: MP,
+        identity: ID,
+        reduce_op: RD,
+    ) -> Result<T>
+    where
+        MP: for<'b> Fn(Element<'b>) -> T + Sync + Send,
+        RD: Fn(T, T) -> T + Sync + Send,
+        ID: Fn() -> T + Sync + Send,
+        T: Send,
+    {
+        self.element_reader()?
+            .par_map_reduce(map_op, identity, reduce_op)
+    }
+}
@@ -0,0 +1,574 @@
+//! Regroup the elements of data blocks into blocks of a target size
+//!
+//! Only blocks with the same granularity and offsets are merged, so coordinates and timestamps
+//! are copied without a loss of precision. Strings are collected in a new stringtable per block.
+//! The sizes of the blocks are tracked with an upper bound of their encoded size.
+
+use crate::blob::{Blob, BlobType};
+use crate::decode::DecodeContext;
+use crate::error::{new_error, new_protobuf_error, Error, ErrorKind, Result};
+use crate::options::DecodeOptions;
+use crate::proto::{fileformat, osmformat};
+use protobuf::Message;
+use std::collections::{HashMap, VecDeque};
+
+/// Upper bound of the encoded size of the fields of a block besides its groups and strings.
+const BLOCK_OVERHEAD: u64 = 64;
+
+/// Upper bound of the encoded size of a group besides its elements: the tag and length prefix.
+const GROUP_OVERHEAD: u64 = 6;
+
+/// Upper bound of the encoded size of a group of dense nodes besides the nodes, including the tags
+/// and length prefixes of all packed fields.
+const DENSE_GROUP_OVERHEAD: u64 = 80;
+
+/// Size limits of regrouped data blocks.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BlockLimits {
+    /// Maximum number of elements in a block. A single element is always accepted.
+    pub(crate) max_elements: usize,
+    /// Maximum size of the encoded, uncompressed block in bytes. A single element is always
+    /// accepted, even if it exceeds the limit.
+    pub(crate) max_bytes: u64,
+}
+
+/// An iterator that regroups the elements of consecutive data blobs into new uncompressed data
+/// blobs within the limits. Other blobs are passed on unchanged and end the current block.
+pub(crate) struct NormalizeBlocks<I> {
+    blobs: I,
+    builder: BlockBuilder,
+    ready: VecDeque<Result<Blob>>,
+    decode: DecodeOptions,
+    done: bool,
+}
+
+impl<I> NormalizeBlocks<I> {
+    pub(crate) fn new(blobs: I, limits: BlockLimits, decode: DecodeOptions) -> NormalizeBlocks<I> {
+        NormalizeBlocks {
+            blobs,
+            builder: BlockBuilder::new(limits),
+            ready: VecDeque::new(),
+            decode,
+            done: false,
+        }
+    }
+
+    fn push(&mut self, blob: Blob) -> Result<()> {
+        if !matches!(blob.get_type(), BlobType::OsmData) {
+            self.flush();
+            self.ready.push_back(Ok(blob));
+            return Ok(());
+        }
+
+        let with_blob = |e: Error| e.with_blob(blob.offset(), blob.index());
+        let block = DecodeContext::with(|context| {
+            let data = context.decompress(&blob.raw().blob_ref(), self.decode.max_blob_size)?;
+            osmformat::PrimitiveBlock::parse_from_bytes(data)
+                .map_err(|e| new_protobuf_error(e, "primitive block"))
+        })
+        .map_err(with_blob)?;
+        let result = self.builder.append(&block).map_err(with_blob);
+        self.take_finished();
+        result
+    }
+
+    fn flush(&mut self) {
+        self.builder.flush();
+        self.take_finished();
+    }
+
+    /// Moves the finished blocks of the builder to the blobs that are ready.
+    fn take_finished(&mut self) {
+        while let Some(block) = self.builder.finished.pop_front() {
+            self.ready.push_back(encode_block(&block, self.decode));
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Blob>>> Iterator for NormalizeBlocks<I> {
+    type Item = Result<Blob>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(blob) = self.ready.pop_front() {
+                return Some(blob);
+            }
+            if self.done {
+                return None;
+            }
+            match self.blobs.next() {
+                Some(Ok(blob)) => {
+                    if let Err(err) = self.push(blob) {
+                        self.flush();
+                        self.ready.push_back(Err(err));
+                    }
+                }
+                Some(Err(err)) => {
+                    self.flush();
+                    self.ready.push_back(Err(err));
+                }
+                None => {
+                    self.flush();
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+/// Returns an uncompressed data blob with the block.
+fn encode_block(block: &osmformat::PrimitiveBlock, decode: DecodeOptions) -> Result<Blob> {
+    let mut blob = fileformat::Blob::new();
+    let data = block
+        .write_to_bytes()
+        .map_err(|e| new_protobuf_error(e, "primitive block"))?;
+    blob.set_raw(data);
+    let content = blob
+        .write_to_bytes()
+        .map_err(|e| new_protobuf_error(e, "blob content"))?;
+
+    let mut header = fileformat::BlobHeader::new();
+    header.set_type(BlobType::OsmData.as_str().to_string());
+    Blob::from_content(header, &content, decode)
+}
+
+/// The kind of elements in a group. Dense nodes with and without metadata or visibility flags are
+/// stored in separate groups, because the metadata is stored for all nodes of a group or none.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum GroupKind {
+    Nodes,
+    Dense { info: bool, visible: bool },
+    Ways,
+    Relations,
+    Changesets,
+}
+
+impl GroupKind {
+    fn overhead(self) -> u64 {
+        match self {
+            GroupKind::Dense { .. } => DENSE_GROUP_OVERHEAD,
+            _ => GROUP_OVERHEAD,
+        }
+    }
+}
+
+/// A dense node with absolute values instead of deltas.
+#[derive(Clone, Copy, Debug, Default)]
+struct DenseNode {
+    id: i64,
+    lat: i64,
+    lon: i64,
+    version: i32,
+    timestamp: i64,
+    changeset: i64,
+    uid: i32,
+    user_sid: i32,
+    visible: bool,
+}
+
+/// An element of an input block. String ids refer to the stringtable of the input block.
+enum Element<'a> {
+    Node(&'a osmformat::Node),
+    /// A dense node and its keys and values.
+    Dense(DenseNode, &'a [i32]),
+    Way(&'a osmformat::Way),
+    Relation(&'a osmformat::Relation),
+    Changeset(&'a osmformat::ChangeSet),
+}
+
+/// An element with string ids of the output block.
+enum Encoded {
+    Node(osmformat::Node),
+    Dense(DenseNode, Vec<i32>),
+    Way(osmformat::Way),
+    Relation(osmformat::Relation),
+    Changeset(osmformat::ChangeSet),
+}
+
+/// Collects elements into blocks that stay within the limits.
+struct BlockBuilder {
+    limits: BlockLimits,
+    block: osmformat::PrimitiveBlock,
+    strings: Vec<Vec<u8>>,
+    string_ids: HashMap<Vec<u8>, u32>,
+    /// Upper bound of the encoded size of the block.
+    bytes: u64,
+    elements: usize,
+    /// The kind of the last group of the block.
+    group: Option<GroupKind>,
+    /// The last dense node of the last group, which the next node is delta coded against.
+    last_node: DenseNode,
+    finished: VecDeque<osmformat::PrimitiveBlock>,
+}
+
+impl BlockBuilder {
+    fn new(limits: BlockLimits) -> BlockBuilder {
+        let mut builder = BlockBuilder {
+            limits,
+            block: osmformat::PrimitiveBlock::new(),
+            strings: vec![],
+            string_ids: HashMap::new(),
+            bytes: 0,
+            elements: 0,
+            group: None,
+            last_node: DenseNode::default(),
+            finished: VecDeque::new(),
+        };
+        builder.reset();
+        builder
+    }
+
+    /// Starts a new, empty block with the same granularity and offsets.
+    fn reset(&mut self) {
+        self.block = empty_block(&self.block);
+
+        // The first string is empty, so string id 0 can be used as delimiter
+        self.strings = vec![vec![]];
+        self.string_ids.clear();
+        self.string_ids.insert(vec![], 0);
+        self.bytes = BLOCK_OVERHEAD + string_size(b"");
+        self.elements = 0;
+        self.group = None;
+    }
+
+    /// Finishes the current block, if it is not empty.
+    fn flush(&mut self) {
+        if self.elements > 0 {
+            let empty = empty_block(&self.block);
+            let mut block = std::mem::replace(&mut self.block, empty);
+            block.stringtable.mut_or_insert_default().s = std::mem::take(&mut self.strings);
+            self.finished.push_back(block);
+        }
+        self.reset();
+    }
+
+    /// Adds the elements of the block.
+    fn append(&mut self, block: &osmformat::PrimitiveBlock) -> Result<()> {
+        let params = |block: &osmformat::PrimitiveBlock| {
+            (
+                block.granularity(),
+                block.lat_offset(),
+                block.lon_offset(),
+                block.date_granularity(),
+            )
+        };
+        if params(block) != params(&self.block) {
+            self.flush();
+        }
+        self.block.set_granularity(block.granularity());
+        self.block.set_lat_offset(block.lat_offset());
+        self.block.set_lon_offset(block.lon_offset());
+        self.block.set_date_granularity(block.date_granularity());
+
+        let strings = block.stringtable.s.as_slice();
+        for group in &block.primitivegroup {
+            for node in &group.nodes {
+                self.add(GroupKind::Nodes, Element::Node(node), strings)?;
+            }
+            if let Some(dense) = group.dense.as_ref() {
+                self.append_dense(dense, strings)?;
+            }
+            for way in &group.ways {
+                self.add(GroupKind::Ways, Element::Way(way), strings)?;
+            }
+            for relation in &group.relations {
+                self.add(GroupKind::Relations, Element::Relation(relation), strings)?;
+            }
+            for changeset in &group.changesets {
+                self.add(
+                    GroupKind::Changesets,
+                    Element::Changeset(changeset),
+                    strings,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn append_dense(&mut self, dense: &osmformat::DenseNodes, strings: &[Vec<u8>]) -> Result<()> {
+        let info = dense.denseinfo.as_ref();
+        let mut node = DenseNode::default();
+        let mut keys_vals = dense.keys_vals.iter().copied();
+        let mut tags = vec![];
+        for (i, id) in dense.id.iter().enumerate() {
+            node.id = node.id.wrapping_add(*id);
+            node.lat = node.lat.wrapping_add(value_at(&dense.lat, i));
+            node.lon = node.lon.wrapping_add(value_at(&dense.lon, i));
+
+            let has_info = info.is_some_and(|info| i < info.version.len());
+            let visible = info.is_some_and(|info| i < info.visible.len());
+            if let Some(info) = info {
+                node.version = info.version.get(i).copied().unwrap_or(-1);
+                node.timestamp = node.timestamp.wrapping_add(value_at(&info.timestamp, i));
+                node.changeset = node.changeset.wrapping_add(value_at(&info.changeset, i));
+                node.uid = node.uid.wrapping_add(value_at(&info.uid, i));
+                node.user_sid = node.user_sid.wrapping_add(value_at(&info.user_sid, i));
+                node.visible = info.visible.get(i).copied().unwrap_or(true);
+            }
+
+            tags.clear();
+            tags.extend(keys_vals.by_ref().take_while(|&s| s != 0));
+            let kind = GroupKind::Dense {
+                info: has_info,
+                visible,
+            };
+            self.add(kind, Element::Dense(node, &tags), strings)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an element to the current block or, if it does not fit, to a new block.
+    fn add(&mut self, kind: GroupKind, element: Element, strings: &[Vec<u8>]) -> Result<()> {
+        let mark = self.strings.len();
+        let (mut encoded, mut size) = self.encode(kind, &element, strings, mark)?;
+        if self.elements > 0
+            && (self.elements >= self.limits.max_elements
+                || self.bytes + size > self.limits.max_bytes)
+        {
+            self.truncate_strings(mark);
+            self.flush();
+            (encoded, size) = self.encode(kind, &element, strings, self.strings.len())?;
+        }
+
+        if self.group != Some(kind) {
+            self.block
+                .primitivegroup
+                .push(osmformat::PrimitiveGroup::new());
+            self.group = Some(kind);
+            self.last_node = DenseNode::default();
+        }
+        let group = self.block.primitivegroup.last_mut().unwrap();
+        match encoded {
+            Encoded::Node(node) => group.nodes.push(node),
+            Encoded::Dense(node, keys_vals) => {
+                let last = self.last_node;
+                let dense = group.dense.mut_or_insert_default();
+                dense.id.push(node.id.wrapping_sub(last.id));
+                dense.lat.push(node.lat.wrapping_sub(last.lat));
+                dense.lon.push(node.lon.wrapping_sub(last.lon));
+                dense.keys_vals.extend(keys_vals);
+                dense.keys_vals.push(0);
+                if let GroupKind::Dense {
+                    info: true,
+                    visible,
+                } = kind
+                {
+                    let info = dense.denseinfo.mut_or_insert_default();
+                    info.version.push(node.version);
+                    info.timestamp
+                        .push(node.timestamp.wrapping_sub(last.timestamp));
+                    info.changeset
+                        .push(node.changeset.wrapping_sub(last.changeset));
+                    info.uid.push(node.uid.wrapping_sub(last.uid));
+                    info.user_sid
+                        .push(node.user_sid.wrapping_sub(last.user_sid));
+                    if visible {
+                        info.visible.push(node.visible);
+                    }
+                }
+                self.last_node = node;
+            }
+            Encoded::Way(way) => group.ways.push(way),
+            Encoded::Relation(relation) => group.relations.push(relation),
+            Encoded::Changeset(changeset) => group.changesets.push(changeset),
+        }
+        self.bytes += size;
+        self.elements += 1;
+        Ok(())
+    }
+
+    /// Returns the element with the string ids of the current block and an upper bound of the
+    /// size that it adds to the block, including the strings that were added since `mark`.
+    fn encode(
+        &mut self,
+        kind: GroupKind,
+        element: &Element,
+        strings: &[Vec<u8>],
+        mark: usize,
+    ) -> Result<(Encoded, u64)> {
+        let result = self.encode_element(kind, element, strings);
+        if result.is_err() {
+            self.truncate_strings(mark);
+        }
+        let (encoded, mut size) = result?;
+        size += self.strings[mark..]
+            .iter()
+            .map(|s| string_size(s))
+            .sum::<u64>();
+        if self.group != Some(kind) {
+            size += kind.overhead();
+        }
+        Ok((encoded, size))
+    }
+
+    fn encode_element(
+        &mut self,
+        kind: GroupKind,
+        element: &Element,
+        strings: &[Vec<u8>],
+    ) -> Result<(Encoded, u64)> {
+        Ok(match *element {
+            Element::Node(node) => {
+                let mut node = node.clone();
+                self.intern_all(&mut node.keys, strings)?;
+                self.intern_all(&mut node.vals, strings)?;
+                self.intern_info(&mut node.info, strings)?;
+                let size = message_size(&node);
+                (Encoded::Node(node), size)
+            }
+            Element::Dense(mut node, tags) => {
+                let keys_vals = tags
+                    .iter()
+                    .map(|&s| Ok(self.intern(string_index(s), strings)? as i32))
+                    .collect::<Result<Vec<_>>>()?;
+                let info = matches!(kind, GroupKind::Dense { info: true, .. });
+                if info {
+                    node.user_sid = self.intern(string_index(node.user_sid), strings)? as i32;
+                }
+
+                let last = if self.group == Some(kind) {
+                    self.last_node
+                } else {
+                    DenseNode::default()
+                };
+                let mut size = signed_size(node.id.wrapping_sub(last.id))
+                    + signed_size(node.lat.wrapping_sub(last.lat))
+                    + signed_size(node.lon.wrapping_sub(last.lon))
+                    + keys_vals
+                        .iter()
+                        .map(|&s| varint_size(s as u64))
+                        .sum::<u64>()
+                    + 1;
+                if info {
+                    size += varint_size(node.version as i64 as u64)
+                        + signed_size(node.timestamp.wrapping_sub(last.timestamp))
+                        + signed_size(node.changeset.wrapping_sub(last.changeset))
+                        + signed_size(node.uid.wrapping_sub(last.uid).into())
+                        + signed_size(node.user_sid.wrapping_sub(last.user_sid).into())
+                        + 1;
+                }
+                (Encoded::Dense(node, keys_vals), size)
+            }
+            Element::Way(way) => {
+                let mut way = way.clone();
+                self.intern_all(&mut way.keys, strings)?;
+                self.intern_all(&mut way.vals, strings)?;
+                self.intern_info(&mut way.info, strings)?;
+                let size = message_size(&way);
+                (Encoded::Way(way), size)
+            }
+            Element::Relation(relation) => {
+                let mut relation = relation.clone();
+                self.intern_all(&mut relation.keys, strings)?;
+                self.intern_all(&mut relation.vals, strings)?;
+                self.intern_info(&mut relation.info, strings)?;
+                for role in &mut relation.roles_sid {
+                    *role = self.intern(string_index(*role), strings)? as i32;
+                }
+                let size = message_size(&relation);
+                (Encoded::Relation(relation), size)
+            }
+            Element::Changeset(changeset) => {
+                let size = message_size(changeset);
+                (Encoded::Changeset(changeset.clone()), size)
+            }
+        })
+    }
+
+    /// Returns the id of the string in the current block and adds it if necessary.
+    fn intern(&mut self, index: usize, strings: &[Vec<u8>]) -> Result<u32> {
+        let string = strings
+            .get(index)
+            .ok_or_else(|| new_error(ErrorKind::StringtableIndexOutOfBounds { index }))?;
+        if let Some(&id) = self.string_ids.get(string.as_slice()) {
+            return Ok(id);
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(string.clone());
+        self.string_ids.insert(string.clone(), id);
+        Ok(id)
+    }
+
+    fn intern_all(&mut self, ids: &mut [u32], strings: &[Vec<u8>]) -> Result<()> {
+        for id in ids {
+            *id = self.intern(*id as usize, strings)?;
+        }
+        Ok(())
+    }
+
+    fn intern_info(
+        &mut self,
+        info: &mut protobuf::MessageField<osmformat::Info>,
+        strings: &[Vec<u8>],
+    ) -> Result<()> {
+        if let Some(user_sid) = info.as_mut().and_then(|info| info.user_sid.as_mut()) {
+            *user_sid = self.intern(*user_sid as usize, strings)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the strings that were added since `mark`.
+    fn truncate_strings(&mut self, mark: usize) {
+        for string in self.strings.drain(mark..) {
+            self.string_ids.remove(&string);
+        }
+    }
+}
+
+/// Returns an empty block with the granularity and offsets of the block.
+fn empty_block(block: &osmformat::PrimitiveBlock) -> osmformat::PrimitiveBlock {
+    let mut empty = osmformat::PrimitiveBlock::new();
+    empty.granularity = block.granularity;
+    empty.lat_offset = block.lat_offset;
+    empty.lon_offset = block.lon_offset;
+    empty.date_granularity = block.date_granularity;
+    empty
+}
+
+/// Returns the value at the index of a packed field or the default for missing values.
+fn value_at<T: Copy + Default>(values: &[T], i: usize) -> T {
+    values.get(i).copied().unwrap_or_default()
+}
+
+/// Converts a signed string id, negative ids are out of bounds.
+fn string_index(id: i32) -> usize {
+    usize::try_from(id).unwrap_or(usize::MAX)
+}
+
+fn varint_size(value: u64) -> u64 {
+    let bits = u64::from(64 - value.leading_zeros()).max(1);
+    (bits + 6) / 7
+}
+
+/// Returns the size of a zigzag encoded varint.
+fn signed_size(value: i64) -> u64 {
+    varint_size(((value << 1) ^ (value >> 63)) as u64)
+}
+
+/// Returns the size of a string in the stringtable with tag and length prefix.
+fn string_size(string: &[u8]) -> u64 {
+    let len = string.len() as u64;
+    1 + varint_size(len) + len
+}
+
+/// Returns the size of an embedded message with tag and length prefix.
+fn message_size<M: Message>(message: &M) -> u64 {
+    let size = message.compute_size();
+    1 + varint_size(size) + size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_size() {
+        assert_eq!(varint_size(0), 1);
+        assert_eq!(varint_size(127), 1);
+        assert_eq!(varint_size(128), 2);
+        assert_eq!(varint_size(u64::MAX), 10);
+        assert_eq!(signed_size(-64), 1);
+        assert_eq!(signed_size(64), 2);
+        assert_eq!(signed_size(i64::MIN), 10);
+    }
+}
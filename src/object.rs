@@ -0,0 +1,436 @@
+//! A common interface for nodes, ways and relations
+
+use crate::dense::{DenseNode, DenseNodeInfo, DenseTagIter};
+use crate::elements::{Element, ElementType, Info, Node, Relation, TagIter, Way};
+use crate::error::Result;
+
+/// The properties that all OSM elements have in common, independent of their representation in
+/// the PBF file.
+///
+/// This trait is implemented by [`Node`], [`DenseNode`], [`Way`], [`Relation`] and [`Element`].
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let reader = ElementReader::from_path("tests/test.osm.pbf")?;
+///
+/// reader.for_each(|element| {
+///     println!(
+///         "{} {} (version {:?}) has {} tags",
+///         element.element_type().as_str(),
+///         element.id(),
+///         element.version(),
+///         element.tags().len(),
+///     );
+///     if let Some(name) = element.tag("name") {
+///         println!("name: {name}");
+///     }
+/// })?;
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+pub trait OsmObject<'a> {
+    /// Returns the element id. Ids are only unique among elements of the same type.
+    fn id(&self) -> i64;
+
+    /// Returns the type of the element.
+    fn element_type(&self) -> ElementType;
+
+    /// Returns an iterator over the tags of the element.
+    fn tags(&self) -> ElementTagIter<'a>;
+
+    /// Returns additional metadata of the element.
+    fn metadata(&self) -> Metadata<'a>;
+
+    /// Returns the value of the tag with the given key.
+    fn tag(&self, key: &str) -> Option<&'a str> {
+        self.tags().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Returns true if the element has a tag with the given key.
+    fn has_tag(&self, key: &str) -> bool {
+        self.tag(key).is_some()
+    }
+
+    /// Returns true if the element has a tag with the given key and value.
+    fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        self.tag(key) == Some(value)
+    }
+
+    /// Returns the version of the element.
+    fn version(&self) -> Option<i32> {
+        self.metadata().version()
+    }
+
+    /// Returns the time stamp in milliseconds since the epoch.
+    fn milli_timestamp(&self) -> Option<i64> {
+        self.metadata().milli_timestamp()
+    }
+
+    /// Returns the id of the changeset that last modified the element.
+    fn changeset(&self) -> Option<i64> {
+        self.metadata().changeset()
+    }
+
+    /// Returns the user id.
+    fn uid(&self) -> Option<i32> {
+        self.metadata().uid()
+    }
+
+    /// Returns the user name.
+    fn user(&self) -> Option<Result<&'a str>> {
+        self.metadata().user()
+    }
+
+    /// Returns the visibility status of the element. This is only relevant if the PBF file
+    /// contains historical information.
+    fn visible(&self) -> bool {
+        self.metadata().visible()
+    }
+}
+
+/// Additional metadata of an element. This is a common view of [`Info`] and [`DenseNodeInfo`].
+#[derive(Clone, Debug)]
+pub struct Metadata<'a> {
+    inner: MetadataInner<'a>,
+}
+
+#[derive(Clone, Debug)]
+enum MetadataInner<'a> {
+    Info(Info<'a>),
+    Dense(DenseNodeInfo<'a>),
+    Missing,
+}
+
+impl<'a> Metadata<'a> {
+    /// Returns the version of the element.
+    pub fn version(&self) -> Option<i32> {
+        match self.inner {
+            MetadataInner::Info(ref info) => info.version(),
+            MetadataInner::Dense(ref info) => Some(info.version()),
+            MetadataInner::Missing => None,
+        }
+    }
+
+    /// Returns the time stamp in milliseconds since the epoch.
+    pub fn milli_timestamp(&self) -> Option<i64> {
+        match self.inner {
+            MetadataInner::Info(ref info) => info.milli_timestamp(),
+            MetadataInner::Dense(ref info) => Some(info.milli_timestamp()),
+            MetadataInner::Missing => None,
+        }
+    }
+
+    /// Returns the changeset id.
+    pub fn changeset(&self) -> Option<i64> {
+        match self.inner {
+            MetadataInner::Info(ref info) => info.changeset(),
+            MetadataInner::Dense(ref info) => Some(info.changeset()),
+            MetadataInner::Missing => None,
+        }
+    }
+
+    /// Returns the user id.
+    pub fn uid(&self) -> Option<i32> {
+        match self.inner {
+            MetadataInner::Info(ref info) => info.uid(),
+            MetadataInner::Dense(ref info) => Some(info.uid()),
+            MetadataInner::Missing => None,
+        }
+    }
+
+    /// Returns the user name.
+    pub fn user(&self) -> Option<Result<&'a str>> {
+        match self.inner {
+            MetadataInner::Info(ref info) => info.user(),
+            MetadataInner::Dense(ref info) => Some(info.user()),
+            MetadataInner::Missing => None,
+        }
+    }
+
+    /// Returns the visibility status of the element. This is only relevant if the PBF file
+    /// contains historical information.
+    pub fn visible(&self) -> bool {
+        match self.inner {
+            MetadataInner::Info(ref info) => info.visible(),
+            MetadataInner::Dense(ref info) => info.visible(),
+            MetadataInner::Missing => true,
+        }
+    }
+
+    /// Returns true if the element was deleted.
+    /// This is a convenience function that just returns the inverse of [`Metadata::visible`].
+    pub fn deleted(&self) -> bool {
+        !self.visible()
+    }
+}
+
+impl<'a> From<Info<'a>> for Metadata<'a> {
+    fn from(info: Info<'a>) -> Metadata<'a> {
+        Metadata {
+            inner: MetadataInner::Info(info),
+        }
+    }
+}
+
+impl<'a> From<Option<DenseNodeInfo<'a>>> for Metadata<'a> {
+    fn from(info: Option<DenseNodeInfo<'a>>) -> Metadata<'a> {
+        Metadata {
+            inner: match info {
+                Some(info) => MetadataInner::Dense(info),
+                None => MetadataInner::Missing,
+            },
+        }
+    }
+}
+
+/// An iterator over the tags of any element. It returns a pair of strings (key and value).
+#[derive(Clone, Debug)]
+pub enum ElementTagIter<'a> {
+    /// Tags of a [`Node`], [`Way`] or [`Relation`].
+    Tags(TagIter<'a>),
+    /// Tags of a [`DenseNode`].
+    DenseTags(DenseTagIter<'a>),
+}
+
+impl<'a> Iterator for ElementTagIter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ElementTagIter::Tags(iter) => iter.next(),
+            ElementTagIter::DenseTags(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ElementTagIter::Tags(iter) => iter.size_hint(),
+            ElementTagIter::DenseTags(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<'a> ExactSizeIterator for ElementTagIter<'a> {}
+
+impl<'a> OsmObject<'a> for Node<'a> {
+    fn id(&self) -> i64 {
+        Node::id(self)
+    }
+
+    fn element_type(&self) -> ElementType {
+        ElementType::Node
+    }
+
+    fn tags(&self) -> ElementTagIter<'a> {
+        ElementTagIter::Tags(Node::tags(self))
+    }
+
+    fn tag(&self, key: &str) -> Option<&'a str> {
+        Node::tag(self, key)
+    }
+
+    fn has_tag(&self, key: &str) -> bool {
+        Node::has_tag(self, key)
+    }
+
+    fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        Node::has_tag_value(self, key, value)
+    }
+
+    fn metadata(&self) -> Metadata<'a> {
+        self.info().into()
+    }
+}
+
+impl<'a> OsmObject<'a> for DenseNode<'a> {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn element_type(&self) -> ElementType {
+        ElementType::Node
+    }
+
+    fn tags(&self) -> ElementTagIter<'a> {
+        ElementTagIter::DenseTags(DenseNode::tags(self))
+    }
+
+    fn tag(&self, key: &str) -> Option<&'a str> {
+        DenseNode::tag(self, key)
+    }
+
+    fn has_tag(&self, key: &str) -> bool {
+        DenseNode::has_tag(self, key)
+    }
+
+    fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        DenseNode::has_tag_value(self, key, value)
+    }
+
+    fn metadata(&self) -> Metadata<'a> {
+        self.info.clone().into()
+    }
+}
+
+impl<'a> OsmObject<'a> for Way<'a> {
+    fn id(&self) -> i64 {
+        Way::id(self)
+    }
+
+    fn element_type(&self) -> ElementType {
+        ElementType::Way
+    }
+
+    fn tags(&self) -> ElementTagIter<'a> {
+        ElementTagIter::Tags(Way::tags(self))
+    }
+
+    fn tag(&self, key: &str) -> Option<&'a str> {
+        Way::tag(self, key)
+    }
+
+    fn has_tag(&self, key: &str) -> bool {
+        Way::has_tag(self, key)
+    }
+
+    fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        Way::has_tag_value(self, key, value)
+    }
+
+    fn metadata(&self) -> Metadata<'a> {
+        self.info().into()
+    }
+}
+
+impl<'a> OsmObject<'a> for Relation<'a> {
+    fn id(&self) -> i64 {
+        Relation::id(self)
+    }
+
+    fn element_type(&self) -> ElementType {
+        ElementType::Relation
+    }
+
+    fn tags(&self) -> ElementTagIter<'a> {
+        ElementTagIter::Tags(Relation::tags(self))
+    }
+
+    fn tag(&self, key: &str) -> Option<&'a str> {
+        Relation::tag(self, key)
+    }
+
+    fn has_tag(&self, key: &str) -> bool {
+        Relation::has_tag(self, key)
+    }
+
+    fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        Relation::has_tag_value(self, key, value)
+    }
+
+    fn metadata(&self) -> Metadata<'a> {
+        self.info().into()
+    }
+}
+
+impl<'a> OsmObject<'a> for Element<'a> {
+    fn id(&self) -> i64 {
+        match self {
+            Element::Node(node) => OsmObject::id(node),
+            Element::DenseNode(node) => OsmObject::id(node),
+            Element::Way(way) => OsmObject::id(way),
+            Element::Relation(relation) => OsmObject::id(relation),
+        }
+    }
+
+    fn element_type(&self) -> ElementType {
+        match self {
+            Element::Node(_) | Element::DenseNode(_) => ElementType::Node,
+            Element::Way(_) => ElementType::Way,
+            Element::Relation(_) => ElementType::Relation,
+        }
+    }
+
+    fn tags(&self) -> ElementTagIter<'a> {
+        match self {
+            Element::Node(node) => OsmObject::tags(node),
+            Element::DenseNode(node) => OsmObject::tags(node),
+            Element::Way(way) => OsmObject::tags(way),
+            Element::Relation(relation) => OsmObject::tags(relation),
+        }
+    }
+
+    fn tag(&self, key: &str) -> Option<&'a str> {
+        match self {
+            Element::Node(node) => node.tag(key),
+            Element::DenseNode(node) => node.tag(key),
+            Element::Way(way) => way.tag(key),
+            Element::Relation(relation) => relation.tag(key),
+        }
+    }
+
+    fn has_tag(&self, key: &str) -> bool {
+        match self {
+            Element::Node(node) => node.has_tag(key),
+            Element::DenseNode(node) => node.has_tag(key),
+            Element::Way(way) => way.has_tag(key),
+            Element::Relation(relation) => relation.has_tag(key),
+        }
+    }
+
+    fn has_tag_value(&self, key: &str, value: &str) -> bool {
+        match self {
+            Element::Node(node) => node.has_tag_value(key, value),
+            Element::DenseNode(node) => node.has_tag_value(key, value),
+            Element::Way(way) => way.has_tag_value(key, value),
+            Element::Relation(relation) => relation.has_tag_value(key, value),
+        }
+    }
+
+    fn metadata(&self) -> Metadata<'a> {
+        match self {
+            Element::Node(node) => node.metadata(),
+            Element::DenseNode(node) => node.metadata(),
+            Element::Way(way) => way.metadata(),
+            Element::Relation(relation) => relation.metadata(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::ElementReader;
+
+    #[test]
+    fn test_element_accessors() {
+        let reader = ElementReader::from_path("tests/test.osm.pbf").unwrap();
+        let mut elements = vec![];
+        reader
+            .for_each(|element| {
+                elements.push((
+                    element.element_type(),
+                    element.id(),
+                    element.tag("name").map(str::to_string),
+                    element.version(),
+                    element.user().map(|user| user.unwrap().to_string()),
+                    element.visible(),
+                ));
+            })
+            .unwrap();
+
+        assert_eq!(elements.len(), 5);
+        assert_eq!(elements[3].0, ElementType::Way);
+        assert_eq!(elements[3].1, 107);
+        assert_eq!(elements[4].0, ElementType::Relation);
+        assert_eq!(elements[4].1, 120);
+        for (element_type, _, _, version, user, visible) in &elements {
+            assert!(version.is_some(), "{element_type:?}");
+            assert!(user.is_some());
+            assert!(visible);
+        }
+    }
+}
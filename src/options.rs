@@ -0,0 +1,338 @@
+//! Configure limits and policies of the readers
+
+use crate::blob::{ByteOffset, MAX_BLOB_HEADER_SIZE, MAX_BLOB_MESSAGE_SIZE};
+use crate::block::{BlockDecoding, HeaderBlock, TagDecoding};
+use crate::error::{new_blob_error, new_error, BlobError, ErrorKind, Result};
+use crate::proto::fileformat;
+use std::fmt;
+use std::sync::Arc;
+
+/// The required features of a file header that this library can read.
+pub const SUPPORTED_FEATURES: &[&str] = &["OsmSchema-V0.6", "DenseNodes", "HistoricalInformation"];
+
+/// What a reader does with blobs of an unknown type. The PBF format allows other blob types
+/// besides `OSMHeader` and `OSMData`, which readers should ignore if they do not expect them.
+#[derive(Clone, Default)]
+pub enum UnknownBlobPolicy {
+    /// Blob readers return unknown blobs (see [`BlobType::Unknown`]) and element readers skip
+    /// them.
+    ///
+    /// [`BlobType::Unknown`]: crate::blob::BlobType::Unknown
+    #[default]
+    Skip,
+    /// Readers return a [`BlobError::UnknownType`] error.
+    Error,
+    /// The callback is called with the type and the offset of each unknown blob, which is then
+    /// handled like with [`UnknownBlobPolicy::Skip`].
+    Callback(UnknownBlobCallback),
+}
+
+/// A callback for [`UnknownBlobPolicy::Callback`] that receives the type and the offset of a blob.
+pub type UnknownBlobCallback = Arc<dyn Fn(&str, Option<ByteOffset>) + Send + Sync>;
+
+impl fmt::Debug for UnknownBlobPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownBlobPolicy::Skip => write!(f, "Skip"),
+            UnknownBlobPolicy::Error => write!(f, "Error"),
+            UnknownBlobPolicy::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+/// Options for reading PBF files that can be passed to the readers of this library, for example
+/// with [`BlobReader::set_options`] or [`ElementReader::set_options`].
+///
+/// The options are built by chaining the setters, starting with the defaults of
+/// [`ReaderOptions::new`].
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let options = ReaderOptions::new()
+///     .max_blob_size(16 * 1024 * 1024)
+///     .unknown_blobs(UnknownBlobPolicy::Error)
+///     .tag_decoding(TagDecoding::Strict)
+///     .validate_features(true);
+///
+/// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+/// reader.set_options(options);
+///
+/// let mut ways = 0_u64;
+/// reader.for_each(|element| {
+///     if let Element::Way(_) = element {
+///         ways += 1;
+///     }
+/// })?;
+/// # assert_eq!(ways, 1);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`BlobReader::set_options`]: crate::blob::BlobReader::set_options
+/// [`ElementReader::set_options`]: crate::reader::ElementReader::set_options
+#[derive(Clone, Debug)]
+pub struct ReaderOptions {
+    pub(crate) max_header_size: u64,
+    pub(crate) max_blob_size: u64,
+    pub(crate) unknown_blobs: UnknownBlobPolicy,
+    pub(crate) error_recovery: bool,
+    pub(crate) tag_decoding: TagDecoding,
+    pub(crate) block_decoding: BlockDecoding,
+    pub(crate) validate_features: bool,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        ReaderOptions::new()
+    }
+}
+
+impl ReaderOptions {
+    /// Creates the default options: the size limits [`MAX_BLOB_HEADER_SIZE`] and
+    /// [`MAX_BLOB_MESSAGE_SIZE`], [`UnknownBlobPolicy::Skip`], no error recovery, the default
+    /// tag and block decoding policies and no feature validation.
+    pub fn new() -> ReaderOptions {
+        ReaderOptions {
+            max_header_size: MAX_BLOB_HEADER_SIZE,
+            max_blob_size: MAX_BLOB_MESSAGE_SIZE,
+            unknown_blobs: UnknownBlobPolicy::default(),
+            error_recovery: false,
+            tag_decoding: TagDecoding::default(),
+            block_decoding: BlockDecoding::default(),
+            validate_features: false,
+        }
+    }
+
+    /// Sets the maximum size of a blob header in bytes (default: [`MAX_BLOB_HEADER_SIZE`]).
+    /// Larger headers cause a [`BlobError::HeaderTooBig`] error.
+    pub fn max_header_size(mut self, size: u64) -> ReaderOptions {
+        self.max_header_size = size;
+        self
+    }
+
+    /// Sets the maximum size of the content of a blob in bytes (default:
+    /// [`MAX_BLOB_MESSAGE_SIZE`]). The limit applies to the stored and to the decompressed
+    /// content. Larger blobs cause a [`BlobError::MessageTooBig`] error.
+    pub fn max_blob_size(mut self, size: u64) -> ReaderOptions {
+        self.max_blob_size = size;
+        self
+    }
+
+    /// Sets what happens with blobs of an unknown type (default: [`UnknownBlobPolicy::Skip`]).
+    pub fn unknown_blobs(mut self, policy: UnknownBlobPolicy) -> ReaderOptions {
+        self.unknown_blobs = policy;
+        self
+    }
+
+    /// Enables or disables the error recovery mode (default: disabled). See
+    /// [`BlobReader::set_error_recovery`](crate::blob::BlobReader::set_error_recovery).
+    pub fn error_recovery(mut self, enabled: bool) -> ReaderOptions {
+        self.error_recovery = enabled;
+        self
+    }
+
+    /// Sets the policy for tags that cannot be decoded (default: [`TagDecoding::Unchecked`]).
+    pub fn tag_decoding(mut self, tag_decoding: TagDecoding) -> ReaderOptions {
+        self.tag_decoding = tag_decoding;
+        self
+    }
+
+    /// Sets how blocks are decoded (default: [`BlockDecoding::Eager`]).
+    pub fn block_decoding(mut self, block_decoding: BlockDecoding) -> ReaderOptions {
+        self.block_decoding = block_decoding;
+        self
+    }
+
+    /// Enables or disables the validation of the required features in the header block
+    /// (default: disabled). If enabled, readers decode every header blob when it is read and
+    /// return an [`ErrorKind::UnsupportedFeature`] error if it requires a feature that is not in
+    /// [`SUPPORTED_FEATURES`].
+    pub fn validate_features(mut self, enabled: bool) -> ReaderOptions {
+        self.validate_features = enabled;
+        self
+    }
+
+    /// Returns the options that are needed to decode a blob.
+    pub(crate) fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            tag_decoding: self.tag_decoding,
+            block_decoding: self.block_decoding,
+            max_blob_size: self.max_blob_size,
+        }
+    }
+
+    /// Returns an error if the size of the blob header is invalid.
+    pub(crate) fn check_header_size(&self, size: u64) -> Result<()> {
+        if size >= self.max_header_size {
+            Err(new_blob_error(BlobError::HeaderTooBig { size }))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an error if the size of the blob content is invalid.
+    pub(crate) fn check_blob_size(&self, header: &fileformat::BlobHeader) -> Result<()> {
+        let size = header.datasize();
+        if size < 0 {
+            Err(new_blob_error(BlobError::NegativeMessageSize { size }))
+        } else if size as u64 >= self.max_blob_size {
+            Err(new_blob_error(BlobError::MessageTooBig {
+                size: size as u64,
+            }))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies the unknown blob policy and the feature validation to a blob that was read. The
+    /// header block is only decoded if the features are validated.
+    pub(crate) fn check_blob<F>(
+        &self,
+        blob_type: &str,
+        offset: Option<ByteOffset>,
+        header_block: F,
+    ) -> Result<()>
+    where
+        F: FnOnce() -> Result<HeaderBlock>,
+    {
+        match blob_type {
+            "OSMHeader" if self.validate_features => check_features(&header_block()?),
+            "OSMHeader" | "OSMData" => Ok(()),
+            x => self.check_unknown_blob(x, offset),
+        }
+    }
+
+    /// Applies the unknown blob policy to a blob of the given type.
+    pub(crate) fn check_unknown_blob(
+        &self,
+        blob_type: &str,
+        offset: Option<ByteOffset>,
+    ) -> Result<()> {
+        match self.unknown_blobs {
+            UnknownBlobPolicy::Skip => Ok(()),
+            UnknownBlobPolicy::Error => Err(new_blob_error(BlobError::UnknownType {
+                blob_type: blob_type.to_string(),
+            })),
+            UnknownBlobPolicy::Callback(ref callback) => {
+                callback(blob_type, offset);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns an error if the header block requires a feature that is not supported.
+fn check_features(header: &HeaderBlock) -> Result<()> {
+    match header
+        .required_features()
+        .iter()
+        .find(|feature| !SUPPORTED_FEATURES.contains(&feature.as_str()))
+    {
+        Some(feature) => Err(new_error(ErrorKind::UnsupportedFeature {
+            feature: feature.clone(),
+        })),
+        None => Ok(()),
+    }
+}
+
+/// The options that are stored with a blob to decode it later.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DecodeOptions {
+    pub(crate) tag_decoding: TagDecoding,
+    pub(crate) block_decoding: BlockDecoding,
+    pub(crate) max_blob_size: u64,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        ReaderOptions::new().decode_options()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_check_blob() {
+        let header = || -> Result<HeaderBlock> { unreachable!("header is not decoded") };
+        let options = ReaderOptions::new();
+        assert!(options.check_blob("OSMHeader", None, header).is_ok());
+        assert!(options.check_blob("OSMData", None, header).is_ok());
+        assert!(options.check_blob("Index", None, header).is_ok());
+
+        let options = options.unknown_blobs(UnknownBlobPolicy::Error);
+        let err = options.check_blob("Index", None, header).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::Blob(BlobError::UnknownType { blob_type }) if blob_type == "Index"
+        ));
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let callback = {
+            let seen = seen.clone();
+            move |blob_type: &str, offset: Option<ByteOffset>| {
+                seen.lock().unwrap().push((blob_type.to_string(), offset));
+            }
+        };
+        let options = options.unknown_blobs(UnknownBlobPolicy::Callback(Arc::new(callback)));
+        assert!(options
+            .check_blob("Index", Some(ByteOffset(7)), header)
+            .is_ok());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [("Index".to_string(), Some(ByteOffset(7)))]
+        );
+        assert_eq!(format!("{:?}", options.unknown_blobs), "Callback(..)");
+    }
+
+    #[test]
+    fn test_check_features() {
+        let mut proto = crate::proto::osmformat::HeaderBlock::new();
+        proto.required_features = vec!["OsmSchema-V0.6".to_string(), "DenseNodes".to_string()];
+        let options = ReaderOptions::new().validate_features(true);
+        let header = HeaderBlock::new(proto.clone());
+        assert!(options.check_blob("OSMHeader", None, || Ok(header)).is_ok());
+
+        proto.required_features.push("Sort.Geographic".to_string());
+        let header = HeaderBlock::new(proto);
+        let err = options
+            .check_blob("OSMHeader", None, || Ok(header.clone()))
+            .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::UnsupportedFeature { feature } if feature == "Sort.Geographic"
+        ));
+        // Not validated by default
+        assert!(ReaderOptions::new()
+            .check_blob("OSMHeader", None, || Ok(header))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let options = ReaderOptions::new().max_header_size(10).max_blob_size(100);
+        assert!(options.check_header_size(9).is_ok());
+        assert!(options.check_header_size(10).is_err());
+
+        let mut header = fileformat::BlobHeader::new();
+        header.set_datasize(99);
+        assert!(options.check_blob_size(&header).is_ok());
+        header.set_datasize(100);
+        assert!(matches!(
+            options.check_blob_size(&header).unwrap_err().kind(),
+            ErrorKind::Blob(BlobError::MessageTooBig { size: 100 })
+        ));
+        header.set_datasize(-1);
+        assert!(matches!(
+            options.check_blob_size(&header).unwrap_err().kind(),
+            ErrorKind::Blob(BlobError::NegativeMessageSize { size: -1 })
+        ));
+        assert_eq!(options.decode_options().max_blob_size, 100);
+    }
+}
@@ -0,0 +1,449 @@
+//! A parallel pipeline with bounded memory usage
+//!
+//! A dedicated I/O thread reads the compressed blobs and sends them to rayon workers that decode
+//! and process them. The I/O thread only reads ahead while the number and the estimated size of
+//! the blobs in flight (read, but not yet processed) stay within the configured limits, so slow
+//! workers throttle the reading instead of letting blobs pile up in memory.
+
+use crate::blob::{Blob, BlobReader};
+use crate::error::Result;
+use rayon::prelude::*;
+use rayon::{Scope, ThreadPool};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// The rayon thread pool that runs the parallel work of a reader.
+#[derive(Clone, Debug, Default)]
+pub(crate) enum WorkerPool {
+    /// The pool of the calling thread, usually the global pool.
+    #[default]
+    Current,
+    /// A new pool with the given number of threads for each call.
+    Threads(usize),
+    /// A pool that is provided by the caller.
+    Pool(Arc<ThreadPool>),
+}
+
+impl WorkerPool {
+    /// Returns the pool for the given number of threads, where 0 selects the current pool.
+    pub(crate) fn with_threads(threads: usize) -> WorkerPool {
+        match threads {
+            0 => WorkerPool::Current,
+            threads => WorkerPool::Threads(threads),
+        }
+    }
+
+    /// Runs the closure in the thread pool.
+    pub(crate) fn install<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send,
+        T: Send,
+    {
+        match self {
+            WorkerPool::Current => Ok(f()),
+            WorkerPool::Threads(threads) => Ok(build_pool(*threads)?.install(f)),
+            WorkerPool::Pool(pool) => Ok(pool.install(f)),
+        }
+    }
+
+    /// Runs the closure on the calling thread with a scope that spawns tasks in the thread pool.
+    pub(crate) fn in_place_scope<'scope, T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Scope<'scope>) -> T,
+    {
+        match self {
+            WorkerPool::Current => Ok(rayon::in_place_scope(f)),
+            WorkerPool::Threads(threads) => Ok(build_pool(*threads)?.in_place_scope(f)),
+            WorkerPool::Pool(pool) => Ok(pool.in_place_scope(f)),
+        }
+    }
+
+    /// Returns the number of worker threads of the thread pool.
+    fn num_threads(&self) -> usize {
+        match self {
+            WorkerPool::Current => rayon::current_num_threads(),
+            WorkerPool::Threads(threads) => *threads,
+            WorkerPool::Pool(pool) => pool.current_num_threads(),
+        }
+    }
+}
+
+fn build_pool(threads: usize) -> Result<ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e).into())
+}
+
+/// Limits of the parallel pipeline.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PipelineOptions {
+    /// Maximum number of blobs in flight or `None` for twice the number of worker threads.
+    pub(crate) max_blobs: Option<usize>,
+    /// Maximum estimated size of the blobs in flight in bytes.
+    pub(crate) max_bytes: Option<u64>,
+    /// The pool of the worker threads.
+    pub(crate) pool: WorkerPool,
+}
+
+/// Counts the blobs in flight and blocks the I/O thread while the limits are exceeded.
+struct InFlight {
+    max_blobs: usize,
+    max_bytes: Option<u64>,
+    state: Mutex<InFlightState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct InFlightState {
+    blobs: usize,
+    bytes: u64,
+    closed: bool,
+}
+
+impl InFlight {
+    fn new(options: &PipelineOptions) -> InFlight {
+        let workers = options.pool.num_threads();
+        InFlight {
+            max_blobs: options.max_blobs.unwrap_or(2 * workers).max(1),
+            max_bytes: options.max_bytes,
+            state: Mutex::new(InFlightState::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Waits until a blob of the given size fits into the limits and adds it. A single blob is
+    /// always accepted, even if it is larger than the byte limit. Returns false if the consumer
+    /// is gone.
+    fn acquire(&self, bytes: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.closed
+            && state.blobs > 0
+            && (state.blobs >= self.max_blobs
+                || self.max_bytes.is_some_and(|max| state.bytes + bytes > max))
+        {
+            state = self.changed.wait(state).unwrap();
+        }
+        if state.closed {
+            return false;
+        }
+        state.blobs += 1;
+        state.bytes += bytes;
+        true
+    }
+
+    fn release(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.blobs -= 1;
+        state.bytes -= bytes;
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Stops the I/O thread when the consumer returns or panics.
+struct CloseOnDrop<'a>(&'a InFlight);
+
+impl Drop for CloseOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Returns the estimated memory usage of a blob while it is processed: the compressed content and
+/// the decompressed content, if its size is known.
+fn estimated_size(blob: &Result<Blob>) -> u64 {
+    match blob {
+        Ok(blob) => {
+            let (stored, raw) = blob.content_sizes();
+            stored + raw.unwrap_or(0)
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Reads the blobs on a dedicated I/O thread and calls `map_op` on each blob in parallel. The
+/// results are combined with `reduce_op`, see
+/// [`ElementReader::par_map_reduce`](crate::reader::ElementReader::par_map_reduce).
+pub(crate) fn par_map_reduce_blobs<R, MP, RD, ID, T>(
+    reader: BlobReader<R>,
+    options: &PipelineOptions,
+    map_op: MP,
+    identity: ID,
+    reduce_op: RD,
+) -> Result<T>
+where
+    R: Read + Send,
+    MP: Fn(Blob) -> Result<T> + Sync + Send,
+    RD: Fn(T, T) -> T + Sync + Send,
+    ID: Fn() -> T + Sync + Send,
+    T: Send,
+{
+    options
+        .pool
+        .install(|| pipeline(reader, options, map_op, identity, reduce_op))?
+}
+
+/// Runs the pipeline in the current thread pool.
+fn pipeline<R, MP, RD, ID, T>(
+    reader: BlobReader<R>,
+    options: &PipelineOptions,
+    map_op: MP,
+    identity: ID,
+    reduce_op: RD,
+) -> Result<T>
+where
+    R: Read + Send,
+    MP: Fn(Blob) -> Result<T> + Sync + Send,
+    RD: Fn(T, T) -> T + Sync + Send,
+    ID: Fn() -> T + Sync + Send,
+    T: Send,
+{
+    let in_flight = InFlight::new(options);
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        let in_flight = &in_flight;
+        scope.spawn(move || {
+            for blob in reader {
+                let bytes = estimated_size(&blob);
+                if !in_flight.acquire(bytes) || sender.send((blob, bytes)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let _close = CloseOnDrop(in_flight);
+        receiver
+            .into_iter()
+            .par_bridge()
+            .map(|(blob, bytes)| {
+                let result = blob.and_then(&map_op);
+                in_flight.release(bytes);
+                result
+            })
+            .reduce(
+                || Ok(identity()),
+                |a, b| match (a, b) {
+                    (Ok(x), Ok(y)) => Ok(reduce_op(x, y)),
+                    (x, y) => x.and(y),
+                },
+            )
+    })
+}
+
+/// Reads the blobs on a dedicated I/O thread, calls `map_op` on each blob in parallel and passes
+/// the results to `sink` on the calling thread in the order of the blobs. A blob stays in flight
+/// until its result is passed to `sink`, so results that wait for a slower predecessor are covered
+/// by the limits as well.
+pub(crate) fn par_map_blobs_ordered<I, MP, SK, T>(
+    blobs: I,
+    options: &PipelineOptions,
+    map_op: MP,
+    mut sink: SK,
+) -> Result<()>
+where
+    I: IntoIterator<Item = Result<Blob>>,
+    I::IntoIter: Send,
+    MP: Fn(Blob) -> Result<T> + Sync + Send,
+    SK: FnMut(T) -> Result<()>,
+    T: Send,
+{
+    let in_flight = &InFlight::new(options);
+    let map_op = &map_op;
+    let blobs = blobs.into_iter();
+    let (sender, receiver) = mpsc::channel();
+
+    options.pool.in_place_scope(|scope| {
+        std::thread::scope(|threads| {
+            threads.spawn(move || {
+                for (index, blob) in blobs.enumerate() {
+                    let bytes = estimated_size(&blob);
+                    if !in_flight.acquire(bytes) {
+                        break;
+                    }
+                    let sender = sender.clone();
+                    scope.spawn(move |_| {
+                        // Panics are passed on, so the missing result cannot block the pipeline
+                        let result =
+                            panic::catch_unwind(AssertUnwindSafe(|| blob.and_then(map_op)));
+                        let _ = sender.send((index, bytes, result));
+                    });
+                }
+            });
+
+            let _close = CloseOnDrop(in_flight);
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            while let Some((index, bytes, result)) = receive(&receiver) {
+                pending.insert(index, (bytes, result));
+                while let Some((bytes, result)) = pending.remove(&next) {
+                    next += 1;
+                    in_flight.release(bytes);
+                    match result {
+                        Ok(result) => sink(result?)?,
+                        Err(payload) => panic::resume_unwind(payload),
+                    }
+                }
+            }
+            Ok(())
+        })
+    })?
+}
+
+/// Waits for the next message. A rayon worker thread runs pending tasks of its pool in the
+/// meantime, so the pipeline also makes progress if it is called from the only worker thread.
+fn receive<T>(receiver: &mpsc::Receiver<T>) -> Option<T> {
+    if rayon::current_thread_index().is_none() {
+        return receiver.recv().ok();
+    }
+    loop {
+        if rayon::yield_now() == Some(rayon::Yield::Executed) {
+            match receiver.try_recv() {
+                Ok(message) => return Some(message),
+                Err(mpsc::TryRecvError::Empty) => continue,
+                Err(mpsc::TryRecvError::Disconnected) => return None,
+            }
+        }
+        match receiver.recv_timeout(Duration::from_millis(1)) {
+            Ok(message) => return Some(message),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_in_flight_limits() {
+        let in_flight = Arc::new(InFlight {
+            max_blobs: 2,
+            max_bytes: Some(100),
+            state: Mutex::new(InFlightState::default()),
+            changed: Condvar::new(),
+        });
+        // A single blob is accepted even if it is too large
+        assert!(in_flight.acquire(500));
+        in_flight.release(500);
+
+        assert!(in_flight.acquire(60));
+        let acquired = Arc::new(AtomicUsize::new(0));
+        let thread = {
+            let (in_flight, acquired) = (in_flight.clone(), acquired.clone());
+            std::thread::spawn(move || {
+                // Waits until the first blob is released
+                assert!(in_flight.acquire(60));
+                acquired.fetch_add(1, Ordering::SeqCst);
+                // Waits until the pipeline is closed
+                assert!(!in_flight.acquire(60));
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(acquired.load(Ordering::SeqCst), 0);
+        in_flight.release(60);
+        while acquired.load(Ordering::SeqCst) == 0 {
+            std::thread::yield_now();
+        }
+        in_flight.close();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_map_reduce_blobs() {
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+        for (max_blobs, max_bytes, pool) in [
+            (None, None, WorkerPool::Current),
+            (Some(1), Some(1), WorkerPool::Threads(2)),
+            (Some(3), None, WorkerPool::Pool(pool)),
+        ] {
+            let options = PipelineOptions {
+                max_blobs,
+                max_bytes,
+                pool,
+            };
+            let reader = BlobReader::from_path("tests/test.osm.pbf").unwrap();
+            let blobs =
+                par_map_reduce_blobs(reader, &options, |_blob| Ok(1), || 0, |a, b| a + b).unwrap();
+            assert_eq!(blobs, 2);
+        }
+
+        // The first error is returned
+        let reader = BlobReader::new(&[0, 0, 0xff, 0xff, 1][..]);
+        let options = PipelineOptions::default();
+        assert!(par_map_reduce_blobs(reader, &options, |_blob| Ok(()), || (), |_, _| ()).is_err());
+    }
+
+    #[test]
+    fn test_map_blobs_ordered() {
+        let data = std::fs::read("tests/test.osm.pbf").unwrap().repeat(10);
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+        let single = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        for (max_blobs, pool) in [
+            (None, WorkerPool::Current),
+            (Some(1), WorkerPool::Threads(3)),
+            (Some(4), WorkerPool::Pool(pool)),
+        ] {
+            let options = PipelineOptions {
+                max_blobs,
+                max_bytes: None,
+                pool,
+            };
+            let run = || {
+                let mut indices = vec![];
+                par_map_blobs_ordered(
+                    BlobReader::new(data.as_slice()),
+                    &options,
+                    |blob| {
+                        // Earlier blobs take longer
+                        let index = blob.index().unwrap();
+                        std::thread::sleep(Duration::from_micros(100 * (20 - index)));
+                        Ok(index)
+                    },
+                    |index| {
+                        indices.push(index);
+                        Ok(())
+                    },
+                )
+                .unwrap();
+                indices
+            };
+            for indices in [run(), single.install(run)] {
+                assert_eq!(indices, (0..20).collect::<Vec<_>>());
+            }
+        }
+
+        // Errors of the sink stop the pipeline
+        let mut calls = 0;
+        let options = PipelineOptions::default();
+        let reader = BlobReader::new(data.as_slice());
+        let result = par_map_blobs_ordered(reader, &options, Ok, |_blob| {
+            calls += 1;
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "sink").into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        // Panics of `map_op` are passed on
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let reader = BlobReader::new(data.as_slice());
+            par_map_blobs_ordered(reader, &options, |_blob| -> Result<()> { panic!() }, Ok)
+        }));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Report the progress of long reads
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A snapshot of the progress of a reader that is passed to the callback of
+/// [`BlobReader::set_progress`](crate::blob::BlobReader::set_progress) and the `set_progress`
+/// methods of the other readers.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Progress {
+    /// The number of bytes that were read from the stream. Blobs that are skipped by seeking,
+    /// like in [`IndexedReader`](crate::indexed::IndexedReader), are not included.
+    pub bytes_read: u64,
+    /// The size of the stream in bytes, if known.
+    pub total_bytes: Option<u64>,
+    /// The number of blobs that were read from the stream.
+    pub blobs_read: u64,
+    /// The number of blobs that were decoded by the element readers.
+    pub blobs_decoded: u64,
+    /// The number of elements that were processed by the element readers.
+    pub elements: u64,
+}
+
+impl Progress {
+    /// Returns the fraction of the stream that was read, between 0 and 1, if the size of the
+    /// stream is known.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total_bytes {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.bytes_read as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+}
+
+/// The callback that receives progress updates.
+pub(crate) type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Marks an unknown total size.
+const UNKNOWN_TOTAL: u64 = u64::MAX;
+
+/// Collects the progress of a reader and its parallel workers and calls the callback at most
+/// once per interval.
+pub(crate) struct ProgressTracker {
+    callback: ProgressCallback,
+    interval: Duration,
+    total_bytes: AtomicU64,
+    bytes_read: AtomicU64,
+    blobs_read: AtomicU64,
+    blobs_decoded: AtomicU64,
+    elements: AtomicU64,
+    last_report: Mutex<LastReport>,
+}
+
+#[derive(Default)]
+struct LastReport {
+    time: Option<Instant>,
+    progress: Option<Progress>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(
+        callback: ProgressCallback,
+        interval: Duration,
+        total_bytes: Option<u64>,
+    ) -> ProgressTracker {
+        ProgressTracker {
+            callback,
+            interval,
+            total_bytes: AtomicU64::new(total_bytes.unwrap_or(UNKNOWN_TOTAL)),
+            bytes_read: AtomicU64::new(0),
+            blobs_read: AtomicU64::new(0),
+            blobs_decoded: AtomicU64::new(0),
+            elements: AtomicU64::new(0),
+            last_report: Mutex::new(LastReport::default()),
+        }
+    }
+
+    pub(crate) fn set_total_bytes(&self, total_bytes: Option<u64>) {
+        self.total_bytes
+            .store(total_bytes.unwrap_or(UNKNOWN_TOTAL), Ordering::Relaxed);
+    }
+
+    /// Records the bytes read so far and a newly read blob.
+    pub(crate) fn blob_read(&self, bytes_read: u64) {
+        self.bytes_read.store(bytes_read, Ordering::Relaxed);
+        self.blobs_read.fetch_add(1, Ordering::Relaxed);
+        self.report(false);
+    }
+
+    /// Records a decoded blob and the number of its processed elements.
+    pub(crate) fn blob_decoded(&self, elements: u64) {
+        self.blobs_decoded.fetch_add(1, Ordering::Relaxed);
+        self.elements.fetch_add(elements, Ordering::Relaxed);
+        self.report(false);
+    }
+
+    /// Records the bytes read at the end of the stream and reports the final progress.
+    pub(crate) fn finish(&self, bytes_read: u64) {
+        self.bytes_read.store(bytes_read, Ordering::Relaxed);
+        self.report(true);
+    }
+
+    /// Reports the final progress of the element readers.
+    pub(crate) fn finish_elements(&self) {
+        self.report(true);
+    }
+
+    pub(crate) fn progress(&self) -> Progress {
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed);
+        Progress {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            total_bytes: (total_bytes != UNKNOWN_TOTAL).then_some(total_bytes),
+            blobs_read: self.blobs_read.load(Ordering::Relaxed),
+            blobs_decoded: self.blobs_decoded.load(Ordering::Relaxed),
+            elements: self.elements.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Calls the callback if the interval has passed or if `force` is set. Unchanged progress is
+    /// not reported twice. Other threads skip the report while the callback is running.
+    fn report(&self, force: bool) {
+        let last = if force {
+            self.last_report.lock().ok()
+        } else {
+            self.last_report.try_lock().ok()
+        };
+        let Some(mut last) = last else {
+            return;
+        };
+        let now = Instant::now();
+        if !force
+            && last
+                .time
+                .is_some_and(|time| now.duration_since(time) < self.interval)
+        {
+            return;
+        }
+        let progress = self.progress();
+        if last.progress == Some(progress) {
+            return;
+        }
+        last.time = Some(now);
+        last.progress = Some(progress);
+        (self.callback)(&progress);
+    }
+}
+
+impl fmt::Debug for ProgressTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressTracker")
+            .field("interval", &self.interval)
+            .field("progress", &self.progress())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording_tracker(interval: Duration) -> (ProgressTracker, Arc<Mutex<Vec<Progress>>>) {
+        let reports = Arc::new(Mutex::new(vec![]));
+        let callback = {
+            let reports = reports.clone();
+            move |progress: &Progress| reports.lock().unwrap().push(*progress)
+        };
+        let tracker = ProgressTracker::new(Arc::new(callback), interval, Some(100));
+        (tracker, reports)
+    }
+
+    #[test]
+    fn test_report_interval() {
+        let (tracker, reports) = recording_tracker(Duration::from_secs(3600));
+        tracker.blob_read(10);
+        tracker.blob_read(50);
+        tracker.blob_decoded(7);
+        // Only the first update is reported within the interval
+        assert_eq!(reports.lock().unwrap().len(), 1);
+
+        tracker.finish(100);
+        tracker.finish_elements();
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(
+            reports[1],
+            Progress {
+                bytes_read: 100,
+                total_bytes: Some(100),
+                blobs_read: 2,
+                blobs_decoded: 1,
+                elements: 7,
+            }
+        );
+        assert_eq!(reports[1].fraction(), Some(1.0));
+    }
+
+    #[test]
+    fn test_report_every_update() {
+        let (tracker, reports) = recording_tracker(Duration::ZERO);
+        tracker.set_total_bytes(None);
+        tracker.blob_read(10);
+        tracker.blob_decoded(3);
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].elements, 3);
+        assert_eq!(reports[1].fraction(), None);
+    }
+}
@@ -1,17 +1,24 @@
 //! High level reader interface
 
-use crate::blob::{BlobDecode, BlobReader};
+use crate::blob::{Blob, BlobDecode, BlobReader};
+use crate::block::{BlockDecoding, TagDecoding};
 use crate::elements::Element;
 use crate::error::Result;
-use rayon::prelude::*;
+use crate::options::ReaderOptions;
+use crate::pipeline::{par_map_reduce_blobs, PipelineOptions, WorkerPool};
+use crate::progress::Progress;
+use rayon::ThreadPool;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// A reader for PBF files that gives access to the stored elements: nodes, ways and relations.
 #[derive(Clone, Debug)]
 pub struct ElementReader<R: Read + Send> {
     blob_iter: BlobReader<R>,
+    pipeline: PipelineOptions,
 }
 
 impl<R: Read + Send> ElementReader<R> {
@@ -34,9 +41,220 @@ impl<R: Read + Send> ElementReader<R> {
     pub fn new(reader: R) -> ElementReader<R> {
         ElementReader {
             blob_iter: BlobReader::new(reader),
+            pipeline: PipelineOptions::default(),
         }
     }
 
+    /// Replaces the options of the underlying [`BlobReader`], see [`ReaderOptions`]. The methods
+    /// of this reader still return the first error, even if error recovery is enabled.
+    pub fn set_options(&mut self, options: ReaderOptions) {
+        self.blob_iter.set_options(options);
+    }
+
+    /// Calls `callback` with the progress of the reader, at most once per `interval` and once
+    /// at the end. Besides the bytes and blobs that were read, the progress includes the decoded
+    /// blobs and the processed elements. The parallel methods call the callback from the I/O
+    /// thread and from the worker threads, but never concurrently.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_progress(Duration::from_secs(10), |progress| {
+    ///     eprintln!(
+    ///         "{} of {:?} bytes, {} elements",
+    ///         progress.bytes_read, progress.total_bytes, progress.elements
+    ///     );
+    /// });
+    ///
+    /// let nodes = reader.par_map_reduce(
+    ///     |element| matches!(element, Element::DenseNode(_)) as u64,
+    ///     || 0,
+    ///     |a, b| a + b,
+    /// )?;
+    /// # assert_eq!(nodes, 3);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_progress<F>(&mut self, interval: Duration, callback: F)
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.blob_iter.set_progress(interval, callback);
+    }
+
+    /// Sets the size of the stream in bytes for the progress reports, see
+    /// [`BlobReader::set_total_bytes`].
+    pub fn set_total_bytes(&mut self, total_bytes: u64) {
+        self.blob_iter.set_total_bytes(total_bytes);
+    }
+
+    /// Sets the policy for tags that cannot be decoded (default: [`TagDecoding::Unchecked`]).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    ///
+    /// // Replace invalid UTF-8 sequences instead of skipping tags
+    /// reader.set_tag_decoding(TagDecoding::Lossy);
+    ///
+    /// reader.for_each(|element| {
+    ///     if let Element::Way(way) = element {
+    ///         for (key, value) in way.tags() {
+    ///             println!("key: {key}, value: {value}");
+    ///         }
+    ///     }
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_tag_decoding(&mut self, tag_decoding: TagDecoding) {
+        self.blob_iter.set_tag_decoding(tag_decoding);
+    }
+
+    /// Sets how blocks are decoded (default: [`BlockDecoding::Eager`]). Lazy decoding avoids
+    /// allocations for fields that are never accessed.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_block_decoding(BlockDecoding::Lazy);
+    ///
+    /// let buildings = reader.par_map_reduce(
+    ///     |element| match element {
+    ///         Element::Way(way) if way.has_tag("building") => 1,
+    ///         _ => 0,
+    ///     },
+    ///     || 0_u64,
+    ///     |a, b| a + b,
+    /// )?;
+    ///
+    /// # assert_eq!(buildings, 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_block_decoding(&mut self, block_decoding: BlockDecoding) {
+        self.blob_iter.set_block_decoding(block_decoding);
+    }
+
+    /// Sets the maximum number of blobs that the parallel methods keep in memory at the same time
+    /// (default: twice the number of worker threads). This includes blobs that were read ahead
+    /// and blobs that are being decoded.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_max_blobs_in_flight(8);
+    /// reader.set_max_bytes_in_flight(512 * 1024 * 1024);
+    /// reader.set_threads(4);
+    ///
+    /// let elements = reader.par_map_reduce(|_| 1_u64, || 0, |a, b| a + b)?;
+    /// # assert_eq!(elements, 5);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_max_blobs_in_flight(&mut self, max_blobs: usize) {
+        self.pipeline.max_blobs = Some(max_blobs.max(1));
+    }
+
+    /// Sets a memory budget in bytes for the blobs that the parallel methods keep in memory at
+    /// the same time (default: unlimited). The size of a blob is estimated from its compressed
+    /// size and the decompressed size stored in the blob, so the budget does not cover the
+    /// decoded elements. A single blob is always processed, even if it exceeds the budget.
+    pub fn set_max_bytes_in_flight(&mut self, max_bytes: u64) {
+        self.pipeline.max_bytes = Some(max_bytes);
+    }
+
+    /// Sets the number of worker threads for the parallel methods (default: 0). With 0, the
+    /// current rayon thread pool is used, which is the global pool unless the method is called
+    /// inside of [`ThreadPool::install`]. Otherwise a thread pool of the given size is created for
+    /// each call. The blobs are always read on a separate I/O thread.
+    ///
+    /// Replaces a thread pool that was set with
+    /// [`set_thread_pool`](ElementReader::set_thread_pool).
+    pub fn set_threads(&mut self, threads: usize) {
+        self.pipeline.pool = WorkerPool::with_threads(threads);
+    }
+
+    /// Runs the parallel methods on the given rayon thread pool instead of the global pool, so
+    /// decoding does not compete with other parallel work of the application. Replaces the number
+    /// of threads set with [`set_threads`](ElementReader::set_threads).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    /// use std::sync::Arc;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+    ///
+    /// let mut reader = ElementReader::from_path("tests/test.osm.pbf")?;
+    /// reader.set_thread_pool(pool.clone());
+    ///
+    /// let ways = reader.par_map_reduce(
+    ///     |element| matches!(element, Element::Way(_)) as u64,
+    ///     || 0,
+    ///     |a, b| a + b,
+    /// )?;
+    /// # assert_eq!(ways, 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_thread_pool(&mut self, pool: Arc<ThreadPool>) {
+        self.pipeline.pool = WorkerPool::Pool(pool);
+    }
+
+    /// Processes the blobs with the bounded parallel pipeline. `map_op` returns its result and
+    /// the number of processed elements for the progress reports.
+    pub(crate) fn par_map_reduce_blobs<MP, RD, ID, T>(
+        self,
+        map_op: MP,
+        identity: ID,
+        reduce_op: RD,
+    ) -> Result<T>
+    where
+        MP: Fn(Blob) -> Result<(T, u64)> + Sync + Send,
+        RD: Fn(T, T) -> T + Sync + Send,
+        ID: Fn() -> T + Sync + Send,
+        T: Send,
+    {
+        let progress = self.blob_iter.progress_tracker().cloned();
+        let result = par_map_reduce_blobs(
+            self.blob_iter,
+            &self.pipeline,
+            |blob| {
+                let (result, elements) = map_op(blob)?;
+                if let Some(progress) = &progress {
+                    progress.blob_decoded(elements);
+                }
+                Ok(result)
+            },
+            identity,
+            reduce_op,
+        );
+        if let Some(progress) = &progress {
+            progress.finish_elements();
+        }
+        result
+    }
+
     /// Decodes the PBF structure sequentially and calls the given closure on each element.
     /// Consider using `par_map_reduce` instead if you need better performance.
     ///
@@ -68,17 +286,29 @@ impl<R: Read + Send> ElementReader<R> {
     where
         F: for<'a> FnMut(Element<'a>),
     {
+        let progress = self.blob_iter.progress_tracker().cloned();
         //TODO do something useful with header blocks
         for blob in self.blob_iter {
-            match blob?.decode() {
-                Ok(BlobDecode::OsmHeader(_)) | Ok(BlobDecode::Unknown(_)) => {}
+            let elements = match blob?.decode() {
+                Ok(BlobDecode::OsmHeader(_)) | Ok(BlobDecode::Unknown(_)) => 0,
                 Ok(BlobDecode::OsmData(block)) => {
-                    block.for_each_element(&mut f);
+                    let mut elements = 0;
+                    block.for_each_element(|element| {
+                        elements += 1;
+                        f(element);
+                    });
+                    elements
                 }
                 Err(e) => return Err(e),
+            };
+            if let Some(progress) = &progress {
+                progress.blob_decoded(elements);
             }
         }
 
+        if let Some(progress) = &progress {
+            progress.finish_elements();
+        }
         Ok(())
     }
 
@@ -89,6 +319,10 @@ impl<R: Read + Send> ElementReader<R> {
     /// necessary. The number of times that this identity value is inserted should not alter the
     /// result.
     ///
+    /// The blobs are read on a separate I/O thread that stops reading ahead when the limits set
+    /// with [`set_max_blobs_in_flight`](ElementReader::set_max_blobs_in_flight) and
+    /// [`set_max_bytes_in_flight`](ElementReader::set_max_bytes_in_flight) are reached.
+    ///
     /// # Errors
     /// Returns the first Error encountered while parsing the PBF structure.
     ///
@@ -123,22 +357,24 @@ impl<R: Read + Send> ElementReader<R> {
         ID: Fn() -> T + Sync + Send,
         T: Send,
     {
-        self.blob_iter
-            .par_bridge()
-            .map(|blob| match blob?.decode() {
-                Ok(BlobDecode::OsmHeader(_)) | Ok(BlobDecode::Unknown(_)) => Ok(identity()),
-                Ok(BlobDecode::OsmData(block)) => {
-                    Ok(block.elements().map(&map_op).fold(identity(), &reduce_op))
+        self.par_map_reduce_blobs(
+            |blob| match blob.decode()? {
+                BlobDecode::OsmHeader(_) | BlobDecode::Unknown(_) => Ok((identity(), 0)),
+                BlobDecode::OsmData(block) => {
+                    let mut elements = 0;
+                    let result = block
+                        .elements()
+                        .map(|element| {
+                            elements += 1;
+                            map_op(element)
+                        })
+                        .fold(identity(), &reduce_op);
+                    Ok((result, elements))
                 }
-                Err(e) => Err(e),
-            })
-            .reduce(
-                || Ok(identity()),
-                |a, b| match (a, b) {
-                    (Ok(x), Ok(y)) => Ok(reduce_op(x, y)),
-                    (x, y) => x.and(y),
-                },
-            )
+            },
+            &identity,
+            &reduce_op,
+        )
     }
 }
 
@@ -161,6 +397,7 @@ impl ElementReader<BufReader<File>> {
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         Ok(ElementReader {
             blob_iter: BlobReader::from_path(path)?,
+            pipeline: PipelineOptions::default(),
         })
     }
 }
@@ -0,0 +1,462 @@
+//! Change the compression of blobs
+
+use crate::blob::{message_size, Blob, BlobCompression, RawBlob, MAX_BLOB_MESSAGE_SIZE};
+use crate::decode::DecodeContext;
+use crate::error::{new_blob_error, new_error, new_protobuf_error, BlobError, ErrorKind, Result};
+use crate::normalize::{BlockLimits, NormalizeBlocks};
+use crate::options::DecodeOptions;
+use crate::pipeline::{par_map_blobs_ordered, PipelineOptions, WorkerPool};
+use crate::proto::fileformat;
+use crate::writer::BlobWriter;
+use protobuf::Message;
+use rayon::ThreadPool;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Maximum size of a regrouped data block in bytes if only the number of elements is limited. This
+/// is the block size that is recommended by the PBF format.
+pub const DEFAULT_MAX_BLOCK_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The compression of re-encoded blobs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BlobEncoding {
+    /// Store the content without compression.
+    Raw,
+    /// Compress the content with zlib at the given level from 0 (fastest) to 9 (smallest).
+    Zlib {
+        /// The compression level.
+        level: u32,
+    },
+    /// Compress the content with Zstandard at the given level from 1 (fastest) to 22 (smallest).
+    /// Level 0 selects the default level of the zstd library, negative levels trade compression
+    /// ratio for even more speed.
+    #[cfg(feature = "zstd")]
+    Zstd {
+        /// The compression level.
+        level: i32,
+    },
+    /// Compress the content to an LZ4 block.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Default for BlobEncoding {
+    /// Returns zlib with the default level 6, the compression that is supported by all readers.
+    fn default() -> Self {
+        BlobEncoding::Zlib { level: 6 }
+    }
+}
+
+impl BlobEncoding {
+    /// Returns the compression of blobs with this encoding.
+    pub fn compression(&self) -> BlobCompression {
+        match self {
+            BlobEncoding::Raw => BlobCompression::Raw,
+            BlobEncoding::Zlib { .. } => BlobCompression::Zlib,
+            #[cfg(feature = "zstd")]
+            BlobEncoding::Zstd { .. } => BlobCompression::Zstd,
+            #[cfg(feature = "lz4")]
+            BlobEncoding::Lz4 => BlobCompression::Lz4,
+        }
+    }
+
+    /// Returns an error if the compression level is not supported by the compression.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// assert!(BlobEncoding::Zlib { level: 9 }.check_level().is_ok());
+    /// assert!(BlobEncoding::Zlib { level: 10 }.check_level().is_err());
+    /// ```
+    pub fn check_level(&self) -> Result<()> {
+        let invalid = match *self {
+            BlobEncoding::Zlib { level } if level > 9 => Some(i64::from(level)),
+            #[cfg(feature = "zstd")]
+            BlobEncoding::Zstd { level } if !zstd::compression_level_range().contains(&level) => {
+                Some(i64::from(level))
+            }
+            _ => None,
+        };
+        match invalid {
+            None => Ok(()),
+            Some(level) => Err(new_error(ErrorKind::InvalidCompressionLevel {
+                compression: self.compression(),
+                level,
+            })),
+        }
+    }
+
+    /// Returns the `Blob` message with the encoded data.
+    fn encode(&self, data: &[u8]) -> Result<fileformat::Blob> {
+        self.check_level()?;
+        let mut blob = fileformat::Blob::new();
+        match *self {
+            BlobEncoding::Raw => {
+                blob.set_raw(data.to_vec());
+                return Ok(blob);
+            }
+            BlobEncoding::Zlib { level } => {
+                let level = flate2::Compression::new(level);
+                let mut encoder = flate2::write::ZlibEncoder::new(vec![], level);
+                encoder.write_all(data)?;
+                blob.set_zlib_data(encoder.finish()?);
+            }
+            #[cfg(feature = "zstd")]
+            BlobEncoding::Zstd { level } => blob.set_zstd_data(zstd::bulk::compress(data, level)?),
+            #[cfg(feature = "lz4")]
+            BlobEncoding::Lz4 => blob.set_lz4_data(lz4_flex::block::compress(data)),
+        }
+        blob.set_raw_size(message_size(data.len())?);
+        Ok(blob)
+    }
+}
+
+/// Decodes the content of blobs and encodes it again with a different compression.
+///
+/// The `datasize` of the blob header and the `raw_size` of the blob are updated, other fields of
+/// the blob header are kept. Blobs without content are copied unchanged. Decoding Zstandard and
+/// LZ4 blobs requires the `zstd` and `lz4` features.
+///
+/// With [`set_max_block_elements`](Recompressor::set_max_block_elements) or
+/// [`set_max_block_bytes`](Recompressor::set_max_block_bytes), the elements of the data blocks are
+/// also regrouped into blocks of the given size.
+///
+/// # Example
+/// Convert a file to uncompressed blobs:
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let reader = BlobReader::from_path("tests/test.osm.pbf")?;
+/// let mut writer = BlobWriter::new(vec![]);
+///
+/// let mut recompressor = Recompressor::new(BlobEncoding::Raw);
+/// recompressor.set_parallel(true);
+/// recompressor.recompress(reader, &mut writer)?;
+///
+/// let data = writer.into_inner()?;
+/// for blob in MmapBlobReader::from_slice(&data) {
+///     assert_eq!(blob?.raw().compression(), Some(BlobCompression::Raw));
+/// }
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Recompressor {
+    encoding: BlobEncoding,
+    max_blob_size: u64,
+    parallel: bool,
+    pipeline: PipelineOptions,
+    max_block_elements: Option<usize>,
+    max_block_bytes: Option<u64>,
+}
+
+impl Recompressor {
+    /// Creates a new `Recompressor` that encodes blobs with the given compression.
+    pub fn new(encoding: BlobEncoding) -> Recompressor {
+        Recompressor {
+            encoding,
+            max_blob_size: MAX_BLOB_MESSAGE_SIZE,
+            parallel: false,
+            pipeline: PipelineOptions::default(),
+            max_block_elements: None,
+            max_block_bytes: None,
+        }
+    }
+
+    /// Sets whether [`recompress`](Recompressor::recompress) encodes blobs in parallel. The blobs
+    /// are read on a separate I/O thread and encoded in the thread pool that is selected with
+    /// [`set_threads`](Recompressor::set_threads) or
+    /// [`set_thread_pool`](Recompressor::set_thread_pool). The order of the blobs is preserved.
+    /// Disabled by default.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    /// Sets the maximum number of blobs that are read, but not yet written, during parallel
+    /// recompression (default: twice the number of worker threads). Blobs that are encoded before
+    /// one of their predecessors count until they are written.
+    pub fn set_max_blobs_in_flight(&mut self, max_blobs: usize) {
+        self.pipeline.max_blobs = Some(max_blobs.max(1));
+    }
+
+    /// Sets a memory budget in bytes for the blobs that parallel recompression keeps in memory at
+    /// the same time (default: unlimited). The size of a blob is estimated from its compressed
+    /// size and the decompressed size stored in the blob. A single blob is always processed, even
+    /// if it exceeds the budget.
+    pub fn set_max_bytes_in_flight(&mut self, max_bytes: u64) {
+        self.pipeline.max_bytes = Some(max_bytes);
+    }
+
+    /// Sets the number of worker threads for parallel recompression (default: 0). With 0, the
+    /// current rayon thread pool is used. Otherwise a thread pool of the given size is created for
+    /// each call of [`recompress`](Recompressor::recompress). Replaces a thread pool that was set
+    /// with [`set_thread_pool`](Recompressor::set_thread_pool).
+    pub fn set_threads(&mut self, threads: usize) {
+        self.pipeline.pool = WorkerPool::with_threads(threads);
+    }
+
+    /// Encodes the blobs of parallel recompression on the given rayon thread pool instead of the
+    /// current pool. Replaces the number of threads set with
+    /// [`set_threads`](Recompressor::set_threads).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    /// use std::sync::Arc;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+    ///
+    /// let mut recompressor = Recompressor::new(BlobEncoding::Zlib { level: 9 });
+    /// recompressor.set_parallel(true);
+    /// recompressor.set_thread_pool(pool);
+    ///
+    /// let mut writer = BlobWriter::new(vec![]);
+    /// recompressor.recompress(BlobReader::from_path("tests/test.osm.pbf")?, &mut writer)?;
+    /// # assert_eq!(writer.blobs_written(), 2);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_thread_pool(&mut self, pool: Arc<ThreadPool>) {
+        self.pipeline.pool = WorkerPool::Pool(pool);
+    }
+
+    /// Sets the maximum size of the decompressed and the re-encoded content of a blob. Defaults
+    /// to [`MAX_BLOB_MESSAGE_SIZE`].
+    pub fn set_max_blob_size(&mut self, max_blob_size: u64) {
+        self.max_blob_size = max_blob_size;
+    }
+
+    /// Regroups the elements of the data blocks into blocks with at most the given number of
+    /// nodes, ways, relations and changesets. Small blocks are merged and large blocks are split,
+    /// the order of the elements is preserved. Blocks are only merged if they have the same
+    /// granularity and offsets, and other blobs, like header blobs, end the current block. Blocks
+    /// are also limited to [`DEFAULT_MAX_BLOCK_BYTES`], unless a different size is set with
+    /// [`set_max_block_bytes`](Recompressor::set_max_block_bytes).
+    ///
+    /// Regrouping only applies to [`recompress`](Recompressor::recompress). The data blocks are
+    /// decoded and regrouped sequentially, the new blocks are encoded in parallel if enabled with
+    /// [`set_parallel`](Recompressor::set_parallel).
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let mut recompressor = Recompressor::new(BlobEncoding::default());
+    /// recompressor.set_max_block_elements(8000);
+    ///
+    /// let mut writer = BlobWriter::new(vec![]);
+    /// recompressor.recompress(BlobReader::from_path("tests/test.osm.pbf")?, &mut writer)?;
+    /// # assert_eq!(writer.blobs_written(), 2);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn set_max_block_elements(&mut self, max_elements: usize) {
+        self.max_block_elements = Some(max_elements.max(1));
+    }
+
+    /// Regroups the elements of the data blocks into blocks with an uncompressed size of at most
+    /// the given number of bytes, see
+    /// [`set_max_block_elements`](Recompressor::set_max_block_elements). A block with a single
+    /// element may exceed the size.
+    pub fn set_max_block_bytes(&mut self, max_bytes: u64) {
+        self.max_block_bytes = Some(max_bytes);
+    }
+
+    /// Returns the blob with its content encoded with the compression of this `Recompressor`.
+    ///
+    /// # Errors
+    /// Returns an error if the compression level of the encoding is invalid, if the content of the
+    /// blob cannot be decompressed or if the decompressed or the re-encoded content is not
+    /// smaller than the maximum blob size.
+    ///
+    /// # Example
+    /// ```
+    /// use osmpbf::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// let recompressor = Recompressor::new(BlobEncoding::Zlib { level: 9 });
+    /// let data = std::fs::read("tests/test.osm.pbf")?;
+    /// for blob in MmapBlobReader::from_slice(&data) {
+    ///     let blob = recompressor.recompress_blob(&blob?.raw())?;
+    ///     # assert!(blob.raw().raw_size().is_some());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn recompress_blob(&self, blob: &RawBlob) -> Result<Blob> {
+        let header = fileformat::BlobHeader::parse_from_bytes(blob.header())
+            .map_err(|e| new_protobuf_error(e, "blob header"))?;
+        if blob.payload().is_none() {
+            return Blob::new(
+                header,
+                blob.bytes().to_vec(),
+                None,
+                None,
+                self.decode_options(),
+            );
+        }
+
+        let content = DecodeContext::with(|context| {
+            let data = context.decompress(&blob.blob_ref(), self.max_blob_size)?;
+            self.encoding.encode(data)
+        })?
+        .write_to_bytes()
+        .map_err(|e| new_protobuf_error(e, "blob content"))?;
+        if content.len() as u64 >= self.max_blob_size {
+            let size = content.len() as u64;
+            return Err(new_blob_error(BlobError::MessageTooBig { size }));
+        }
+        Blob::from_content(header, &content, self.decode_options())
+    }
+
+    /// Re-encodes all blobs and writes them in their original order.
+    ///
+    /// # Errors
+    /// Returns the first error of the blobs, of re-encoding a blob or of writing to `writer`.
+    /// Blobs that were re-encoded before the error are already written. An invalid compression
+    /// level is reported before any blob is read.
+    pub fn recompress<I, W>(&self, blobs: I, writer: &mut BlobWriter<W>) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<Blob>>,
+        I::IntoIter: Send,
+        W: Write,
+    {
+        self.encoding.check_level()?;
+        let blobs = blobs.into_iter();
+        match self.block_limits() {
+            Some(limits) => {
+                let blobs = NormalizeBlocks::new(blobs, limits, self.decode_options());
+                self.write_recompressed(blobs, writer)
+            }
+            None => self.write_recompressed(blobs, writer),
+        }
+    }
+
+    fn write_recompressed<I, W>(&self, blobs: I, writer: &mut BlobWriter<W>) -> Result<()>
+    where
+        I: Iterator<Item = Result<Blob>> + Send,
+        W: Write,
+    {
+        let recompress = |blob: Blob| {
+            self.recompress_blob(&blob.raw())
+                .map_err(|e| e.with_blob(blob.offset(), blob.index()))
+        };
+        let mut write = |blob: Blob| writer.write_blob(&blob).map(|_| ());
+
+        if self.parallel {
+            par_map_blobs_ordered(blobs, &self.pipeline, recompress, write)
+        } else {
+            blobs
+                .into_iter()
+                .try_for_each(|blob| write(recompress(blob?)?))
+        }
+    }
+
+    fn block_limits(&self) -> Option<BlockLimits> {
+        if self.max_block_elements.is_none() && self.max_block_bytes.is_none() {
+            return None;
+        }
+        Some(BlockLimits {
+            max_elements: self.max_block_elements.unwrap_or(usize::MAX),
+            max_bytes: self.max_block_bytes.unwrap_or(DEFAULT_MAX_BLOCK_BYTES),
+        })
+    }
+
+    fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            max_blob_size: self.max_blob_size,
+            ..DecodeOptions::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::{BlobDecode, BlobReader};
+
+    fn encodings() -> Vec<BlobEncoding> {
+        vec![
+            BlobEncoding::Raw,
+            BlobEncoding::Zlib { level: 0 },
+            BlobEncoding::Zlib { level: 9 },
+            #[cfg(feature = "zstd")]
+            BlobEncoding::Zstd { level: 3 },
+            #[cfg(feature = "lz4")]
+            BlobEncoding::Lz4,
+        ]
+    }
+
+    #[test]
+    fn test_recompress_blob() {
+        for encoding in encodings() {
+            let recompressor = Recompressor::new(encoding);
+            for blob in BlobReader::from_path("tests/test.osm.pbf").unwrap() {
+                let blob = blob.unwrap();
+                let recompressed = recompressor.recompress_blob(&blob.raw()).unwrap();
+                let raw = recompressed.raw();
+                assert_eq!(raw.compression(), Some(encoding.compression()));
+                assert_eq!(recompressed.get_type(), blob.get_type());
+
+                // The sizes match the new content
+                let header = fileformat::BlobHeader::parse_from_bytes(raw.header()).unwrap();
+                assert_eq!(header.datasize() as usize, raw.content().len());
+                match encoding {
+                    BlobEncoding::Raw => assert_eq!(raw.raw_size(), None),
+                    _ => assert_eq!(raw.raw_size(), blob.raw().raw_size()),
+                }
+                assert!(matches!(
+                    (blob.decode().unwrap(), recompressed.decode().unwrap()),
+                    (BlobDecode::OsmHeader(_), BlobDecode::OsmHeader(_))
+                        | (BlobDecode::OsmData(_), BlobDecode::OsmData(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut recompressor = Recompressor::new(BlobEncoding::Raw);
+        recompressor.set_max_blob_size(128);
+        let blob = BlobReader::from_path("tests/test.osm.pbf")
+            .unwrap()
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        assert!(recompressor.recompress_blob(&blob.raw()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        let invalid = vec![
+            BlobEncoding::Zlib { level: 10 },
+            #[cfg(feature = "zstd")]
+            BlobEncoding::Zstd { level: 23 },
+        ];
+        for encoding in invalid {
+            let err = encoding.check_level().unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                ErrorKind::InvalidCompressionLevel { compression, .. }
+                    if *compression == encoding.compression()
+            ));
+
+            let recompressor = Recompressor::new(encoding);
+            let mut writer = BlobWriter::new(vec![]);
+            let reader = BlobReader::from_path("tests/test.osm.pbf").unwrap();
+            assert!(recompressor.recompress(reader, &mut writer).is_err());
+            assert_eq!(writer.blobs_written(), 0);
+        }
+        for encoding in encodings() {
+            assert!(encoding.check_level().is_ok());
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Text formatting that is shared by the exporters and the command line tool
+
+/// Formats a timestamp in milliseconds since the epoch as an ISO 8601 string in UTC with second
+/// precision, for example `2003-04-05T06:07:08Z`.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// assert_eq!(format_timestamp(1_049_522_828_000), "2003-04-05T06:07:08Z");
+/// ```
+pub fn format_timestamp(milli_timestamp: i64) -> String {
+    let seconds = milli_timestamp.div_euclid(1000);
+    let (days, seconds) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // Convert days since the epoch to a date in the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Appends a string as a quoted JSON string literal and escapes quotes, backslashes and control
+/// characters.
+///
+/// # Example
+/// ```
+/// use osmpbf::*;
+///
+/// let mut json = String::new();
+/// push_json_string(&mut json, "\"a\"\n");
+/// assert_eq!(json, r#""\"a\"\n""#);
+/// ```
+pub fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1_049_522_828_000), "2003-04-05T06:07:08Z");
+        assert_eq!(format_timestamp(951_782_400_999), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(-1000), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_push_json_string() {
+        let mut out = String::new();
+        push_json_string(&mut out, "a\\b\t\u{1}ä");
+        assert_eq!(out, r#""a\\b\t\u0001ä""#);
+    }
+}
@@ -0,0 +1,155 @@
+//! Write blobs to a PBF stream without decoding them
+
+use crate::blob::{Blob, ByteOffset, RawBlob};
+use crate::error::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// A writer that appends encoded blobs to a stream byte for byte.
+///
+/// Blobs are copied without decompressing their content, so files can be split, concatenated or
+/// trimmed at blob granularity. The writer does not check the order of the blobs: a valid PBF file
+/// starts with an `OSMHeader` blob, which is followed by `OSMData` blobs.
+///
+/// # Example
+/// Copy the header and the first data blob of a file:
+/// ```
+/// use osmpbf::*;
+///
+/// # fn foo() -> Result<()> {
+/// let mut writer = BlobWriter::new(vec![]);
+/// let mut data_blobs = 0;
+/// for blob in BlobReader::from_path("tests/test.osm.pbf")? {
+///     let blob = blob?;
+///     if blob.get_type() == BlobType::OsmData {
+///         if data_blobs == 1 {
+///             break;
+///         }
+///         data_blobs += 1;
+///     }
+///     writer.write_blob(&blob)?;
+/// }
+/// let bytes = writer.into_inner()?;
+/// # assert_eq!(bytes, std::fs::read("tests/test.osm.pbf")?);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct BlobWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    blobs_written: u64,
+}
+
+impl<W: Write> BlobWriter<W> {
+    /// Creates a new `BlobWriter` that writes to the given stream.
+    pub fn new(writer: W) -> BlobWriter<W> {
+        BlobWriter {
+            writer,
+            offset: 0,
+            blobs_written: 0,
+        }
+    }
+
+    /// Writes the encoded blob verbatim and returns its offset in the output stream.
+    ///
+    /// # Errors
+    /// Returns the I/O errors of the underlying stream.
+    pub fn write_raw(&mut self, blob: &RawBlob) -> Result<ByteOffset> {
+        let offset = ByteOffset(self.offset);
+        self.writer.write_all(blob.bytes())?;
+        self.offset += blob.bytes().len() as u64;
+        self.blobs_written += 1;
+        Ok(offset)
+    }
+
+    /// Writes the blob verbatim and returns its offset in the output stream. This is a shortcut
+    /// for [`write_raw`](BlobWriter::write_raw) with [`Blob::raw`].
+    ///
+    /// # Errors
+    /// Returns the I/O errors of the underlying stream.
+    pub fn write_blob(&mut self, blob: &Blob) -> Result<ByteOffset> {
+        self.write_raw(&blob.raw())
+    }
+
+    /// Returns the number of bytes that were written, which is also the offset of the next blob.
+    pub fn offset(&self) -> ByteOffset {
+        ByteOffset(self.offset)
+    }
+
+    /// Returns the number of blobs that were written.
+    pub fn blobs_written(&self) -> u64 {
+        self.blobs_written
+    }
+
+    /// Flushes the underlying stream.
+    ///
+    /// # Errors
+    /// Returns the I/O errors of the underlying stream.
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Flushes and returns the underlying stream.
+    ///
+    /// # Errors
+    /// Returns the I/O errors of the underlying stream.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl BlobWriter<BufWriter<File>> {
+    /// Creates a new `BlobWriter` that writes to a buffered file at the given path. An existing
+    /// file is truncated.
+    ///
+    /// Call [`flush`](BlobWriter::flush) or [`into_inner`](BlobWriter::into_inner) after the
+    /// last blob to see errors that happen while writing the end of the file.
+    ///
+    /// # Errors
+    /// Returns the same errors that `std::fs::File::create` returns.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let f = File::create(path)?;
+        Ok(BlobWriter::new(BufWriter::new(f)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::BlobReader;
+    use crate::mmap_blob::MmapBlobReader;
+
+    #[test]
+    fn test_copy_blobs() {
+        let data = std::fs::read("tests/test.osm.pbf").unwrap();
+        let mut writer = BlobWriter::new(vec![]);
+        for blob in BlobReader::new_seekable(std::io::Cursor::new(&data)).unwrap() {
+            let blob = blob.unwrap();
+            let offset = writer.write_blob(&blob).unwrap();
+            assert_eq!(Some(offset), blob.offset());
+        }
+        assert_eq!(writer.offset(), ByteOffset(data.len() as u64));
+        assert_eq!(writer.blobs_written(), 2);
+        assert_eq!(writer.into_inner().unwrap(), data);
+
+        // Concatenate the data blobs of two mapped copies
+        let mut writer = BlobWriter::new(vec![]);
+        for (i, blob) in MmapBlobReader::from_slice(&data)
+            .chain(MmapBlobReader::from_slice(&data))
+            .enumerate()
+        {
+            let blob = blob.unwrap();
+            if i != 2 {
+                writer.write_raw(&blob.raw()).unwrap();
+            }
+        }
+        let copy = writer.into_inner().unwrap();
+        assert_eq!(copy.len(), 2 * data.len() - 66);
+        assert_eq!(copy[..data.len()], data[..]);
+        assert_eq!(copy[data.len()..], data[66..]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::process::{Command, Output};
+
+static TEST_FILE: &str = "tests/test.osm.pbf";
+
+/// Runs the command line tool with the given arguments.
+fn osmpbf(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_osmpbf"))
+        .args(args)
+        .output()
+        .expect("failed to run osmpbf")
+}
+
+/// Runs the command line tool and returns its standard output. Fails if the tool fails.
+fn stdout(args: &[&str]) -> String {
+    let output = osmpbf(args);
+    assert!(
+        output.status.success(),
+        "osmpbf {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn info() {
+    let out = stdout(&["info", TEST_FILE]);
+    assert!(out.contains("Size: 302 bytes"));
+    assert!(out.contains("Required features: OsmSchema-V0.6, DenseNodes"));
+    assert!(!out.contains("Nodes:"));
+
+    let out = stdout(&["info", "--extended", TEST_FILE]);
+    assert!(out.contains("Header blobs: 1"));
+    assert!(out.contains("Data blobs: 1"));
+    assert!(out.contains("Nodes: 3 (ids 105..=108)"));
+    assert!(out.contains("Ways: 1 (ids 107..=107)"));
+    assert!(out.contains("Relations: 1 (ids 120..=120)"));
+    assert!(out.contains("Sorted by type, then id: yes"));
+}
+
+#[test]
+fn cat() {
+    let out = stdout(&["cat", TEST_FILE]);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].starts_with("n105 v1"));
+    assert!(lines[4].starts_with("r120 v1"));
+
+    let out = stdout(&["cat", "--type", "way,relation", TEST_FILE]);
+    let ids: Vec<&str> = out.lines().map(|line| &line[..4]).collect();
+    assert_eq!(ids, ["w107", "r120"]);
+
+    let out = stdout(&["cat", "--format", "json", "--filter", "building", TEST_FILE]);
+    assert_eq!(out.lines().count(), 1);
+    assert!(out.starts_with(r#"{"type":"way","id":107,"#));
+}
+
+#[test]
+fn count() {
+    let out = stdout(&["count", TEST_FILE]);
+    assert_eq!(out, "Nodes: 3\nWays: 1\nRelations: 1\n");
+}
+
+#[test]
+fn get() {
+    let out = stdout(&["get", TEST_FILE, "r120", "n106", "n1"]);
+    let ids: Vec<&str> = out.lines().map(|line| &line[..4]).collect();
+    assert_eq!(ids, ["n106", "r120"]);
+
+    let output = osmpbf(&["get", TEST_FILE, "x1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid id 'x1'"));
+}
+
+#[test]
+fn blobs() {
+    let out = stdout(&["blobs", TEST_FILE]);
+    let rows: Vec<Vec<&str>> = out
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(
+        rows,
+        [["0", "0", "49", "OSMHeader"], ["1", "66", "220", "OSMData"]]
+    );
+}
+
+#[test]
+fn check() {
+    let out = stdout(&["check", "--refs", TEST_FILE]);
+    assert_eq!(out, "OK: 2 blobs, 3 nodes, 1 ways, 1 relations\n");
+
+    let output = osmpbf(&["check", "tests/missing.osm.pbf"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("osmpbf: "));
+}
@@ -1,5 +1,6 @@
 use assert_approx_eq::assert_approx_eq;
 use osmpbf::*;
+use rayon::iter::ParallelIterator;
 
 static REQ_SCHEMA_V6: &str = "OsmSchema-V0.6";
 static REQ_DENSE_NODES: &str = "DenseNodes";
@@ -48,26 +49,26 @@ static LOC_ON_WAYS_FILE_PATH: TestFile = TestFile {
 
 // Helper functions to simplify testing
 trait Getter {
-    fn t_nodes(&self) -> Vec<Node>;
-    fn t_dense_nodes(&self) -> Vec<DenseNode>;
-    fn t_ways(&self) -> Vec<Way>;
-    fn t_relations(&self) -> Vec<Relation>;
+    fn t_nodes(&self) -> Vec<Node<'_>>;
+    fn t_dense_nodes(&self) -> Vec<DenseNode<'_>>;
+    fn t_ways(&self) -> Vec<Way<'_>>;
+    fn t_relations(&self) -> Vec<Relation<'_>>;
 }
 
 impl Getter for PrimitiveBlock {
-    fn t_nodes(&self) -> Vec<Node> {
+    fn t_nodes(&self) -> Vec<Node<'_>> {
         self.groups().flat_map(|g| g.nodes()).collect()
     }
 
-    fn t_dense_nodes(&self) -> Vec<DenseNode> {
+    fn t_dense_nodes(&self) -> Vec<DenseNode<'_>> {
         self.groups().flat_map(|g| g.dense_nodes()).collect()
     }
 
-    fn t_ways(&self) -> Vec<Way> {
+    fn t_ways(&self) -> Vec<Way<'_>> {
         self.groups().flat_map(|g| g.ways()).collect()
     }
 
-    fn t_relations(&self) -> Vec<Relation> {
+    fn t_relations(&self) -> Vec<Relation<'_>> {
         self.groups().flat_map(|g| g.relations()).collect()
     }
 }
@@ -291,6 +292,592 @@ fn par_read_elements() {
     }
 }
 
+#[test]
+fn par_read_elements_bounded() {
+    for test_file in TEST_FILE_PATHS.iter().chain([&HISTORY_FILE_PATH]) {
+        let expected = ElementReader::from_path(test_file.path)
+            .unwrap()
+            .par_map_reduce(|element| element.id(), || 0_i64, |a, b| a + b)
+            .unwrap();
+
+        for (max_blobs, max_bytes, threads) in [(1, 1, 1), (2, 1 << 20, 3)] {
+            let mut reader = ElementReader::from_path(test_file.path).unwrap();
+            reader.set_max_blobs_in_flight(max_blobs);
+            reader.set_max_bytes_in_flight(max_bytes);
+            reader.set_threads(threads);
+            let sum = reader
+                .par_map_reduce(|element| element.id(), || 0_i64, |a, b| a + b)
+                .unwrap();
+            assert_eq!(sum, expected);
+        }
+    }
+}
+
+#[test]
+fn par_read_elements_in_thread_pool() {
+    let pool = std::sync::Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .thread_name(|i| format!("pbf-worker-{i}"))
+            .build()
+            .unwrap(),
+    );
+    let in_pool = |_element: Element| {
+        let name = std::thread::current().name().map(str::to_string);
+        usize::from(name.is_some_and(|name| name.starts_with("pbf-worker-")))
+    };
+
+    let mut reader = ElementReader::from_path(TEST_FILE_PATHS[0].path).unwrap();
+    reader.set_thread_pool(pool.clone());
+    let elements = reader.par_map_reduce(in_pool, || 0, |a, b| a + b).unwrap();
+    assert_eq!(elements, 5);
+
+    let data = std::fs::read(TEST_FILE_PATHS[0].path).unwrap();
+    let mut reader = MmapElementReader::from_slice(&data).unwrap();
+    reader.set_thread_pool(pool);
+    let elements = reader.par_map_reduce(in_pool, || 0, |a, b| a + b).unwrap();
+    assert_eq!(elements, 5);
+
+    // A new pool for the call
+    reader.set_threads(1);
+    let elements = reader.par_map_reduce(in_pool, || 0, |a, b| a + b).unwrap();
+    assert_eq!(elements, 0);
+}
+
+#[test]
+fn read_mmap_elements() {
+    for test_file in TEST_FILE_PATHS {
+        let mmap = unsafe { Mmap::from_path(test_file.path).unwrap() };
+        let reader = MmapElementReader::new(&mmap).unwrap();
+        assert_eq!(reader.blobs().len(), 2);
+
+        let mut ids = vec![];
+        reader.for_each(|element| ids.push(element.id())).unwrap();
+        assert_eq!(ids, [105, 106, 108, 107, 120]);
+
+        let elements = reader
+            .par_map_reduce(|_element| 1, || 0_usize, |a, b| a + b)
+            .unwrap();
+        assert_eq!(elements, 5);
+
+        let decoded: Vec<_> = reader.par_decode().collect::<Result<_>>().unwrap();
+        assert!(matches!(decoded[0], BlobDecode::OsmHeader(_)));
+        if let BlobDecode::OsmData(primitive_block) = &decoded[1] {
+            check_primitive_block_content(primitive_block);
+        } else {
+            panic!("Unexpected blob type");
+        }
+    }
+}
+
+#[test]
+fn read_slice_elements() {
+    static EMBEDDED: &[u8] = include_bytes!("test.osm.pbf");
+
+    for test_file in TEST_FILE_PATHS {
+        let data = std::fs::read(test_file.path).unwrap();
+        let bytes = bytes::Bytes::from(data.clone());
+        for slice in [data.as_slice(), &bytes[..]] {
+            let blobs = MmapBlobReader::from_slice(slice)
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(blobs.len(), 2);
+            assert_eq!(blobs[0].offset(), ByteOffset(0));
+
+            let reader = MmapElementReader::from_slice(slice).unwrap();
+            let mut ids = vec![];
+            reader.for_each(|element| ids.push(element.id())).unwrap();
+            assert_eq!(ids, [105, 106, 108, 107, 120]);
+        }
+    }
+
+    let reader = MmapElementReader::from_slice(EMBEDDED).unwrap();
+    let elements = reader
+        .par_map_reduce(|_element| 1, || 0_usize, |a, b| a + b)
+        .unwrap();
+    assert_eq!(elements, 5);
+
+    // Owned buffers
+    let data = std::fs::read(TEST_FILE_PATHS[0].path).unwrap();
+    let readers = (
+        MemoryReader::new(data.clone()),
+        MemoryReader::new(bytes::Bytes::from(data)),
+    );
+    let handle = std::thread::spawn(move || {
+        let (vec, bytes) = readers;
+        let mut ids = vec![];
+        vec.for_each(|element| ids.push(element.id())).unwrap();
+        assert_eq!(ids, [105, 106, 108, 107, 120]);
+        assert_eq!(bytes.blob_iter().count(), 2);
+        bytes
+            .par_map_reduce(|_element| 1, || 0_usize, |a, b| a + b)
+            .unwrap()
+    });
+    assert_eq!(handle.join().unwrap(), 5);
+
+    // Truncated data
+    let mut reader = SliceBlobReader::from_slice(&EMBEDDED[..EMBEDDED.len() - 1]);
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_err());
+    assert!(reader.next().is_none());
+    assert!(MmapElementReader::from_slice(&EMBEDDED[..2]).is_err());
+    assert!(MmapBlobReader::from_slice(&[]).next().is_none());
+}
+
+fn describe_info(info: &Info) -> String {
+    format!(
+        "{:?} {:?} {:?} {:?} {:?} {}",
+        info.version(),
+        info.milli_timestamp(),
+        info.changeset(),
+        info.uid(),
+        info.user().and_then(|user| user.ok()),
+        info.visible(),
+    )
+}
+
+/// Formats all decoded fields of an element.
+fn describe_element(element: &Element) -> String {
+    match element {
+        Element::Node(node) => format!(
+            "n{} {:?} {} {} {}",
+            node.id(),
+            node.tags().collect::<Vec<_>>(),
+            node.nano_lat(),
+            node.nano_lon(),
+            describe_info(&node.info()),
+        ),
+        Element::DenseNode(node) => format!(
+            "n{} {:?} {} {} {:?}",
+            node.id(),
+            node.tags().collect::<Vec<_>>(),
+            node.nano_lat(),
+            node.nano_lon(),
+            node.info().map(|info| (
+                info.version(),
+                info.milli_timestamp(),
+                info.changeset(),
+                info.uid(),
+                info.user().ok(),
+                info.visible(),
+            )),
+        ),
+        Element::Way(way) => format!(
+            "w{} {:?} {} {:?} {:?} {:?}",
+            way.id(),
+            way.tags().collect::<Vec<_>>(),
+            describe_info(&way.info()),
+            way.refs().collect::<Vec<_>>(),
+            way.raw_refs(),
+            way.node_locations()
+                .map(|loc| (loc.nano_lat(), loc.nano_lon()))
+                .collect::<Vec<_>>(),
+        ),
+        Element::Relation(relation) => format!(
+            "r{} {:?} {} {:?}",
+            relation.id(),
+            relation.tags().collect::<Vec<_>>(),
+            describe_info(&relation.info()),
+            relation
+                .members()
+                .map(|m| (m.role().ok(), m.member_id, m.member_type))
+                .collect::<Vec<_>>(),
+        ),
+    }
+}
+
+#[test]
+fn read_lazy_blocks() {
+    let test_files = TEST_FILE_PATHS
+        .iter()
+        .chain([&HISTORY_FILE_PATH, &LOC_ON_WAYS_FILE_PATH]);
+    for test_file in test_files {
+        let read = |block_decoding| {
+            let mut reader = ElementReader::from_path(test_file.path).unwrap();
+            reader.set_block_decoding(block_decoding);
+            let mut elements = vec![];
+            reader
+                .for_each(|element| elements.push(describe_element(&element)))
+                .unwrap();
+            elements
+        };
+        let eager = read(BlockDecoding::Eager);
+        assert!(!eager.is_empty());
+        assert_eq!(read(BlockDecoding::Lazy), eager);
+
+        let mmap = unsafe { Mmap::from_path(test_file.path).unwrap() };
+        let mut reader = MmapElementReader::new(&mmap).unwrap();
+        reader.set_block_decoding(BlockDecoding::Lazy);
+        let mut elements = vec![];
+        reader
+            .for_each(|element| elements.push(describe_element(&element)))
+            .unwrap();
+        assert_eq!(elements, eager);
+
+        // Other tag decoding policies need eagerly decoded blocks
+        for (tag_decoding, is_lazy) in [(TagDecoding::Unchecked, true), (TagDecoding::Lossy, false)]
+        {
+            let mut reader = BlobReader::from_path(test_file.path).unwrap();
+            reader.set_block_decoding(BlockDecoding::Lazy);
+            reader.set_tag_decoding(tag_decoding);
+            for blob in reader {
+                if let BlobDecode::OsmData(block) = blob.unwrap().decode().unwrap() {
+                    assert_eq!(block.is_lazy(), is_lazy);
+                }
+            }
+        }
+    }
+
+    let mut reader = BlobReader::from_path(TEST_FILE_PATHS[0].path).unwrap();
+    reader.set_block_decoding(BlockDecoding::Lazy);
+    let blob = reader.nth(1).unwrap().unwrap();
+    if let BlobDecode::OsmData(block) = blob.decode().unwrap() {
+        assert!(block.is_lazy());
+        check_primitive_block_content(&block);
+    } else {
+        panic!("Unexpected blob type");
+    }
+}
+
+#[test]
+fn read_dense_columns() {
+    let test_files = TEST_FILE_PATHS.iter().chain([&HISTORY_FILE_PATH]);
+    for test_file in test_files {
+        for block_decoding in [BlockDecoding::Eager, BlockDecoding::Lazy] {
+            let mut reader = BlobReader::from_path(test_file.path).unwrap();
+            reader.set_block_decoding(block_decoding);
+            for blob in reader {
+                let block = match blob.unwrap().decode().unwrap() {
+                    BlobDecode::OsmData(block) => block,
+                    _ => continue,
+                };
+                let nodes = block.t_dense_nodes();
+                let columns = block.dense_columns();
+                assert_eq!(columns.len(), nodes.len());
+                assert_eq!(columns.tag_offsets.len(), nodes.len() + 1);
+                let has_info = !nodes.is_empty() && nodes.iter().all(|n| n.info().is_some());
+                assert_eq!(columns.has_info(), has_info);
+                for (index, node) in nodes.iter().enumerate() {
+                    assert_eq!(columns.ids[index], node.id());
+                    assert_eq!(columns.nano_lats[index], node.nano_lat());
+                    assert_eq!(columns.nano_lons[index], node.nano_lon());
+                    let raw_tags: Vec<i32> = node.raw_tags().flat_map(|(k, v)| [k, v]).collect();
+                    assert_eq!(columns.raw_tags(index), raw_tags.as_slice());
+                    if let Some(info) = node.info() {
+                        assert_eq!(columns.versions[index], info.version());
+                        assert_eq!(columns.milli_timestamps[index], info.milli_timestamp());
+                        assert_eq!(columns.changesets[index], info.changeset());
+                        assert_eq!(columns.uids[index], info.uid());
+                        assert_eq!(
+                            block.raw_stringtable()[columns.user_sids[index] as usize],
+                            info.user().unwrap().as_bytes()
+                        );
+                        assert_eq!(columns.visible[index], info.visible());
+                    }
+                }
+                let group_len: usize = block.groups().map(|g| g.dense_columns().len()).sum();
+                assert_eq!(group_len, nodes.len());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn read_record_batches() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, Int64Type};
+    use arrow_array::Array;
+
+    let read_batches = |path| {
+        let mut batches = vec![];
+        for blob in BlobReader::from_path(path).unwrap() {
+            if let BlobDecode::OsmData(block) = blob.unwrap().decode().unwrap() {
+                let block_batches = block.to_record_batches().unwrap();
+                assert_eq!(block_batches.nodes.schema(), node_schema());
+                assert_eq!(block_batches.ways.schema(), way_schema());
+                assert_eq!(block_batches.relations.schema(), relation_schema());
+                batches.push(block_batches);
+            }
+        }
+        let concat = |schema, batches: Vec<&RecordBatch>| {
+            arrow_select::concat::concat_batches(&schema, batches).unwrap()
+        };
+        (
+            concat(node_schema(), batches.iter().map(|b| &b.nodes).collect()),
+            concat(way_schema(), batches.iter().map(|b| &b.ways).collect()),
+            concat(
+                relation_schema(),
+                batches.iter().map(|b| &b.relations).collect(),
+            ),
+        )
+    };
+    let (nodes, _, relations) = read_batches(TEST_FILE_PATHS[0].path);
+    let (_, ways, _) = read_batches(LOC_ON_WAYS_FILE_PATH.path);
+
+    let ids = nodes["id"].as_primitive::<Int64Type>();
+    assert_eq!(ids.values(), &[105, 106, 108]);
+    let lats = nodes["lat"].as_primitive::<Float64Type>();
+    assert!(approx_eq(lats.value(1), 52.11992359584));
+    assert_eq!(
+        nodes["version"]
+            .as_primitive::<arrow_array::types::Int32Type>()
+            .value(0),
+        1
+    );
+
+    assert_eq!(ways.num_rows(), 1);
+    let refs = ways["refs"].as_list::<i32>().value(0);
+    assert_eq!(
+        refs.as_primitive::<Int64Type>().values(),
+        &[105, 106, 108, 105]
+    );
+    let locations = ways["locations"].as_list::<i32>();
+    assert!(locations.is_valid(0));
+    assert_eq!(locations.value(0).len(), 4);
+    let tags = ways["tags"].as_map();
+    let keys = tags.keys().as_string::<i32>();
+    let values = tags.values().as_string::<i32>();
+    let mut way_tags: Vec<(&str, &str)> =
+        keys.iter().flatten().zip(values.iter().flatten()).collect();
+    way_tags.sort();
+    assert_eq!(way_tags, [("building", "yes"), ("name", "triangle")]);
+
+    assert_eq!(relations.num_rows(), 1);
+    let members = relations["members"].as_list::<i32>().value(0);
+    let members = members.as_struct();
+    assert_eq!(
+        members
+            .column_by_name("type")
+            .unwrap()
+            .as_string::<i32>()
+            .value(0),
+        "way"
+    );
+    assert_eq!(
+        members
+            .column_by_name("ref")
+            .unwrap()
+            .as_primitive::<Int64Type>()
+            .value(0),
+        107
+    );
+    assert_eq!(
+        members
+            .column_by_name("role")
+            .unwrap()
+            .as_string::<i32>()
+            .value(0),
+        "test_role"
+    );
+
+    // Ways without locations
+    let reader = ElementReader::from_path(TEST_FILE_PATHS[0].path).unwrap();
+    let way_locations = std::sync::Mutex::new(vec![]);
+    reader
+        .par_for_each_record_batches(|batches| {
+            let locations = batches.ways["locations"].as_list::<i32>().clone();
+            way_locations
+                .lock()
+                .unwrap()
+                .extend(locations.iter().map(|l| l.is_some()));
+        })
+        .unwrap();
+    assert_eq!(way_locations.into_inner().unwrap(), [false]);
+}
+
+#[cfg(feature = "geoparquet")]
+#[test]
+fn export_geoparquet() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int64Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let mut exporter = GeoParquetExporter::new();
+    exporter.set_tag_columns(["name"]);
+    for test_file in [&TEST_FILE_PATHS[0], &LOC_ON_WAYS_FILE_PATH] {
+        let mut buffer = vec![];
+        assert_eq!(exporter.export(test_file.path, &mut buffer).unwrap(), 1);
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(buffer)).unwrap();
+        let geo = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .find(|kv| kv.key == "geo")
+            .and_then(|kv| kv.value.clone())
+            .unwrap();
+        assert!(geo.contains(r#""encoding":"WKB","geometry_types":["Polygon"]"#));
+
+        let batches: Vec<RecordBatch> = builder.build().unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(batch["osm_type"].as_string::<i32>().value(0), "way");
+        assert_eq!(batch["osm_id"].as_primitive::<Int64Type>().value(0), 107);
+        assert_eq!(batch["name"].as_string::<i32>().value(0), "triangle");
+        let wkb = batch["geometry"].as_binary::<i32>().value(0);
+        // Polygon with one ring of four points
+        assert_eq!(&wkb[..13], &[1, 3, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(wkb.len(), 13 + 4 * 16);
+        let lat = f64::from_le_bytes(wkb[21..29].try_into().unwrap());
+        assert_approx_eq!(lat, 52.1224031);
+    }
+
+    // Without areas the closed way is not exported
+    exporter.set_options(ExportOptions::new().areas(false));
+    assert_eq!(exporter.export(TEST_FILE_PATHS[0].path, vec![]).unwrap(), 0);
+
+    exporter.set_tag_columns(["osm_id"]);
+    assert!(exporter.export(TEST_FILE_PATHS[0].path, vec![]).is_err());
+}
+
+#[test]
+fn export_geojson() {
+    let mut exporter = GeoJsonExporter::new();
+    let mut buffer = vec![];
+    assert_eq!(
+        exporter
+            .export(TEST_FILE_PATHS[0].path, &mut buffer)
+            .unwrap(),
+        1
+    );
+    let collection = String::from_utf8(buffer).unwrap();
+    assert!(collection.starts_with("{\"type\":\"FeatureCollection\",\"features\":[\n{"));
+    assert!(collection.ends_with("}\n]}\n"));
+    assert!(collection.contains(r#""id":"way/107","geometry":{"type":"Polygon","coordinates":[[["#));
+    assert!(collection.contains(r#""properties":{"building":"yes","name":"triangle"}"#));
+
+    // The same features with metadata, one per line
+    exporter.set_format(GeoJsonFormat::Sequence);
+    exporter.set_options(ExportOptions::new().metadata(true));
+    for test_file in [&TEST_FILE_PATHS[0], &LOC_ON_WAYS_FILE_PATH] {
+        let mut buffer = vec![];
+        assert_eq!(exporter.export(test_file.path, &mut buffer).unwrap(), 1);
+        let sequence = String::from_utf8(buffer).unwrap();
+        assert_eq!(sequence.lines().count(), 1);
+        assert!(sequence.starts_with(r#"{"type":"Feature","id":"way/107""#));
+        assert!(sequence.contains(r#""@version":"#));
+        assert!(sequence.ends_with("}\n"));
+    }
+
+    exporter.set_options(ExportOptions::new().areas(false));
+    let mut buffer = vec![];
+    assert_eq!(
+        exporter
+            .export(TEST_FILE_PATHS[0].path, &mut buffer)
+            .unwrap(),
+        0
+    );
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn export_geojson_with_store() {
+    #[derive(Default)]
+    struct HashMapStore {
+        locations: std::collections::HashMap<i64, (i32, i32)>,
+        finished: bool,
+    }
+
+    impl NodeLocationStore for HashMapStore {
+        fn insert(&mut self, id: i64, lat: i32, lon: i32) -> Result<()> {
+            assert!(!self.finished);
+            self.locations.insert(id, (lat, lon));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<()> {
+            self.finished = true;
+            Ok(())
+        }
+
+        fn get(&self, id: i64) -> Option<(i32, i32)> {
+            self.locations.get(&id).copied()
+        }
+    }
+
+    let exporter = GeoJsonExporter::new();
+    let mut expected = vec![];
+    exporter
+        .export(TEST_FILE_PATHS[0].path, &mut expected)
+        .unwrap();
+
+    let mut store = HashMapStore::default();
+    let mut buffer = vec![];
+    assert_eq!(
+        exporter
+            .export_with_store(TEST_FILE_PATHS[0].path, &mut buffer, &mut store)
+            .unwrap(),
+        1
+    );
+    assert_eq!(buffer, expected);
+    // Only the nodes of the exported way are stored
+    assert!(store.finished);
+    assert_eq!(store.locations.len(), 3);
+
+    // The sorted store is used by default
+    let mut store = SortedNodeLocations::new();
+    let mut buffer = vec![];
+    exporter
+        .export_with_store(TEST_FILE_PATHS[0].path, &mut buffer, &mut store)
+        .unwrap();
+    assert_eq!(buffer, expected);
+    assert_eq!(store.len(), 3);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn read_async() {
+    use futures_util::StreamExt;
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for test_file in TEST_FILE_PATHS.iter().chain([&HISTORY_FILE_PATH]) {
+        let mut expected = vec![];
+        ElementReader::from_path(test_file.path)
+            .unwrap()
+            .for_each(|e| expected.push((e.element_type(), e.id())))
+            .unwrap();
+
+        let ids = runtime.block_on(async {
+            let file = tokio::fs::File::open(test_file.path).await.unwrap();
+            let mut reader = AsyncElementReader::new(tokio::io::BufReader::new(file));
+            reader.set_concurrency(2);
+            let mut ids = vec![];
+            reader
+                .for_each(|e| ids.push((e.element_type(), e.id())))
+                .await
+                .unwrap();
+            ids
+        });
+        // Blocks are returned in the order of the stream
+        assert_eq!(ids, expected);
+
+        // The blob stream has the same blobs as the blocking reader
+        let blob_types = runtime.block_on(async {
+            let file = tokio::fs::File::open(test_file.path).await.unwrap();
+            AsyncBlobReader::with_offset(file, ByteOffset(0))
+                .into_stream()
+                .map(|blob| {
+                    let blob = blob.unwrap();
+                    (blob.get_type().as_str().to_string(), blob.offset())
+                })
+                .collect::<Vec<_>>()
+                .await
+        });
+        let expected_types: Vec<_> = BlobReader::from_path(test_file.path)
+            .unwrap()
+            .map(|blob| {
+                let blob = blob.unwrap();
+                (blob.get_type().as_str().to_string(), blob.offset())
+            })
+            .collect();
+        assert_eq!(blob_types, expected_types);
+    }
+}
+
 #[test]
 fn read_ways_and_deps() {
     for test_file in TEST_FILE_PATHS {
@@ -418,3 +1005,590 @@ fn read_loc_on_ways_file() {
         assert_eq!(members[0].role().unwrap(), "test_role");
     }
 }
+
+#[test]
+fn read_file_info() {
+    for test_file in TEST_FILE_PATHS {
+        let info = FileInfo::from_path(test_file.path).unwrap();
+
+        let header = info.header.as_ref().unwrap();
+        check_header_block_content(header, test_file);
+
+        assert_eq!(info.header_blobs, 1);
+        assert_eq!(info.data_blobs, 1);
+        assert_eq!(info.unknown_blobs, 0);
+        assert!(info.compressed_size > 0);
+
+        assert_eq!(info.nodes.count, 3);
+        assert_eq!(info.nodes.ids, Some(105..=108));
+        assert_eq!(info.ways.count, 1);
+        assert_eq!(info.ways.ids, Some(107..=107));
+        assert_eq!(info.relations.count, 1);
+        assert_eq!(info.relations.ids, Some(120..=120));
+
+        let bbox = info.bbox.unwrap();
+        assert_approx_eq!(bbox.left, 11.62564468943, 1.0e-6);
+        assert_approx_eq!(bbox.right, 11.63101926915, 1.0e-6);
+        assert_approx_eq!(bbox.top, 52.12240315616, 1.0e-6);
+        assert_approx_eq!(bbox.bottom, 52.11989910567, 1.0e-6);
+
+        // 2003-04-05T06:07:08Z and 2003-04-05T06:07:12Z
+        assert_eq!(info.first_timestamp, Some(1049522828000));
+        assert_eq!(info.last_timestamp, Some(1049522832000));
+        assert!(info.has_metadata);
+        assert!(!info.locations_on_ways);
+        assert!(info.sorted);
+    }
+
+    let info = FileInfo::from_path(LOC_ON_WAYS_FILE_PATH.path).unwrap();
+    assert_eq!(info.data_blobs, 2);
+    assert!(info.locations_on_ways);
+    assert!(info.sorted);
+}
+
+#[test]
+fn check_integrity() {
+    for test_file in TEST_FILE_PATHS {
+        let report = IntegrityReport::from_path(test_file.path).unwrap();
+        assert!(report.is_ok(), "{report:?}");
+    }
+
+    // This file only contains ways and relations.
+    let report = IntegrityReport::from_path(LOC_ON_WAYS_FILE_PATH.path).unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.missing_way_nodes.count, 3);
+    assert_eq!(report.missing_way_nodes.samples, [105, 106, 108]);
+    assert!(report.missing_relation_ways.is_empty());
+    assert!(report.duplicate_ways.is_empty());
+    assert!(report.empty_ways.is_empty());
+}
+
+/// Returns the bytes of the blobs (including headers) of the given file.
+fn blob_bytes(path: &str) -> Vec<Vec<u8>> {
+    let data = std::fs::read(path).unwrap();
+    let mut offsets: Vec<usize> = BlobReader::from_path(path)
+        .unwrap()
+        .map(|blob| blob.unwrap().offset().unwrap().0 as usize)
+        .collect();
+    offsets.push(data.len());
+    offsets
+        .windows(2)
+        .map(|w| data[w[0]..w[1]].to_vec())
+        .collect()
+}
+
+#[test]
+fn recover_from_corrupted_blobs() {
+    let blobs = blob_bytes(TEST_FILE_PATHS[0].path);
+    assert_eq!(blobs.len(), 2);
+
+    // Garbage before the first blob, between the blobs and a truncated blob at the end
+    let mut data = vec![0xff; 7];
+    data.extend_from_slice(&blobs[0]);
+    data.extend_from_slice(&[0x00, 0x00, 0x00, 0x0d, 0x0a, 0x07, b'O', b'S', b'M']);
+    data.extend_from_slice(&blobs[1]);
+    data.extend_from_slice(&blobs[1][..20]);
+
+    let offsets_of_blobs = [7, 7 + blobs[0].len() as u64 + 9];
+    let expected_resyncs = [
+        Resync {
+            offset: Some(ByteOffset(0)),
+            skipped_bytes: 7,
+        },
+        Resync {
+            offset: Some(ByteOffset(7 + blobs[0].len() as u64)),
+            skipped_bytes: 9,
+        },
+        Resync {
+            offset: Some(ByteOffset(data.len() as u64 - 20)),
+            skipped_bytes: 20,
+        },
+    ];
+
+    // Without recovery, the iteration stops at the first error
+    let mut reader = BlobReader::new(std::io::Cursor::new(data.clone()));
+    assert!(reader.next().unwrap().is_err());
+    assert!(reader.next().is_none());
+
+    let mut reader = BlobReader::new_seekable(std::io::Cursor::new(data.clone())).unwrap();
+    reader.set_error_recovery(true);
+    let results: Vec<_> = reader.by_ref().collect();
+    let offsets: Vec<u64> = results
+        .iter()
+        .filter_map(|blob| blob.as_ref().ok())
+        .map(|blob| blob.offset().unwrap().0)
+        .collect();
+    assert_eq!(offsets, offsets_of_blobs);
+    assert_eq!(results.iter().filter(|blob| blob.is_err()).count(), 3);
+    assert_eq!(reader.resyncs(), expected_resyncs);
+    for blob in results.into_iter().flatten() {
+        blob.decode().unwrap();
+    }
+
+    let dir = std::env::temp_dir().join("osmpbf-recover-test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("corrupted.osm.pbf");
+    std::fs::write(&path, &data).unwrap();
+
+    let mmap = unsafe { Mmap::from_path(&path).unwrap() };
+    let mut reader = MmapBlobReader::new(&mmap);
+    reader.set_error_recovery(true);
+    let results: Vec<_> = reader.by_ref().collect();
+    let offsets: Vec<u64> = results
+        .iter()
+        .filter_map(|blob| blob.as_ref().ok())
+        .map(|blob| blob.offset().0)
+        .collect();
+    assert_eq!(offsets, offsets_of_blobs);
+    assert_eq!(results.iter().filter(|blob| blob.is_err()).count(), 3);
+    assert_eq!(reader.resyncs(), expected_resyncs);
+    for blob in results.iter().flatten() {
+        blob.decode().unwrap();
+    }
+}
+
+#[test]
+fn recover_after_large_garbage() {
+    let blobs = blob_bytes(TEST_FILE_PATHS[0].path);
+
+    // Garbage with many partial signatures, so that the size prefix and the signature of the
+    // first blob are split between two reads of the search buffer.
+    let mut data: Vec<u8> = b"\x0a\x07OSMDat"
+        .iter()
+        .copied()
+        .cycle()
+        .take(65_533)
+        .collect();
+    let garbage_len = data.len() as u64;
+    data.extend_from_slice(&blobs[0]);
+    data.extend_from_slice(&blobs[1]);
+
+    let mut reader = BlobReader::new_seekable(std::io::Cursor::new(data)).unwrap();
+    reader.set_error_recovery(true);
+    let results: Vec<_> = reader.by_ref().collect();
+    assert_eq!(results.len(), 3);
+    assert!(results[0].is_err());
+    let offsets: Vec<u64> = results[1..]
+        .iter()
+        .map(|blob| blob.as_ref().unwrap().offset().unwrap().0)
+        .collect();
+    assert_eq!(offsets, [garbage_len, garbage_len + blobs[0].len() as u64]);
+    assert_eq!(
+        reader.resyncs(),
+        [Resync {
+            offset: Some(ByteOffset(0)),
+            skipped_bytes: garbage_len,
+        }]
+    );
+}
+
+#[test]
+fn error_context() {
+    let blobs = blob_bytes(TEST_FILE_PATHS[0].path);
+    let mut data = blobs[0].clone();
+    data.extend_from_slice(&[0xff; 8]);
+
+    let mut reader = BlobReader::new_seekable(std::io::Cursor::new(data.clone())).unwrap();
+    let first_blob = reader.next().unwrap().unwrap();
+    assert_eq!(first_blob.index(), Some(0));
+
+    let err = reader.next().unwrap().unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::Blob(BlobError::HeaderTooBig { .. })
+    ));
+    assert_eq!(err.blob_index(), Some(1));
+    assert_eq!(err.blob_offset(), Some(ByteOffset(blobs[0].len() as u64)));
+    assert_eq!(err.element(), None);
+    assert!(err
+        .to_string()
+        .ends_with(&format!("(blob #1, offset {})", blobs[0].len())));
+
+    // Damage the compressed data of the first blob
+    let mut data = blobs[0].clone();
+    let len = data.len();
+    data[len - 8..].copy_from_slice(&[0; 8]);
+    let mut reader = BlobReader::new(std::io::Cursor::new(data));
+    let blob = reader.next().unwrap().unwrap();
+    let err = blob.decode().unwrap_err();
+    assert_eq!(err.blob_index(), Some(0));
+    assert_eq!(err.blob_offset(), None);
+}
+
+#[test]
+fn reader_options() {
+    let blobs = blob_bytes(TEST_FILE_PATHS[0].path);
+    // A blob of the unknown type "Index" with two bytes of raw content
+    let unknown_blob = [
+        &[0x00, 0x00, 0x00, 0x09, 0x0a, 0x05][..],
+        b"Index",
+        &[0x18, 0x04, 0x0a, 0x02, 0x01, 0x02],
+    ]
+    .concat();
+    let data = [&blobs[0][..], &unknown_blob, &blobs[1]].concat();
+    let unknown_offset = ByteOffset(blobs[0].len() as u64);
+
+    // Unknown blobs are returned by default
+    let reader = BlobReader::new_seekable(std::io::Cursor::new(data.clone())).unwrap();
+    let types: Vec<_> = reader
+        .map(|blob| blob.unwrap().get_type().as_str().to_string())
+        .collect();
+    assert_eq!(types, ["OSMHeader", "Index", "OSMData"]);
+
+    // The iteration stops at an unknown blob unless error recovery is enabled
+    let options = ReaderOptions::new().unknown_blobs(UnknownBlobPolicy::Error);
+    for recovery in [false, true] {
+        let mut reader = BlobReader::new_seekable(std::io::Cursor::new(data.clone())).unwrap();
+        reader.set_options(options.clone().error_recovery(recovery));
+        let results: Vec<_> = reader.by_ref().collect();
+        assert_eq!(results.len(), if recovery { 3 } else { 2 });
+        let err = results[1].as_ref().unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::Blob(BlobError::UnknownType { blob_type }) if blob_type == "Index"
+        ));
+        assert_eq!(err.blob_offset(), Some(unknown_offset));
+        assert!(reader.resyncs().is_empty());
+
+        let mut reader = MmapBlobReader::from_slice(&data);
+        reader.set_options(options.clone().error_recovery(recovery));
+        assert_eq!(reader.count(), if recovery { 3 } else { 2 });
+    }
+
+    let mut reader = ElementReader::new(data.as_slice());
+    reader.set_options(options.clone());
+    assert!(reader.for_each(|_| {}).is_err());
+
+    let mut reader = IndexedReader::new(std::io::Cursor::new(data.clone())).unwrap();
+    reader.set_options(options);
+    assert!(reader.create_index().is_err());
+
+    // The callback is notified about unknown blobs, which are skipped by the element readers
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let callback = {
+        let seen = seen.clone();
+        move |blob_type: &str, offset: Option<ByteOffset>| {
+            seen.lock().unwrap().push((blob_type.to_string(), offset));
+        }
+    };
+    let options = ReaderOptions::new()
+        .unknown_blobs(UnknownBlobPolicy::Callback(std::sync::Arc::new(callback)))
+        .validate_features(true);
+    let mut reader = IndexedReader::new(std::io::Cursor::new(data.clone())).unwrap();
+    reader.set_options(options.clone());
+    reader.create_index().unwrap();
+    let mut reader = ElementReader::new(data.as_slice());
+    reader.set_options(options);
+    let elements = reader
+        .par_map_reduce(|_| 1, || 0_u64, |a, b| a + b)
+        .unwrap();
+    assert_eq!(elements, 5);
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            ("Index".to_string(), Some(unknown_offset)),
+            ("Index".to_string(), None)
+        ]
+    );
+
+    // Header features are validated for all test files
+    for path in TEST_FILE_PATHS
+        .iter()
+        .map(|f| f.path)
+        .chain([HISTORY_FILE_PATH.path])
+    {
+        let mut reader = BlobReader::from_path(path).unwrap();
+        reader.set_options(ReaderOptions::new().validate_features(true));
+        assert!(reader.all(|blob| blob.is_ok()));
+    }
+
+    // Size limits
+    let mut reader = ElementReader::from_path(TEST_FILE_PATHS[0].path).unwrap();
+    reader.set_options(ReaderOptions::new().max_blob_size(64));
+    let err = reader.for_each(|_| {}).unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::Blob(BlobError::MessageTooBig { .. })
+    ));
+    let mut reader = BlobReader::from_path(TEST_FILE_PATHS[0].path).unwrap();
+    reader.set_options(ReaderOptions::new().max_header_size(8));
+    assert!(matches!(
+        reader.next().unwrap().unwrap_err().kind(),
+        ErrorKind::Blob(BlobError::HeaderTooBig { .. })
+    ));
+}
+
+#[test]
+fn report_progress() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let path = TEST_FILE_PATHS[0].path;
+    let total = std::fs::metadata(path).unwrap().len();
+    let recorder = || {
+        let reports = Arc::new(Mutex::new(Vec::<Progress>::new()));
+        let callback = {
+            let reports = reports.clone();
+            move |progress: &Progress| reports.lock().unwrap().push(*progress)
+        };
+        (reports, callback)
+    };
+    let expected = Progress {
+        bytes_read: total,
+        total_bytes: Some(total),
+        blobs_read: 2,
+        blobs_decoded: 2,
+        elements: 5,
+    };
+
+    let (reports, callback) = recorder();
+    let mut reader = BlobReader::from_path(path).unwrap();
+    reader.set_progress(Duration::ZERO, callback);
+    assert_eq!(reader.count(), 2);
+    let last = *reports.lock().unwrap().last().unwrap();
+    assert_eq!(
+        last,
+        Progress {
+            blobs_decoded: 0,
+            elements: 0,
+            ..expected
+        }
+    );
+    assert_eq!(last.fraction(), Some(1.0));
+
+    for parallel in [false, true] {
+        let (reports, callback) = recorder();
+        let mut reader = ElementReader::from_path(path).unwrap();
+        reader.set_progress(Duration::from_secs(3600), callback);
+        if parallel {
+            reader.par_map_reduce(|_| (), || (), |_, _| ()).unwrap();
+        } else {
+            reader.for_each(|_| {}).unwrap();
+        }
+        let reports = reports.lock().unwrap();
+        // The first update and the final progress
+        assert!(reports.len() <= 3);
+        assert_eq!(*reports.last().unwrap(), expected);
+    }
+
+    // The total size is unknown for streams
+    let (reports, callback) = recorder();
+    let data = std::fs::read(path).unwrap();
+    let mut reader = ElementReader::new(data.as_slice());
+    reader.set_progress(Duration::ZERO, callback);
+    reader.for_each(|_| {}).unwrap();
+    let last = *reports.lock().unwrap().last().unwrap();
+    assert_eq!(
+        last,
+        Progress {
+            total_bytes: None,
+            ..expected
+        }
+    );
+
+    let (reports, callback) = recorder();
+    let mut reader = IndexedReader::from_path(path).unwrap();
+    reader.set_progress(Duration::ZERO, callback);
+    let mut ways = 0;
+    reader.for_each_way(|_| ways += 1).unwrap();
+    let last = *reports.lock().unwrap().last().unwrap();
+    assert_eq!(ways, 1);
+    assert_eq!(last.blobs_decoded, 1);
+    assert_eq!(last.elements, 1);
+    assert_eq!(last.total_bytes, Some(total));
+    assert!(last.bytes_read < total);
+}
+
+#[test]
+fn copy_raw_blobs() {
+    let count_elements = |data: &[u8]| {
+        let mut elements = 0_u64;
+        ElementReader::new(data)
+            .for_each(|_| elements += 1)
+            .unwrap();
+        elements
+    };
+
+    for path in TEST_FILE_PATHS
+        .iter()
+        .map(|f| f.path)
+        .chain([HISTORY_FILE_PATH.path])
+    {
+        let data = std::fs::read(path).unwrap();
+        let elements = count_elements(&data);
+
+        // Byte-identical copy
+        let mut writer = BlobWriter::new(vec![]);
+        for blob in BlobReader::from_path(path).unwrap() {
+            let blob = blob.unwrap();
+            let raw = blob.raw();
+            assert_eq!(
+                raw.bytes().len() as u64,
+                4 + raw.header().len() as u64 + raw.content().len() as u64
+            );
+            assert!(raw.payload().is_some());
+            writer.write_blob(&blob).unwrap();
+        }
+        assert_eq!(writer.into_inner().unwrap(), data);
+
+        // Append the data blobs a second time
+        let mut writer = BlobWriter::new(vec![]);
+        let blobs: Vec<_> = MmapBlobReader::from_slice(&data)
+            .collect::<Result<_>>()
+            .unwrap();
+        for blob in &blobs {
+            writer.write_raw(&blob.raw()).unwrap();
+        }
+        for blob in blobs.iter().filter(|b| b.get_type() == BlobType::OsmData) {
+            writer.write_raw(&blob.raw()).unwrap();
+        }
+        let doubled = writer.into_inner().unwrap();
+        assert_eq!(count_elements(&doubled), 2 * elements);
+
+        // Keep the header only
+        let mut writer = BlobWriter::new(vec![]);
+        writer.write_raw(&blobs[0].raw()).unwrap();
+        let trimmed = writer.into_inner().unwrap();
+        assert_eq!(count_elements(&trimmed), 0);
+        assert_eq!(BlobReader::new(trimmed.as_slice()).count(), 1);
+    }
+}
+
+#[test]
+fn recompress_blobs() {
+    let element_ids = |data: &[u8]| {
+        let mut ids = vec![];
+        ElementReader::new(data)
+            .for_each(|element| ids.push(element.id()))
+            .unwrap();
+        ids
+    };
+    let encodings = [
+        BlobEncoding::Raw,
+        BlobEncoding::Zlib { level: 1 },
+        #[cfg(feature = "zstd")]
+        BlobEncoding::Zstd { level: 19 },
+        #[cfg(feature = "lz4")]
+        BlobEncoding::Lz4,
+    ];
+
+    for path in TEST_FILE_PATHS
+        .iter()
+        .map(|f| f.path)
+        .chain([HISTORY_FILE_PATH.path])
+    {
+        let data = std::fs::read(path).unwrap();
+        let ids = element_ids(&data);
+        let blob_count = BlobReader::new(data.as_slice()).count();
+
+        for encoding in encodings {
+            let mut outputs = vec![];
+            for (parallel, threads) in [(false, 0), (true, 0), (true, 2)] {
+                let mut recompressor = Recompressor::new(encoding);
+                recompressor.set_parallel(parallel);
+                if threads > 0 {
+                    recompressor.set_threads(threads);
+                    recompressor.set_max_blobs_in_flight(1);
+                }
+                let mut writer = BlobWriter::new(vec![]);
+                recompressor
+                    .recompress(BlobReader::new(data.as_slice()), &mut writer)
+                    .unwrap();
+                assert_eq!(writer.blobs_written(), blob_count as u64);
+                outputs.push(writer.into_inner().unwrap());
+            }
+            assert!(outputs.iter().all(|output| *output == outputs[0]));
+            let output = &outputs[0];
+            for blob in MmapBlobReader::from_slice(output) {
+                let raw = blob.unwrap().raw();
+                assert_eq!(raw.compression(), Some(encoding.compression()));
+            }
+            assert_eq!(element_ids(output), ids);
+
+            // Convert back to zlib
+            let mut writer = BlobWriter::new(vec![]);
+            Recompressor::new(BlobEncoding::default())
+                .recompress(BlobReader::new(output.as_slice()), &mut writer)
+                .unwrap();
+            assert_eq!(element_ids(&writer.into_inner().unwrap()), ids);
+        }
+    }
+}
+
+#[test]
+fn recompress_block_sizes() {
+    let describe = |data: &[u8]| {
+        let mut elements = vec![];
+        ElementReader::new(data)
+            .for_each(|element| elements.push(describe_element(&element)))
+            .unwrap();
+        elements
+    };
+
+    for path in TEST_FILE_PATHS
+        .iter()
+        .chain([&HISTORY_FILE_PATH, &LOC_ON_WAYS_FILE_PATH])
+        .map(|f| f.path)
+    {
+        // The header and three copies of the data blobs
+        let data = std::fs::read(path).unwrap();
+        let blobs: Vec<_> = MmapBlobReader::from_slice(&data)
+            .map(|blob| blob.unwrap())
+            .collect();
+        let mut writer = BlobWriter::new(vec![]);
+        writer.write_raw(&blobs[0].raw()).unwrap();
+        for _ in 0..3 {
+            for blob in &blobs[1..] {
+                writer.write_raw(&blob.raw()).unwrap();
+            }
+        }
+        let input = writer.into_inner().unwrap();
+        let elements = describe(&input);
+
+        for (max_elements, max_bytes) in [
+            (Some(1), None),
+            (Some(2), None),
+            (Some(1000), None),
+            (None, Some(100)),
+            (None, Some(400)),
+            (Some(4), Some(300)),
+        ] {
+            for parallel in [false, true] {
+                let mut recompressor = Recompressor::new(BlobEncoding::default());
+                recompressor.set_parallel(parallel);
+                if let Some(max_elements) = max_elements {
+                    recompressor.set_max_block_elements(max_elements);
+                }
+                if let Some(max_bytes) = max_bytes {
+                    recompressor.set_max_block_bytes(max_bytes);
+                }
+                let mut writer = BlobWriter::new(vec![]);
+                recompressor
+                    .recompress(BlobReader::new(input.as_slice()), &mut writer)
+                    .unwrap();
+                let output = writer.into_inner().unwrap();
+                assert_eq!(describe(&output), elements);
+
+                let mut blocks = 0;
+                for blob in MmapBlobReader::from_slice(&output) {
+                    let blob = blob.unwrap();
+                    let block = match blob.decode().unwrap() {
+                        BlobDecode::OsmData(block) => block,
+                        _ => continue,
+                    };
+                    blocks += 1;
+                    let count = block.elements().count();
+                    assert!(count <= max_elements.unwrap_or(usize::MAX));
+                    let size = blob.raw().raw_size().unwrap() as u64;
+                    assert!(count == 1 || size <= max_bytes.unwrap_or(DEFAULT_MAX_BLOCK_BYTES));
+                }
+                match max_elements {
+                    Some(1) => assert_eq!(blocks, elements.len()),
+                    // All data blocks are merged
+                    Some(1000) => assert_eq!(blocks, 1),
+                    _ => {}
+                }
+            }
+        }
+    }
+}